@@ -0,0 +1,257 @@
+use std::io::Write;
+use std::path::PathBuf;
+
+use log::error;
+use crate::model::*;
+use crate::handler::attachment::load_files;
+use crate::handler::file::{blob_path, file_id_from_url};
+use crate::tree;
+
+use async_zip::{Compression, ZipDateTime, ZipEntryBuilder};
+use async_zip::tokio::write::ZipFileWriter;
+use axum::debug_handler;
+use axum::{
+    body::Body,
+    extract::{Path, Query, State},
+    response::{Json, Response},
+    http::{header, StatusCode},
+};
+
+use futures_util::StreamExt;
+use sqlx::Postgres;
+use sqlx::pool::Pool;
+use tokio::io::{AsyncWriteExt, DuplexStream};
+use tokio_util::compat::FuturesAsyncWriteCompatExt;
+use tokio_util::io::{ReaderStream, SyncIoBridge};
+
+// Archives are written while they are sent, through a pipe, so neither the
+// archive nor a whole file is ever held in memory. Files that are not
+// stored here (urls of other servers) are left out.
+
+struct ArchiveFile {
+    path:           String,
+    blob:           PathBuf,
+    mime:           Option<String>,
+    mtime:          chrono::NaiveDateTime,
+}
+
+// A cell's files and empty directories as ?format=zip (default) or tar.gz
+#[debug_handler]
+pub async fn cell_archive(
+    Path(cell_id): Path<uuid::Uuid>,
+    Query(query): Query<ArchiveQuery>,
+    State(pool): State<Pool<Postgres>>,
+) -> Result<Response, StatusCode> {
+    let mut cell: CellRow = match sqlx::query_as("SELECT * FROM cells WHERE cell_id=$1")
+        .bind(cell_id)
+        .fetch_one(&pool)
+        .await {
+            Ok(cell) => cell,
+            Err(e) => {
+                error!("{}", e);
+                return Err(StatusCode::NOT_FOUND)
+            }
+        };
+    if let Err(e) = load_files(std::slice::from_mut(&mut cell), &pool).await {
+        error!("{}", e);
+        return Err(StatusCode::INTERNAL_SERVER_ERROR)
+    }
+    send_archive(&cell.fileprops, cell.dirs, query.format, &cell_id.to_string())
+}
+
+// The same for files given in the request, such as a desktop app's cell
+#[debug_handler]
+pub async fn make_archive(
+    Query(query): Query<ArchiveQuery>,
+    Json(payload): Json<ArchiveReq>,
+) -> Result<Response, StatusCode> {
+    let layout = match &payload.rootdir {
+        Some(_) if !payload.fileprops.is_empty() || !payload.dirs.is_empty() => {
+            error!("archive request has both a rootdir and flat fileprops");
+            return Err(StatusCode::BAD_REQUEST)
+        }
+        Some(rootdir) => tree::to_flat(rootdir),
+        None => tree::normalize(&payload.fileprops, &payload.dirs),
+    };
+    let (fileprops, dirs) = match layout {
+        Ok(layout) => layout,
+        Err(e) => {
+            error!("{}", e);
+            return Err(StatusCode::BAD_REQUEST)
+        }
+    };
+    send_archive(&fileprops, dirs, query.format, "flowfs")
+}
+
+fn send_archive(
+    fileprops: &[FileProp],
+    dirs: Vec<String>,
+    format: ArchiveFormat,
+    name: &str,
+) -> Result<Response, StatusCode> {
+    // Entries without a modification time of their own get the archive's
+    let now = chrono::Utc::now().naive_utc();
+    let files: Vec<ArchiveFile> = fileprops.iter()
+        .filter_map(|fileprop| {
+            let blob = blob_path(file_id_from_url(&fileprop.url)?);
+            blob.is_file().then(|| ArchiveFile {
+                path: fileprop.path.clone(),
+                blob,
+                mime: fileprop.mime.clone(),
+                mtime: fileprop.mtime.unwrap_or(now),
+            })
+        })
+        .collect();
+    let (body, mime, extension) = archive_body(files, dirs, format, now);
+    Response::builder()
+        .header(header::CONTENT_TYPE, mime)
+        .header(header::CONTENT_DISPOSITION, format!("attachment; filename=\"{}.{}\"", name, extension))
+        .body(body)
+        .map_err(|e| {
+            error!("{}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })
+}
+
+// The archive as a body, with its mime type and file extension
+fn archive_body(
+    files: Vec<ArchiveFile>,
+    dirs: Vec<String>,
+    format: ArchiveFormat,
+    now: chrono::NaiveDateTime,
+) -> (Body, &'static str, &'static str) {
+    let (writer, reader) = tokio::io::duplex(64 * 1024);
+    let (task, mime, extension) = match format {
+        ArchiveFormat::Zip => {
+            let task = tokio::spawn(async move {
+                write_zip(writer, files, dirs, now).await.map_err(std::io::Error::other)
+            });
+            (task, "application/zip", "zip")
+        }
+        ArchiveFormat::TarGz => {
+            let writer = SyncIoBridge::new(writer);
+            let task = tokio::task::spawn_blocking(move || write_tar_gz(writer, files, dirs, now));
+            (task, "application/gzip", "tar.gz")
+        }
+    };
+    // As for exports, a failure ends the body with an error, so the client
+    // sees the archive cut short instead of a complete looking one
+    let outcome = futures_util::stream::once(async move {
+        let res = match task.await {
+            Ok(res) => res,
+            Err(e) => Err(std::io::Error::other(e)),
+        };
+        res.err().map(|e| {
+            error!("archive failed: {}", e);
+            Err(e)
+        })
+    }).filter_map(std::future::ready);
+    (Body::from_stream(ReaderStream::new(reader).chain(outcome)), mime, extension)
+}
+
+async fn write_zip(
+    writer: DuplexStream,
+    files: Vec<ArchiveFile>,
+    dirs: Vec<String>,
+    now: chrono::NaiveDateTime,
+) -> async_zip::error::Result<()> {
+    let mut zip = ZipFileWriter::with_tokio(writer);
+    for dir in dirs.iter() {
+        let entry = ZipEntryBuilder::new(format!("{}/", dir).into(), Compression::Stored)
+            .last_modification_date(ZipDateTime::from_chrono(&now.and_utc()));
+        zip.write_entry_whole(entry, &[]).await?;
+    }
+    for file in files.into_iter() {
+        let mut blob = tokio::fs::File::open(&file.blob).await?;
+        // Deflating media or archives again only costs time
+        let compression = match file.mime.as_deref() {
+            Some(mime) if is_compressed(mime) => Compression::Stored,
+            _ => Compression::Deflate,
+        };
+        let entry = ZipEntryBuilder::new(file.path.into(), compression)
+            .last_modification_date(ZipDateTime::from_chrono(&file.mtime.and_utc()));
+        let mut entry = zip.write_entry_stream(entry).await?.compat_write();
+        tokio::io::copy(&mut blob, &mut entry).await?;
+        entry.into_inner().close().await?;
+    }
+    zip.close().await?.into_inner().shutdown().await?;
+    Ok(())
+}
+
+fn write_tar_gz(
+    writer: impl Write,
+    files: Vec<ArchiveFile>,
+    dirs: Vec<String>,
+    now: chrono::NaiveDateTime,
+) -> std::io::Result<()> {
+    let encoder = flate2::write::GzEncoder::new(writer, flate2::Compression::default());
+    let mut builder = tar::Builder::new(encoder);
+    for dir in dirs.iter() {
+        let mut header = tar::Header::new_gnu();
+        header.set_entry_type(tar::EntryType::Directory);
+        header.set_size(0);
+        header.set_mode(0o755);
+        header.set_mtime(unix_time(now));
+        header.set_cksum();
+        builder.append_data(&mut header, format!("{}/", dir), std::io::empty())?;
+    }
+    for file in files.into_iter() {
+        let blob = std::fs::File::open(&file.blob)?;
+        let mut header = tar::Header::new_gnu();
+        header.set_size(blob.metadata()?.len());
+        header.set_mode(0o644);
+        header.set_mtime(unix_time(file.mtime));
+        header.set_cksum();
+        builder.append_data(&mut header, &file.path, blob)?;
+    }
+    builder.into_inner()?.finish()?.flush()
+}
+
+fn unix_time(time: chrono::NaiveDateTime) -> u64 {
+    time.and_utc().timestamp().max(0) as u64
+}
+
+fn is_compressed(mime: &str) -> bool {
+    let media = mime.starts_with("image/") && mime != "image/svg+xml"
+        || mime.starts_with("video/")
+        || mime.starts_with("audio/");
+    media || matches!(mime, "application/zip" | "application/gzip" | "application/x-7z-compressed" | "application/pdf")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn missing_file() -> ArchiveFile {
+        ArchiveFile {
+            path: "a.txt".to_string(),
+            blob: std::env::temp_dir().join(format!("flowfs-missing-{}", uuid::Uuid::new_v4())),
+            mime: None,
+            mtime: chrono::Utc::now().naive_utc(),
+        }
+    }
+
+    #[tokio::test]
+    async fn failed_zip_ends_body_with_error() {
+        let now = chrono::Utc::now().naive_utc();
+        let (body, _, _) = archive_body(vec![missing_file()], vec![], ArchiveFormat::Zip, now);
+        assert!(axum::body::to_bytes(body, usize::MAX).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn failed_tar_gz_ends_body_with_error() {
+        let now = chrono::Utc::now().naive_utc();
+        let (body, _, _) = archive_body(vec![missing_file()], vec![], ArchiveFormat::TarGz, now);
+        assert!(axum::body::to_bytes(body, usize::MAX).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn empty_archive_ends_cleanly() {
+        let now = chrono::Utc::now().naive_utc();
+        let dirs = vec!["out".to_string()];
+        let (body, _, _) = archive_body(vec![], dirs.clone(), ArchiveFormat::Zip, now);
+        assert!(!axum::body::to_bytes(body, usize::MAX).await.unwrap().is_empty());
+        let (body, _, _) = archive_body(vec![], dirs, ArchiveFormat::TarGz, now);
+        assert!(!axum::body::to_bytes(body, usize::MAX).await.unwrap().is_empty());
+    }
+}
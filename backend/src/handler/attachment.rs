@@ -0,0 +1,363 @@
+use std::collections::HashMap;
+
+use log::error;
+use crate::model::*;
+use crate::diff::diff_files;
+use crate::handler::file::{
+    add_refs, blob_path, complete_fileprops, file_id_from_url, guess_mime, release_refs,
+    remove_blobs, send_blob,
+};
+use crate::handler::revision::{keep_original, record_revision, same_files};
+use crate::patch;
+use crate::tree;
+
+use axum::debug_handler;
+use axum::{
+    extract::{Path, Query, State},
+    response::{IntoResponse, Json, Redirect, Response},
+    http::{HeaderMap, StatusCode},
+};
+
+use sqlx::{PgConnection, Postgres, QueryBuilder};
+use sqlx::pool::Pool;
+
+// Attached files live in the files table, one row per path of a cell, and
+// directories without files in the dirs table. Cells are read without them
+// and get their fileprops and dirs filled in here.
+
+// Search the files of a user's cells, newest cell first. `name` matches the
+// last path segment exactly, `glob` (* and ?) matches it or, when it holds a
+// slash, the whole path. `mime` may be a glob too, e.g. image/*. Matching
+// ignores case, except for the hash.
+#[debug_handler]
+pub async fn search_files(
+    Query(filter): Query<FileFilter>,
+    State(pool): State<Pool<Postgres>>,
+) -> Result<Json<FileMatches>, StatusCode> {
+    let mut query = QueryBuilder::<Postgres>::new(
+        "SELECT files.*, cells.created_at FROM files JOIN cells ON cells.cell_id=files.cell_id
+        WHERE cells.user_id="
+    );
+    query.push_bind(filter.user_id);
+    if let Some(name) = filter.name {
+        query.push(" AND lower(files.name)=lower(").push_bind(name).push(")");
+    }
+    if let Some(glob) = filter.glob {
+        let column = if glob.contains('/') { "files.path" } else { "files.name" };
+        query.push(format!(" AND {} ILIKE ", column)).push_bind(like_pattern(&glob));
+    }
+    if let Some(mime) = filter.mime {
+        query.push(" AND files.mime ILIKE ").push_bind(like_pattern(&mime));
+    }
+    if let Some(sha256) = filter.sha256 {
+        query.push(" AND files.sha256=").push_bind(sha256.to_lowercase());
+    }
+    if let Some(since) = filter.since {
+        query.push(" AND cells.created_at>=").push_bind(since);
+    }
+    if let Some(until) = filter.until {
+        query.push(" AND cells.created_at<").push_bind(until);
+    }
+    query.push(" ORDER BY cells.created_at DESC, files.path");
+    if let Some(limit) = filter.limit {
+        query.push(" LIMIT ").push_bind(limit);
+    }
+    let rows: Vec<FileMatchRow> = match query.build_query_as().fetch_all(&pool).await {
+        Ok(rows) => rows,
+        Err(e) => {
+            error!("{}", e);
+            return Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    };
+    let files = rows.into_iter()
+        .map(|row| FileMatch {
+            cell_id: row.file.cell_id,
+            created_at: row.created_at,
+            fileprop: row.file.into(),
+        })
+        .collect();
+    Ok(Json(FileMatches { files }))
+}
+
+// The content of one file of a cell, by its path. HEAD, ETag and Range
+// requests work as for GET /files/:file_id.
+#[debug_handler]
+pub async fn show_file(
+    Path((cell_id, path)): Path<(uuid::Uuid, String)>,
+    headers: HeaderMap,
+    State(pool): State<Pool<Postgres>>,
+) -> Result<Response, StatusCode> {
+    let row: Option<FileRow> = match sqlx::query_as("SELECT * FROM files WHERE cell_id=$1 AND path=$2")
+        .bind(cell_id)
+        .bind(path.trim_end_matches('/'))
+        .fetch_optional(&pool)
+        .await {
+            Ok(row) => row,
+            Err(e) => {
+                error!("{}", e);
+                return Err(StatusCode::INTERNAL_SERVER_ERROR)
+            }
+        };
+    let Some(row) = row else {
+        return Err(StatusCode::NOT_FOUND)
+    };
+    send_file(&row.into(), &headers).await
+}
+
+pub async fn send_file(fileprop: &FileProp, headers: &HeaderMap) -> Result<Response, StatusCode> {
+    match file_id_from_url(&fileprop.url) {
+        Some(file_id) if blob_path(file_id).is_file() => {
+            let mime = fileprop.mime.clone().unwrap_or_else(|| guess_mime(&fileprop.path));
+            send_blob(file_id, &mime, headers).await
+        }
+        // Files stored elsewhere are fetched from where they live
+        _ => Ok(Redirect::temporary(&fileprop.url).into_response()),
+    }
+}
+
+// Compare the files of two cells, the first one being the old side. Files
+// are told apart by path, and one whose content turns up at another path
+// counts as renamed.
+#[debug_handler]
+pub async fn diff_cells(
+    Path((cell_id, other_id)): Path<(uuid::Uuid, uuid::Uuid)>,
+    State(pool): State<Pool<Postgres>>,
+) -> Result<Json<CellsDiff>, StatusCode> {
+    let mut conn = pool.acquire().await.map_err(internal_error)?;
+    let old = cell_files(&mut conn, cell_id).await?;
+    let new = cell_files(&mut conn, other_id).await?;
+    let files = diff_files(&old.fileprops, &old.dirs, &new.fileprops, &new.dirs);
+    Ok(Json(CellsDiff { cell_id, other_id, files }))
+}
+
+// Apply a list of edits to a cell's files, all of them or none. A path that
+// is not acceptable gives 400, a missing one 404 and a clash 409.
+#[debug_handler]
+pub async fn patch_files(
+    Path(cell_id): Path<uuid::Uuid>,
+    State(pool): State<Pool<Postgres>>,
+    Json(mut payload): Json<FilePatch>,
+) -> Result<Json<CellFiles>, StatusCode> {
+    complete_ops(&mut payload.ops);
+    let device_id = payload.device_id.clone();
+    let cell_files = update_files(cell_id, device_id.as_deref(), &pool, |fileprops, dirs| {
+        let (new_fileprops, new_dirs) = apply_ops(fileprops, dirs, &payload.ops)?;
+        *fileprops = new_fileprops.clone();
+        *dirs = new_dirs.clone();
+        Ok(CellFiles { fileprops: new_fileprops, dirs: new_dirs })
+    }).await?;
+    Ok(Json(cell_files))
+}
+
+// Fill in what the files added by `ops` lack, as for new cells
+pub fn complete_ops(ops: &mut [FileOp]) {
+    for op in ops.iter_mut() {
+        if let FileOp::Add(fileprop) | FileOp::Replace(fileprop) = op {
+            complete_fileprops(std::slice::from_mut(fileprop));
+        }
+    }
+}
+
+// patch::apply with its errors as statuses: 400 for a bad path, 404 for a
+// missing one and 409 for a clash
+pub fn apply_ops(
+    fileprops: &[FileProp],
+    dirs: &[String],
+    ops: &[FileOp],
+) -> Result<(Vec<FileProp>, Vec<String>), StatusCode> {
+    patch::apply(fileprops, dirs, ops).map_err(|e| {
+        error!("{}", e);
+        match e {
+            patch::PatchError::Path(_) => StatusCode::BAD_REQUEST,
+            patch::PatchError::NotFound(_) => StatusCode::NOT_FOUND,
+            _ => StatusCode::CONFLICT,
+        }
+    })
+}
+
+// Turn a glob into a LIKE pattern, escaping LIKE's own wildcards
+fn like_pattern(glob: &str) -> String {
+    let mut pattern = String::with_capacity(glob.len());
+    for c in glob.chars() {
+        match c {
+            '*' => pattern.push('%'),
+            '?' => pattern.push('_'),
+            '%' | '_' | '\\' => {
+                pattern.push('\\');
+                pattern.push(c);
+            }
+            _ => pattern.push(c),
+        }
+    }
+    pattern
+}
+
+// Fill in the fileprops of `cells`, ordered by path, and their empty dirs
+pub async fn load_files(cells: &mut [CellRow], pool: &Pool<Postgres>) -> Result<(), sqlx::Error> {
+    let cell_ids: Vec<uuid::Uuid> = cells.iter().map(|cell| cell.cell_id).collect();
+    let rows: Vec<FileRow> = sqlx::query_as("SELECT * FROM files WHERE cell_id=ANY($1) ORDER BY path")
+        .bind(&cell_ids)
+        .fetch_all(pool)
+        .await?;
+    let mut by_cell: HashMap<uuid::Uuid, Vec<FileProp>> = HashMap::new();
+    for row in rows.into_iter() {
+        by_cell.entry(row.cell_id).or_default().push(row.into());
+    }
+    let dir_rows: Vec<(uuid::Uuid, String)> = sqlx::query_as(
+        "SELECT cell_id, path FROM dirs WHERE cell_id=ANY($1) ORDER BY path"
+    )
+        .bind(&cell_ids)
+        .fetch_all(pool)
+        .await?;
+    let mut dirs_by_cell: HashMap<uuid::Uuid, Vec<String>> = HashMap::new();
+    for (cell_id, path) in dir_rows.into_iter() {
+        dirs_by_cell.entry(cell_id).or_default().push(path);
+    }
+    for cell in cells.iter_mut() {
+        cell.fileprops = by_cell.remove(&cell.cell_id).unwrap_or_default();
+        cell.dirs = dirs_by_cell.remove(&cell.cell_id).unwrap_or_default();
+    }
+    Ok(())
+}
+
+// The files and empty dirs of one cell, 404 if there is no such cell. Within
+// a transaction the cell's files cannot change until it ends.
+pub async fn cell_files(conn: &mut PgConnection, cell_id: uuid::Uuid) -> Result<CellFiles, StatusCode> {
+    let found: Option<(uuid::Uuid,)> = sqlx::query_as("SELECT cell_id FROM cells WHERE cell_id=$1 FOR SHARE")
+        .bind(cell_id)
+        .fetch_optional(&mut *conn)
+        .await
+        .map_err(internal_error)?;
+    found.ok_or(StatusCode::NOT_FOUND)?;
+    let rows: Vec<FileRow> = sqlx::query_as("SELECT * FROM files WHERE cell_id=$1 ORDER BY path")
+        .bind(cell_id)
+        .fetch_all(&mut *conn)
+        .await
+        .map_err(internal_error)?;
+    let dirs: Vec<(String,)> = sqlx::query_as("SELECT path FROM dirs WHERE cell_id=$1 ORDER BY path")
+        .bind(cell_id)
+        .fetch_all(&mut *conn)
+        .await
+        .map_err(internal_error)?;
+    Ok(CellFiles {
+        fileprops: rows.into_iter().map(FileProp::from).collect(),
+        dirs: dirs.into_iter().map(|(path,)| path).collect(),
+    })
+}
+
+pub async fn insert_dirs(
+    conn: &mut PgConnection,
+    cell_id: uuid::Uuid,
+    dirs: &[String],
+) -> Result<(), sqlx::Error> {
+    if dirs.is_empty() {
+        return Ok(())
+    }
+    sqlx::query("INSERT INTO dirs (cell_id, path) SELECT $1, * FROM UNNEST($2::text[])")
+        .bind(cell_id)
+        .bind(dirs)
+        .execute(conn)
+        .await?;
+    Ok(())
+}
+
+pub async fn take_dirs(
+    conn: &mut PgConnection,
+    cell_id: uuid::Uuid,
+) -> Result<Vec<String>, sqlx::Error> {
+    let rows: Vec<(String,)> = sqlx::query_as("DELETE FROM dirs WHERE cell_id=$1 RETURNING path")
+        .bind(cell_id)
+        .fetch_all(conn)
+        .await?;
+    let mut dirs: Vec<String> = rows.into_iter().map(|(path,)| path).collect();
+    dirs.sort();
+    Ok(dirs)
+}
+
+pub async fn insert_fileprops(
+    conn: &mut PgConnection,
+    cell_id: uuid::Uuid,
+    fileprops: &[FileProp],
+) -> Result<(), sqlx::Error> {
+    if fileprops.is_empty() {
+        return Ok(())
+    }
+    let paths: Vec<&str> = fileprops.iter().map(|f| f.path.as_str()).collect();
+    let names: Vec<&str> = paths.iter().map(|p| p.rsplit('/').next().unwrap_or(p)).collect();
+    sqlx::query(
+        "INSERT INTO files (cell_id, path, name, url, completed, size, sha256, mime, mtime)
+        SELECT $1, * FROM UNNEST($2::text[], $3::text[], $4::text[], $5::bool[], $6::bigint[], $7::text[], $8::text[], $9::timestamp[])"
+    )
+        .bind(cell_id)
+        .bind(&paths)
+        .bind(&names)
+        .bind(fileprops.iter().map(|f| f.url.as_str()).collect::<Vec<_>>())
+        .bind(fileprops.iter().map(|f| f.completed).collect::<Vec<_>>())
+        .bind(fileprops.iter().map(|f| f.size.map(|s| s as i64)).collect::<Vec<_>>())
+        .bind(fileprops.iter().map(|f| f.sha256.as_deref()).collect::<Vec<_>>())
+        .bind(fileprops.iter().map(|f| f.mime.as_deref()).collect::<Vec<_>>())
+        .bind(fileprops.iter().map(|f| f.mtime).collect::<Vec<_>>())
+        .execute(conn)
+        .await?;
+    Ok(())
+}
+
+// Remove the files of a cell, returning what it had
+pub async fn take_fileprops(
+    conn: &mut PgConnection,
+    cell_id: uuid::Uuid,
+) -> Result<Vec<FileProp>, sqlx::Error> {
+    let rows: Vec<FileRow> = sqlx::query_as("DELETE FROM files WHERE cell_id=$1 RETURNING *")
+        .bind(cell_id)
+        .fetch_all(conn)
+        .await?;
+    let mut fileprops: Vec<FileProp> = rows.into_iter().map(FileProp::from).collect();
+    fileprops.sort_by(|a, b| a.path.cmp(&b.path));
+    Ok(fileprops)
+}
+
+// Apply `change` to the files and empty directories of a cell with the row
+// locked, so concurrent writes to the same cell do not lose each other's
+// files. The result is normalized and a clash between names gives 409. A
+// change is kept as a revision from `device_id`.
+pub async fn update_files<T>(
+    cell_id: uuid::Uuid,
+    device_id: Option<&str>,
+    pool: &Pool<Postgres>,
+    change: impl FnOnce(&mut Vec<FileProp>, &mut Vec<String>) -> Result<T, StatusCode>,
+) -> Result<T, StatusCode> {
+    let mut tx = pool.begin().await.map_err(internal_error)?;
+    let locked: Option<(uuid::Uuid,)> = sqlx::query_as("SELECT cell_id FROM cells WHERE cell_id=$1 FOR UPDATE")
+        .bind(cell_id)
+        .fetch_optional(&mut *tx)
+        .await
+        .map_err(internal_error)?;
+    locked.ok_or(StatusCode::NOT_FOUND)?;
+    keep_original(&mut tx, cell_id).await.map_err(internal_error)?;
+    let old_fileprops = take_fileprops(&mut tx, cell_id).await.map_err(internal_error)?;
+    let mut fileprops = old_fileprops.clone();
+    let mut dirs = take_dirs(&mut tx, cell_id).await.map_err(internal_error)?;
+    let old_dirs = dirs.clone();
+    let res = change(&mut fileprops, &mut dirs)?;
+    let (fileprops, dirs) = tree::normalize(&fileprops, &dirs).map_err(|e| {
+        error!("{}", e);
+        StatusCode::CONFLICT
+    })?;
+    // Count the new references first so that files kept by the change never
+    // drop to zero on the way
+    add_refs(&mut tx, &fileprops).await?;
+    let unused = release_refs(&mut tx, &old_fileprops).await.map_err(internal_error)?;
+    insert_fileprops(&mut tx, cell_id, &fileprops).await.map_err(internal_error)?;
+    insert_dirs(&mut tx, cell_id, &dirs).await.map_err(internal_error)?;
+    if !same_files(&old_fileprops, &old_dirs, &fileprops, &dirs) {
+        record_revision(&mut tx, cell_id, device_id).await.map_err(internal_error)?;
+    }
+    tx.commit().await.map_err(internal_error)?;
+    remove_blobs(&unused).await;
+    Ok(res)
+}
+
+fn internal_error<E: std::fmt::Display>(e: E) -> StatusCode {
+    error!("{}", e);
+    StatusCode::INTERNAL_SERVER_ERROR
+}
@@ -1,5 +1,13 @@
+use std::collections::{HashMap, HashSet};
+
 use log::error;
 use crate::model::*;
+use crate::tree;
+use crate::handler::attachment::{
+    apply_ops, cell_files, complete_ops, insert_dirs, insert_fileprops, load_files, take_fileprops,
+};
+use crate::handler::file::{add_refs, complete_fileprops, release_refs, remove_blobs};
+use crate::handler::revision::{change_cell, replies_among, take_revisions};
 use axum::debug_handler;
 use axum::{
     extract::{Path, State},
@@ -7,7 +15,7 @@ use axum::{
     http::StatusCode,
 };
 
-use sqlx::Postgres;
+use sqlx::{Acquire, PgConnection, Postgres, QueryBuilder};
 use sqlx::pool::Pool;
 
 use async_recursion::async_recursion;
@@ -17,8 +25,26 @@ pub async fn list_cells(
     State(pool): State<Pool<Postgres>>,
     Json(payload): Json<CellFilter>,
 ) -> Result<Json<Cells>, StatusCode> {
-    let id_cells: Vec<IdRes> = match sqlx::query_as("SELECT cell_id AS id FROM cells WHERE user_id=$1")
-        .bind(payload.user_id).fetch_all(&pool).await {
+    let mut query = QueryBuilder::<Postgres>::new("SELECT cell_id AS id FROM cells WHERE user_id=");
+    query.push_bind(payload.user_id);
+    if let Some(device_id) = payload.device_id {
+        query.push(" AND device_id=").push_bind(device_id);
+    }
+    if let Some(text) = payload.text {
+        query.push(" AND text ILIKE ").push_bind(format!("%{}%", text));
+    }
+    if let Some(since) = payload.since {
+        query.push(" AND created_at>=").push_bind(since);
+    }
+    if let Some(until) = payload.until {
+        query.push(" AND created_at<").push_bind(until);
+    }
+    query.push(" ORDER BY created_at DESC");
+    if let Some(limit) = payload.limit {
+        query.push(" LIMIT ").push_bind(limit);
+    }
+    let id_cells: Vec<IdRes> = match query.build_query_as()
+        .fetch_all(&pool).await {
             Ok(cells) =>cells,
             Err(e) => {
                 error!("{}", e);
@@ -45,41 +71,441 @@ pub async fn create_cell(
     State(pool): State<Pool<Postgres>>,
     Json(payload): Json<CellReq>
 ) -> Result<Json<IdRes>, StatusCode> {
+    let (fileprops, dirs) = match cell_layout(&payload) {
+        Ok(layout) => layout,
+        Err(e) => {
+            error!("{}", e);
+            return Err(StatusCode::BAD_REQUEST)
+        }
+    };
+    let mut tx = match pool.begin().await {
+        Ok(tx) => tx,
+        Err(e) => {
+            error!("{}", e);
+            return Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    };
+    let created = insert_cell(&mut tx, &payload, &fileprops, &dirs).await?;
+    if created {
+        insert_edges(&mut tx, &payload).await?;
+        if on_cycle(&mut tx, &payload).await? {
+            error!("cell {} would be on a cycle", payload.cell_id);
+            return Err(StatusCode::BAD_REQUEST)
+        }
+    } else if !has_edges(&mut tx, &payload).await? {
+        error!("cell {} already exists with other edges", payload.cell_id);
+        return Err(StatusCode::CONFLICT)
+    }
+    if let Err(e) = tx.commit().await {
+        error!("{}", e);
+        return Err(StatusCode::INTERNAL_SERVER_ERROR)
+    };
+    Ok(Json(IdRes{id: payload.cell_id}))
+}
+
+// Reply to a cell with its files, changed by the ops of the request. The
+// reply refers to the same stored content as its parent, so only files the
+// ops add or replace take new space. Retrying is safe as for create_cell.
+#[debug_handler]
+pub async fn fork_cell(
+    Path(parent_id): Path<uuid::Uuid>,
+    State(pool): State<Pool<Postgres>>,
+    Json(mut payload): Json<CellFork>,
+) -> Result<Json<IdRes>, StatusCode> {
+    complete_ops(&mut payload.ops);
+    let mut tx = match pool.begin().await {
+        Ok(tx) => tx,
+        Err(e) => {
+            error!("{}", e);
+            return Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    };
+    let parent = cell_files(&mut tx, parent_id).await?;
+    let (fileprops, dirs) = apply_ops(&parent.fileprops, &parent.dirs, &payload.ops)?;
+    let req = CellReq {
+        cell_id: payload.cell_id,
+        user_id: payload.user_id,
+        device_id: payload.device_id,
+        text: payload.text,
+        is_open: payload.is_open,
+        fileprops: fileprops.clone(),
+        dirs: dirs.clone(),
+        rootdir: None,
+        parent_ids: vec![parent_id],
+        child_ids: vec![],
+    };
+    let created = insert_cell(&mut tx, &req, &fileprops, &dirs).await?;
+    if created {
+        insert_edges(&mut tx, &req).await?;
+    } else if !has_edges(&mut tx, &req).await? {
+        error!("cell {} already exists with other edges", req.cell_id);
+        return Err(StatusCode::CONFLICT)
+    }
+    if let Err(e) = tx.commit().await {
+        error!("{}", e);
+        return Err(StatusCode::INTERNAL_SERVER_ERROR)
+    };
+    Ok(Json(IdRes{id: req.cell_id}))
+}
+
+// Create many cells at once, all of them or none. Edges may refer to cells
+// of the same batch, in any order, but may not form a cycle. Every cell gets
+// a result; when one fails the others are reported as skipped.
+#[debug_handler]
+pub async fn create_cells(
+    State(pool): State<Pool<Postgres>>,
+    Json(payload): Json<CellBatch>,
+) -> (StatusCode, Json<BatchRes>) {
+    let cells = payload.cells;
+    let mut results: Vec<BatchItem> = cells.iter()
+        .map(|cell| BatchItem { cell_id: cell.cell_id, status: BatchStatus::Skipped, error: None })
+        .collect();
+    let mut layouts = vec![];
+    for (index, cell) in cells.iter().enumerate() {
+        match cell_layout(cell) {
+            Ok(layout) => layouts.push(layout),
+            Err(e) => {
+                results[index].fail(BatchStatus::Invalid, e);
+                layouts.push((vec![], vec![]));
+            }
+        }
+    }
+    check_batch(&cells, &mut results);
+    if results.iter().any(|r| r.status != BatchStatus::Skipped) {
+        return batch_res(false, results)
+    }
+
+    let mut tx = match pool.begin().await {
+        Ok(tx) => tx,
+        Err(e) => {
+            error!("{}", e);
+            return (StatusCode::INTERNAL_SERVER_ERROR, Json(BatchRes { committed: false, results }))
+        }
+    };
+    // Each cell goes in a savepoint, so one failure does not hide the others
+    for (index, cell) in cells.iter().enumerate() {
+        let (fileprops, dirs) = &layouts[index];
+        let res = match tx.begin().await {
+            Ok(mut savepoint) => match insert_cell(&mut savepoint, cell, fileprops, dirs).await {
+                Ok(created) => savepoint.commit().await.map(|_| created).map_err(|e| {
+                    error!("{}", e);
+                    StatusCode::INTERNAL_SERVER_ERROR
+                }),
+                Err(code) => Err(code),
+            },
+            Err(e) => {
+                error!("{}", e);
+                Err(StatusCode::INTERNAL_SERVER_ERROR)
+            }
+        };
+        results[index] = match res {
+            Ok(true) => BatchItem { cell_id: cell.cell_id, status: BatchStatus::Created, error: None },
+            Ok(false) => BatchItem { cell_id: cell.cell_id, status: BatchStatus::Existing, error: None },
+            Err(code) => failed_item(cell.cell_id, code),
+        };
+    }
+    // Edges once every cell of the batch is there
+    for (index, cell) in cells.iter().enumerate() {
+        let res = match results[index].status {
+            BatchStatus::Created => match tx.begin().await {
+                Ok(mut savepoint) => match insert_edges(&mut savepoint, cell).await {
+                    Ok(()) => savepoint.commit().await.map_err(|e| {
+                        error!("{}", e);
+                        StatusCode::INTERNAL_SERVER_ERROR
+                    }),
+                    Err(code) => Err(code),
+                },
+                Err(e) => {
+                    error!("{}", e);
+                    Err(StatusCode::INTERNAL_SERVER_ERROR)
+                }
+            },
+            BatchStatus::Existing => match has_edges(&mut tx, cell).await {
+                Ok(true) => Ok(()),
+                Ok(false) => Err(StatusCode::CONFLICT),
+                Err(code) => Err(code),
+            },
+            _ => continue,
+        };
+        if let Err(code) = res {
+            results[index] = failed_item(cell.cell_id, code);
+        }
+    }
+    // A new cycle runs through a cell whose edges were just added, and may
+    // take in stored edges that `check_batch` could not see
+    for (index, cell) in cells.iter().enumerate() {
+        if results[index].status != BatchStatus::Created {
+            continue
+        }
+        match on_cycle(&mut tx, cell).await {
+            Ok(false) => (),
+            Ok(true) => results[index].fail(
+                BatchStatus::Invalid,
+                format!("cell {} is on a cycle with stored cells", cell.cell_id),
+            ),
+            Err(code) => results[index] = failed_item(cell.cell_id, code),
+        }
+    }
+    if results.iter().any(|r| !matches!(r.status, BatchStatus::Created | BatchStatus::Existing)) {
+        for result in results.iter_mut().filter(|r| matches!(r.status, BatchStatus::Created | BatchStatus::Existing)) {
+            result.status = BatchStatus::Skipped;
+        }
+        return batch_res(false, results)
+    }
+    if let Err(e) = tx.commit().await {
+        error!("{}", e);
+        return (StatusCode::INTERNAL_SERVER_ERROR, Json(BatchRes { committed: false, results }))
+    }
+    batch_res(true, results)
+}
+
+// Ids used twice and cycles among the edges of the batch, including those
+// running through a cell outside of it that batch cells both reply to and
+// are replied to by. Cycles that also need stored edges are found once the
+// edges are inserted, by `check_cycles`.
+fn check_batch(cells: &[CellReq], results: &mut [BatchItem]) {
+    let mut index_of: HashMap<uuid::Uuid, usize> = HashMap::new();
+    for (index, cell) in cells.iter().enumerate() {
+        if index_of.insert(cell.cell_id, index).is_some() {
+            results[index].fail(BatchStatus::Invalid, format!("cell {} appears more than once", cell.cell_id));
+        }
+    }
+    // Cells outside of the batch come after those in it
+    for cell in cells.iter() {
+        for id in cell.parent_ids.iter().chain(cell.child_ids.iter()) {
+            let next = index_of.len();
+            index_of.entry(*id).or_insert(next);
+        }
+    }
+    // Parents of each cell as far as the batch tells
+    let mut parents: Vec<HashSet<usize>> = vec![HashSet::new(); index_of.len()];
+    for (index, cell) in cells.iter().enumerate() {
+        if cell.parent_ids.contains(&cell.cell_id) || cell.child_ids.contains(&cell.cell_id) {
+            results[index].fail(BatchStatus::Invalid, format!("cell {} refers to itself", cell.cell_id));
+        }
+        for parent_id in cell.parent_ids.iter() {
+            parents[index].insert(index_of[parent_id]);
+        }
+        for child_id in cell.child_ids.iter() {
+            parents[index_of[child_id]].insert(index);
+        }
+    }
+    // Take away cells whose parents or whose children are all gone; what is
+    // left is on a cycle, not merely above or below one
+    let mut left: HashSet<usize> = (0..index_of.len()).collect();
+    loop {
+        let ready: Vec<usize> = left.iter()
+            .copied()
+            .filter(|index| {
+                parents[*index].iter().all(|p| !left.contains(p))
+                    || left.iter().all(|child| !parents[*child].contains(index))
+            })
+            .collect();
+        if ready.is_empty() {
+            break
+        }
+        for index in ready {
+            left.remove(&index);
+        }
+    }
+    for index in left.into_iter().filter(|index| *index < cells.len()) {
+        if results[index].status == BatchStatus::Skipped {
+            results[index].fail(BatchStatus::Invalid, format!("cell {} is on a cycle", cells[index].cell_id));
+        }
+    }
+}
+
+// The result of a cell whose insert returned `code`
+fn failed_item(cell_id: uuid::Uuid, code: StatusCode) -> BatchItem {
+    let (status, error) = match code {
+        StatusCode::BAD_REQUEST => (BatchStatus::Invalid, "refers to an unknown user or cell"),
+        StatusCode::CONFLICT => (BatchStatus::Conflict, "already exists with other content"),
+        _ => (BatchStatus::Failed, "could not be stored"),
+    };
+    BatchItem { cell_id, status, error: Some(format!("cell {} {}", cell_id, error)) }
+}
+
+fn batch_res(committed: bool, results: Vec<BatchItem>) -> (StatusCode, Json<BatchRes>) {
+    let status = if committed {
+        StatusCode::OK
+    } else if results.iter().any(|r| r.status == BatchStatus::Failed) {
+        StatusCode::INTERNAL_SERVER_ERROR
+    } else if results.iter().any(|r| r.status == BatchStatus::Invalid) {
+        StatusCode::BAD_REQUEST
+    } else {
+        StatusCode::CONFLICT
+    };
+    (status, Json(BatchRes { committed, results }))
+}
+
+// The files of a request, flat and normalized, as they are stored
+pub fn cell_layout(payload: &CellReq) -> Result<(Vec<FileProp>, Vec<String>), String> {
+    let layout = match &payload.rootdir {
+        Some(_) if !payload.fileprops.is_empty() || !payload.dirs.is_empty() => {
+            return Err(format!("cell {} has both a rootdir and flat fileprops", payload.cell_id))
+        }
+        Some(rootdir) => tree::to_flat(rootdir),
+        None => tree::normalize(&payload.fileprops, &payload.dirs),
+    };
+    let (mut fileprops, dirs) = layout.map_err(|e| format!("cell {}: {}", payload.cell_id, e))?;
+    complete_fileprops(&mut fileprops);
+    Ok((fileprops, dirs))
+}
+
+// Insert a cell with its files. A cell that already exists is taken as an
+// earlier try of the same request and left as it is, unless it differs from
+// `payload` (409). Returns whether the cell was new; edges are added apart,
+// by insert_edges, so that cells of a batch can refer to each other.
+pub async fn insert_cell(
+    conn: &mut PgConnection,
+    payload: &CellReq,
+    fileprops: &[FileProp],
+    dirs: &[String],
+) -> Result<bool, StatusCode> {
     let cell_id = payload.cell_id;
-    let parent_ids = payload.parent_ids;
-    let child_ids = payload.child_ids;
-    if let Err(e) = sqlx::query(
-      "INSERT INTO cells (cell_id, user_id, device_id, text, fileprops, is_open) 
-      VALUES ($1, $2, $3, $4, $5, $6)"
+    let inserted: Option<(uuid::Uuid,)> = match sqlx::query_as(
+      "INSERT INTO cells (cell_id, user_id, device_id, text, is_open) 
+      VALUES ($1, $2, $3, $4, $5) ON CONFLICT (cell_id) DO NOTHING RETURNING cell_id"
     )
         .bind(cell_id)
         .bind(payload.user_id)
-        .bind(payload.device_id)
-        .bind(payload.text)
-        .bind(sqlx::types::Json(payload.fileprops))
+        .bind(&payload.device_id)
+        .bind(&payload.text)
         .bind(payload.is_open)
-        .execute(&pool)
+        .fetch_optional(&mut *conn)
         .await {
-            error!("{}", e);
-            return Err(StatusCode::INTERNAL_SERVER_ERROR)
+            Ok(inserted) => inserted,
+            Err(e) => {
+                error!("{}", e);
+                return Err(insert_error(&e))
+            }
         };
+    if inserted.is_none() {
+        return match is_same_cell(conn, payload, fileprops, dirs).await {
+            Ok(true) => Ok(false),
+            Ok(false) => {
+                error!("cell {} already exists with other content", cell_id);
+                Err(StatusCode::CONFLICT)
+            }
+            Err(e) => {
+                error!("{}", e);
+                Err(StatusCode::INTERNAL_SERVER_ERROR)
+            }
+        }
+    }
+    if let Err(e) = insert_fileprops(conn, cell_id, fileprops).await {
+        error!("{}", e);
+        return Err(StatusCode::INTERNAL_SERVER_ERROR)
+    };
+    if let Err(e) = insert_dirs(conn, cell_id, dirs).await {
+        error!("{}", e);
+        return Err(StatusCode::INTERNAL_SERVER_ERROR)
+    };
+    add_refs(conn, fileprops).await?;
+    Ok(true)
+}
 
-    // Prepare the query
-    let query = "INSERT INTO family_tree (child_id, parent_id) SELECT * FROM UNNEST($1::uuid[], $2::uuid[])".to_string();
-    let child_count = child_ids.len();
-    let all_child_ids = [vec![cell_id; parent_ids.len()], child_ids].concat();
-    let all_parent_ids = [parent_ids, vec![cell_id; child_count]].concat();
-    if !all_child_ids.is_empty() {
-        if let Err(e) = sqlx::query(&query)
+// Add the edges of `payload` that are not there yet
+pub async fn insert_edges(conn: &mut PgConnection, payload: &CellReq) -> Result<(), StatusCode> {
+    let cell_id = payload.cell_id;
+    let all_child_ids = [vec![cell_id; payload.parent_ids.len()], payload.child_ids.clone()].concat();
+    let all_parent_ids = [payload.parent_ids.clone(), vec![cell_id; payload.child_ids.len()]].concat();
+    if all_child_ids.is_empty() {
+        return Ok(())
+    }
+    if let Err(e) = sqlx::query(
+        "INSERT INTO family_tree (child_id, parent_id)
+        SELECT DISTINCT e.child_id, e.parent_id FROM UNNEST($1::uuid[], $2::uuid[]) AS e(child_id, parent_id)
+        WHERE NOT EXISTS (
+            SELECT 1 FROM family_tree f WHERE f.child_id=e.child_id AND f.parent_id=e.parent_id
+        )"
+    )
         .bind(all_child_ids)
         .bind(all_parent_ids)
-        .execute(&pool)
+        .execute(&mut *conn)
         .await {
             error!("{}", e);
-            return Err(StatusCode::INTERNAL_SERVER_ERROR)
+            return Err(insert_error(&e))
         };
+    Ok(())
+}
+
+// Whether the stored edges lead from the cell of `payload` back to itself
+async fn on_cycle(conn: &mut PgConnection, payload: &CellReq) -> Result<bool, StatusCode> {
+    if payload.parent_ids.is_empty() && payload.child_ids.is_empty() {
+        return Ok(false)
     }
-    Ok(Json(IdRes{id: cell_id}))
+    match replies_among(conn, payload.cell_id, &[payload.cell_id]).await {
+        Ok(replies) => Ok(!replies.is_empty()),
+        Err(e) => {
+            error!("{}", e);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+// Whether every edge of `payload` is stored; later cells may have added more
+pub async fn has_edges(conn: &mut PgConnection, payload: &CellReq) -> Result<bool, StatusCode> {
+    let cell_id = payload.cell_id;
+    let edges: Vec<Edge> = match sqlx::query_as("SELECT * FROM family_tree WHERE child_id=$1 OR parent_id=$1")
+        .bind(cell_id)
+        .fetch_all(&mut *conn)
+        .await {
+            Ok(edges) => edges,
+            Err(e) => {
+                error!("{}", e);
+                return Err(StatusCode::INTERNAL_SERVER_ERROR)
+            }
+        };
+    let edges: HashSet<(uuid::Uuid, uuid::Uuid)> = edges.into_iter().map(|e| (e.child_id, e.parent_id)).collect();
+    Ok(payload.parent_ids.iter().all(|p| edges.contains(&(cell_id, *p)))
+        && payload.child_ids.iter().all(|c| edges.contains(&(*c, cell_id))))
+}
+
+// Unknown users or cells referred to by a request are the client's mistake
+fn insert_error(e: &sqlx::Error) -> StatusCode {
+    match e.as_database_error() {
+        Some(e) if e.is_foreign_key_violation() => StatusCode::BAD_REQUEST,
+        _ => StatusCode::INTERNAL_SERVER_ERROR,
+    }
+}
+
+// Whether the stored cell has the owner, text, flags and files `payload`
+// would have given it
+async fn is_same_cell(
+    conn: &mut PgConnection,
+    payload: &CellReq,
+    fileprops: &[FileProp],
+    dirs: &[String],
+) -> Result<bool, sqlx::Error> {
+    let cell_id = payload.cell_id;
+    let (user_id, device_id, text, is_open): (uuid::Uuid, String, String, bool) = sqlx::query_as(
+        "SELECT user_id, device_id, text, is_open FROM cells WHERE cell_id=$1"
+    )
+        .bind(cell_id)
+        .fetch_one(&mut *conn)
+        .await?;
+    if user_id != payload.user_id || device_id != payload.device_id
+        || text != payload.text || is_open != payload.is_open {
+        return Ok(false)
+    }
+    let stored_files: Vec<(String, String, Option<String>)> = sqlx::query_as(
+        "SELECT path, url, sha256 FROM files WHERE cell_id=$1 ORDER BY path"
+    )
+        .bind(cell_id)
+        .fetch_all(&mut *conn)
+        .await?;
+    let files: Vec<(String, String, Option<String>)> = fileprops.iter()
+        .map(|f| (f.path.clone(), f.url.clone(), f.sha256.clone()))
+        .collect();
+    if stored_files != files {
+        return Ok(false)
+    }
+    let stored_dirs: Vec<(String,)> = sqlx::query_as("SELECT path FROM dirs WHERE cell_id=$1 ORDER BY path")
+        .bind(cell_id)
+        .fetch_all(&mut *conn)
+        .await?;
+    Ok(stored_dirs.into_iter().map(|d| d.0).eq(dirs.iter().cloned()))
 }
 
 #[debug_handler]
@@ -101,54 +527,97 @@ pub async fn delete_cell(
     Path(cell_id): Path<uuid::Uuid>,
     State(pool): State<Pool<Postgres>>,
 ) -> Result<Json<IdRes>, StatusCode> {
-    match sqlx::query("DELETE FROM cells WHERE cell_id=$1")
+    let mut tx = match pool.begin().await {
+        Ok(tx) => tx,
+        Err(e) => {
+            error!("{}", e);
+            return Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    };
+    let mut fileprops = match take_fileprops(&mut tx, cell_id).await {
+        Ok(fileprops) => fileprops,
+        Err(e) => {
+            error!("{}", e);
+            return Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    };
+    // Files only earlier revisions had are let go as well
+    match take_revisions(&mut tx, cell_id).await {
+        Ok(revision_fileprops) => fileprops.extend(revision_fileprops),
+        Err(e) => {
+            error!("{}", e);
+            return Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    };
+    if let Err(e) = sqlx::query("DELETE FROM cells WHERE cell_id=$1")
     .bind(cell_id)
-    .execute(&pool)
+    .execute(&mut *tx)
     .await {
+        error!("{}", e);
+        return Err(StatusCode::NOT_FOUND)
+    };
+    let unused = match release_refs(&mut tx, &fileprops).await {
+        Ok(unused) => unused,
         Err(e) => {
             error!("{}", e);
-            Err(StatusCode::NOT_FOUND)
+            return Err(StatusCode::INTERNAL_SERVER_ERROR)
         }
-        Ok(_) => Ok(Json(IdRes{id: cell_id})),
-    }
+    };
+    if let Err(e) = tx.commit().await {
+        error!("{}", e);
+        return Err(StatusCode::INTERNAL_SERVER_ERROR)
+    };
+    remove_blobs(&unused).await;
+    Ok(Json(IdRes{id: cell_id}))
 }
 
 
-/*
+// Change a cell's text, flag, files or parents; what the request leaves out
+// stays as it is. The change is kept as a revision, which is returned.
 #[debug_handler]
 pub async fn update_cell(
-    Path((user_id, cell_id)): Path<(ObjectId, ObjectId)>,
-    State(users): State<Collection<User>>,
-    Json(payload): Json<UpdateCellReq>
-) -> Result<Json<User>, StatusCode> {
-    let filter = doc! {"_id": user_id, "cells._id": cell_id};
-    let options = FindOneAndUpdateOptions::builder()
-        .return_document(ReturnDocument::After)
-        .build();
-    let update = doc! {
-        "$set": {
-            "cells.$.ancestor_ids": payload.ancestor_ids.unwrap(),
-            "cells.$.text": payload.text.unwrap(),
-            "cells.$.is_open": payload.is_open.unwrap(),
-        }
-    };
-    match users.find_one_and_update(filter, update, options).await {
+    Path(cell_id): Path<uuid::Uuid>,
+    State(pool): State<Pool<Postgres>>,
+    Json(payload): Json<CellUpdate>,
+) -> Result<Json<Revision>, StatusCode> {
+    let layout = match (&payload.rootdir, &payload.fileprops, &payload.dirs) {
+        (None, None, None) => None,
+        (Some(_), Some(_), _) | (Some(_), _, Some(_)) => {
+            error!("cell {} has both a rootdir and flat fileprops", cell_id);
+            return Err(StatusCode::BAD_REQUEST)
+        }
+        (Some(rootdir), _, _) => Some(tree::to_flat(rootdir)),
+        (None, fileprops, dirs) => Some(tree::normalize(
+            fileprops.as_deref().unwrap_or_default(),
+            dirs.as_deref().unwrap_or_default(),
+        )),
+    };
+    let files = match layout.transpose() {
+        Ok(files) => files,
         Err(e) => {
-            error!("{}", e);
-            Err(StatusCode::INTERNAL_SERVER_ERROR)
-        },
-        Ok(ou) => {
-            match ou {
-                None => {
-                    error!("Cell not found.");
-                    Err(StatusCode::NOT_FOUND)
-                },
-                Some(user) => Ok(Json(user))
-            }
-        },
-    }
+            error!("cell {}: {}", cell_id, e);
+            return Err(StatusCode::BAD_REQUEST)
+        }
+    };
+    let revision = change_cell(cell_id, payload.device_id.as_deref(), &pool, |cell| {
+        if let Some(text) = payload.text {
+            cell.text = text;
+        }
+        if let Some(is_open) = payload.is_open {
+            cell.is_open = is_open;
+        }
+        if let Some((mut fileprops, dirs)) = files {
+            complete_fileprops(&mut fileprops);
+            cell.fileprops = fileprops;
+            cell.dirs = dirs;
+        }
+        if let Some(parent_ids) = payload.parent_ids {
+            cell.parent_ids = parent_ids;
+        }
+        Ok(())
+    }).await?;
+    Ok(Json(revision))
 }
-*/
 
 
 async fn get_cell(
@@ -160,7 +629,10 @@ async fn get_cell(
     )
     .bind(cell_id)
     .fetch_one(pool).await {
-        Ok(cell) => Ok(cell),
+        Ok(mut cell) => {
+            load_files(std::slice::from_mut(&mut cell), pool).await?;
+            Ok(cell)
+        }
         Err(e) => {
             error!("{}", e);
             Err(e)
@@ -228,4 +700,128 @@ async fn make_parent_tree(
         }
     }
     Some(cell_ext)
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cell(cell_id: uuid::Uuid, parent_ids: &[uuid::Uuid], child_ids: &[uuid::Uuid]) -> CellReq {
+        CellReq {
+            cell_id,
+            user_id: uuid::Uuid::nil(),
+            device_id: "test".to_string(),
+            text: String::new(),
+            is_open: true,
+            fileprops: vec![],
+            dirs: vec![],
+            rootdir: None,
+            parent_ids: parent_ids.to_vec(),
+            child_ids: child_ids.to_vec(),
+        }
+    }
+
+    // The status of each cell after check_batch
+    fn check(cells: &[CellReq]) -> Vec<BatchStatus> {
+        let mut results: Vec<BatchItem> = cells.iter()
+            .map(|cell| BatchItem { cell_id: cell.cell_id, status: BatchStatus::Skipped, error: None })
+            .collect();
+        check_batch(cells, &mut results);
+        results.into_iter().map(|r| r.status).collect()
+    }
+
+    fn ids(n: usize) -> Vec<uuid::Uuid> {
+        (0..n).map(|_| uuid::Uuid::new_v4()).collect()
+    }
+
+    use BatchStatus::{Invalid, Skipped};
+
+    #[test]
+    fn own_parent() {
+        let id = ids(2);
+        assert_eq!(check(&[cell(id[0], &[id[0]], &[]), cell(id[1], &[], &[])]), [Invalid, Skipped]);
+        assert_eq!(check(&[cell(id[0], &[], &[id[0]])]), [Invalid]);
+    }
+
+    #[test]
+    fn two_cell_cycle() {
+        let id = ids(3);
+        let cells = [
+            cell(id[0], &[id[1]], &[]),
+            cell(id[1], &[id[0]], &[]),
+            cell(id[2], &[id[0]], &[]),
+        ];
+        // The reply to a cell on the cycle is not on it
+        assert_eq!(check(&cells), [Invalid, Invalid, Skipped]);
+        let cells = [
+            cell(id[2], &[], &[id[0]]),
+            cell(id[0], &[id[1]], &[]),
+            cell(id[1], &[id[0]], &[]),
+        ];
+        assert_eq!(check(&cells), [Skipped, Invalid, Invalid]);
+        // One edge declared from both ends is not a cycle
+        let cells = [cell(id[0], &[id[1]], &[]), cell(id[1], &[], &[id[0]])];
+        assert_eq!(check(&cells), [Skipped, Skipped]);
+        let cells = [cell(id[0], &[], &[id[1]]), cell(id[1], &[], &[id[0]])];
+        assert_eq!(check(&cells), [Invalid, Invalid]);
+    }
+
+    #[test]
+    fn longer_cycle() {
+        let id = ids(4);
+        let cells = [
+            cell(id[0], &[id[3]], &[]),
+            cell(id[1], &[id[0]], &[]),
+            cell(id[2], &[id[1]], &[]),
+            cell(id[3], &[id[2]], &[]),
+        ];
+        assert_eq!(check(&cells), [Invalid; 4]);
+    }
+
+    #[test]
+    fn cycle_through_outside_cell() {
+        let id = ids(3);
+        let stored = uuid::Uuid::new_v4();
+        // Both a parent and a reply of a stored cell
+        let cells = [cell(id[0], &[stored], &[stored]), cell(id[1], &[id[0]], &[])];
+        assert_eq!(check(&cells), [Invalid, Skipped]);
+        // A loop from a stored cell back to it through the batch
+        let cells = [
+            cell(id[0], &[stored], &[]),
+            cell(id[1], &[id[0]], &[stored]),
+            cell(id[2], &[stored], &[]),
+        ];
+        assert_eq!(check(&cells), [Invalid, Invalid, Skipped]);
+        // Replying to a stored cell from two sides is fine
+        let cells = [cell(id[0], &[stored], &[]), cell(id[1], &[id[0], stored], &[])];
+        assert_eq!(check(&cells), [Skipped, Skipped]);
+    }
+
+    #[test]
+    fn forward_references() {
+        // Parents listed after their replies, through parent_ids and
+        // child_ids, are fine
+        let id = ids(4);
+        let cells = [
+            cell(id[0], &[id[1]], &[]),
+            cell(id[1], &[id[2]], &[]),
+            cell(id[2], &[], &[id[3]]),
+            cell(id[3], &[uuid::Uuid::new_v4()], &[]),
+        ];
+        assert_eq!(check(&cells), [Skipped; 4]);
+        // A cell may be the parent of several, listed before or after
+        let cells = [
+            cell(id[0], &[id[2]], &[]),
+            cell(id[1], &[id[2]], &[]),
+            cell(id[2], &[], &[id[3]]),
+            cell(id[3], &[], &[]),
+        ];
+        assert_eq!(check(&cells), [Skipped; 4]);
+    }
+
+    #[test]
+    fn repeated_ids() {
+        let id = ids(2);
+        let cells = [cell(id[0], &[], &[]), cell(id[1], &[], &[]), cell(id[0], &[], &[])];
+        assert_eq!(check(&cells), [Skipped, Skipped, Invalid]);
+    }
+}
@@ -0,0 +1,295 @@
+use std::collections::{HashMap, HashSet};
+
+use log::error;
+use crate::model::*;
+use crate::handler::attachment::load_files;
+use axum::debug_handler;
+use axum::{
+    extract::{Path, Query, State},
+    response::Json,
+    http::StatusCode,
+};
+
+use sqlx::Postgres;
+use sqlx::pool::Pool;
+
+// Walks of the family tree stop here even without a depth, so that edges
+// forming a cycle cannot keep a query running
+const MAX_DEPTH: i32 = 1000;
+
+fn internal_error<E: std::fmt::Display>(e: E) -> StatusCode {
+    error!("{}", e);
+    StatusCode::INTERNAL_SERVER_ERROR
+}
+
+// Parents, their parents and so on up to ?depth= levels, nearest first
+#[debug_handler]
+pub async fn list_ancestors(
+    Path(cell_id): Path<uuid::Uuid>,
+    Query(query): Query<DagQuery>,
+    State(pool): State<Pool<Postgres>>,
+) -> Result<Json<Dag>, StatusCode> {
+    walk(cell_id, Direction::Up, query, &pool).await.map(Json)
+}
+
+// Replies, their replies and so on up to ?depth= levels, nearest first
+#[debug_handler]
+pub async fn list_descendants(
+    Path(cell_id): Path<uuid::Uuid>,
+    Query(query): Query<DagQuery>,
+    State(pool): State<Pool<Postgres>>,
+) -> Result<Json<Dag>, StatusCode> {
+    walk(cell_id, Direction::Down, query, &pool).await.map(Json)
+}
+
+// A user's cells without parents, newest first
+#[debug_handler]
+pub async fn list_roots(
+    Query(filter): Query<DagFilter>,
+    State(pool): State<Pool<Postgres>>,
+) -> Result<Json<Dag>, StatusCode> {
+    list_ends("child_id", filter, &pool).await.map(Json)
+}
+
+// A user's cells without replies, newest first
+#[debug_handler]
+pub async fn list_leaves(
+    Query(filter): Query<DagFilter>,
+    State(pool): State<Pool<Postgres>>,
+) -> Result<Json<Dag>, StatusCode> {
+    list_ends("parent_id", filter, &pool).await.map(Json)
+}
+
+// The fewest edges leading from one cell to the other, following replies
+// down from the first or, failing that, parents up from it. 404 when
+// neither reaches the other.
+#[debug_handler]
+pub async fn shortest_path(
+    Path((cell_id, other_id)): Path<(uuid::Uuid, uuid::Uuid)>,
+    Query(query): Query<DagQuery>,
+    State(pool): State<Pool<Postgres>>,
+) -> Result<Json<Dag>, StatusCode> {
+    check_cells(&[cell_id, other_id], &pool).await?;
+    let limit = query.depth.unwrap_or(MAX_DEPTH).min(MAX_DEPTH);
+    let mut path = None;
+    for direction in [Direction::Down, Direction::Up] {
+        path = find_path(cell_id, other_id, direction, limit, &pool).await.map_err(internal_error)?;
+        if path.is_some() {
+            break
+        }
+    }
+    let Some(path) = path else {
+        return Err(StatusCode::NOT_FOUND)
+    };
+    let depths: Vec<(uuid::Uuid, i32)> = path.iter().enumerate().map(|(i, id)| (*id, i as i32)).collect();
+    let edges = path.windows(2).map(|pair| (pair[0], pair[1])).collect::<Vec<_>>();
+    let mut dag = make_dag(&depths, query.shallow, &pool).await?;
+    dag.edges = load_edges(&path, &pool).await?
+        .into_iter()
+        .filter(|e| edges.iter().any(|(a, b)| {
+            (e.parent_id, e.child_id) == (*a, *b) || (e.child_id, e.parent_id) == (*a, *b)
+        }))
+        .collect();
+    Ok(Json(dag))
+}
+
+// Cells that both cells descend from, a cell counting as its own ancestor.
+// `depth` is the longer of the two distances, so the nearest come first.
+#[debug_handler]
+pub async fn common_ancestors(
+    Path((cell_id, other_id)): Path<(uuid::Uuid, uuid::Uuid)>,
+    Query(query): Query<DagQuery>,
+    State(pool): State<Pool<Postgres>>,
+) -> Result<Json<Dag>, StatusCode> {
+    check_cells(&[cell_id, other_id], &pool).await?;
+    let limit = query.depth.unwrap_or(MAX_DEPTH).min(MAX_DEPTH);
+    let mine: HashMap<uuid::Uuid, i32> = reach(cell_id, Direction::Up, limit, &pool).await
+        .map_err(internal_error)?
+        .into_iter()
+        .chain([(cell_id, 0)])
+        .collect();
+    let mut common: Vec<(uuid::Uuid, i32)> = reach(other_id, Direction::Up, limit, &pool).await
+        .map_err(internal_error)?
+        .into_iter()
+        .chain([(other_id, 0)])
+        .filter_map(|(id, depth)| mine.get(&id).map(|mine| (id, depth.max(*mine))))
+        .collect();
+    common.sort_by_key(|(id, depth)| (*depth, *id));
+    let ids: Vec<uuid::Uuid> = common.iter().map(|(id, _)| *id).collect();
+    let mut dag = make_dag(&common, query.shallow, &pool).await?;
+    dag.edges = load_edges(&ids, &pool).await?;
+    Ok(Json(dag))
+}
+
+#[derive(Clone, Copy)]
+enum Direction {
+    Up,
+    Down,
+}
+
+impl Direction {
+    // Columns of family_tree to go from and to
+    fn columns(self) -> (&'static str, &'static str) {
+        match self {
+            Direction::Up => ("child_id", "parent_id"),
+            Direction::Down => ("parent_id", "child_id"),
+        }
+    }
+}
+
+async fn walk(
+    cell_id: uuid::Uuid,
+    direction: Direction,
+    query: DagQuery,
+    pool: &Pool<Postgres>,
+) -> Result<Dag, StatusCode> {
+    check_cells(&[cell_id], pool).await?;
+    let limit = query.depth.unwrap_or(MAX_DEPTH).min(MAX_DEPTH);
+    let reached = reach(cell_id, direction, limit, pool).await.map_err(internal_error)?;
+    let mut ids: Vec<uuid::Uuid> = reached.iter().map(|(id, _)| *id).collect();
+    let mut dag = make_dag(&reached, query.shallow, pool).await?;
+    ids.push(cell_id);
+    dag.edges = load_edges(&ids, pool).await?;
+    Ok(dag)
+}
+
+// Every cell within `limit` edges of `cell_id` in `direction`, with its
+// smallest distance, nearest first
+async fn reach(
+    cell_id: uuid::Uuid,
+    direction: Direction,
+    limit: i32,
+    pool: &Pool<Postgres>,
+) -> Result<Vec<(uuid::Uuid, i32)>, sqlx::Error> {
+    let (from, to) = direction.columns();
+    sqlx::query_as(&format!(
+        "WITH RECURSIVE walk(cell_id, depth) AS (
+            SELECT {to}, 1 FROM family_tree WHERE {from}=$1 AND $2 > 0
+            UNION
+            SELECT f.{to}, walk.depth + 1 FROM walk JOIN family_tree f ON f.{from}=walk.cell_id
+            WHERE walk.depth < $2
+        )
+        SELECT cell_id, MIN(depth) AS depth FROM walk WHERE cell_id<>$1
+        GROUP BY cell_id ORDER BY depth, cell_id"
+    ))
+        .bind(cell_id)
+        .bind(limit)
+        .fetch_all(pool)
+        .await
+}
+
+// Breadth-first search from `cell_id` for `other_id`, one query per level
+async fn find_path(
+    cell_id: uuid::Uuid,
+    other_id: uuid::Uuid,
+    direction: Direction,
+    limit: i32,
+    pool: &Pool<Postgres>,
+) -> Result<Option<Vec<uuid::Uuid>>, sqlx::Error> {
+    let (from, to) = direction.columns();
+    let mut came_from: HashMap<uuid::Uuid, uuid::Uuid> = HashMap::new();
+    let mut seen: HashSet<uuid::Uuid> = HashSet::from([cell_id]);
+    let mut frontier = vec![cell_id];
+    let mut depth = 0;
+    while cell_id != other_id && !frontier.is_empty() && depth < limit && !seen.contains(&other_id) {
+        let edges: Vec<(uuid::Uuid, uuid::Uuid)> = sqlx::query_as(&format!(
+            "SELECT {from}, {to} FROM family_tree WHERE {from}=ANY($1) ORDER BY {to}"
+        ))
+            .bind(&frontier)
+            .fetch_all(pool)
+            .await?;
+        frontier.clear();
+        for (prev, next) in edges.into_iter() {
+            if seen.insert(next) {
+                came_from.insert(next, prev);
+                frontier.push(next);
+            }
+        }
+        depth += 1;
+    }
+    if !seen.contains(&other_id) {
+        return Ok(None)
+    }
+    let mut path = vec![other_id];
+    while let Some(prev) = came_from.get(path.last().unwrap()) {
+        path.push(*prev);
+    }
+    path.reverse();
+    Ok(Some(path))
+}
+
+async fn list_ends(
+    column: &str,
+    filter: DagFilter,
+    pool: &Pool<Postgres>,
+) -> Result<Dag, StatusCode> {
+    let ids: Vec<(uuid::Uuid,)> = sqlx::query_as(&format!(
+        "SELECT cell_id FROM cells c WHERE user_id=$1
+        AND NOT EXISTS (SELECT 1 FROM family_tree f WHERE f.{}=c.cell_id)
+        ORDER BY created_at DESC LIMIT $2",
+        column,
+    ))
+        .bind(filter.user_id)
+        .bind(filter.limit)
+        .fetch_all(pool)
+        .await
+        .map_err(internal_error)?;
+    let cells: Vec<(uuid::Uuid, i32)> = ids.into_iter().map(|(id,)| (id, 0)).collect();
+    make_dag(&cells, filter.shallow, pool).await
+}
+
+// 404 unless every cell exists
+async fn check_cells(cell_ids: &[uuid::Uuid], pool: &Pool<Postgres>) -> Result<(), StatusCode> {
+    let (count,): (i64,) = sqlx::query_as("SELECT COUNT(*) FROM cells WHERE cell_id=ANY($1)")
+        .bind(cell_ids)
+        .fetch_one(pool)
+        .await
+        .map_err(internal_error)?;
+    let distinct: HashSet<&uuid::Uuid> = cell_ids.iter().collect();
+    if count as usize != distinct.len() {
+        return Err(StatusCode::NOT_FOUND)
+    }
+    Ok(())
+}
+
+// The cells in the order given, with their text only when `shallow`
+async fn make_dag(
+    cells: &[(uuid::Uuid, i32)],
+    shallow: bool,
+    pool: &Pool<Postgres>,
+) -> Result<Dag, StatusCode> {
+    let ids: Vec<uuid::Uuid> = cells.iter().map(|(id, _)| *id).collect();
+    let mut rows: Vec<CellRow> = sqlx::query_as("SELECT * FROM cells WHERE cell_id=ANY($1)")
+        .bind(&ids)
+        .fetch_all(pool)
+        .await
+        .map_err(internal_error)?;
+    if !shallow {
+        load_files(&mut rows, pool).await.map_err(internal_error)?;
+    }
+    let mut rows: HashMap<uuid::Uuid, CellRow> = rows.into_iter().map(|row| (row.cell_id, row)).collect();
+    let cells = cells.iter()
+        .filter_map(|(id, depth)| {
+            let row = rows.remove(id)?;
+            Some(DagCell {
+                cell_id: row.cell_id,
+                text: row.text.clone(),
+                depth: *depth,
+                cell: (!shallow).then(|| CellExtracted::from_cell_row(row, vec![], vec![])),
+            })
+        })
+        .collect();
+    Ok(Dag { cells, edges: vec![] })
+}
+
+// Edges between the given cells
+async fn load_edges(cell_ids: &[uuid::Uuid], pool: &Pool<Postgres>) -> Result<Vec<Edge>, StatusCode> {
+    sqlx::query_as(
+        "SELECT DISTINCT child_id, parent_id FROM family_tree
+        WHERE child_id=ANY($1) AND parent_id=ANY($1) ORDER BY parent_id, child_id"
+    )
+        .bind(cell_ids)
+        .fetch_all(pool)
+        .await
+        .map_err(internal_error)
+}
@@ -0,0 +1,478 @@
+//! WebDAV view of a user's cells, mounted at /dav/<user_id>/.
+//!
+//! /dav/<user_id>/                           one directory per day
+//! /dav/<user_id>/<YYYY-MM-DD>/              one directory per cell created that day
+//! /dav/<user_id>/<YYYY-MM-DD>/<cell_id>/    the cell's files
+//!
+//! Files can be read, written with PUT and removed with DELETE inside a cell
+//! directory. Directories are created with MKCOL and removed with DELETE.
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+use log::error;
+use crate::model::*;
+use crate::handler::attachment::{load_files, send_file, update_files};
+use crate::handler::file::{blob_path, file_id_from_url, file_url, guess_mime, store_blob};
+use crate::validate::validate_path;
+
+use axum::{
+    body::Body,
+    extract::{Request, State},
+    response::{IntoResponse, Response},
+    http::{header, HeaderMap, StatusCode, Uri},
+};
+
+use chrono::{NaiveDate, NaiveDateTime};
+use percent_encoding::{percent_decode_str, utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use sqlx::Postgres;
+use sqlx::pool::Pool;
+
+const DAV_PREFIX: &str = "/dav/";
+// Recorded as the device of changes made over WebDAV
+const DAV_DEVICE: &str = "webdav";
+const ALLOW: &str = "OPTIONS, GET, HEAD, PUT, DELETE, PROPFIND, MKCOL";
+
+// Everything but unreserved characters is escaped in hrefs
+const SEGMENT: &AsciiSet = &NON_ALPHANUMERIC.remove(b'-').remove(b'.').remove(b'_').remove(b'~');
+
+enum Resource {
+    Root,
+    Day(NaiveDate),
+    Cell(CellRow),
+    Dir(CellRow, String),
+    File(CellRow, FileProp),
+}
+
+struct Entry {
+    href:           String,
+    name:           String,
+    is_dir:         bool,
+    mime:           Option<String>,
+    size:           Option<u64>,
+    modified:       NaiveDateTime,
+    etag:           Option<String>,
+}
+
+fn internal_error<E: std::fmt::Display>(e: E) -> StatusCode {
+    error!("{}", e);
+    StatusCode::INTERNAL_SERVER_ERROR
+}
+
+// WebDAV methods are not known to the router, so every method lands here
+pub async fn dav(
+    State(pool): State<Pool<Postgres>>,
+    request: Request,
+) -> Result<Response, StatusCode> {
+    let (parts, body) = request.into_parts();
+    let (user_id, names) = split_uri(&parts.uri)?;
+    match parts.method.as_str() {
+        "OPTIONS" => Ok((
+            StatusCode::OK,
+            [(header::ALLOW, ALLOW), (header::HeaderName::from_static("dav"), "1")],
+        ).into_response()),
+        "GET" | "HEAD" => get(user_id, &names, &parts.headers, &pool).await,
+        "PROPFIND" => propfind(user_id, &names, &parts.headers, &pool).await,
+        "PUT" => put(user_id, &names, &pool, body).await,
+        "DELETE" => delete(user_id, &names, &pool).await,
+        "MKCOL" => mkcol(user_id, &names, &pool).await,
+        _ => Ok((StatusCode::METHOD_NOT_ALLOWED, [(header::ALLOW, ALLOW)]).into_response()),
+    }
+}
+
+// "/dav/<user_id>/a%20b/c/" -> (user_id, ["a b", "c"])
+fn split_uri(uri: &Uri) -> Result<(uuid::Uuid, Vec<String>), StatusCode> {
+    let Some(rest) = uri.path().strip_prefix(DAV_PREFIX) else {
+        return Err(StatusCode::NOT_FOUND)
+    };
+    let mut names = vec![];
+    for name in rest.split('/').filter(|n| !n.is_empty()) {
+        let name = percent_decode_str(name).decode_utf8().map_err(|_| StatusCode::BAD_REQUEST)?;
+        names.push(name.to_string());
+    }
+    if names.is_empty() {
+        return Err(StatusCode::NOT_FOUND)
+    }
+    let user_id = uuid::Uuid::parse_str(&names.remove(0)).map_err(|_| StatusCode::NOT_FOUND)?;
+    Ok((user_id, names))
+}
+
+fn href(user_id: uuid::Uuid, names: &[&str], is_dir: bool) -> String {
+    let mut href = format!("{}{}/", DAV_PREFIX, user_id);
+    for name in names {
+        href.push_str(&utf8_percent_encode(name, SEGMENT).to_string());
+        href.push('/');
+    }
+    if !is_dir {
+        href.pop();
+    }
+    href
+}
+
+async fn resolve(
+    user_id: uuid::Uuid,
+    names: &[String],
+    pool: &Pool<Postgres>,
+) -> Result<Resource, StatusCode> {
+    let Some((day, rest)) = names.split_first() else {
+        let user: Option<(uuid::Uuid,)> = sqlx::query_as("SELECT user_id FROM users WHERE user_id=$1")
+            .bind(user_id)
+            .fetch_optional(pool)
+            .await
+            .map_err(internal_error)?;
+        return user.map(|_| Resource::Root).ok_or(StatusCode::NOT_FOUND)
+    };
+    let day = NaiveDate::parse_from_str(day, "%Y-%m-%d").map_err(|_| StatusCode::NOT_FOUND)?;
+    let Some((cell_id, rest)) = rest.split_first() else {
+        let cells = cells_of_day(user_id, day, pool).await?;
+        if cells.is_empty() {
+            return Err(StatusCode::NOT_FOUND)
+        }
+        return Ok(Resource::Day(day))
+    };
+    let cell_id = uuid::Uuid::parse_str(cell_id).map_err(|_| StatusCode::NOT_FOUND)?;
+    let mut cell: CellRow = sqlx::query_as(
+        "SELECT * FROM cells WHERE cell_id=$1 AND user_id=$2 AND created_at::date=$3"
+    )
+        .bind(cell_id)
+        .bind(user_id)
+        .bind(day)
+        .fetch_optional(pool)
+        .await
+        .map_err(internal_error)?
+        .ok_or(StatusCode::NOT_FOUND)?;
+    load_files(std::slice::from_mut(&mut cell), pool).await.map_err(internal_error)?;
+    if rest.is_empty() {
+        return Ok(Resource::Cell(cell))
+    }
+    let path = rest.join("/");
+    if let Some(fileprop) = cell.fileprops.iter().find(|f| f.path == path).cloned() {
+        return Ok(Resource::File(cell, fileprop))
+    }
+    let prefix = format!("{}/", path);
+    let has_files = cell.fileprops.iter().any(|f| f.path.starts_with(&prefix));
+    if has_files || cell.dirs.iter().any(|d| *d == path || d.starts_with(&prefix)) {
+        return Ok(Resource::Dir(cell, path))
+    }
+    Err(StatusCode::NOT_FOUND)
+}
+
+async fn cells_of_day(
+    user_id: uuid::Uuid,
+    day: NaiveDate,
+    pool: &Pool<Postgres>,
+) -> Result<Vec<CellRow>, StatusCode> {
+    let mut cells: Vec<CellRow> = sqlx::query_as(
+        "SELECT * FROM cells WHERE user_id=$1 AND created_at::date=$2 ORDER BY created_at"
+    )
+        .bind(user_id)
+        .bind(day)
+        .fetch_all(pool)
+        .await
+        .map_err(internal_error)?;
+    load_files(&mut cells, pool).await.map_err(internal_error)?;
+    Ok(cells)
+}
+
+async fn get(
+    user_id: uuid::Uuid,
+    names: &[String],
+    headers: &HeaderMap,
+    pool: &Pool<Postgres>,
+) -> Result<Response, StatusCode> {
+    let Resource::File(_, fileprop) = resolve(user_id, names, pool).await? else {
+        return Ok((StatusCode::METHOD_NOT_ALLOWED, [(header::ALLOW, ALLOW)]).into_response())
+    };
+    send_file(&fileprop, headers).await
+}
+
+async fn propfind(
+    user_id: uuid::Uuid,
+    names: &[String],
+    headers: &HeaderMap,
+    pool: &Pool<Postgres>,
+) -> Result<Response, StatusCode> {
+    // Clients walk the tree themselves; listing all of it at once is refused
+    // as RFC 4918 allows. A request without the header gets one level.
+    let depth = headers.get("depth").and_then(|d| d.to_str().ok()).unwrap_or("1");
+    match depth {
+        "0" | "1" => {}
+        depth if depth.eq_ignore_ascii_case("infinity") => return finite_depth_only(),
+        _ => return Err(StatusCode::BAD_REQUEST),
+    }
+    let resource = resolve(user_id, names, pool).await?;
+    let names: Vec<&str> = names.iter().map(|n| n.as_str()).collect();
+    let mut entries = vec![];
+    match &resource {
+        Resource::Root => {
+            let days: Vec<(NaiveDate, NaiveDateTime)> = sqlx::query_as(
+                "SELECT created_at::date, MAX(created_at) FROM cells WHERE user_id=$1
+                GROUP BY created_at::date ORDER BY created_at::date"
+            )
+                .bind(user_id)
+                .fetch_all(pool)
+                .await
+                .map_err(internal_error)?;
+            let modified = days.iter().map(|(_, m)| *m).max().unwrap_or_default();
+            entries.push(dir_entry(user_id, &names, modified));
+            if depth != "0" {
+                for (day, modified) in days {
+                    let day = day.format("%Y-%m-%d").to_string();
+                    entries.push(dir_entry(user_id, &[&day], modified));
+                }
+            }
+        }
+        Resource::Day(day) => {
+            let cells = cells_of_day(user_id, *day, pool).await?;
+            let modified = cells.iter().map(|c| c.created_at).max().unwrap_or_default();
+            entries.push(dir_entry(user_id, &names, modified));
+            if depth != "0" {
+                for cell in cells {
+                    let cell_id = cell.cell_id.to_string();
+                    entries.push(dir_entry(user_id, &[names[0], &cell_id], cell.created_at));
+                }
+            }
+        }
+        Resource::Cell(cell) => {
+            entries.push(dir_entry(user_id, &names, cell.created_at));
+            if depth != "0" {
+                entries.extend(child_entries(user_id, &names, cell, ""));
+            }
+        }
+        Resource::Dir(cell, path) => {
+            entries.push(dir_entry(user_id, &names, cell.created_at));
+            if depth != "0" {
+                entries.extend(child_entries(user_id, &names, cell, &format!("{}/", path)));
+            }
+        }
+        Resource::File(cell, fileprop) => {
+            entries.push(file_entry(user_id, &names, cell, fileprop));
+        }
+    }
+
+    Response::builder()
+        .status(StatusCode::MULTI_STATUS)
+        .header(header::CONTENT_TYPE, "application/xml; charset=utf-8")
+        .body(Body::from(multistatus(&entries)))
+        .map_err(internal_error)
+}
+
+// 403 with the precondition a client can tell "Depth: infinity" from
+fn finite_depth_only() -> Result<Response, StatusCode> {
+    Response::builder()
+        .status(StatusCode::FORBIDDEN)
+        .header(header::CONTENT_TYPE, "application/xml; charset=utf-8")
+        .body(Body::from(
+            "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n\
+            <D:error xmlns:D=\"DAV:\"><D:propfind-finite-depth/></D:error>\n"
+        ))
+        .map_err(internal_error)
+}
+
+fn dir_entry(user_id: uuid::Uuid, names: &[&str], modified: NaiveDateTime) -> Entry {
+    Entry {
+        href: href(user_id, names, true),
+        name: names.last().map(|n| n.to_string()).unwrap_or_else(|| user_id.to_string()),
+        is_dir: true,
+        mime: None,
+        size: None,
+        modified,
+        etag: None,
+    }
+}
+
+fn file_entry(user_id: uuid::Uuid, names: &[&str], cell: &CellRow, fileprop: &FileProp) -> Entry {
+    let file_id = file_id_from_url(&fileprop.url);
+    let meta = file_id.and_then(|id| blob_path(id).metadata().ok());
+    let modified = fileprop.mtime
+        .or_else(|| meta.as_ref()
+            .and_then(|m| m.modified().ok())
+            .map(|t| chrono::DateTime::<chrono::Utc>::from(t).naive_utc()))
+        .unwrap_or(cell.created_at);
+    Entry {
+        href: href(user_id, names, false),
+        name: names.last().map(|n| n.to_string()).unwrap_or_default(),
+        is_dir: false,
+        mime: fileprop.mime.clone(),
+        size: meta.map(|m| m.len()).or(fileprop.size),
+        modified,
+        // Blobs are never rewritten, so the id identifies the content
+        etag: file_id.map(|id| format!("\"{}\"", id)),
+    }
+}
+
+// Direct children of the directory `prefix` ("" for the cell root)
+fn child_entries(user_id: uuid::Uuid, names: &[&str], cell: &CellRow, prefix: &str) -> Vec<Entry> {
+    let mut dirs = BTreeMap::new();
+    let mut files = vec![];
+    for fileprop in cell.fileprops.iter() {
+        let Some(rest) = fileprop.path.strip_prefix(prefix) else {
+            continue
+        };
+        match rest.split_once('/') {
+            Some((dir, _)) => {
+                dirs.entry(dir.to_string()).or_insert(());
+            }
+            None => files.push((rest.to_string(), fileprop)),
+        }
+    }
+    for dir in cell.dirs.iter() {
+        if let Some(rest) = dir.strip_prefix(prefix) {
+            let name = rest.split('/').next().unwrap_or(rest);
+            dirs.entry(name.to_string()).or_insert(());
+        }
+    }
+    let mut entries = vec![];
+    for dir in dirs.keys() {
+        let child: Vec<&str> = names.iter().copied().chain([dir.as_str()]).collect();
+        entries.push(dir_entry(user_id, &child, cell.created_at));
+    }
+    for (name, fileprop) in files {
+        let child: Vec<&str> = names.iter().copied().chain([name.as_str()]).collect();
+        entries.push(file_entry(user_id, &child, cell, fileprop));
+    }
+    entries
+}
+
+fn multistatus(entries: &[Entry]) -> String {
+    let mut xml = String::from("<?xml version=\"1.0\" encoding=\"utf-8\"?>\n<D:multistatus xmlns:D=\"DAV:\">\n");
+    for entry in entries {
+        let _ = write!(xml, "<D:response><D:href>{}</D:href><D:propstat><D:prop>", escape(&entry.href));
+        let _ = write!(xml, "<D:displayname>{}</D:displayname>", escape(&entry.name));
+        let _ = write!(xml, "<D:creationdate>{}</D:creationdate>", entry.modified.format("%Y-%m-%dT%H:%M:%SZ"));
+        let _ = write!(xml, "<D:getlastmodified>{}</D:getlastmodified>", entry.modified.format("%a, %d %b %Y %H:%M:%S GMT"));
+        if entry.is_dir {
+            xml.push_str("<D:resourcetype><D:collection/></D:resourcetype>");
+        } else {
+            let mime = entry.mime.as_deref().unwrap_or("application/octet-stream");
+            let _ = write!(xml, "<D:resourcetype/><D:getcontenttype>{}</D:getcontenttype>", escape(mime));
+            let _ = write!(xml, "<D:getcontentlength>{}</D:getcontentlength>", entry.size.unwrap_or(0));
+        }
+        if let Some(etag) = &entry.etag {
+            let _ = write!(xml, "<D:getetag>{}</D:getetag>", escape(etag));
+        }
+        xml.push_str("</D:prop><D:status>HTTP/1.1 200 OK</D:status></D:propstat></D:response>\n");
+    }
+    xml.push_str("</D:multistatus>\n");
+    xml
+}
+
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+async fn put(
+    user_id: uuid::Uuid,
+    names: &[String],
+    pool: &Pool<Postgres>,
+    body: Body,
+) -> Result<Response, StatusCode> {
+    // Files can only be written inside a cell
+    if names.len() < 3 {
+        return Err(StatusCode::FORBIDDEN)
+    }
+    let cell = match resolve(user_id, &names[..2], pool).await {
+        Ok(Resource::Cell(cell)) => cell,
+        Ok(_) => return Err(StatusCode::FORBIDDEN),
+        Err(StatusCode::NOT_FOUND) => return Err(StatusCode::CONFLICT),
+        Err(e) => return Err(e),
+    };
+    let path = names[2..].join("/");
+    if let Err(e) = validate_path(&path) {
+        error!("{}", e);
+        return Err(StatusCode::BAD_REQUEST)
+    }
+
+    // If the cell cannot take the file, the blob stays unreferenced and is
+    // removed by `collect_blobs` after the grace period, unless another file
+    // has the same content
+    let stored = store_blob(body, pool).await?;
+    let file_id = stored.id;
+    let fileprop = FileProp {
+        url: file_url(file_id),
+        completed: true,
+        size: Some(stored.size),
+        sha256: Some(stored.sha256),
+        mime: Some(guess_mime(&path)),
+        mtime: Some(chrono::Utc::now().naive_utc()),
+        path,
+    };
+    let res = update_files(cell.cell_id, Some(DAV_DEVICE), pool, |fileprops, _| {
+        let created = match fileprops.iter_mut().find(|f| f.path == fileprop.path) {
+            Some(old) => {
+                *old = fileprop;
+                false
+            }
+            None => {
+                fileprops.push(fileprop);
+                true
+            }
+        };
+        Ok(created)
+    }).await;
+    match res? {
+        true => Ok(StatusCode::CREATED.into_response()),
+        false => Ok(StatusCode::NO_CONTENT.into_response()),
+    }
+}
+
+async fn delete(
+    user_id: uuid::Uuid,
+    names: &[String],
+    pool: &Pool<Postgres>,
+) -> Result<Response, StatusCode> {
+    let (cell, path, is_dir) = match resolve(user_id, names, pool).await? {
+        Resource::File(cell, fileprop) => (cell, fileprop.path, false),
+        Resource::Dir(cell, path) => (cell, format!("{}/", path), true),
+        // Cells are removed through the cells API
+        _ => return Err(StatusCode::FORBIDDEN),
+    };
+    update_files(cell.cell_id, Some(DAV_DEVICE), pool, |fileprops, dirs| {
+        if is_dir {
+            fileprops.retain(|f| !f.path.starts_with(&path));
+            dirs.retain(|d| format!("{}/", d) != path && !d.starts_with(&path));
+        } else {
+            fileprops.retain(|f| f.path != path);
+        }
+        // The directory holding what was removed stays, even if now empty
+        if let Some((parent, _)) = path.trim_end_matches('/').rsplit_once('/') {
+            dirs.push(parent.to_string());
+        }
+        Ok(())
+    }).await?;
+    Ok(StatusCode::NO_CONTENT.into_response())
+}
+
+async fn mkcol(
+    user_id: uuid::Uuid,
+    names: &[String],
+    pool: &Pool<Postgres>,
+) -> Result<Response, StatusCode> {
+    if names.len() < 3 {
+        return Err(StatusCode::FORBIDDEN)
+    }
+    match resolve(user_id, names, pool).await {
+        Ok(_) => return Err(StatusCode::METHOD_NOT_ALLOWED),
+        Err(StatusCode::NOT_FOUND) => {}
+        Err(e) => return Err(e),
+    }
+    let cell = match resolve(user_id, &names[..2], pool).await {
+        Ok(Resource::Cell(cell)) => cell,
+        Err(StatusCode::NOT_FOUND) => return Err(StatusCode::CONFLICT),
+        Ok(_) => return Err(StatusCode::FORBIDDEN),
+        Err(e) => return Err(e),
+    };
+    let path = names[2..].join("/");
+    if let Err(e) = validate_path(&path) {
+        error!("{}", e);
+        return Err(StatusCode::BAD_REQUEST)
+    }
+    update_files(cell.cell_id, Some(DAV_DEVICE), pool, |_, dirs| {
+        dirs.push(path);
+        Ok(())
+    }).await?;
+    Ok(StatusCode::CREATED.into_response())
+}
@@ -0,0 +1,481 @@
+use std::collections::BTreeSet;
+use std::io::Read;
+use std::path::{Path as FsPath, PathBuf};
+
+use log::error;
+use crate::model::*;
+use crate::handler::file::{blob_path, file_url, guess_mime, register_blob, HashWriter};
+use crate::utils;
+use crate::tree;
+use crate::validate::{validate_layout, validate_path};
+
+use async_zip::tokio::read::fs::ZipFileReader;
+use axum::debug_handler;
+use axum::{
+    body::Body,
+    extract::State,
+    response::Json,
+    http::StatusCode,
+};
+
+use futures_util::StreamExt;
+use sha2::{Digest, Sha256};
+use sqlx::Postgres;
+use sqlx::pool::Pool;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio_util::compat::FuturesAsyncReadCompatExt;
+
+// Limits on what a single archive may expand to. Sizes are counted on the
+// bytes actually extracted, not on what the archive's headers claim.
+#[derive(Clone, Copy)]
+struct Limits {
+    entries:        usize,
+    size:           u64,
+}
+
+const LIMITS: Limits = Limits {
+    entries: 10_000,
+    size: 4 * 1024 * 1024 * 1024,
+};
+
+fn internal_error<E: std::fmt::Display>(e: E) -> StatusCode {
+    error!("{}", e);
+    StatusCode::INTERNAL_SERVER_ERROR
+}
+
+fn bad_request<E: std::fmt::Display>(e: E) -> StatusCode {
+    error!("{}", e);
+    StatusCode::BAD_REQUEST
+}
+
+struct Entry {
+    path:           String,
+    upload:         PathBuf,
+    sha256:         String,
+    size:           u64,
+    mtime:          Option<chrono::NaiveDateTime>,
+}
+
+struct Expanded {
+    limits:         Limits,
+    files:          Vec<Entry>,
+    dirs:           BTreeSet<String>,
+    size:           u64,
+}
+
+impl Expanded {
+    fn new(limits: Limits) -> Self {
+        Expanded {
+            limits,
+            files: vec![],
+            dirs: BTreeSet::new(),
+            size: 0,
+        }
+    }
+
+    fn count(&self) -> usize {
+        self.files.len() + self.dirs.len()
+    }
+
+    // Room left for an entry claiming `size` bytes, or 413 if there is none
+    fn remaining(&self, size: u64) -> Result<u64, StatusCode> {
+        let remaining = self.limits.size - self.size;
+        if self.count() >= self.limits.entries {
+            error!("archive has more than {} entries", self.limits.entries);
+            return Err(StatusCode::PAYLOAD_TOO_LARGE)
+        }
+        if size > remaining {
+            return Err(self.too_large())
+        }
+        Ok(remaining)
+    }
+
+    fn too_large(&self) -> StatusCode {
+        error!("archive expands to more than {} bytes", self.limits.size);
+        StatusCode::PAYLOAD_TOO_LARGE
+    }
+}
+
+// Store the files of a zip, tar or tar.gz body as blobs and return them as a
+// cell's files, ready to be sent as a CellReq's fileprops and dirs
+#[debug_handler]
+pub async fn expand_archive(
+    State(pool): State<Pool<Postgres>>,
+    body: Body,
+) -> Result<Json<CellFiles>, StatusCode> {
+    let blob_dir = PathBuf::from(utils::get_blob_path());
+    let work_dir = blob_dir.join(format!(".expand-{}", uuid::Uuid::new_v4()));
+    tokio::fs::create_dir_all(&work_dir).await.map_err(internal_error)?;
+    let res = expand(&pool, body, &work_dir).await;
+    if let Err(e) = tokio::fs::remove_dir_all(&work_dir).await {
+        error!("{}", e);
+    }
+    res.map(Json)
+}
+
+async fn expand(
+    pool: &Pool<Postgres>,
+    body: Body,
+    work_dir: &FsPath,
+) -> Result<CellFiles, StatusCode> {
+    let archive_path = work_dir.join("archive");
+    let mut file = tokio::fs::File::create(&archive_path).await.map_err(internal_error)?;
+    let mut stream = body.into_data_stream();
+    while let Some(chunk) = stream.next().await {
+        let chunk = chunk.map_err(bad_request)?;
+        file.write_all(&chunk).await.map_err(internal_error)?;
+    }
+    file.flush().await.map_err(internal_error)?;
+    drop(file);
+
+    // The format is told by the first bytes rather than by a name
+    let mut magic = [0; 2];
+    let mut file = tokio::fs::File::open(&archive_path).await.map_err(internal_error)?;
+    let read = file.read(&mut magic).await.map_err(internal_error)?;
+    drop(file);
+    let expanded = match &magic[..read] {
+        b"PK" => expand_zip(&archive_path, work_dir, LIMITS).await?,
+        [0x1f, 0x8b] => {
+            let (archive_path, work_dir) = (archive_path.clone(), work_dir.to_path_buf());
+            tokio::task::spawn_blocking(move || {
+                let file = std::fs::File::open(&archive_path).map_err(internal_error)?;
+                expand_tar(flate2::read::GzDecoder::new(file), &work_dir, LIMITS)
+            })
+                .await
+                .map_err(internal_error)??
+        }
+        _ => {
+            let (archive_path, work_dir) = (archive_path.clone(), work_dir.to_path_buf());
+            tokio::task::spawn_blocking(move || {
+                let file = std::fs::File::open(&archive_path).map_err(internal_error)?;
+                expand_tar(file, &work_dir, LIMITS)
+            })
+                .await
+                .map_err(internal_error)??
+        }
+    };
+
+    // Nothing is stored unless the whole archive fits in a cell
+    validate_layout(
+        expanded.files.iter().map(|f| f.path.as_str()),
+        expanded.dirs.iter().map(|d| d.as_str()),
+    ).map_err(bad_request)?;
+
+    let mut conn = pool.acquire().await.map_err(internal_error)?;
+    let mut fileprops = vec![];
+    for entry in expanded.files.into_iter() {
+        let file_id = match register_blob(uuid::Uuid::new_v4(), &entry.sha256, entry.size, &mut conn).await {
+            Ok(id) => {
+                tokio::fs::rename(&entry.upload, blob_path(id)).await.map_err(internal_error)?;
+                id
+            }
+            Err(Some(id)) => id,
+            Err(None) => return Err(StatusCode::INTERNAL_SERVER_ERROR),
+        };
+        fileprops.push(FileProp {
+            mime: Some(guess_mime(&entry.path)),
+            path: entry.path,
+            url: file_url(file_id),
+            completed: true,
+            size: Some(entry.size),
+            sha256: Some(entry.sha256),
+            mtime: entry.mtime,
+        });
+    }
+    let dirs: Vec<String> = expanded.dirs.into_iter().collect();
+    let (fileprops, dirs) = tree::normalize(&fileprops, &dirs).map_err(bad_request)?;
+    Ok(CellFiles { fileprops, dirs })
+}
+
+// The path an archive entry is stored under, None for entries that only
+// name the top directory ("./"). Paths leaving the cell are refused.
+fn entry_path(name: &str) -> Result<Option<String>, StatusCode> {
+    let name = name.replace('\\', "/");
+    let mut path = name.trim_end_matches('/');
+    while let Some(rest) = path.strip_prefix("./") {
+        path = rest.trim_start_matches('/');
+    }
+    if path.is_empty() || path == "." {
+        return Ok(None)
+    }
+    validate_path(path).map_err(bad_request)?;
+    Ok(Some(path.to_string()))
+}
+
+async fn expand_zip(archive_path: &FsPath, work_dir: &FsPath, limits: Limits) -> Result<Expanded, StatusCode> {
+    let reader = ZipFileReader::new(archive_path).await.map_err(bad_request)?;
+    let mut expanded = Expanded::new(limits);
+    for (index, entry) in reader.file().entries().iter().enumerate() {
+        let name = String::from_utf8_lossy(entry.filename().as_bytes()).to_string();
+        let Some(path) = entry_path(&name)? else {
+            continue
+        };
+        expanded.remaining(0)?;
+        if name.ends_with('/') || name.ends_with('\\') {
+            expanded.dirs.insert(path);
+            continue
+        }
+        // Symbolic links are left out
+        if entry.unix_permissions().is_some_and(|mode| mode & 0o170000 == 0o120000) {
+            continue
+        }
+        let remaining = expanded.remaining(entry.uncompressed_size())?;
+        let mut src = reader.reader_with_entry(index).await.map_err(bad_request)?.compat();
+        let upload = work_dir.join(expanded.files.len().to_string());
+        let mut out = tokio::fs::File::create(&upload).await.map_err(internal_error)?;
+        let mut hasher = Sha256::new();
+        let mut size = 0;
+        let mut buf = vec![0; 64 * 1024];
+        loop {
+            let n = src.read(&mut buf).await.map_err(bad_request)?;
+            if n == 0 {
+                break
+            }
+            size += n as u64;
+            if size > remaining {
+                return Err(expanded.too_large())
+            }
+            hasher.update(&buf[..n]);
+            out.write_all(&buf[..n]).await.map_err(internal_error)?;
+        }
+        out.flush().await.map_err(internal_error)?;
+        if src.get_mut().compute_hash() != entry.crc32() {
+            error!("zip entry {} fails its CRC check", path);
+            return Err(StatusCode::BAD_REQUEST)
+        }
+        expanded.size += size;
+        expanded.files.push(Entry {
+            path,
+            upload,
+            sha256: format!("{:x}", hasher.finalize()),
+            size,
+            mtime: entry.last_modification_date().as_chrono().single().map(|t| t.naive_utc()),
+        });
+    }
+    Ok(expanded)
+}
+
+fn expand_tar(reader: impl Read, work_dir: &FsPath, limits: Limits) -> Result<Expanded, StatusCode> {
+    let mut archive = tar::Archive::new(reader);
+    let mut expanded = Expanded::new(limits);
+    for entry in archive.entries().map_err(bad_request)? {
+        let mut entry = entry.map_err(bad_request)?;
+        let name = String::from_utf8_lossy(&entry.path_bytes()).to_string();
+        let entry_type = entry.header().entry_type();
+        // Links, devices and extension headers are left out
+        if !entry_type.is_dir() && !entry_type.is_file() {
+            continue
+        }
+        let Some(path) = entry_path(&name)? else {
+            continue
+        };
+        if entry_type.is_dir() {
+            expanded.remaining(0)?;
+            expanded.dirs.insert(path);
+            continue
+        }
+        let remaining = expanded.remaining(entry.header().size().map_err(bad_request)?)?;
+        let upload = work_dir.join(expanded.files.len().to_string());
+        let out = std::fs::File::create(&upload).map_err(internal_error)?;
+        let mut writer = HashWriter { out, hasher: Sha256::new() };
+        let size = std::io::copy(&mut (&mut entry).take(remaining + 1), &mut writer).map_err(bad_request)?;
+        expanded.remaining(size)?;
+        expanded.size += size;
+        expanded.files.push(Entry {
+            path,
+            upload,
+            sha256: format!("{:x}", writer.hasher.finalize()),
+            size,
+            mtime: entry.header().mtime().ok()
+                .and_then(|t| chrono::DateTime::from_timestamp(t as i64, 0))
+                .map(|t| t.naive_utc()),
+        });
+    }
+    Ok(expanded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use async_zip::{Compression, ZipEntryBuilder};
+    use async_zip::tokio::write::ZipFileWriter;
+
+    const SMALL: Limits = Limits {
+        entries: 4,
+        size: 100,
+    };
+
+    // A scratch directory removed when dropped
+    struct WorkDir(PathBuf);
+
+    impl WorkDir {
+        fn new() -> Self {
+            let dir = std::env::temp_dir().join(format!("flowfs-expand-{}", uuid::Uuid::new_v4()));
+            std::fs::create_dir_all(&dir).unwrap();
+            WorkDir(dir)
+        }
+    }
+
+    impl Drop for WorkDir {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    // A tar of regular files, names written as given without the checks
+    // tar::Builder makes, and with `size` claimed in place of the real size
+    fn tar_of(entries: &[(&str, &[u8], Option<u64>)]) -> Vec<u8> {
+        let mut builder = tar::Builder::new(vec![]);
+        for (name, data, size) in entries {
+            let mut header = tar::Header::new_old();
+            header.as_old_mut().name[..name.len()].copy_from_slice(name.as_bytes());
+            header.set_entry_type(tar::EntryType::Regular);
+            header.set_mode(0o644);
+            header.set_size(size.unwrap_or(data.len() as u64));
+            header.set_cksum();
+            builder.append(&header, &data[..]).unwrap();
+        }
+        builder.into_inner().unwrap()
+    }
+
+    fn expand_tar_bytes(tar: &[u8], limits: Limits) -> Result<Expanded, StatusCode> {
+        let work_dir = WorkDir::new();
+        expand_tar(tar, &work_dir.0, limits)
+    }
+
+    async fn zip_of(entries: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut writer = ZipFileWriter::with_tokio(vec![]);
+        for (name, data) in entries {
+            let entry = ZipEntryBuilder::new(name.to_string().into(), Compression::Deflate);
+            writer.write_entry_whole(entry, data).await.unwrap();
+        }
+        writer.close().await.unwrap().into_inner()
+    }
+
+    async fn expand_zip_bytes(zip: &[u8], limits: Limits) -> Result<Expanded, StatusCode> {
+        let work_dir = WorkDir::new();
+        let archive_path = work_dir.0.join("archive");
+        std::fs::write(&archive_path, zip).unwrap();
+        expand_zip(&archive_path, &work_dir.0, limits).await
+    }
+
+    // Overwrite the uncompressed size of the only entry of a zip, both in
+    // its local header and in the central directory
+    fn understate_zip(zip: &mut [u8], size: u32) {
+        let find = |signature: [u8; 4]| zip.windows(4).position(|w| w == signature).unwrap();
+        let local = find([0x50, 0x4b, 0x03, 0x04]);
+        let central = find([0x50, 0x4b, 0x01, 0x02]);
+        zip[local + 22..local + 26].copy_from_slice(&size.to_le_bytes());
+        zip[central + 24..central + 28].copy_from_slice(&size.to_le_bytes());
+    }
+
+    #[test]
+    fn entry_paths() {
+        assert_eq!(entry_path("./a/b.txt"), Ok(Some("a/b.txt".to_string())));
+        assert_eq!(entry_path("dir\\file.txt"), Ok(Some("dir/file.txt".to_string())));
+        assert_eq!(entry_path("docs/"), Ok(Some("docs".to_string())));
+        assert_eq!(entry_path("./"), Ok(None));
+        assert_eq!(entry_path("../a.txt"), Err(StatusCode::BAD_REQUEST));
+        assert_eq!(entry_path("a/../../b"), Err(StatusCode::BAD_REQUEST));
+        assert_eq!(entry_path("..\\b"), Err(StatusCode::BAD_REQUEST));
+        assert_eq!(entry_path("/etc/passwd"), Err(StatusCode::BAD_REQUEST));
+        assert_eq!(entry_path("C:/x"), Err(StatusCode::BAD_REQUEST));
+    }
+
+    #[test]
+    fn tar_files() {
+        let tar = tar_of(&[("./a.txt", b"hello", None), ("docs/b.txt", b"world!", None)]);
+        let expanded = expand_tar_bytes(&tar, SMALL).unwrap();
+        let paths: Vec<&str> = expanded.files.iter().map(|f| f.path.as_str()).collect();
+        assert_eq!(paths, ["a.txt", "docs/b.txt"]);
+        assert_eq!(expanded.size, 11);
+        assert_eq!(expanded.files[0].sha256, format!("{:x}", Sha256::digest(b"hello")));
+    }
+
+    #[test]
+    fn tar_parent_path() {
+        let tar = tar_of(&[("a.txt", b"ok", None), ("../evil.txt", b"x", None)]);
+        assert_eq!(expand_tar_bytes(&tar, SMALL).err(), Some(StatusCode::BAD_REQUEST));
+        let tar = tar_of(&[("docs/../../evil.txt", b"x", None)]);
+        assert_eq!(expand_tar_bytes(&tar, SMALL).err(), Some(StatusCode::BAD_REQUEST));
+    }
+
+    #[test]
+    fn tar_absolute_path() {
+        let tar = tar_of(&[("/etc/cron.d/evil", b"x", None)]);
+        assert_eq!(expand_tar_bytes(&tar, SMALL).err(), Some(StatusCode::BAD_REQUEST));
+    }
+
+    #[test]
+    fn tar_too_many_entries() {
+        let names = ["1", "2", "3", "4", "5"];
+        let entries: Vec<(&str, &[u8], Option<u64>)> = names.iter().map(|n| (*n, &b"x"[..], None)).collect();
+        assert!(expand_tar_bytes(&tar_of(&entries[..4]), SMALL).is_ok());
+        assert_eq!(expand_tar_bytes(&tar_of(&entries), SMALL).err(), Some(StatusCode::PAYLOAD_TOO_LARGE));
+    }
+
+    #[test]
+    fn tar_too_large() {
+        let data = vec![7; 60];
+        let tar = tar_of(&[("a", &data, None), ("b", &data, None)]);
+        assert_eq!(expand_tar_bytes(&tar, SMALL).err(), Some(StatusCode::PAYLOAD_TOO_LARGE));
+        // Refused on the claim alone, before any content is read
+        let tar = tar_of(&[("a", b"", Some(5 * 1024 * 1024 * 1024))]);
+        assert_eq!(expand_tar_bytes(&tar, LIMITS).err(), Some(StatusCode::PAYLOAD_TOO_LARGE));
+    }
+
+    #[test]
+    fn tar_understated_size() {
+        // What follows the claimed size is read as the next header, so the
+        // extra bytes never end up in a file
+        let data = vec![b'x'; 2048];
+        let tar = tar_of(&[("a", &data, Some(10))]);
+        match expand_tar_bytes(&tar, SMALL) {
+            Ok(expanded) => assert!(expanded.files.iter().all(|f| f.size <= 10)),
+            Err(status) => assert_eq!(status, StatusCode::BAD_REQUEST),
+        }
+    }
+
+    #[tokio::test]
+    async fn zip_files() {
+        let zip = zip_of(&[("a.txt", b"hello"), ("docs/", b""), ("docs/b.txt", b"world!")]).await;
+        let expanded = expand_zip_bytes(&zip, SMALL).await.unwrap();
+        let paths: Vec<&str> = expanded.files.iter().map(|f| f.path.as_str()).collect();
+        assert_eq!(paths, ["a.txt", "docs/b.txt"]);
+        assert!(expanded.dirs.contains("docs"));
+        assert_eq!(expanded.size, 11);
+    }
+
+    #[tokio::test]
+    async fn zip_unsafe_paths() {
+        let zip = zip_of(&[("../evil.txt", b"x")]).await;
+        assert_eq!(expand_zip_bytes(&zip, SMALL).await.err(), Some(StatusCode::BAD_REQUEST));
+        let zip = zip_of(&[("/tmp/evil.txt", b"x")]).await;
+        assert_eq!(expand_zip_bytes(&zip, SMALL).await.err(), Some(StatusCode::BAD_REQUEST));
+        let zip = zip_of(&[("..\\evil.txt", b"x")]).await;
+        assert_eq!(expand_zip_bytes(&zip, SMALL).await.err(), Some(StatusCode::BAD_REQUEST));
+    }
+
+    #[tokio::test]
+    async fn zip_too_many_entries() {
+        let zip = zip_of(&[("1", b"x"), ("2", b"x"), ("3/", b""), ("4", b"x"), ("5", b"x")]).await;
+        assert_eq!(expand_zip_bytes(&zip, SMALL).await.err(), Some(StatusCode::PAYLOAD_TOO_LARGE));
+    }
+
+    #[tokio::test]
+    async fn zip_too_large() {
+        let zip = zip_of(&[("a", &[0; 101])]).await;
+        assert_eq!(expand_zip_bytes(&zip, SMALL).await.err(), Some(StatusCode::PAYLOAD_TOO_LARGE));
+    }
+
+    #[tokio::test]
+    async fn zip_understated_size() {
+        // Compresses well, so the entry passes on its claimed size and is
+        // only stopped by counting what it expands to
+        let mut zip = zip_of(&[("a", &[0; 5000])]).await;
+        understate_zip(&mut zip, 10);
+        let reader = async_zip::base::read::mem::ZipFileReader::new(zip.clone()).await.unwrap();
+        assert_eq!(reader.file().entries()[0].uncompressed_size(), 10);
+        assert_eq!(expand_zip_bytes(&zip, SMALL).await.err(), Some(StatusCode::PAYLOAD_TOO_LARGE));
+    }
+}
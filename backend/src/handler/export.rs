@@ -0,0 +1,402 @@
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::io::Read;
+use std::path::PathBuf;
+
+use log::error;
+use crate::model::*;
+use crate::handler::attachment::{insert_dirs, insert_fileprops, load_files, take_dirs, take_fileprops};
+use crate::handler::file::{
+    add_refs, blob_path, file_id_from_url, file_url, register_blob, release_refs, remove_blobs, HashWriter,
+};
+use crate::handler::revision::{insert_revision, take_revisions};
+use crate::utils;
+use crate::tree;
+
+use axum::debug_handler;
+use axum::{
+    body::Body,
+    extract::{Path, Query, State},
+    response::{Json, Response},
+    http::{header, StatusCode},
+};
+
+use futures_util::StreamExt;
+use sha2::{Digest, Sha256};
+use sqlx::Postgres;
+use sqlx::pool::Pool;
+use tokio::io::AsyncWriteExt;
+use tokio_util::io::{ReaderStream, SyncIoBridge};
+
+const EXPORT_FORMAT: &str = "flowfs-export";
+const MANIFEST_NAME: &str = "manifest.json";
+
+fn internal_error<E: std::fmt::Display>(e: E) -> StatusCode {
+    error!("{}", e);
+    StatusCode::INTERNAL_SERVER_ERROR
+}
+
+// The archive is a tar file with manifest.json first, followed by the
+// content of every uploaded file referenced by the cells as files/<file_id>.
+#[debug_handler]
+pub async fn export_user(
+    Path(user_id): Path<uuid::Uuid>,
+    State(pool): State<Pool<Postgres>>,
+) -> Result<Response, StatusCode> {
+    let user: User = match sqlx::query_as("SELECT user_id, user_name, passhash FROM users WHERE user_id=$1")
+        .bind(user_id)
+        .fetch_one(&pool)
+        .await {
+            Ok(user) => user,
+            Err(e) => {
+                error!("{}", e);
+                return Err(StatusCode::NOT_FOUND)
+            }
+        };
+    let mut cells: Vec<CellRow> = sqlx::query_as("SELECT * FROM cells WHERE user_id=$1 ORDER BY created_at")
+        .bind(user_id)
+        .fetch_all(&pool)
+        .await
+        .map_err(internal_error)?;
+    load_files(&mut cells, &pool).await.map_err(internal_error)?;
+    // Only edges between exported cells can be recreated on import
+    let family_tree: Vec<Edge> = sqlx::query_as(
+        "SELECT child_id, parent_id FROM family_tree
+        WHERE child_id IN (SELECT cell_id FROM cells WHERE user_id=$1)
+        AND parent_id IN (SELECT cell_id FROM cells WHERE user_id=$1)"
+    )
+        .bind(user_id)
+        .fetch_all(&pool)
+        .await
+        .map_err(internal_error)?;
+    let revisions: Vec<Revision> = sqlx::query_as(
+        "SELECT * FROM revisions WHERE cell_id IN (SELECT cell_id FROM cells WHERE user_id=$1)
+        ORDER BY cell_id, revision"
+    )
+        .bind(user_id)
+        .fetch_all(&pool)
+        .await
+        .map_err(internal_error)?;
+    let files: BTreeSet<uuid::Uuid> = cells.iter()
+        .flat_map(|cell| cell.fileprops.iter())
+        .chain(revisions.iter().flat_map(|revision| revision.fileprops.iter()))
+        .filter_map(|fileprop| file_id_from_url(&fileprop.url))
+        .filter(|file_id| blob_path(*file_id).is_file())
+        .collect();
+
+    let manifest = ExportManifest {
+        format: EXPORT_FORMAT.to_string(),
+        version: EXPORT_VERSION,
+        exported_at: chrono::Utc::now().naive_utc(),
+        user,
+        cells,
+        family_tree,
+        revisions,
+        files: files.into_iter().collect(),
+    };
+    let manifest_json = serde_json::to_vec_pretty(&manifest).map_err(internal_error)?;
+
+    // Build the tar on a blocking thread and stream it out through a pipe
+    let (writer, reader) = tokio::io::duplex(64 * 1024);
+    let writer = SyncIoBridge::new(writer);
+    let file_ids = manifest.files;
+    let task = tokio::task::spawn_blocking(move || {
+        let mut builder = tar::Builder::new(writer);
+        let mut header = tar::Header::new_gnu();
+        header.set_size(manifest_json.len() as u64);
+        header.set_mode(0o644);
+        header.set_mtime(manifest.exported_at.and_utc().timestamp() as u64);
+        header.set_cksum();
+        builder.append_data(&mut header, MANIFEST_NAME, &manifest_json[..])?;
+        for file_id in file_ids.iter() {
+            builder.append_path_with_name(blob_path(*file_id), format!("files/{}", file_id))?;
+        }
+        builder.finish()
+    });
+    // The pipe closes when the thread ends, failed or not. A failure ends the
+    // body with an error, so the client sees the archive cut short instead of
+    // a complete looking one.
+    let outcome = futures_util::stream::once(async move {
+        let res = match task.await {
+            Ok(res) => res,
+            Err(e) => Err(std::io::Error::other(e)),
+        };
+        res.err().map(|e| {
+            error!("export of {} failed: {}", user_id, e);
+            Err(e)
+        })
+    }).filter_map(std::future::ready);
+
+    Response::builder()
+        .header(header::CONTENT_TYPE, "application/x-tar")
+        .header(header::CONTENT_DISPOSITION, format!("attachment; filename=\"flowfs-{}.tar\"", user_id))
+        .body(Body::from_stream(ReaderStream::new(reader).chain(outcome)))
+        .map_err(internal_error)
+}
+
+// Recreates the exported cells under `user_id`, keeping cell ids, creation
+// times and edges. Cells that already exist are handled per `on_conflict`.
+#[debug_handler]
+pub async fn import_user(
+    Path(user_id): Path<uuid::Uuid>,
+    Query(options): Query<ImportOptions>,
+    State(pool): State<Pool<Postgres>>,
+    body: Body,
+) -> Result<Json<ImportRes>, StatusCode> {
+    let blob_dir = PathBuf::from(utils::get_blob_path());
+    tokio::fs::create_dir_all(&blob_dir).await.map_err(internal_error)?;
+    let archive_path = blob_dir.join(format!(".import-{}.tar", uuid::Uuid::new_v4()));
+    let res = import_archive(user_id, options.on_conflict, &pool, body, &archive_path).await;
+    let _ = tokio::fs::remove_file(&archive_path).await;
+    res.map(Json)
+}
+
+async fn import_archive(
+    user_id: uuid::Uuid,
+    on_conflict: OnConflict,
+    pool: &Pool<Postgres>,
+    body: Body,
+    archive_path: &std::path::Path,
+) -> Result<ImportRes, StatusCode> {
+    // Spool the upload to disk, the archive is read twice
+    let mut file = tokio::fs::File::create(archive_path).await.map_err(internal_error)?;
+    let mut stream = body.into_data_stream();
+    while let Some(chunk) = stream.next().await {
+        let chunk = chunk.map_err(|e| {
+            error!("{}", e);
+            StatusCode::BAD_REQUEST
+        })?;
+        file.write_all(&chunk).await.map_err(internal_error)?;
+    }
+    file.flush().await.map_err(internal_error)?;
+
+    let path = archive_path.to_path_buf();
+    let mut manifest = tokio::task::spawn_blocking(move || read_manifest(&path))
+        .await
+        .map_err(internal_error)??;
+    if manifest.format != EXPORT_FORMAT || manifest.version > EXPORT_VERSION {
+        error!("unsupported archive {} version {}", manifest.format, manifest.version);
+        return Err(StatusCode::BAD_REQUEST)
+    }
+
+    for cell in manifest.cells.iter_mut() {
+        match tree::normalize(&cell.fileprops, &cell.dirs) {
+            Ok((fileprops, dirs)) => {
+                cell.fileprops = fileprops;
+                cell.dirs = dirs;
+            }
+            Err(e) => {
+                error!("cell {}: {}", cell.cell_id, e);
+                return Err(StatusCode::BAD_REQUEST)
+            }
+        }
+    }
+    for revision in manifest.revisions.iter_mut() {
+        match tree::normalize(&revision.fileprops, &revision.dirs) {
+            Ok((fileprops, dirs)) => {
+                revision.fileprops = fileprops;
+                revision.dirs = dirs;
+            }
+            Err(e) => {
+                error!("cell {} revision {}: {}", revision.cell_id, revision.revision, e);
+                return Err(StatusCode::BAD_REQUEST)
+            }
+        }
+    }
+
+    let file_ids: HashSet<uuid::Uuid> = manifest.files.iter().copied().collect();
+    let path = archive_path.to_path_buf();
+    let extracted = tokio::task::spawn_blocking(move || extract_files(&path, &file_ids))
+        .await
+        .map_err(internal_error)??;
+    let res = import_cells(user_id, on_conflict, pool, manifest, &extracted).await;
+    if res.is_err() {
+        let files: Vec<uuid::Uuid> = extracted.iter().map(|f| f.0).collect();
+        remove_blobs(&files).await;
+    }
+    res
+}
+
+async fn import_cells(
+    user_id: uuid::Uuid,
+    on_conflict: OnConflict,
+    pool: &Pool<Postgres>,
+    mut manifest: ExportManifest,
+    extracted: &[(uuid::Uuid, String, u64)],
+) -> Result<ImportRes, StatusCode> {
+    let mut res = ImportRes::default();
+    let mut tx = pool.begin().await.map_err(internal_error)?;
+
+    // Content this instance already stores is kept once, under its existing id
+    let mut stored: HashMap<uuid::Uuid, uuid::Uuid> = HashMap::new();
+    let mut duplicates = vec![];
+    for (file_id, sha256, size) in extracted.iter() {
+        match register_blob(*file_id, sha256, *size, &mut tx).await {
+            Ok(id) => {
+                stored.insert(*file_id, id);
+            }
+            Err(Some(id)) => {
+                stored.insert(*file_id, id);
+                duplicates.push(*file_id);
+            }
+            Err(None) => return Err(StatusCode::INTERNAL_SERVER_ERROR),
+        }
+    }
+    res.files = stored.len() - duplicates.len();
+    // Files are served by this instance from now on, including those that
+    // were already here from an earlier import
+    let file_ids: HashSet<uuid::Uuid> = manifest.files.iter().copied().collect();
+    let local_id = |id: uuid::Uuid| match stored.get(&id) {
+        Some(stored_id) => Some(*stored_id),
+        None => Some(id).filter(|id| file_ids.contains(id) && blob_path(*id).is_file()),
+    };
+    let cell_files = manifest.cells.iter_mut().flat_map(|cell| cell.fileprops.iter_mut());
+    let revision_files = manifest.revisions.iter_mut().flat_map(|revision| revision.fileprops.iter_mut());
+    for fileprop in cell_files.chain(revision_files) {
+        if let Some(file_id) = file_id_from_url(&fileprop.url).and_then(local_id) {
+            fileprop.url = file_url(file_id);
+        }
+    }
+    // Revisions of cells left out of the archive are not imported
+    let mut revisions: HashMap<uuid::Uuid, Vec<Revision>> = HashMap::new();
+    for revision in manifest.revisions {
+        revisions.entry(revision.cell_id).or_default().push(revision);
+    }
+
+    sqlx::query(
+        "INSERT INTO users (user_id, user_name, passhash) VALUES ($1, $2, $3)
+        ON CONFLICT (user_id) DO NOTHING"
+    )
+        .bind(user_id)
+        .bind(&manifest.user.user_name)
+        .bind(&manifest.user.passhash)
+        .execute(&mut *tx)
+        .await
+        .map_err(internal_error)?;
+
+    let mut unused = vec![];
+    for cell in manifest.cells {
+        let owner: Option<(uuid::Uuid,)> = sqlx::query_as("SELECT user_id FROM cells WHERE cell_id=$1")
+            .bind(cell.cell_id)
+            .fetch_optional(&mut *tx)
+            .await
+            .map_err(internal_error)?;
+        let query = match (owner, on_conflict) {
+            (None, _) => {
+                add_refs(&mut tx, &cell.fileprops).await?;
+                res.created.push(cell.cell_id);
+                "INSERT INTO cells (cell_id, user_id, device_id, text, is_open, created_at)
+                VALUES ($1, $2, $3, $4, $5, $6)"
+            }
+            // Cells of other users are never touched
+            (Some((owner,)), _) if owner != user_id => return Err(StatusCode::CONFLICT),
+            (Some(_), OnConflict::Fail) => return Err(StatusCode::CONFLICT),
+            (Some(_), OnConflict::Skip) => {
+                res.skipped.push(cell.cell_id);
+                continue
+            }
+            (Some(_), OnConflict::Overwrite) => {
+                sqlx::query("SELECT cell_id FROM cells WHERE cell_id=$1 FOR UPDATE")
+                    .bind(cell.cell_id)
+                    .execute(&mut *tx)
+                    .await
+                    .map_err(internal_error)?;
+                // The cell is replaced with its history and parents; edges
+                // from cells that reply to it stay
+                let mut old_fileprops = take_fileprops(&mut tx, cell.cell_id).await.map_err(internal_error)?;
+                old_fileprops.extend(take_revisions(&mut tx, cell.cell_id).await.map_err(internal_error)?);
+                take_dirs(&mut tx, cell.cell_id).await.map_err(internal_error)?;
+                sqlx::query("DELETE FROM family_tree WHERE child_id=$1")
+                    .bind(cell.cell_id)
+                    .execute(&mut *tx)
+                    .await
+                    .map_err(internal_error)?;
+                add_refs(&mut tx, &cell.fileprops).await?;
+                unused.extend(release_refs(&mut tx, &old_fileprops).await.map_err(internal_error)?);
+                res.overwritten.push(cell.cell_id);
+                "UPDATE cells SET user_id=$2, device_id=$3, text=$4, is_open=$5, created_at=$6
+                WHERE cell_id=$1"
+            }
+        };
+        sqlx::query(query)
+            .bind(cell.cell_id)
+            .bind(user_id)
+            .bind(cell.device_id)
+            .bind(cell.text)
+            .bind(cell.is_open)
+            .bind(cell.created_at)
+            .execute(&mut *tx)
+            .await
+            .map_err(internal_error)?;
+        insert_fileprops(&mut tx, cell.cell_id, &cell.fileprops).await.map_err(internal_error)?;
+        insert_dirs(&mut tx, cell.cell_id, &cell.dirs).await.map_err(internal_error)?;
+        for revision in revisions.remove(&cell.cell_id).unwrap_or_default().iter() {
+            insert_revision(&mut tx, revision, Some(revision.created_at)).await?;
+        }
+    }
+
+    for edge in manifest.family_tree.iter() {
+        sqlx::query(
+            "INSERT INTO family_tree (child_id, parent_id) SELECT $1, $2
+            WHERE NOT EXISTS (SELECT 1 FROM family_tree WHERE child_id=$1 AND parent_id=$2)"
+        )
+            .bind(edge.child_id)
+            .bind(edge.parent_id)
+            .execute(&mut *tx)
+            .await
+            .map_err(internal_error)?;
+    }
+
+    tx.commit().await.map_err(internal_error)?;
+    remove_blobs(&duplicates).await;
+    remove_blobs(&unused).await;
+    Ok(res)
+}
+
+fn read_manifest(archive_path: &std::path::Path) -> Result<ExportManifest, StatusCode> {
+    let file = std::fs::File::open(archive_path).map_err(internal_error)?;
+    let mut archive = tar::Archive::new(file);
+    let mut entries = archive.entries().map_err(internal_error)?;
+    let Some(Ok(mut entry)) = entries.next() else {
+        return Err(StatusCode::BAD_REQUEST)
+    };
+    if entry.path().map_err(internal_error)?.to_str() != Some(MANIFEST_NAME) {
+        error!("archive does not start with {}", MANIFEST_NAME);
+        return Err(StatusCode::BAD_REQUEST)
+    }
+    let mut manifest_json = vec![];
+    entry.read_to_end(&mut manifest_json).map_err(internal_error)?;
+    serde_json::from_slice(&manifest_json).map_err(|e| {
+        error!("{}", e);
+        StatusCode::BAD_REQUEST
+    })
+}
+
+// Only entries named files/<file_id> for files listed in the manifest are
+// written, so entry names can never point outside the blob directory.
+// Returns the id, digest and size of each file written.
+fn extract_files(
+    archive_path: &std::path::Path,
+    file_ids: &HashSet<uuid::Uuid>,
+) -> Result<Vec<(uuid::Uuid, String, u64)>, StatusCode> {
+    let file = std::fs::File::open(archive_path).map_err(internal_error)?;
+    let mut archive = tar::Archive::new(file);
+    let mut extracted = vec![];
+    for entry in archive.entries().map_err(internal_error)? {
+        let mut entry = entry.map_err(internal_error)?;
+        let name = entry.path().map_err(internal_error)?.to_string_lossy().to_string();
+        let Some(file_id) = name.strip_prefix("files/")
+            .and_then(|id| uuid::Uuid::parse_str(id).ok())
+            .filter(|id| file_ids.contains(id)) else {
+            continue
+        };
+        let dest = blob_path(file_id);
+        if dest.exists() {
+            continue
+        }
+        let out = std::fs::File::create(&dest).map_err(internal_error)?;
+        let mut writer = HashWriter { out, hasher: Sha256::new() };
+        let size = std::io::copy(&mut entry, &mut writer).map_err(internal_error)?;
+        extracted.push((file_id, format!("{:x}", writer.hasher.finalize()), size));
+    }
+    Ok(extracted)
+}
@@ -0,0 +1,605 @@
+use std::collections::HashSet;
+use std::path::PathBuf;
+
+use log::error;
+use crate::model::*;
+use crate::utils;
+
+use axum::debug_handler;
+use axum::{
+    body::Body,
+    extract::{Path, State},
+    response::{Json, Response},
+    http::{header, HeaderMap, StatusCode},
+};
+
+use futures_util::StreamExt;
+use sha2::{Digest, Sha256};
+use sqlx::{PgConnection, Postgres};
+use sqlx::pool::Pool;
+use tokio::fs;
+use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
+use tokio_util::io::ReaderStream;
+
+#[debug_handler]
+pub async fn upload_file(
+    State(pool): State<Pool<Postgres>>,
+    body: Body,
+) -> Result<Json<FileRes>, StatusCode> {
+    Ok(Json(store_blob(body, &pool).await?))
+}
+
+// Lets a client skip uploading content the server already stores
+#[debug_handler]
+pub async fn find_file(
+    Path(sha256): Path<String>,
+    State(pool): State<Pool<Postgres>>,
+) -> Result<Json<FileRes>, StatusCode> {
+    let blob: Option<(uuid::Uuid, i64)> = sqlx::query_as(
+        "UPDATE blobs SET touched_at=CURRENT_TIMESTAMP WHERE sha256=$1 RETURNING file_id, size"
+    )
+        .bind(&sha256)
+        .fetch_optional(&pool)
+        .await
+        .map_err(|e| {
+            error!("{}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+    match blob {
+        Some((id, size)) => Ok(Json(FileRes { id, size: size as u64, sha256 })),
+        None => Err(StatusCode::NOT_FOUND),
+    }
+}
+
+// Write a request body to disk chunk by chunk, hashing it on the way. Content
+// the server already has is dropped and the existing blob returned instead.
+pub async fn store_blob(body: Body, pool: &Pool<Postgres>) -> Result<FileRes, StatusCode> {
+    let blob_dir = PathBuf::from(utils::get_blob_path());
+    if let Err(e) = fs::create_dir_all(&blob_dir).await {
+        error!("{}", e);
+        return Err(StatusCode::INTERNAL_SERVER_ERROR)
+    };
+    let upload_path = blob_dir.join(format!(".upload-{}", uuid::Uuid::new_v4()));
+    let mut file = match fs::File::create(&upload_path).await {
+        Ok(file) => file,
+        Err(e) => {
+            error!("{}", e);
+            return Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    };
+
+    let mut hasher = Sha256::new();
+    let mut size = 0;
+    let mut stream = body.into_data_stream();
+    while let Some(chunk) = stream.next().await {
+        let written = match chunk {
+            Ok(chunk) => {
+                hasher.update(&chunk);
+                size += chunk.len() as u64;
+                file.write_all(&chunk).await
+            }
+            Err(e) => {
+                error!("{}", e);
+                let _ = fs::remove_file(&upload_path).await;
+                return Err(StatusCode::BAD_REQUEST)
+            }
+        };
+        if let Err(e) = written {
+            error!("{}", e);
+            let _ = fs::remove_file(&upload_path).await;
+            return Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+    if let Err(e) = file.flush().await {
+        error!("{}", e);
+        let _ = fs::remove_file(&upload_path).await;
+        return Err(StatusCode::INTERNAL_SERVER_ERROR)
+    };
+    drop(file);
+
+    let sha256 = format!("{:x}", hasher.finalize());
+    let mut conn = match pool.acquire().await {
+        Ok(conn) => conn,
+        Err(e) => {
+            error!("{}", e);
+            let _ = fs::remove_file(&upload_path).await;
+            return Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    };
+    let res = match register_blob(uuid::Uuid::new_v4(), &sha256, size, &mut conn).await {
+        Ok(id) => fs::rename(&upload_path, blob_path(id)).await.map(|_| id),
+        Err(Some(id)) => fs::remove_file(&upload_path).await.map(|_| id),
+        Err(None) => {
+            let _ = fs::remove_file(&upload_path).await;
+            return Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    };
+    match res {
+        Ok(id) => Ok(FileRes { id, size, sha256 }),
+        Err(e) => {
+            error!("{}", e);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+// Record new content under `file_id`. Content that is already stored gives
+// Err(Some(existing id)), a failed query Err(None).
+pub async fn register_blob(
+    file_id: uuid::Uuid,
+    sha256: &str,
+    size: u64,
+    conn: &mut PgConnection,
+) -> Result<uuid::Uuid, Option<uuid::Uuid>> {
+    let inserted: Option<(uuid::Uuid,)> = sqlx::query_as(
+        "INSERT INTO blobs (file_id, sha256, size) VALUES ($1, $2, $3)
+        ON CONFLICT (sha256) DO NOTHING RETURNING file_id"
+    )
+        .bind(file_id)
+        .bind(sha256)
+        .bind(size as i64)
+        .fetch_optional(&mut *conn)
+        .await
+        .map_err(|e| {
+            error!("{}", e);
+            None
+        })?;
+    if let Some((id,)) = inserted {
+        return Ok(id)
+    }
+    let existing: (uuid::Uuid,) = sqlx::query_as(
+        "UPDATE blobs SET touched_at=CURRENT_TIMESTAMP WHERE sha256=$1 RETURNING file_id"
+    )
+        .bind(sha256)
+        .fetch_one(conn)
+        .await
+        .map_err(|e| {
+            error!("{}", e);
+            None
+        })?;
+    Err(Some(existing.0))
+}
+
+#[debug_handler]
+pub async fn download_file(
+    Path(file_id): Path<uuid::Uuid>,
+    headers: HeaderMap,
+) -> Result<Response, StatusCode> {
+    send_blob(file_id, "application/octet-stream", &headers).await
+}
+
+// Stream a stored blob, or the single byte range asked for in `headers`.
+// Blobs never change, so their id makes a strong ETag.
+pub async fn send_blob(
+    file_id: uuid::Uuid,
+    mime: &str,
+    headers: &HeaderMap,
+) -> Result<Response, StatusCode> {
+    let mut file = match fs::File::open(blob_path(file_id)).await {
+        Ok(file) => file,
+        Err(e) => {
+            error!("{}", e);
+            return Err(StatusCode::NOT_FOUND)
+        }
+    };
+    let size = match file.metadata().await {
+        Ok(meta) => meta.len(),
+        Err(e) => {
+            error!("{}", e);
+            return Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    };
+    let etag = format!("\"{}\"", file_id);
+    let builder = Response::builder()
+        .header(header::CONTENT_TYPE, mime)
+        .header(header::ETAG, &etag)
+        .header(header::ACCEPT_RANGES, "bytes");
+    let header_str = |name| headers.get(name).and_then(|v: &header::HeaderValue| v.to_str().ok());
+    let not_modified = header_str(header::IF_NONE_MATCH)
+        .is_some_and(|tags| tags.split(',').any(|t| t.trim() == etag || t.trim() == "*"));
+    // A range of other content than the client has is no use to it
+    let same_content = header_str(header::IF_RANGE).is_none_or(|tag| tag == etag);
+    let range = match header_str(header::RANGE) {
+        Some(range) if same_content => byte_range(range, size),
+        _ => ByteRange::Full,
+    };
+    let res = if not_modified {
+        builder.status(StatusCode::NOT_MODIFIED).body(Body::empty())
+    } else {
+        match range {
+            ByteRange::Full => builder
+                .header(header::CONTENT_LENGTH, size)
+                .body(Body::from_stream(ReaderStream::new(file))),
+            ByteRange::Part(start, end) => {
+                if let Err(e) = file.seek(std::io::SeekFrom::Start(start)).await {
+                    error!("{}", e);
+                    return Err(StatusCode::INTERNAL_SERVER_ERROR)
+                }
+                builder
+                    .status(StatusCode::PARTIAL_CONTENT)
+                    .header(header::CONTENT_RANGE, format!("bytes {}-{}/{}", start, end, size))
+                    .header(header::CONTENT_LENGTH, end - start + 1)
+                    .body(Body::from_stream(ReaderStream::new(file.take(end - start + 1))))
+            }
+            ByteRange::Unsatisfiable => builder
+                .status(StatusCode::RANGE_NOT_SATISFIABLE)
+                .header(header::CONTENT_RANGE, format!("bytes */{}", size))
+                .body(Body::empty()),
+        }
+    };
+    res.map_err(|e| {
+        error!("{}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })
+}
+
+#[derive(Debug, PartialEq)]
+enum ByteRange {
+    Full,
+    // First and last byte, both included
+    Part(u64, u64),
+    Unsatisfiable,
+}
+
+// Read a Range header such as "bytes=100-", "bytes=0-99" or "bytes=-500".
+// Several ranges at once and malformed values get the whole content.
+fn byte_range(value: &str, size: u64) -> ByteRange {
+    let Some(spec) = value.trim().strip_prefix("bytes=") else {
+        return ByteRange::Full
+    };
+    let Some((start, end)) = spec.split_once('-') else {
+        return ByteRange::Full
+    };
+    if spec.contains(',') {
+        return ByteRange::Full
+    }
+    let (start, end) = (start.trim(), end.trim());
+    if start.is_empty() {
+        return match end.parse::<u64>() {
+            Ok(0) => ByteRange::Unsatisfiable,
+            Ok(_) if size == 0 => ByteRange::Unsatisfiable,
+            Ok(suffix) => ByteRange::Part(size.saturating_sub(suffix), size - 1),
+            Err(_) => ByteRange::Full,
+        }
+    }
+    let Ok(start) = start.parse::<u64>() else {
+        return ByteRange::Full
+    };
+    if start >= size {
+        return ByteRange::Unsatisfiable
+    }
+    let end = match end {
+        "" => size - 1,
+        end => match end.parse::<u64>() {
+            Ok(end) if end >= start => end.min(size - 1),
+            _ => return ByteRange::Full,
+        },
+    };
+    ByteRange::Part(start, end)
+}
+
+pub fn blob_path(file_id: uuid::Uuid) -> PathBuf {
+    PathBuf::from(utils::get_blob_path()).join(file_id.to_string())
+}
+
+// Blobs referenced by `fileprops`, once per reference
+fn blob_refs(fileprops: &[FileProp]) -> Vec<uuid::Uuid> {
+    fileprops.iter().filter_map(|f| file_id_from_url(&f.url)).collect()
+}
+
+#[derive(Debug)]
+pub enum RefError {
+    // A file points at a blob this server does not store
+    Missing(uuid::Uuid),
+    Db(sqlx::Error),
+}
+
+impl std::fmt::Display for RefError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RefError::Missing(id) => write!(f, "no stored blob {}", id),
+            RefError::Db(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl From<sqlx::Error> for RefError {
+    fn from(e: sqlx::Error) -> Self {
+        RefError::Db(e)
+    }
+}
+
+impl From<RefError> for StatusCode {
+    fn from(e: RefError) -> Self {
+        error!("{}", e);
+        match e {
+            RefError::Missing(_) => StatusCode::BAD_REQUEST,
+            RefError::Db(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+// Count new references to stored blobs. Nothing is counted when one of them
+// is not stored, or was collected since the client uploaded it.
+pub async fn add_refs(conn: &mut PgConnection, fileprops: &[FileProp]) -> Result<(), RefError> {
+    let refs = blob_refs(fileprops);
+    if refs.is_empty() {
+        return Ok(())
+    }
+    let counted: Vec<(uuid::Uuid,)> = sqlx::query_as(
+        "UPDATE blobs SET refcount = refcount + refs.n
+        FROM (SELECT id, COUNT(*) AS n FROM UNNEST($1::uuid[]) AS id GROUP BY id) AS refs
+        WHERE blobs.file_id = refs.id RETURNING blobs.file_id"
+    )
+        .bind(&refs)
+        .fetch_all(&mut *conn)
+        .await?;
+    let counted: HashSet<uuid::Uuid> = counted.into_iter().map(|(id,)| id).collect();
+    match refs.into_iter().find(|id| !counted.contains(id)) {
+        // The caller's transaction is rolled back with the counts made here
+        Some(id) => Err(RefError::Missing(id)),
+        None => Ok(()),
+    }
+}
+
+// Drop references to stored blobs and forget the blobs nothing refers to
+// any more, unless a client was handed them lately; `collect_blobs` takes
+// those later. Their files are removed with `remove_blobs` once committed.
+pub async fn release_refs(conn: &mut PgConnection, fileprops: &[FileProp]) -> Result<Vec<uuid::Uuid>, sqlx::Error> {
+    let refs = blob_refs(fileprops);
+    sqlx::query(
+        "UPDATE blobs SET refcount = refcount - refs.n
+        FROM (SELECT id, COUNT(*) AS n FROM UNNEST($1::uuid[]) AS id GROUP BY id) AS refs
+        WHERE blobs.file_id = refs.id"
+    )
+        .bind(&refs)
+        .execute(&mut *conn)
+        .await?;
+    let unused: Vec<(uuid::Uuid,)> = sqlx::query_as(
+        "DELETE FROM blobs WHERE file_id = ANY($1) AND refcount <= 0
+        AND touched_at < CURRENT_TIMESTAMP - $2 * INTERVAL '1 second' RETURNING file_id"
+    )
+        .bind(&refs)
+        .bind(utils::get_blob_grace().as_secs() as f64)
+        .fetch_all(conn)
+        .await?;
+    Ok(unused.into_iter().map(|(id,)| id).collect())
+}
+
+// Remove the blobs nothing has referred to since the grace period, such as
+// uploads no cell ever used. A blob counted meanwhile by `add_refs` is kept,
+// as the delete waits for its row.
+pub async fn collect_blobs(pool: &Pool<Postgres>) -> Result<usize, sqlx::Error> {
+    let unused: Vec<(uuid::Uuid,)> = sqlx::query_as(
+        "DELETE FROM blobs WHERE refcount <= 0
+        AND touched_at < CURRENT_TIMESTAMP - $1 * INTERVAL '1 second' RETURNING file_id"
+    )
+        .bind(utils::get_blob_grace().as_secs() as f64)
+        .fetch_all(pool)
+        .await?;
+    let unused: Vec<uuid::Uuid> = unused.into_iter().map(|(id,)| id).collect();
+    remove_blobs(&unused).await;
+    Ok(unused.len())
+}
+
+pub async fn remove_blobs(file_ids: &[uuid::Uuid]) {
+    for file_id in file_ids {
+        if let Err(e) = fs::remove_file(blob_path(*file_id)).await {
+            error!("{}", e);
+        }
+    }
+}
+
+// Url of an uploaded file, the same whichever client it is given to
+pub fn file_url(file_id: uuid::Uuid) -> String {
+    format!("{}/files/{}", utils::get_public_url(), file_id)
+}
+
+pub fn guess_mime(path: &str) -> String {
+    mime_guess::from_path(path).first_or_octet_stream().to_string()
+}
+
+// Fill in what the server can tell about files a client left out:
+// the MIME type from the name and the size of blobs stored here
+pub fn complete_fileprops(fileprops: &mut [FileProp]) {
+    for fileprop in fileprops.iter_mut() {
+        if fileprop.mime.is_none() {
+            fileprop.mime = Some(guess_mime(&fileprop.path));
+        }
+        if fileprop.size.is_none() {
+            fileprop.size = file_id_from_url(&fileprop.url)
+                .and_then(|id| blob_path(id).metadata().ok())
+                .map(|m| m.len());
+        }
+    }
+}
+
+// FileProp urls of uploaded files end with "/files/<file_id>"
+pub fn file_id_from_url(url: &str) -> Option<uuid::Uuid> {
+    let (_, id) = url.rsplit_once("/files/")?;
+    uuid::Uuid::parse_str(id).ok()
+}
+
+// Hash what goes through to the file
+pub struct HashWriter {
+    pub out:        std::fs::File,
+    pub hasher:     Sha256,
+}
+
+impl std::io::Write for HashWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let n = self.out.write(buf)?;
+        self.hasher.update(&buf[..n]);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.out.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A stored blob holding the bytes 0..=99, removed when dropped
+    struct TestBlob(uuid::Uuid);
+
+    impl TestBlob {
+        fn new() -> Self {
+            let file_id = uuid::Uuid::new_v4();
+            std::fs::create_dir_all(utils::get_blob_path()).unwrap();
+            std::fs::write(blob_path(file_id), (0..100).collect::<Vec<u8>>()).unwrap();
+            TestBlob(file_id)
+        }
+
+        fn etag(&self) -> String {
+            format!("\"{}\"", self.0)
+        }
+    }
+
+    impl Drop for TestBlob {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(blob_path(self.0));
+        }
+    }
+
+    async fn send(blob: &TestBlob, headers: &[(header::HeaderName, &str)]) -> (StatusCode, HeaderMap, Vec<u8>) {
+        let mut map = HeaderMap::new();
+        for (name, value) in headers {
+            map.insert(name.clone(), value.parse().unwrap());
+        }
+        let res = send_blob(blob.0, "text/plain", &map).await.unwrap();
+        let (parts, body) = res.into_parts();
+        let body = axum::body::to_bytes(body, usize::MAX).await.unwrap();
+        (parts.status, parts.headers, body.to_vec())
+    }
+
+    #[test]
+    fn ranges() {
+        assert_eq!(byte_range("bytes=0-99", 1000), ByteRange::Part(0, 99));
+        assert_eq!(byte_range("bytes=10-10", 1000), ByteRange::Part(10, 10));
+        assert_eq!(byte_range(" bytes= 5 - 9 ", 1000), ByteRange::Part(5, 9));
+        // The end is cut to the content
+        assert_eq!(byte_range("bytes=900-5000", 1000), ByteRange::Part(900, 999));
+    }
+
+    #[test]
+    fn suffix_ranges() {
+        assert_eq!(byte_range("bytes=-500", 1000), ByteRange::Part(500, 999));
+        assert_eq!(byte_range("bytes=-1", 1000), ByteRange::Part(999, 999));
+        assert_eq!(byte_range("bytes=-2000", 1000), ByteRange::Part(0, 999));
+        assert_eq!(byte_range("bytes=-0", 1000), ByteRange::Unsatisfiable);
+        assert_eq!(byte_range("bytes=-10", 0), ByteRange::Unsatisfiable);
+    }
+
+    #[test]
+    fn open_ended_ranges() {
+        assert_eq!(byte_range("bytes=100-", 1000), ByteRange::Part(100, 999));
+        assert_eq!(byte_range("bytes=0-", 1000), ByteRange::Part(0, 999));
+        assert_eq!(byte_range("bytes=999-", 1000), ByteRange::Part(999, 999));
+    }
+
+    #[test]
+    fn out_of_bounds_ranges() {
+        assert_eq!(byte_range("bytes=1000-", 1000), ByteRange::Unsatisfiable);
+        assert_eq!(byte_range("bytes=5000-6000", 1000), ByteRange::Unsatisfiable);
+        assert_eq!(byte_range("bytes=0-", 0), ByteRange::Unsatisfiable);
+    }
+
+    #[test]
+    fn other_ranges_get_everything() {
+        assert_eq!(byte_range("bytes=0-1,5-6", 1000), ByteRange::Full);
+        assert_eq!(byte_range("bytes=-5,10-", 1000), ByteRange::Full);
+        assert_eq!(byte_range("items=0-1", 1000), ByteRange::Full);
+        assert_eq!(byte_range("bytes=abc", 1000), ByteRange::Full);
+        assert_eq!(byte_range("bytes=x-5", 1000), ByteRange::Full);
+        assert_eq!(byte_range("bytes=-x", 1000), ByteRange::Full);
+        assert_eq!(byte_range("bytes=9-2", 1000), ByteRange::Full);
+    }
+
+    #[tokio::test]
+    async fn whole_blob() {
+        let blob = TestBlob::new();
+        let (status, headers, body) = send(&blob, &[]).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(headers[header::ETAG], blob.etag().as_str());
+        assert_eq!(headers[header::ACCEPT_RANGES], "bytes");
+        assert_eq!(headers[header::CONTENT_LENGTH], "100");
+        assert_eq!(body, (0..100).collect::<Vec<u8>>());
+    }
+
+    #[tokio::test]
+    async fn partial_blob() {
+        let blob = TestBlob::new();
+        let (status, headers, body) = send(&blob, &[(header::RANGE, "bytes=10-19")]).await;
+        assert_eq!(status, StatusCode::PARTIAL_CONTENT);
+        assert_eq!(headers[header::CONTENT_RANGE], "bytes 10-19/100");
+        assert_eq!(headers[header::CONTENT_LENGTH], "10");
+        assert_eq!(body, (10..20).collect::<Vec<u8>>());
+
+        let (status, headers, body) = send(&blob, &[(header::RANGE, "bytes=-5")]).await;
+        assert_eq!(status, StatusCode::PARTIAL_CONTENT);
+        assert_eq!(headers[header::CONTENT_RANGE], "bytes 95-99/100");
+        assert_eq!(body, (95..100).collect::<Vec<u8>>());
+
+        let (status, _, body) = send(&blob, &[(header::RANGE, "bytes=90-")]).await;
+        assert_eq!(status, StatusCode::PARTIAL_CONTENT);
+        assert_eq!(body.len(), 10);
+    }
+
+    #[tokio::test]
+    async fn unsatisfiable_range() {
+        let blob = TestBlob::new();
+        let (status, headers, body) = send(&blob, &[(header::RANGE, "bytes=100-200")]).await;
+        assert_eq!(status, StatusCode::RANGE_NOT_SATISFIABLE);
+        assert_eq!(headers[header::CONTENT_RANGE], "bytes */100");
+        assert!(body.is_empty());
+    }
+
+    #[tokio::test]
+    async fn multiple_ranges() {
+        let blob = TestBlob::new();
+        let (status, headers, body) = send(&blob, &[(header::RANGE, "bytes=0-9,20-29")]).await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(headers.get(header::CONTENT_RANGE).is_none());
+        assert_eq!(body.len(), 100);
+    }
+
+    #[tokio::test]
+    async fn if_none_match() {
+        let blob = TestBlob::new();
+        let etag = blob.etag();
+        for tags in [etag.clone(), format!("\"other\", {}", etag), "*".to_string()] {
+            let (status, headers, body) = send(&blob, &[(header::IF_NONE_MATCH, &tags)]).await;
+            assert_eq!(status, StatusCode::NOT_MODIFIED, "{}", tags);
+            assert_eq!(headers[header::ETAG], etag.as_str());
+            assert!(body.is_empty());
+        }
+        let (status, _, body) = send(&blob, &[(header::IF_NONE_MATCH, "\"other\"")]).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body.len(), 100);
+        // A cached copy also answers a range request
+        let (status, _, _) = send(&blob, &[(header::IF_NONE_MATCH, &etag), (header::RANGE, "bytes=0-9")]).await;
+        assert_eq!(status, StatusCode::NOT_MODIFIED);
+    }
+
+    #[tokio::test]
+    async fn if_range() {
+        let blob = TestBlob::new();
+        let etag = blob.etag();
+        let (status, _, body) = send(&blob, &[(header::IF_RANGE, &etag), (header::RANGE, "bytes=0-9")]).await;
+        assert_eq!(status, StatusCode::PARTIAL_CONTENT);
+        assert_eq!(body.len(), 10);
+        // Other content than the client has: all of it
+        let (status, _, body) = send(&blob, &[(header::IF_RANGE, "\"other\""), (header::RANGE, "bytes=0-9")]).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body.len(), 100);
+    }
+
+    #[tokio::test]
+    async fn missing_blob() {
+        let res = send_blob(uuid::Uuid::new_v4(), "text/plain", &HeaderMap::new()).await;
+        assert_eq!(res.err(), Some(StatusCode::NOT_FOUND));
+    }
+}
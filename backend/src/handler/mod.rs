@@ -1,2 +1,10 @@
 pub mod user;
-pub mod cell;
\ No newline at end of file
+pub mod cell;
+pub mod file;
+pub mod attachment;
+pub mod export;
+pub mod dav;
+pub mod archive;
+pub mod expand;
+pub mod dag;
+pub mod revision;
\ No newline at end of file
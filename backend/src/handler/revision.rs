@@ -0,0 +1,422 @@
+use std::collections::{BTreeSet, HashSet};
+
+use log::error;
+use crate::model::*;
+use crate::diff::diff_files;
+use crate::handler::attachment::{insert_dirs, insert_fileprops, take_dirs, take_fileprops};
+use crate::handler::file::{add_refs, release_refs, remove_blobs, RefError};
+use axum::debug_handler;
+use axum::{
+    extract::{Path, Query, State},
+    response::Json,
+    http::StatusCode,
+};
+
+use sqlx::{PgConnection, Postgres};
+use sqlx::pool::Pool;
+
+// Every change to a cell is kept in the revisions table as the whole cell
+// after it: text, flag, files and parents. A cell that was never changed has
+// no rows there and is its own revision 1; its first change also records the
+// cell as created. Revisions hold references to the blobs of their files, so
+// that reverting can bring back files no cell has any more.
+
+fn internal_error<E: std::fmt::Display>(e: E) -> StatusCode {
+    error!("{}", e);
+    StatusCode::INTERNAL_SERVER_ERROR
+}
+
+// Every revision of a cell, oldest first
+#[debug_handler]
+pub async fn list_revisions(
+    Path(cell_id): Path<uuid::Uuid>,
+    State(pool): State<Pool<Postgres>>,
+) -> Result<Json<Vec<Revision>>, StatusCode> {
+    let mut conn = pool.acquire().await.map_err(internal_error)?;
+    load_revisions(&mut conn, cell_id).await.map(Json)
+}
+
+#[debug_handler]
+pub async fn show_revision(
+    Path((cell_id, revision)): Path<(uuid::Uuid, i32)>,
+    State(pool): State<Pool<Postgres>>,
+) -> Result<Json<Revision>, StatusCode> {
+    let mut conn = pool.acquire().await.map_err(internal_error)?;
+    find_revision(&mut conn, cell_id, revision).await.map(Json)
+}
+
+// What changed between ?from= and ?to=, by default the latest change
+#[debug_handler]
+pub async fn diff_revisions(
+    Path(cell_id): Path<uuid::Uuid>,
+    Query(query): Query<DiffQuery>,
+    State(pool): State<Pool<Postgres>>,
+) -> Result<Json<RevisionDiff>, StatusCode> {
+    let mut conn = pool.acquire().await.map_err(internal_error)?;
+    let revisions = load_revisions(&mut conn, cell_id).await?;
+    let latest = revisions.last().map_or(1, |r| r.revision);
+    let to = query.to.unwrap_or(latest);
+    let from = query.from.unwrap_or((to - 1).max(1));
+    let find = |revision: i32| revisions.iter().find(|r| r.revision == revision).ok_or(StatusCode::NOT_FOUND);
+    Ok(Json(diff_revision(find(from)?, find(to)?)))
+}
+
+// Bring a cell back to an earlier revision. This is a change like any other
+// and gets a revision of its own; history is never rewritten.
+#[debug_handler]
+pub async fn revert_cell(
+    Path((cell_id, revision)): Path<(uuid::Uuid, i32)>,
+    Query(query): Query<RevertQuery>,
+    State(pool): State<Pool<Postgres>>,
+) -> Result<Json<Revision>, StatusCode> {
+    let mut conn = pool.acquire().await.map_err(internal_error)?;
+    let target = find_revision(&mut conn, cell_id, revision).await?;
+    drop(conn);
+    change_cell(cell_id, query.device_id.as_deref(), &pool, |cell| {
+        cell.text = target.text;
+        cell.is_open = target.is_open;
+        cell.fileprops = target.fileprops;
+        cell.dirs = target.dirs;
+        cell.parent_ids = target.parent_ids;
+        Ok(())
+    }).await.map(Json)
+}
+
+pub fn diff_revision(from: &Revision, to: &Revision) -> RevisionDiff {
+    RevisionDiff {
+        cell_id: to.cell_id,
+        from: from.revision,
+        to: to.revision,
+        text: (from.text != to.text).then(|| Change { old: from.text.clone(), new: to.text.clone() }),
+        is_open: (from.is_open != to.is_open).then_some(Change { old: from.is_open, new: to.is_open }),
+        files: diff_files(&from.fileprops, &from.dirs, &to.fileprops, &to.dirs),
+        parents_added: to.parent_ids.iter().filter(|id| !from.parent_ids.contains(id)).cloned().collect(),
+        parents_removed: from.parent_ids.iter().filter(|id| !to.parent_ids.contains(id)).cloned().collect(),
+    }
+}
+
+// Whether two sets of files and empty directories are the same, comparing
+// every field of the files and not only their content
+pub fn same_files(
+    old_fileprops: &[FileProp],
+    old_dirs: &[String],
+    new_fileprops: &[FileProp],
+    new_dirs: &[String],
+) -> bool {
+    fn by_path(fileprops: &[FileProp]) -> Vec<&FileProp> {
+        let mut fileprops: Vec<&FileProp> = fileprops.iter().collect();
+        fileprops.sort_by(|a, b| a.path.cmp(&b.path));
+        fileprops
+    }
+    fn sorted(dirs: &[String]) -> BTreeSet<&String> {
+        dirs.iter().collect()
+    }
+    by_path(old_fileprops) == by_path(new_fileprops) && sorted(old_dirs) == sorted(new_dirs)
+}
+
+// Apply `change` to the cell with its row locked and record the result as a
+// new revision, unless nothing changed; either way the latest revision is
+// returned. Files must come normalized; new parents are checked here.
+pub async fn change_cell(
+    cell_id: uuid::Uuid,
+    device_id: Option<&str>,
+    pool: &Pool<Postgres>,
+    change: impl FnOnce(&mut Revision) -> Result<(), StatusCode>,
+) -> Result<Revision, StatusCode> {
+    let mut tx = pool.begin().await.map_err(internal_error)?;
+    let locked: Option<(uuid::Uuid,)> = sqlx::query_as("SELECT cell_id FROM cells WHERE cell_id=$1 FOR UPDATE")
+        .bind(cell_id)
+        .fetch_optional(&mut *tx)
+        .await
+        .map_err(internal_error)?;
+    locked.ok_or(StatusCode::NOT_FOUND)?;
+    let current = cell_state(&mut tx, cell_id).await.map_err(internal_error)?;
+    let mut next = current.clone();
+    change(&mut next)?;
+    next.parent_ids.sort();
+    next.parent_ids.dedup();
+    let diff = diff_revision(&current, &next);
+    let parents_changed = !diff.parents_added.is_empty() || !diff.parents_removed.is_empty();
+    // The diff leaves out what only describes a file, such as its size
+    let files_changed = !same_files(&current.fileprops, &current.dirs, &next.fileprops, &next.dirs);
+    if diff.text.is_none() && diff.is_open.is_none() && !files_changed && !parents_changed {
+        let revisions = load_revisions(&mut tx, cell_id).await?;
+        return revisions.into_iter().last().ok_or(StatusCode::NOT_FOUND)
+    }
+    if parents_changed {
+        check_parents(&mut tx, cell_id, &next.parent_ids).await?;
+    }
+
+    keep_original(&mut tx, cell_id).await.map_err(internal_error)?;
+    sqlx::query("UPDATE cells SET text=$2, is_open=$3 WHERE cell_id=$1")
+        .bind(cell_id)
+        .bind(&next.text)
+        .bind(next.is_open)
+        .execute(&mut *tx)
+        .await
+        .map_err(internal_error)?;
+    let mut unused = vec![];
+    if files_changed {
+        let old_fileprops = take_fileprops(&mut tx, cell_id).await.map_err(internal_error)?;
+        take_dirs(&mut tx, cell_id).await.map_err(internal_error)?;
+        add_refs(&mut tx, &next.fileprops).await?;
+        unused = release_refs(&mut tx, &old_fileprops).await.map_err(internal_error)?;
+        insert_fileprops(&mut tx, cell_id, &next.fileprops).await.map_err(internal_error)?;
+        insert_dirs(&mut tx, cell_id, &next.dirs).await.map_err(internal_error)?;
+    }
+    if parents_changed {
+        sqlx::query("DELETE FROM family_tree WHERE child_id=$1")
+            .bind(cell_id)
+            .execute(&mut *tx)
+            .await
+            .map_err(internal_error)?;
+        sqlx::query("INSERT INTO family_tree (child_id, parent_id) SELECT $1, * FROM UNNEST($2::uuid[])")
+            .bind(cell_id)
+            .bind(&next.parent_ids)
+            .execute(&mut *tx)
+            .await
+            .map_err(internal_error)?;
+    }
+    let revision = record_revision(&mut tx, cell_id, device_id).await.map_err(internal_error)?;
+    tx.commit().await.map_err(internal_error)?;
+    remove_blobs(&unused).await;
+    Ok(revision)
+}
+
+// Parents must exist and must be neither the cell nor one of its replies,
+// which would close a cycle
+async fn check_parents(
+    conn: &mut PgConnection,
+    cell_id: uuid::Uuid,
+    parent_ids: &[uuid::Uuid],
+) -> Result<(), StatusCode> {
+    let existing: Vec<(uuid::Uuid,)> = sqlx::query_as("SELECT cell_id FROM cells WHERE cell_id=ANY($1)")
+        .bind(parent_ids)
+        .fetch_all(&mut *conn)
+        .await
+        .map_err(internal_error)?;
+    let replies = replies_among(conn, cell_id, parent_ids).await.map_err(internal_error)?;
+    let existing: HashSet<uuid::Uuid> = existing.into_iter().map(|(id,)| id).collect();
+    check_parent_ids(cell_id, parent_ids, &existing, &replies)
+}
+
+// Those of `cell_ids` that reply to `cell_id`, directly or not. A cell
+// among its own replies is on a cycle.
+pub async fn replies_among(
+    conn: &mut PgConnection,
+    cell_id: uuid::Uuid,
+    cell_ids: &[uuid::Uuid],
+) -> Result<HashSet<uuid::Uuid>, sqlx::Error> {
+    let replies: Vec<(uuid::Uuid,)> = sqlx::query_as(
+        "WITH RECURSIVE down(cell_id) AS (
+            SELECT child_id FROM family_tree WHERE parent_id=$1
+            UNION
+            SELECT f.child_id FROM family_tree f JOIN down ON f.parent_id=down.cell_id
+        )
+        SELECT cell_id FROM down WHERE cell_id=ANY($2)"
+    )
+        .bind(cell_id)
+        .bind(cell_ids)
+        .fetch_all(&mut *conn)
+        .await?;
+    Ok(replies.into_iter().map(|(id,)| id).collect())
+}
+
+// The checks of `check_parents`, given which of the parents exist and which
+// of them reply to the cell, directly or not
+fn check_parent_ids(
+    cell_id: uuid::Uuid,
+    parent_ids: &[uuid::Uuid],
+    existing: &HashSet<uuid::Uuid>,
+    replies: &HashSet<uuid::Uuid>,
+) -> Result<(), StatusCode> {
+    if parent_ids.contains(&cell_id) {
+        error!("cell {} cannot be its own parent", cell_id);
+        return Err(StatusCode::BAD_REQUEST)
+    }
+    if let Some(missing) = parent_ids.iter().find(|id| !existing.contains(id)) {
+        error!("parent {} of cell {} does not exist", missing, cell_id);
+        return Err(StatusCode::BAD_REQUEST)
+    }
+    if let Some(reply_id) = parent_ids.iter().find(|id| replies.contains(id)) {
+        error!("cell {} replies to cell {} and cannot be its parent", reply_id, cell_id);
+        return Err(StatusCode::BAD_REQUEST)
+    }
+    Ok(())
+}
+
+// The recorded revisions of a cell, or the cell itself as revision 1
+pub async fn load_revisions(conn: &mut PgConnection, cell_id: uuid::Uuid) -> Result<Vec<Revision>, StatusCode> {
+    let revisions: Vec<Revision> = sqlx::query_as("SELECT * FROM revisions WHERE cell_id=$1 ORDER BY revision")
+        .bind(cell_id)
+        .fetch_all(&mut *conn)
+        .await
+        .map_err(internal_error)?;
+    if !revisions.is_empty() {
+        return Ok(revisions)
+    }
+    let exists: Option<(uuid::Uuid,)> = sqlx::query_as("SELECT cell_id FROM cells WHERE cell_id=$1")
+        .bind(cell_id)
+        .fetch_optional(&mut *conn)
+        .await
+        .map_err(internal_error)?;
+    exists.ok_or(StatusCode::NOT_FOUND)?;
+    Ok(vec![cell_state(conn, cell_id).await.map_err(internal_error)?])
+}
+
+async fn find_revision(conn: &mut PgConnection, cell_id: uuid::Uuid, revision: i32) -> Result<Revision, StatusCode> {
+    load_revisions(conn, cell_id).await?
+        .into_iter()
+        .find(|r| r.revision == revision)
+        .ok_or(StatusCode::NOT_FOUND)
+}
+
+// The cell as stored now, numbered as its latest revision. Until it has
+// any, it is revision 1 from the device that created it.
+async fn cell_state(conn: &mut PgConnection, cell_id: uuid::Uuid) -> Result<Revision, sqlx::Error> {
+    let (device_id, text, is_open, created_at): (String, String, bool, chrono::NaiveDateTime) = sqlx::query_as(
+        "SELECT device_id, text, is_open, created_at FROM cells WHERE cell_id=$1"
+    )
+        .bind(cell_id)
+        .fetch_one(&mut *conn)
+        .await?;
+    let rows: Vec<FileRow> = sqlx::query_as("SELECT * FROM files WHERE cell_id=$1 ORDER BY path")
+        .bind(cell_id)
+        .fetch_all(&mut *conn)
+        .await?;
+    let dirs: Vec<(String,)> = sqlx::query_as("SELECT path FROM dirs WHERE cell_id=$1 ORDER BY path")
+        .bind(cell_id)
+        .fetch_all(&mut *conn)
+        .await?;
+    let parent_ids: Vec<(uuid::Uuid,)> = sqlx::query_as(
+        "SELECT DISTINCT parent_id FROM family_tree WHERE child_id=$1 ORDER BY parent_id"
+    )
+        .bind(cell_id)
+        .fetch_all(&mut *conn)
+        .await?;
+    let (latest,): (Option<i32>,) = sqlx::query_as("SELECT MAX(revision) FROM revisions WHERE cell_id=$1")
+        .bind(cell_id)
+        .fetch_one(&mut *conn)
+        .await?;
+    Ok(Revision {
+        cell_id,
+        revision: latest.unwrap_or(1),
+        device_id: Some(device_id),
+        text,
+        is_open,
+        fileprops: rows.into_iter().map(FileProp::from).collect(),
+        dirs: dirs.into_iter().map(|(path,)| path).collect(),
+        parent_ids: parent_ids.into_iter().map(|(id,)| id).collect(),
+        created_at,
+    })
+}
+
+// Record the cell as created, unless it has revisions already. Called
+// before a cell is first changed.
+pub async fn keep_original(conn: &mut PgConnection, cell_id: uuid::Uuid) -> Result<(), RefError> {
+    let (count,): (i64,) = sqlx::query_as("SELECT COUNT(*) FROM revisions WHERE cell_id=$1")
+        .bind(cell_id)
+        .fetch_one(&mut *conn)
+        .await?;
+    if count > 0 {
+        return Ok(())
+    }
+    let state = cell_state(conn, cell_id).await?;
+    insert_revision(conn, &state, Some(state.created_at)).await?;
+    Ok(())
+}
+
+// Record the cell as it is now, changed from `device_id`
+pub async fn record_revision(
+    conn: &mut PgConnection,
+    cell_id: uuid::Uuid,
+    device_id: Option<&str>,
+) -> Result<Revision, RefError> {
+    let mut state = cell_state(conn, cell_id).await?;
+    state.revision += 1;
+    state.device_id = device_id.map(|d| d.to_string());
+    insert_revision(conn, &state, None).await
+}
+
+pub async fn insert_revision(
+    conn: &mut PgConnection,
+    revision: &Revision,
+    created_at: Option<chrono::NaiveDateTime>,
+) -> Result<Revision, RefError> {
+    add_refs(conn, &revision.fileprops).await?;
+    sqlx::query_as(
+        "INSERT INTO revisions (cell_id, revision, device_id, text, is_open, fileprops, dirs, parent_ids, created_at)
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, COALESCE($9, CURRENT_TIMESTAMP)) RETURNING *"
+    )
+        .bind(revision.cell_id)
+        .bind(revision.revision)
+        .bind(&revision.device_id)
+        .bind(&revision.text)
+        .bind(revision.is_open)
+        .bind(sqlx::types::Json(&revision.fileprops))
+        .bind(&revision.dirs)
+        .bind(&revision.parent_ids)
+        .bind(created_at)
+        .fetch_one(conn)
+        .await
+        .map_err(RefError::Db)
+}
+
+// Remove the revisions of a cell about to be deleted, returning the files
+// they referred to so that their blobs can be released
+pub async fn take_revisions(conn: &mut PgConnection, cell_id: uuid::Uuid) -> Result<Vec<FileProp>, sqlx::Error> {
+    let rows: Vec<(sqlx::types::Json<Vec<FileProp>>,)> = sqlx::query_as(
+        "DELETE FROM revisions WHERE cell_id=$1 RETURNING fileprops"
+    )
+        .bind(cell_id)
+        .fetch_all(conn)
+        .await?;
+    Ok(rows.into_iter().flat_map(|(fileprops,)| fileprops.0).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ids(n: usize) -> Vec<uuid::Uuid> {
+        (0..n).map(|_| uuid::Uuid::new_v4()).collect()
+    }
+
+    fn set(ids: &[uuid::Uuid]) -> HashSet<uuid::Uuid> {
+        ids.iter().copied().collect()
+    }
+
+    #[test]
+    fn own_parent() {
+        let id = ids(2);
+        let res = check_parent_ids(id[0], &[id[1], id[0]], &set(&id), &HashSet::new());
+        assert_eq!(res, Err(StatusCode::BAD_REQUEST));
+    }
+
+    #[test]
+    fn reply_as_parent() {
+        // a <- b: making b the parent of a closes a cycle of two
+        let id = ids(2);
+        let (a, b) = (id[0], id[1]);
+        let res = check_parent_ids(a, &[b], &set(&[b]), &set(&[b]));
+        assert_eq!(res, Err(StatusCode::BAD_REQUEST));
+        // a <- b <- c: so does c, further down
+        let c = uuid::Uuid::new_v4();
+        let res = check_parent_ids(a, &[c], &set(&[c]), &set(&[c]));
+        assert_eq!(res, Err(StatusCode::BAD_REQUEST));
+    }
+
+    #[test]
+    fn missing_parent() {
+        let id = ids(3);
+        let res = check_parent_ids(id[0], &[id[1], id[2]], &set(&[id[1]]), &HashSet::new());
+        assert_eq!(res, Err(StatusCode::BAD_REQUEST));
+    }
+
+    #[test]
+    fn other_parents() {
+        let id = ids(3);
+        assert_eq!(check_parent_ids(id[0], &[id[1], id[2]], &set(&id[1..]), &HashSet::new()), Ok(()));
+        assert_eq!(check_parent_ids(id[0], &[], &HashSet::new(), &HashSet::new()), Ok(()));
+    }
+}
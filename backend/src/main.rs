@@ -1,19 +1,29 @@
 use std::env;
-use env_logger;
+use std::time::Duration;
 
 mod utils;
 mod model;
 mod handler;
 
+use flowfs_common::{diff, patch, tree, validate};
+
 use handler::{
     // user::{list_users, create_user, show_user, update_user, delete_user},
     user::{list_users, create_user, show_user, delete_user},
     // cell::{create_cell, show_cell, update_cell, delete_cell},
-    cell::{list_cells, create_cell, show_cell, delete_cell},
+    cell::{list_cells, create_cell, create_cells, show_cell, update_cell, delete_cell, fork_cell},
+    file::{upload_file, find_file, download_file, collect_blobs},
+    attachment::{search_files, show_file, patch_files, diff_cells},
+    archive::{cell_archive, make_archive},
+    dag::{list_ancestors, list_descendants, list_roots, list_leaves, shortest_path, common_ancestors},
+    revision::{list_revisions, show_revision, diff_revisions, revert_cell},
+    expand::expand_archive,
+    export::{export_user, import_user},
+    dav::dav,
 };
 
 use axum::{
-    routing::get,
+    routing::{any, get, patch, post},
     Router,
 };
 
@@ -27,22 +37,61 @@ async fn main() {
 
     // Set web URL
     let listen_url = utils::get_url();
-    let addr = env::args().nth(1).unwrap_or_else(|| listen_url);
+    let addr = env::args().nth(1).unwrap_or(listen_url);
 
     // Setup PostgreSQL client
     let db_url = utils::get_db_path();
     let pool = PgPoolOptions::new().max_connections(16).connect(&db_url).await.unwrap();
-    let _r = init_db(&pool).await.unwrap();
+    init_db(&pool).await.unwrap();
+
+    // Uploads no cell came to use are removed some time after the grace
+    // period, as are blobs released while a client could still reuse them
+    let gc_pool = pool.clone();
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(utils::get_blob_grace().max(Duration::from_secs(60)));
+        loop {
+            interval.tick().await;
+            if let Err(e) = collect_blobs(&gc_pool).await {
+                log::error!("{}", e);
+            }
+        }
+    });
 
     // build our application with a single route
     let app = Router::new()
         .route("/", get(|| async { "Hello, World!" }))
         .route("/users", get(list_users).post(create_user))
         .route("/users/:user_id", get(show_user).delete(delete_user))
+        .route("/users/:user_id/export", get(export_user))
+        .route("/users/:user_id/import", post(import_user))
         // .route("/users/:user_id", get(show_user).put(update_user).delete(delete_user))
         .route("/cells", get(list_cells).post(create_cell))
-        .route("/cells/:cell_id", get(show_cell).delete(delete_cell))
+        .route("/cells/batch", post(create_cells))
+        .route("/cells/roots", get(list_roots))
+        .route("/cells/leaves", get(list_leaves))
+        .route("/cells/:cell_id/ancestors", get(list_ancestors))
+        .route("/cells/:cell_id/descendants", get(list_descendants))
+        .route("/cells/:cell_id/path/:other_id", get(shortest_path))
+        .route("/cells/:cell_id/common/:other_id", get(common_ancestors))
+        .route("/cells/:cell_id/diff/:other_id", get(diff_cells))
+        .route("/cells/:cell_id/fork", post(fork_cell))
+        .route("/cells/:cell_id", get(show_cell).put(update_cell).delete(delete_cell))
+        .route("/cells/:cell_id/revisions", get(list_revisions))
+        .route("/cells/:cell_id/revisions/diff", get(diff_revisions))
+        .route("/cells/:cell_id/revisions/:revision", get(show_revision))
+        .route("/cells/:cell_id/revisions/:revision/revert", post(revert_cell))
+        .route("/cells/:cell_id/files", patch(patch_files))
+        .route("/cells/:cell_id/files/*path", get(show_file))
+        .route("/cells/:cell_id/archive", get(cell_archive))
+        .route("/archive", post(make_archive))
         // .route("/users/:user_id/cells/:cell_id", get(show_cell).put(update_cell).delete(delete_cell))
+        .route("/files", get(search_files).post(upload_file))
+        .route("/files/expand", post(expand_archive))
+        .route("/files/:file_id", get(download_file))
+        .route("/files/sha256/:sha256", get(find_file))
+        .route("/dav/:user_id", any(dav))
+        .route("/dav/:user_id/", any(dav))
+        .route("/dav/:user_id/*path", any(dav))
         .with_state(pool);
 
     // run our app with hyper, listening globally on port 3000
@@ -73,7 +122,6 @@ async fn init_db(db: &sqlx::pool::Pool<sqlx::Postgres>) -> Result<(), sqlx::Erro
             , user_id       UUID NOT NULL REFERENCES users(user_id)
             , device_id     TEXT NOT NULL
             , text          TEXT NOT NULL
-            , fileprops     jsonb NOT NULL
             , is_open       BOOLEAN NOT NULL
             , created_at    TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
         )"
@@ -83,6 +131,98 @@ async fn init_db(db: &sqlx::pool::Pool<sqlx::Postgres>) -> Result<(), sqlx::Erro
 
     println!("{:?}", _r);
 
+    let _r = sqlx::query(
+        "CREATE TABLE IF NOT EXISTS files (
+            cell_id         UUID NOT NULL REFERENCES cells(cell_id) ON DELETE CASCADE
+            , path          TEXT NOT NULL
+            , name          TEXT NOT NULL
+            , url           TEXT NOT NULL
+            , completed     BOOLEAN NOT NULL
+            , size          BIGINT
+            , sha256        TEXT
+            , mime          TEXT
+            , mtime         TIMESTAMP
+            , PRIMARY KEY (cell_id, path)
+        )"
+    )
+    .execute(db)
+    .await?;
+
+    println!("{:?}", _r);
+
+    for index in [
+        "CREATE INDEX IF NOT EXISTS files_name_idx ON files (lower(name))",
+        "CREATE INDEX IF NOT EXISTS files_sha256_idx ON files (sha256)",
+        "CREATE INDEX IF NOT EXISTS files_mime_idx ON files (mime)",
+    ] {
+        sqlx::query(index).execute(db).await?;
+    }
+
+    // Directories holding no file, which the files table cannot show
+    let _r = sqlx::query(
+        "CREATE TABLE IF NOT EXISTS dirs (
+            cell_id         UUID NOT NULL REFERENCES cells(cell_id) ON DELETE CASCADE
+            , path          TEXT NOT NULL
+            , PRIMARY KEY (cell_id, path)
+        )"
+    )
+    .execute(db)
+    .await?;
+
+    println!("{:?}", _r);
+
+    // Cells created before the files table kept their files in a jsonb column
+    let legacy: Option<(String,)> = sqlx::query_as(
+        "SELECT column_name::text FROM information_schema.columns
+        WHERE table_name='cells' AND column_name='fileprops'"
+    )
+    .fetch_optional(db)
+    .await?;
+    if legacy.is_some() {
+        let mut tx = db.begin().await?;
+        let _r = sqlx::query(
+            "INSERT INTO files (cell_id, path, name, url, completed, size, sha256, mime, mtime)
+            SELECT cells.cell_id, f.path, regexp_replace(f.path, '^.*/', ''), f.url, f.completed,
+                f.size, f.sha256, f.mime, f.mtime
+            FROM cells CROSS JOIN LATERAL jsonb_to_recordset(cells.fileprops) AS f(
+                path TEXT, url TEXT, completed BOOLEAN, size BIGINT, sha256 TEXT, mime TEXT, mtime TIMESTAMP
+            )
+            ON CONFLICT DO NOTHING"
+        )
+        .execute(&mut *tx)
+        .await?;
+        println!("{:?}", _r);
+        sqlx::query("ALTER TABLE cells DROP COLUMN fileprops").execute(&mut *tx).await?;
+        tx.commit().await?;
+    }
+
+    // One row per unique file content; refcount is the number of FileProps
+    // pointing at it
+    let _r = sqlx::query(
+        "CREATE TABLE IF NOT EXISTS blobs (
+            file_id         UUID NOT NULL PRIMARY KEY
+            , sha256        TEXT NOT NULL UNIQUE
+            , size          BIGINT NOT NULL
+            , refcount      BIGINT NOT NULL DEFAULT 0
+            , created_at    TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+        )"
+    )
+    .execute(db)
+    .await?;
+
+    println!("{:?}", _r);
+
+    // Last time a client was handed the blob, by an upload or a lookup by
+    // hash. Blobs nothing refers to are kept for a while after it, so that
+    // the cell the client is about to make can still use them.
+    let _r = sqlx::query(
+        "ALTER TABLE blobs ADD COLUMN IF NOT EXISTS touched_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP"
+    )
+    .execute(db)
+    .await?;
+
+    println!("{:?}", _r);
+
     let _r = sqlx::query(
         "CREATE TABLE IF NOT EXISTS family_tree (
             child_id        UUID NOT NULL REFERENCES cells(cell_id)
@@ -94,6 +234,27 @@ async fn init_db(db: &sqlx::pool::Pool<sqlx::Postgres>) -> Result<(), sqlx::Erro
 
     println!("{:?}", _r);
 
+    // The whole cell after each change to it. Its files hold references to
+    // their blobs like those of a cell.
+    let _r = sqlx::query(
+        "CREATE TABLE IF NOT EXISTS revisions (
+            cell_id         UUID NOT NULL REFERENCES cells(cell_id) ON DELETE CASCADE
+            , revision      INTEGER NOT NULL
+            , device_id     TEXT
+            , text          TEXT NOT NULL
+            , is_open       BOOLEAN NOT NULL
+            , fileprops     JSONB NOT NULL
+            , dirs          TEXT[] NOT NULL
+            , parent_ids    UUID[] NOT NULL
+            , created_at    TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+            , PRIMARY KEY (cell_id, revision)
+        )"
+    )
+    .execute(db)
+    .await?;
+
+    println!("{:?}", _r);
+
     Ok(())
 }
 
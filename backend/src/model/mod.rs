@@ -1,6 +1,8 @@
 use serde::{Deserialize, Serialize};
 use sqlx::FromRow;
 
+pub use flowfs_common::model::{Change, Dir, FileOp, FileProp, FilesDiff};
+
 #[derive(FromRow, Serialize, Deserialize, Debug)]
 pub struct IdRes { pub id: uuid::Uuid }
 
@@ -33,11 +35,59 @@ pub struct CellReq {
     pub device_id:      String,
     pub text:           String,
     pub is_open:        bool,
+    // Attachments come either flat, as files plus empty directories, or as
+    // the nested rootdir the desktop app uses
+    #[serde(default)]
     pub fileprops:      Vec<FileProp>,
+    #[serde(default)]
+    pub dirs:           Vec<String>,
+    #[serde(default)]
+    pub rootdir:        Option<Dir>,
     pub parent_ids:     Vec<uuid::Uuid>,
     pub child_ids:      Vec<uuid::Uuid>,
 }
 
+// Cells to create at once with POST /cells/batch
+#[derive(Deserialize, Debug)]
+pub struct CellBatch {
+    pub cells:          Vec<CellReq>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum BatchStatus {
+    Created,
+    // Stored by an earlier try of the same request
+    Existing,
+    Invalid,
+    Conflict,
+    Failed,
+    // Not created because another cell of the batch failed
+    Skipped,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct BatchItem {
+    pub cell_id:        uuid::Uuid,
+    pub status:         BatchStatus,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error:          Option<String>,
+}
+
+impl BatchItem {
+    pub fn fail(&mut self, status: BatchStatus, error: String) {
+        self.status = status;
+        self.error = Some(error);
+    }
+}
+
+// Whether the batch was stored, and what became of each cell in order
+#[derive(Serialize, Deserialize, Debug)]
+pub struct BatchRes {
+    pub committed:      bool,
+    pub results:        Vec<BatchItem>,
+}
+
 #[derive(FromRow, Serialize, Deserialize, Debug)]
 pub struct CellExtracted {
     pub cell_id:        uuid::Uuid,
@@ -46,6 +96,8 @@ pub struct CellExtracted {
     pub text:           String,
     pub is_open:        bool,
     pub fileprops:      Vec<FileProp>,
+    pub dirs:           Vec<String>,
+    pub created_at:     chrono::NaiveDateTime,
     pub parents:        Vec<CellExtracted>,
     pub children:       Vec<CellExtracted>,
 }
@@ -62,7 +114,9 @@ impl CellExtracted {
             device_id: cell_row.device_id,
             text: cell_row.text,
             is_open: cell_row.is_open,
-            fileprops: cell_row.fileprops.0,
+            fileprops: cell_row.fileprops,
+            dirs: cell_row.dirs,
+            created_at: cell_row.created_at,
             parents: parent_cells,
             children: child_cells,
         }
@@ -76,18 +130,289 @@ pub struct CellRow {
     pub device_id:      String,
     pub text:           String,
     pub is_open:        bool,
-    pub fileprops:      sqlx::types::Json<Vec<FileProp>>,
+    // Rows of the files table, filled in after the cell is read
+    #[sqlx(skip)]
+    pub fileprops:      Vec<FileProp>,
+    // Paths of directories without files, from the dirs table
+    #[sqlx(skip)]
+    #[serde(default)]
+    pub dirs:           Vec<String>,
+    pub created_at:     chrono::NaiveDateTime,
 }
 #[derive(FromRow, Serialize, Deserialize, Debug)]
-pub struct CellFilter {
+pub struct Edge {
+    pub child_id:       uuid::Uuid,
+    pub parent_id:      uuid::Uuid,
+}
+
+// ?depth= limits a walk of the family tree to that many edges; ?shallow=true
+// leaves out everything of a cell but its id and text
+#[derive(Deserialize, Debug)]
+pub struct DagQuery {
+    pub depth:          Option<i32>,
+    #[serde(default)]
+    pub shallow:        bool,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct DagFilter {
     pub user_id:        uuid::Uuid,
+    #[serde(default)]
+    pub shallow:        bool,
+    pub limit:          Option<i64>,
+}
+
+// A cell reached in the family tree, `depth` edges away from where the
+// walk started
+#[derive(Serialize, Deserialize, Debug)]
+pub struct DagCell {
+    pub cell_id:        uuid::Uuid,
+    pub text:           String,
+    pub depth:          i32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cell:           Option<CellExtracted>,
+}
+
+// Cells of the family tree with the edges between them
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Dag {
+    pub cells:          Vec<DagCell>,
+    pub edges:          Vec<Edge>,
 }
 
 #[derive(FromRow, Serialize, Deserialize, Debug)]
-pub struct FileProp {
+pub struct CellFilter {
+    pub user_id:        uuid::Uuid,
+    pub device_id:      Option<String>,
+    pub text:           Option<String>,
+    pub since:          Option<chrono::NaiveDateTime>,
+    pub until:          Option<chrono::NaiveDateTime>,
+    pub limit:          Option<i64>,
+}
+
+#[derive(FromRow, Debug)]
+pub struct FileRow {
+    pub cell_id:        uuid::Uuid,
     pub path:           String,
     pub url:            String,
     pub completed:      bool,
+    pub size:           Option<i64>,
+    pub sha256:         Option<String>,
+    pub mime:           Option<String>,
+    pub mtime:          Option<chrono::NaiveDateTime>,
+}
+
+impl From<FileRow> for FileProp {
+    fn from(row: FileRow) -> Self {
+        FileProp {
+            path: row.path,
+            url: row.url,
+            completed: row.completed,
+            size: row.size.map(|s| s as u64),
+            sha256: row.sha256,
+            mime: row.mime,
+            mtime: row.mtime,
+        }
+    }
+}
+
+#[derive(FromRow, Serialize, Deserialize, Debug)]
+pub struct FileFilter {
+    pub user_id:        uuid::Uuid,
+    pub name:           Option<String>,
+    pub glob:           Option<String>,
+    pub mime:           Option<String>,
+    pub sha256:         Option<String>,
+    pub since:          Option<chrono::NaiveDateTime>,
+    pub until:          Option<chrono::NaiveDateTime>,
+    pub limit:          Option<i64>,
+}
+
+#[derive(FromRow, Debug)]
+pub struct FileMatchRow {
+    #[sqlx(flatten)]
+    pub file:           FileRow,
+    pub created_at:     chrono::NaiveDateTime,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct FileMatch {
+    pub cell_id:        uuid::Uuid,
+    pub created_at:     chrono::NaiveDateTime,
+    #[serde(flatten)]
+    pub fileprop:       FileProp,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct FileMatches {
+    pub files:          Vec<FileMatch>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct FilePatch {
+    pub ops:            Vec<FileOp>,
+    // Recorded with the revision the patch makes
+    #[serde(default)]
+    pub device_id:      Option<String>,
+}
+
+// A cell's files and empty directories, as left by a patch or expanded
+// from an archive
+#[derive(Serialize, Deserialize, Debug)]
+pub struct CellFiles {
+    pub fileprops:      Vec<FileProp>,
+    pub dirs:           Vec<String>,
+}
+
+// A change to a cell; what is left out stays as it is. The files are
+// replaced as a whole when fileprops, dirs or rootdir is given.
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct CellUpdate {
+    pub device_id:      Option<String>,
+    pub text:           Option<String>,
+    pub is_open:        Option<bool>,
+    pub fileprops:      Option<Vec<FileProp>>,
+    pub dirs:           Option<Vec<String>>,
+    pub rootdir:        Option<Dir>,
+    pub parent_ids:     Option<Vec<uuid::Uuid>>,
+}
+
+// A cell as it was after one change, revision 1 being the cell as created.
+// `device_id` is the device that made the change, when it said.
+#[derive(FromRow, Serialize, Deserialize, Debug, Clone)]
+pub struct Revision {
+    pub cell_id:        uuid::Uuid,
+    pub revision:       i32,
+    pub device_id:      Option<String>,
+    pub text:           String,
+    pub is_open:        bool,
+    #[sqlx(json)]
+    pub fileprops:      Vec<FileProp>,
+    pub dirs:           Vec<String>,
+    pub parent_ids:     Vec<uuid::Uuid>,
+    pub created_at:     chrono::NaiveDateTime,
+}
+
+// ?from= and ?to= name two revisions; `to` defaults to the latest and `from`
+// to the one before it
+#[derive(Deserialize, Debug)]
+pub struct DiffQuery {
+    pub from:           Option<i32>,
+    pub to:             Option<i32>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct RevertQuery {
+    pub device_id:      Option<String>,
+}
+
+// What differs between two revisions of a cell; parts left as None or empty
+// did not change
+#[derive(Serialize, Deserialize, Debug)]
+pub struct RevisionDiff {
+    pub cell_id:        uuid::Uuid,
+    pub from:           i32,
+    pub to:             i32,
+    pub text:           Option<Change<String>>,
+    pub is_open:        Option<Change<bool>>,
+    pub files:          FilesDiff,
+    pub parents_added:  Vec<uuid::Uuid>,
+    pub parents_removed: Vec<uuid::Uuid>,
+}
+
+// A reply made from the files of the cell it replies to, changed by `ops`
+// as in a FilePatch. The other fields are those of a CellReq.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct CellFork {
+    pub cell_id:        uuid::Uuid,
+    pub user_id:        uuid::Uuid,
+    pub device_id:      String,
+    pub text:           String,
+    pub is_open:        bool,
+    #[serde(default)]
+    pub ops:            Vec<FileOp>,
+}
+
+// How the files of `other_id` differ from those of `cell_id`, such as what
+// a reply changed in its parent's files
+#[derive(Serialize, Deserialize, Debug)]
+pub struct CellsDiff {
+    pub cell_id:        uuid::Uuid,
+    pub other_id:       uuid::Uuid,
+    pub files:          FilesDiff,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+pub enum ArchiveFormat {
+    #[default]
+    #[serde(rename = "zip")]
+    Zip,
+    #[serde(rename = "tar.gz")]
+    TarGz,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ArchiveQuery {
+    #[serde(default)]
+    pub format:         ArchiveFormat,
+}
+
+// Files to pack for POST /archive, flat or nested as in CellReq
+#[derive(Deserialize, Debug)]
+pub struct ArchiveReq {
+    #[serde(default)]
+    pub fileprops:      Vec<FileProp>,
+    #[serde(default)]
+    pub dirs:           Vec<String>,
+    #[serde(default)]
+    pub rootdir:        Option<Dir>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct FileRes {
+    pub id:             uuid::Uuid,
+    pub size:           u64,
+    pub sha256:         String,
+}
+
+// Archive format version written by export; import accepts versions up to it
+pub const EXPORT_VERSION: u32 = 2;
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ExportManifest {
+    pub format:         String,
+    pub version:        u32,
+    pub exported_at:    chrono::NaiveDateTime,
+    pub user:           User,
+    pub cells:          Vec<CellRow>,
+    pub family_tree:    Vec<Edge>,
+    // Since version 2
+    #[serde(default)]
+    pub revisions:      Vec<Revision>,
+    pub files:          Vec<uuid::Uuid>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum OnConflict {
+    #[default]
+    Fail,
+    Skip,
+    Overwrite,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ImportOptions {
+    #[serde(default)]
+    pub on_conflict:    OnConflict,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct ImportRes {
+    pub created:        Vec<uuid::Uuid>,
+    pub skipped:        Vec<uuid::Uuid>,
+    pub overwritten:    Vec<uuid::Uuid>,
+    pub files:          usize,
 }
 
 /*
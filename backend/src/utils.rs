@@ -6,14 +6,17 @@ use serde::Deserialize;
 #[derive(Debug, Deserialize)]
 struct Config {
   web_url: Option<String>,
+  public_url: Option<String>,
   db_path: Option<String>,
+  blob_path: Option<String>,
+  blob_grace_secs: Option<u64>,
 }
 
 // Ref: https://cipepser.hatenablog.com/entry/rust-toml
 fn read_file(path: String) -> Result<String, String> {
   let mut file_content = String::new();
   let mut fr = fs::File::open(path)
-      .map(|f| BufReader::new(f))
+      .map(BufReader::new)
       .map_err(|e| e.to_string())?;
   fr.read_to_string(&mut file_content)
       .map_err(|e| e.to_string())?;
@@ -28,9 +31,9 @@ fn get_config() -> Config {
   };
   let config: Result<Config, toml::de::Error> = toml::from_str(&s);
   match config {
-    Ok(c) => return c,
+    Ok(c) => c,
     Err(e) => panic!("fail to parse toml: {}", e),
-  };
+  }
 }
 
 pub fn get_url() -> String {
@@ -39,8 +42,33 @@ pub fn get_url() -> String {
     .unwrap_or("127.0.0.1:8080".to_string())
 }
 
+// Where clients reach this server, such as "https://flowfs.example.org",
+// which may differ from where it listens when behind a proxy
+pub fn get_public_url() -> String {
+  get_config()
+    .public_url
+    .map(|url| url.trim_end_matches('/').to_string())
+    .unwrap_or(format!("http://{}", get_url()))
+}
+
 pub fn get_db_path() -> String {
   get_config()
     .db_path
     .unwrap_or("flowfs".to_string())
 }
+
+pub fn get_blob_path() -> String {
+  get_config()
+    .blob_path
+    .unwrap_or("./blobs".to_string())
+}
+
+// How long a blob nothing refers to is kept after it was last uploaded or
+// looked up
+pub fn get_blob_grace() -> std::time::Duration {
+  std::time::Duration::from_secs(
+    get_config()
+      .blob_grace_secs
+      .unwrap_or(3600)
+  )
+}
@@ -0,0 +1,205 @@
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+use anyhow::Context as _;
+use flowfs_client::ClientError;
+
+use crate::Context;
+use crate::model::*;
+use crate::handler::file::{expand_archive, upload_dir, upload_file};
+
+pub async fn post_cell(
+    ctx: &Context,
+    text: &str,
+    dir: Option<&Path>,
+    parent_ids: Vec<uuid::Uuid>,
+    is_open: bool,
+) -> anyhow::Result<uuid::Uuid> {
+    let text = read_text(text)?;
+    let (fileprops, dirs) = match dir {
+        Some(archive) if archive.is_file() => expand_archive(ctx, archive).await?,
+        Some(dir) => upload_dir(ctx, dir).await?,
+        None => (vec![], vec![]),
+    };
+    let req = CellReq {
+        cell_id: uuid::Uuid::new_v4(),
+        user_id: ctx.user_id,
+        device_id: ctx.device_id.clone(),
+        text,
+        is_open,
+        fileprops,
+        dirs,
+        rootdir: None,
+        parent_ids,
+        child_ids: vec![],
+    };
+    Ok(ctx.client.create_cell(&req).await?.id)
+}
+
+// Reply to a cell with its files, uploading only what `adds` and `replaces`
+// bring in. Each of them is a path in the cell with the local file to put
+// there.
+pub async fn fork_cell(
+    ctx: &Context,
+    parent_id: uuid::Uuid,
+    text: &str,
+    adds: Vec<(String, PathBuf)>,
+    replaces: Vec<(String, PathBuf)>,
+    removes: Vec<String>,
+    is_open: bool,
+) -> anyhow::Result<uuid::Uuid> {
+    let text = read_text(text)?;
+    let mut ops = vec![];
+    for path in removes.into_iter() {
+        ops.push(FileOp::Remove { path });
+    }
+    for (path, local_path) in replaces.into_iter() {
+        ops.push(FileOp::Replace(upload_file(ctx, &local_path, path).await?));
+    }
+    for (path, local_path) in adds.into_iter() {
+        ops.push(FileOp::Add(upload_file(ctx, &local_path, path).await?));
+    }
+    let fork = CellFork {
+        cell_id: uuid::Uuid::new_v4(),
+        user_id: ctx.user_id,
+        device_id: ctx.device_id.clone(),
+        text,
+        is_open,
+        ops,
+    };
+    match ctx.client.fork_cell(parent_id, &fork).await {
+        Err(ClientError::NotFound) => anyhow::bail!("cell {} or a path to change not found", parent_id),
+        res => Ok(res?.id),
+    }
+}
+
+// "-" stands for the text on stdin
+fn read_text(text: &str) -> anyhow::Result<String> {
+    if text == "-" {
+        let mut buf = String::new();
+        std::io::stdin().read_to_string(&mut buf).context("fail to read text from stdin")?;
+        Ok(buf.trim_end().to_string())
+    } else {
+        Ok(text.to_string())
+    }
+}
+
+pub fn parse_put(s: &str) -> Result<(String, PathBuf), String> {
+    match s.split_once('=') {
+        Some((path, local_path)) if !path.is_empty() && !local_path.is_empty() => {
+            Ok((path.to_string(), PathBuf::from(local_path)))
+        }
+        _ => Err(format!("invalid file '{}', expected PATH=LOCAL_FILE", s)),
+    }
+}
+
+pub async fn list_cells(
+    ctx: &Context,
+    filter: CellFilter,
+    json: bool,
+) -> anyhow::Result<()> {
+    let res = ctx.client.list_cells(&filter).await?;
+    if json {
+        println!("{}", serde_json::to_string_pretty(&res)?);
+        return Ok(())
+    }
+    for cell in res.cells.iter() {
+        println!("{}", summary_line(cell));
+    }
+    Ok(())
+}
+
+pub async fn show_cell(
+    ctx: &Context,
+    cell_id: uuid::Uuid,
+    json: bool,
+) -> anyhow::Result<()> {
+    let cell = fetch_cell(ctx, cell_id).await?;
+    if json {
+        println!("{}", serde_json::to_string_pretty(&cell)?);
+        return Ok(())
+    }
+    print_parents(&cell.parents, 1);
+    println!("* {}", summary_line(&cell));
+    if !cell.text.is_empty() {
+        println!();
+        for line in cell.text.lines() {
+            println!("    {}", line);
+        }
+        println!();
+    }
+    for fileprop in cell.fileprops.iter() {
+        let size = fileprop.size.map(format_size).unwrap_or_default();
+        let mime = fileprop.mime.as_deref().unwrap_or("");
+        println!(
+            "    {:<40} {:>10}  {}{}",
+            fileprop.path, size, mime,
+            if fileprop.completed { "" } else { " (incomplete)" },
+        );
+    }
+    for dir in cell.dirs.iter() {
+        println!("    {}/", dir);
+    }
+    print_children(&cell.children, 1);
+    Ok(())
+}
+
+pub async fn fetch_cell(
+    ctx: &Context,
+    cell_id: uuid::Uuid,
+) -> anyhow::Result<CellExtracted> {
+    match ctx.client.show_cell(cell_id).await {
+        Err(ClientError::NotFound) => anyhow::bail!("cell {} not found", cell_id),
+        res => Ok(res?),
+    }
+}
+
+pub fn parse_datetime(s: &str) -> Result<chrono::NaiveDateTime, String> {
+    if let Ok(dt) = chrono::NaiveDateTime::parse_from_str(s, "%Y-%m-%dT%H:%M:%S") {
+        return Ok(dt)
+    }
+    chrono::NaiveDate::parse_from_str(s, "%Y-%m-%d")
+        .map(|d| d.and_hms_opt(0, 0, 0).unwrap())
+        .map_err(|_| format!("invalid date '{}', expected YYYY-MM-DD[THH:MM:SS]", s))
+}
+
+pub fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KB", "MB", "GB", "TB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} {}", bytes, UNITS[0])
+    } else {
+        format!("{:.1} {}", size, UNITS[unit])
+    }
+}
+
+fn summary_line(cell: &CellExtracted) -> String {
+    let first_line = cell.text.lines().next().unwrap_or("");
+    format!(
+        "{}  {}  {}  {}",
+        cell.cell_id,
+        cell.created_at.format("%Y/%m/%d %H:%M"),
+        cell.device_id,
+        first_line,
+    )
+}
+
+// Ancestors are printed above the cell, farthest first
+fn print_parents(parents: &[CellExtracted], depth: usize) {
+    for parent in parents.iter() {
+        print_parents(&parent.parents, depth + 1);
+        println!("{}^ {}", "  ".repeat(depth), summary_line(parent));
+    }
+}
+
+fn print_children(children: &[CellExtracted], depth: usize) {
+    for child in children.iter() {
+        println!("{}> {}", "  ".repeat(depth), summary_line(child));
+        print_children(&child.children, depth + 1);
+    }
+}
@@ -0,0 +1,80 @@
+use flowfs_client::ClientError;
+
+use crate::Context;
+use crate::model::*;
+
+// Walks of the family tree print one cell per line, indented by distance.
+// --json prints the full cells instead.
+
+fn dag_query(depth: Option<i32>, json: bool) -> DagQuery {
+    DagQuery { depth, shallow: !json }
+}
+
+pub async fn list_ancestors(
+    ctx: &Context,
+    cell_id: uuid::Uuid,
+    depth: Option<i32>,
+    json: bool,
+) -> anyhow::Result<()> {
+    let dag = ctx.client.list_ancestors(cell_id, &dag_query(depth, json)).await?;
+    print_dag(&dag, "^", json)
+}
+
+pub async fn list_descendants(
+    ctx: &Context,
+    cell_id: uuid::Uuid,
+    depth: Option<i32>,
+    json: bool,
+) -> anyhow::Result<()> {
+    let dag = ctx.client.list_descendants(cell_id, &dag_query(depth, json)).await?;
+    print_dag(&dag, ">", json)
+}
+
+pub async fn list_ends(ctx: &Context, leaves: bool, limit: Option<i64>, json: bool) -> anyhow::Result<()> {
+    let filter = DagFilter { user_id: ctx.user_id, shallow: !json, limit };
+    let dag = if leaves {
+        ctx.client.list_leaves(&filter).await?
+    } else {
+        ctx.client.list_roots(&filter).await?
+    };
+    print_dag(&dag, "*", json)
+}
+
+pub async fn shortest_path(
+    ctx: &Context,
+    cell_id: uuid::Uuid,
+    other_id: uuid::Uuid,
+    json: bool,
+) -> anyhow::Result<()> {
+    let dag = match ctx.client.shortest_path(cell_id, other_id, &dag_query(None, json)).await {
+        Err(ClientError::NotFound) => {
+            anyhow::bail!("no path from {} to {}", cell_id, other_id)
+        }
+        res => res?,
+    };
+    print_dag(&dag, "-", json)
+}
+
+pub async fn common_ancestors(
+    ctx: &Context,
+    cell_id: uuid::Uuid,
+    other_id: uuid::Uuid,
+    json: bool,
+) -> anyhow::Result<()> {
+    let dag = ctx.client.common_ancestors(cell_id, other_id, &dag_query(None, json)).await?;
+    print_dag(&dag, "^", json)
+}
+
+fn print_dag(dag: &Dag, mark: &str, json: bool) -> anyhow::Result<()> {
+    if json {
+        println!("{}", serde_json::to_string_pretty(dag)?);
+        return Ok(())
+    }
+    let nearest = dag.cells.iter().map(|c| c.depth).min().unwrap_or(0);
+    for cell in dag.cells.iter() {
+        let first_line = cell.text.lines().next().unwrap_or("");
+        let indent = "  ".repeat((cell.depth - nearest).max(0) as usize);
+        println!("{}{} {}  {}", indent, mark, cell.cell_id, first_line);
+    }
+    Ok(())
+}
@@ -0,0 +1,33 @@
+use std::path::Path;
+
+use anyhow::Context as _;
+
+use crate::Context;
+use crate::model::*;
+
+pub async fn export_user(
+    ctx: &Context,
+    dest: &Path,
+) -> anyhow::Result<()> {
+    let size = ctx.client.export_user(ctx.user_id, dest).await
+        .with_context(|| format!("fail to export to {}", dest.display()))?;
+    eprintln!("exported {} bytes to {}", size, dest.display());
+    Ok(())
+}
+
+pub async fn import_user(
+    ctx: &Context,
+    archive: &Path,
+    on_conflict: OnConflict,
+) -> anyhow::Result<()> {
+    let res = ctx.client.import_user(ctx.user_id, archive, on_conflict).await
+        .with_context(|| format!("fail to import {}", archive.display()))?;
+    println!(
+        "created {}, skipped {}, overwritten {} cells; restored {} files",
+        res.created.len(),
+        res.skipped.len(),
+        res.overwritten.len(),
+        res.files,
+    );
+    Ok(())
+}
@@ -0,0 +1,171 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::Context as _;
+use flowfs_client::hash::sha256_file;
+use flowfs_client::validate::{safe_join, validate_layout};
+
+use crate::Context;
+use crate::model::*;
+use crate::handler::cell::{fetch_cell, format_size};
+
+// Upload an archive for the server to store its entries, which come back
+// as FileProps and empty directories like those of upload_dir
+pub async fn expand_archive(
+    ctx: &Context,
+    archive: &Path,
+) -> anyhow::Result<(Vec<FileProp>, Vec<String>)> {
+    let res = ctx.client.expand_archive(archive).await
+        .with_context(|| format!("fail to expand {}", archive.display()))?;
+    eprintln!("expanded {} files from {}", res.fileprops.len(), archive.display());
+    Ok((res.fileprops, res.dirs))
+}
+
+// Upload every regular file below `dir` and return their FileProps along
+// with the empty directories, with paths relative to `dir` and separated by '/'
+pub async fn upload_dir(
+    ctx: &Context,
+    dir: &Path,
+) -> anyhow::Result<(Vec<FileProp>, Vec<String>)> {
+    let mut fileprops = vec![];
+    let (files, empty_dirs) = walk_dir(dir)?;
+    for rel_path in files {
+        fileprops.push(upload_file(ctx, &dir.join(&rel_path), slash_path(&rel_path)).await?);
+    }
+    let dirs = empty_dirs.iter().map(|rel_path| slash_path(rel_path)).collect();
+    Ok((fileprops, dirs))
+}
+
+// Upload one local file, unless the server has its content already, as the
+// file at `path` of a cell
+pub async fn upload_file(ctx: &Context, local_path: &Path, path: String) -> anyhow::Result<FileProp> {
+    let (res, transferred) = ctx.client.upload_file_once(local_path).await
+        .with_context(|| format!("fail to upload {}", local_path.display()))?;
+    if transferred {
+        eprintln!("uploaded {}", path);
+    } else {
+        eprintln!("already stored {}", path);
+    }
+    let mtime = std::fs::metadata(local_path)?.modified().ok()
+        .map(|t| chrono::DateTime::<chrono::Utc>::from(t).naive_utc());
+    Ok(FileProp {
+        url: ctx.client.file_url(res.id),
+        completed: true,
+        size: Some(res.size),
+        sha256: Some(res.sha256),
+        mime: Some(mime_guess::from_path(&path).first_or_octet_stream().to_string()),
+        mtime,
+        path,
+    })
+}
+
+pub async fn pull_cell(
+    ctx: &Context,
+    cell_id: uuid::Uuid,
+    dest: &Path,
+) -> anyhow::Result<()> {
+    let cell = fetch_cell(ctx, cell_id).await?;
+    validate_layout(
+        cell.fileprops.iter().map(|f| f.path.as_str()),
+        cell.dirs.iter().map(|d| d.as_str()),
+    ).context("refusing to pull cell")?;
+    for dir in cell.dirs.iter() {
+        tokio::fs::create_dir_all(safe_join(dest, dir)?).await?;
+        eprintln!("created {}/", dir);
+    }
+    for fileprop in cell.fileprops.iter() {
+        if !fileprop.completed {
+            eprintln!("skipped {} (incomplete)", fileprop.path);
+            continue
+        }
+        let local_path = safe_join(dest, &fileprop.path)?;
+        if let Some(parent) = local_path.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+        ctx.client.download_url(&fileprop.url, &local_path).await
+            .with_context(|| format!("fail to download {}", fileprop.path))?;
+        if let Err(e) = verify_file(&local_path, fileprop) {
+            let _ = tokio::fs::remove_file(&local_path).await;
+            return Err(e.context(format!("fail to verify {}", fileprop.path)))
+        }
+        eprintln!("downloaded {}", fileprop.path);
+    }
+    Ok(())
+}
+
+// Compare a downloaded file with the size and digest recorded at upload,
+// for files that have them
+pub async fn find_files(
+    ctx: &Context,
+    filter: FileFilter,
+    json: bool,
+) -> anyhow::Result<()> {
+    let res = ctx.client.search_files(&filter).await?;
+    if json {
+        println!("{}", serde_json::to_string_pretty(&res)?);
+        return Ok(())
+    }
+    for file in res.files.iter() {
+        let size = file.fileprop.size.map(format_size).unwrap_or_default();
+        println!(
+            "{}  {}  {:<40} {:>10}  {}",
+            file.cell_id,
+            file.created_at.format("%Y/%m/%d %H:%M"),
+            file.fileprop.path,
+            size,
+            file.fileprop.mime.as_deref().unwrap_or(""),
+        );
+    }
+    Ok(())
+}
+
+fn verify_file(path: &Path, fileprop: &FileProp) -> anyhow::Result<()> {
+    if fileprop.size.is_none() && fileprop.sha256.is_none() {
+        return Ok(())
+    }
+    let (size, sha256) = sha256_file(path)?;
+    if let Some(expected) = fileprop.size.filter(|expected| *expected != size) {
+        anyhow::bail!("expected {} bytes, got {}", expected, size)
+    }
+    if let Some(expected) = fileprop.sha256.as_ref().filter(|expected| **expected != sha256) {
+        anyhow::bail!("expected sha256 {}, got {}", expected, sha256)
+    }
+    Ok(())
+}
+
+// Regular files and empty directories below `dir`, relative to it and in
+// a stable order. Symlinks are not followed.
+fn walk_dir(dir: &Path) -> anyhow::Result<(Vec<PathBuf>, Vec<PathBuf>)> {
+    let mut files = vec![];
+    let mut empty_dirs = vec![];
+    let mut pending = vec![PathBuf::new()];
+    while let Some(rel_dir) = pending.pop() {
+        let entries = std::fs::read_dir(dir.join(&rel_dir))
+            .with_context(|| format!("fail to read {}", dir.join(&rel_dir).display()))?
+            .collect::<Result<Vec<_>, _>>()?;
+        let mut is_empty = true;
+        for entry in entries.into_iter() {
+            let file_type = entry.file_type()?;
+            let rel_path = rel_dir.join(entry.file_name());
+            if file_type.is_dir() {
+                pending.push(rel_path);
+                is_empty = false;
+            } else if file_type.is_file() {
+                files.push(rel_path);
+                is_empty = false;
+            }
+        }
+        if is_empty && !rel_dir.as_os_str().is_empty() {
+            empty_dirs.push(rel_dir);
+        }
+    }
+    files.sort();
+    empty_dirs.sort();
+    Ok((files, empty_dirs))
+}
+
+fn slash_path(rel_path: &Path) -> String {
+    rel_path.iter()
+        .map(|c| c.to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
@@ -0,0 +1,15 @@
+pub mod cell;
+pub mod file;
+pub mod export;
+pub mod dag;
+pub mod revision;
+#[cfg(all(target_os = "linux", feature = "mount"))]
+pub mod mount;
+
+pub use cell::*;
+pub use file::*;
+pub use export::*;
+pub use dag::*;
+pub use revision::*;
+#[cfg(all(target_os = "linux", feature = "mount"))]
+pub use mount::*;
@@ -0,0 +1,405 @@
+//! `flowfs mount`: the user's cells as a read-mostly FUSE file system.
+//!
+//! cells/<cell_id>/...              files attached to each cell
+//! by-date/<YYYY-MM-DD>/<cell_id>   symlinks into cells/
+//! by-device/<device_id>/<cell_id>  symlinks into cells/
+//! replies/<cell_id>/<reply_id>     symlinks to the replies of a cell
+//!
+//! File content is downloaded into the cache folder the first time a file is
+//! opened. `mkdir cells/<text>` posts a new cell and `mkdir replies/<id>/<text>`
+//! a reply; they show up under their ids with the next refresh.
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::ffi::OsStr;
+use std::hash::{Hash, Hasher};
+use std::os::unix::fs::FileExt;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
+
+use anyhow::Context as _;
+use flowfs_client::validate::{validate_name, validate_path};
+use fuser::{
+    FileAttr, FileType, Filesystem, MountOption, ReplyAttr, ReplyData, ReplyDirectory,
+    ReplyEntry, ReplyOpen, Request,
+};
+use libc::{EIO, ENOENT, ENOTDIR, EROFS};
+
+use crate::Context;
+use crate::model::*;
+
+const TTL: Duration = Duration::from_secs(1);
+// The cell list is fetched again when older than this
+const REFRESH: Duration = Duration::from_secs(30);
+const ROOT_INO: u64 = 1;
+
+enum Node {
+    Dir(BTreeSet<String>),
+    // url and size recorded at upload
+    File(String, Option<u64>),
+    Link(String),
+}
+
+struct Entry {
+    node:           Node,
+    mtime:          SystemTime,
+}
+
+struct FlowFs {
+    ctx:            Context,
+    runtime:        tokio::runtime::Handle,
+    cache_dir:      PathBuf,
+    // Paths are relative to the mount point, "" is the root
+    entries:        BTreeMap<String, Entry>,
+    // Inode numbers stay the same for a path across refreshes
+    inodes:         HashMap<String, u64>,
+    paths:          Vec<String>,
+    loaded_at:      Option<Instant>,
+    uid:            u32,
+    gid:            u32,
+}
+
+pub async fn mount(ctx: Context, mountpoint: &Path, cache_dir: PathBuf) -> anyhow::Result<()> {
+    std::fs::create_dir_all(&cache_dir)
+        .with_context(|| format!("fail to create {}", cache_dir.display()))?;
+    let mut fs = FlowFs {
+        ctx,
+        runtime: tokio::runtime::Handle::current(),
+        cache_dir,
+        entries: BTreeMap::new(),
+        inodes: HashMap::new(),
+        paths: vec![],
+        loaded_at: None,
+        uid: unsafe { libc::getuid() },
+        gid: unsafe { libc::getgid() },
+    };
+    fs.inode("");
+    let cells = fs.ctx.client.list_cells(&fs.filter()).await?;
+    fs.entries = build_entries(&cells.cells);
+    fs.loaded_at = Some(Instant::now());
+
+    let mountpoint = mountpoint.to_path_buf();
+    eprintln!("Mounted on {}, unmount with `fusermount -u {}`", mountpoint.display(), mountpoint.display());
+    let options = [
+        MountOption::FSName("flowfs".to_string()),
+        MountOption::DefaultPermissions,
+        MountOption::NoExec,
+    ];
+    // The file system blocks its thread and calls back into the runtime
+    tokio::task::spawn_blocking(move || fuser::mount2(fs, &mountpoint, &options))
+        .await?
+        .context("fail to mount")?;
+    Ok(())
+}
+
+// Lay out every view of `cells` as paths relative to the mount point
+fn build_entries(cells: &[CellExtracted]) -> BTreeMap<String, Entry> {
+    let mut entries = BTreeMap::new();
+    let newest = cells.iter().map(|c| to_system_time(c.created_at)).max().unwrap_or(SystemTime::UNIX_EPOCH);
+    for view in ["cells", "by-date", "by-device", "replies"] {
+        add_dir(&mut entries, view, newest);
+    }
+    let cell_ids: BTreeSet<uuid::Uuid> = cells.iter().map(|c| c.cell_id).collect();
+    for cell in cells {
+        let mtime = to_system_time(cell.created_at);
+        let link = format!("../../cells/{}", cell.cell_id);
+        add_dir(&mut entries, &format!("cells/{}", cell.cell_id), mtime);
+        for fileprop in cell.fileprops.iter() {
+            // Names that are not safe on disk are left out of the mount
+            if validate_path(&fileprop.path).is_err() {
+                continue
+            }
+            let path = format!("cells/{}/{}", cell.cell_id, fileprop.path);
+            add_entry(&mut entries, path, Node::File(fileprop.url.clone(), fileprop.size), fileprop.mtime.map(to_system_time).unwrap_or(mtime));
+        }
+        for dir in cell.dirs.iter().filter(|d| validate_path(d).is_ok()) {
+            add_dir(&mut entries, &format!("cells/{}/{}", cell.cell_id, dir), mtime);
+        }
+        let day = cell.created_at.format("%Y-%m-%d");
+        add_entry(&mut entries, format!("by-date/{}/{}", day, cell.cell_id), Node::Link(link.clone()), mtime);
+        if validate_name(&cell.device_id).is_ok() {
+            let path = format!("by-device/{}/{}", cell.device_id, cell.cell_id);
+            add_entry(&mut entries, path, Node::Link(link), mtime);
+        }
+        for child in cell.children.iter().filter(|c| cell_ids.contains(&c.cell_id)) {
+            let path = format!("replies/{}/{}", cell.cell_id, child.cell_id);
+            let link = format!("../../cells/{}", child.cell_id);
+            add_entry(&mut entries, path, Node::Link(link), to_system_time(child.created_at));
+        }
+    }
+    entries
+}
+
+// Add an entry along with any missing parent directories
+fn add_entry(entries: &mut BTreeMap<String, Entry>, path: String, node: Node, mtime: SystemTime) {
+    let (parent, name) = path.rsplit_once('/').unwrap_or(("", &path));
+    add_dir(entries, parent, mtime);
+    if let Some(Entry { node: Node::Dir(children), .. }) = entries.get_mut(parent) {
+        children.insert(name.to_string());
+    }
+    entries.insert(path, Entry { node, mtime });
+}
+
+fn add_dir(entries: &mut BTreeMap<String, Entry>, path: &str, mtime: SystemTime) {
+    if entries.contains_key(path) {
+        return
+    }
+    if !path.is_empty() {
+        let (parent, name) = path.rsplit_once('/').unwrap_or(("", path));
+        add_dir(entries, parent, mtime);
+        if let Some(Entry { node: Node::Dir(children), .. }) = entries.get_mut(parent) {
+            children.insert(name.to_string());
+        }
+    }
+    entries.insert(path.to_string(), Entry { node: Node::Dir(BTreeSet::new()), mtime });
+}
+
+fn to_system_time(datetime: chrono::NaiveDateTime) -> SystemTime {
+    let secs = datetime.and_utc().timestamp().max(0) as u64;
+    SystemTime::UNIX_EPOCH + Duration::from_secs(secs)
+}
+
+fn child_path(parent: &str, name: &str) -> String {
+    if parent.is_empty() { name.to_string() } else { format!("{}/{}", parent, name) }
+}
+
+impl FlowFs {
+    fn filter(&self) -> CellFilter {
+        CellFilter {
+            user_id: self.ctx.user_id,
+            ..CellFilter::default()
+        }
+    }
+
+    fn inode(&mut self, path: &str) -> u64 {
+        if let Some(ino) = self.inodes.get(path) {
+            return *ino
+        }
+        self.paths.push(path.to_string());
+        let ino = self.paths.len() as u64;
+        self.inodes.insert(path.to_string(), ino);
+        ino
+    }
+
+    fn path(&self, ino: u64) -> Option<String> {
+        self.paths.get(ino.checked_sub(ROOT_INO)? as usize).cloned()
+    }
+
+    // Fetch the cell list again once it is stale, keeping the old one on errors
+    fn refresh(&mut self) {
+        if self.loaded_at.is_some_and(|t| t.elapsed() < REFRESH) {
+            return
+        }
+        match self.runtime.block_on(self.ctx.client.list_cells(&self.filter())) {
+            Ok(cells) => self.entries = build_entries(&cells.cells),
+            Err(e) => eprintln!("flowfs: fail to refresh cells: {}", e),
+        }
+        self.loaded_at = Some(Instant::now());
+    }
+
+    // Downloaded files are kept under a name derived from their url
+    fn cache_path(&self, url: &str) -> PathBuf {
+        let mut hasher = std::collections::hash_map::DefaultHasher::new();
+        url.hash(&mut hasher);
+        self.cache_dir.join(format!("{:016x}", hasher.finish()))
+    }
+
+    fn fetch(&self, url: &str) -> Result<PathBuf, flowfs_client::ClientError> {
+        let cache_path = self.cache_path(url);
+        if cache_path.is_file() {
+            return Ok(cache_path)
+        }
+        let part_path = cache_path.with_extension("part");
+        self.runtime.block_on(self.ctx.client.download_url(url, &part_path))?;
+        std::fs::rename(&part_path, &cache_path)?;
+        Ok(cache_path)
+    }
+
+    fn attr(&mut self, path: &str) -> Option<FileAttr> {
+        let entry = self.entries.get(path)?;
+        let (kind, perm, size) = match &entry.node {
+            Node::Dir(_) => (FileType::Directory, 0o755, 0),
+            // Files uploaded without a size get one once they are cached
+            Node::File(url, size) => {
+                let size = size
+                    .or_else(|| std::fs::metadata(self.cache_path(url)).map(|m| m.len()).ok())
+                    .unwrap_or(0);
+                (FileType::RegularFile, 0o444, size)
+            }
+            Node::Link(target) => (FileType::Symlink, 0o777, target.len() as u64),
+        };
+        let mtime = entry.mtime;
+        Some(FileAttr {
+            ino: self.inode(path),
+            size,
+            blocks: size.div_ceil(512),
+            atime: mtime,
+            mtime,
+            ctime: mtime,
+            crtime: mtime,
+            kind,
+            perm,
+            nlink: if kind == FileType::Directory { 2 } else { 1 },
+            uid: self.uid,
+            gid: self.gid,
+            rdev: 0,
+            blksize: 512,
+            flags: 0,
+        })
+    }
+
+    // Post a cell for `mkdir cells/<text>` or a reply for `mkdir replies/<id>/<text>`
+    fn create_cell(&self, parent: &str, text: &str) -> Result<(), i32> {
+        let parent_ids = match parent.split('/').collect::<Vec<_>>()[..] {
+            ["cells"] => vec![],
+            ["replies", cell_id] => vec![uuid::Uuid::parse_str(cell_id).map_err(|_| ENOENT)?],
+            _ => return Err(EROFS),
+        };
+        let req = CellReq {
+            cell_id: uuid::Uuid::new_v4(),
+            user_id: self.ctx.user_id,
+            device_id: self.ctx.device_id.clone(),
+            text: text.to_string(),
+            is_open: false,
+            fileprops: vec![],
+            dirs: vec![],
+            rootdir: None,
+            parent_ids,
+            child_ids: vec![],
+        };
+        self.runtime.block_on(self.ctx.client.create_cell(&req)).map_err(|e| {
+            eprintln!("flowfs: fail to create cell: {}", e);
+            EIO
+        })?;
+        Ok(())
+    }
+}
+
+impl Filesystem for FlowFs {
+    fn lookup(&mut self, _req: &Request<'_>, parent: u64, name: &OsStr, reply: ReplyEntry) {
+        self.refresh();
+        let (Some(parent), Some(name)) = (self.path(parent), name.to_str()) else {
+            return reply.error(ENOENT)
+        };
+        match self.attr(&child_path(&parent, name)) {
+            Some(attr) => reply.entry(&TTL, &attr, 0),
+            None => reply.error(ENOENT),
+        }
+    }
+
+    fn getattr(&mut self, _req: &Request<'_>, ino: u64, reply: ReplyAttr) {
+        match self.path(ino).and_then(|path| self.attr(&path)) {
+            Some(attr) => reply.attr(&TTL, &attr),
+            None => reply.error(ENOENT),
+        }
+    }
+
+    fn readlink(&mut self, _req: &Request<'_>, ino: u64, reply: ReplyData) {
+        match self.path(ino).and_then(|path| self.entries.get(&path)) {
+            Some(Entry { node: Node::Link(target), .. }) => reply.data(target.as_bytes()),
+            _ => reply.error(ENOENT),
+        }
+    }
+
+    fn open(&mut self, _req: &Request<'_>, ino: u64, _flags: i32, reply: ReplyOpen) {
+        let Some(Entry { node: Node::File(url, size), .. }) = self.path(ino).and_then(|path| self.entries.get(&path)) else {
+            return reply.error(ENOENT)
+        };
+        let url = url.clone();
+        let size_known = size.is_some() || self.cache_path(&url).is_file();
+        match self.fetch(&url) {
+            // The kernel holds a size of 0 for a file without a recorded size
+            // until it is cached, so the first reads must bypass the page cache
+            Ok(_) if !size_known => reply.opened(0, fuser::consts::FOPEN_DIRECT_IO),
+            Ok(_) => reply.opened(0, fuser::consts::FOPEN_KEEP_CACHE),
+            Err(e) => {
+                eprintln!("flowfs: fail to download {}: {}", url, e);
+                reply.error(EIO)
+            }
+        }
+    }
+
+    fn read(
+        &mut self,
+        _req: &Request<'_>,
+        ino: u64,
+        _fh: u64,
+        offset: i64,
+        size: u32,
+        _flags: i32,
+        _lock_owner: Option<u64>,
+        reply: ReplyData,
+    ) {
+        let Some(Entry { node: Node::File(url, _), .. }) = self.path(ino).and_then(|path| self.entries.get(&path)) else {
+            return reply.error(ENOENT)
+        };
+        let Ok(file) = std::fs::File::open(self.cache_path(url)) else {
+            return reply.error(EIO)
+        };
+        let mut buf = vec![0; size as usize];
+        let mut filled = 0;
+        while filled < buf.len() {
+            match file.read_at(&mut buf[filled..], offset as u64 + filled as u64) {
+                Ok(0) => break,
+                Ok(n) => filled += n,
+                Err(_) => return reply.error(EIO),
+            }
+        }
+        reply.data(&buf[..filled]);
+    }
+
+    fn readdir(
+        &mut self,
+        _req: &Request<'_>,
+        ino: u64,
+        _fh: u64,
+        offset: i64,
+        mut reply: ReplyDirectory,
+    ) {
+        if ino == ROOT_INO {
+            self.refresh();
+        }
+        let Some(path) = self.path(ino) else {
+            return reply.error(ENOENT)
+        };
+        let Some(Entry { node: Node::Dir(children), .. }) = self.entries.get(&path) else {
+            return reply.error(ENOTDIR)
+        };
+        let children: Vec<String> = children.iter().cloned().collect();
+        let mut listing = vec![(ino, FileType::Directory, ".".to_string()), (ino, FileType::Directory, "..".to_string())];
+        for name in children {
+            if let Some(attr) = self.attr(&child_path(&path, &name)) {
+                listing.push((attr.ino, attr.kind, name));
+            }
+        }
+        for (i, (ino, kind, name)) in listing.into_iter().enumerate().skip(offset as usize) {
+            if reply.add(ino, (i + 1) as i64, kind, name) {
+                break
+            }
+        }
+        reply.ok();
+    }
+
+    fn mkdir(
+        &mut self,
+        _req: &Request<'_>,
+        parent: u64,
+        name: &OsStr,
+        _mode: u32,
+        _umask: u32,
+        reply: ReplyEntry,
+    ) {
+        let (Some(parent), Some(name)) = (self.path(parent), name.to_str()) else {
+            return reply.error(ENOENT)
+        };
+        if let Err(errno) = self.create_cell(&parent, name) {
+            return reply.error(errno)
+        }
+        // The new directory answers to its name until the next refresh
+        let path = child_path(&parent, name);
+        add_entry(&mut self.entries, path.clone(), Node::Dir(BTreeSet::new()), SystemTime::now());
+        match self.attr(&path) {
+            Some(attr) => reply.entry(&TTL, &attr, 0),
+            None => reply.error(EIO),
+        }
+    }
+}
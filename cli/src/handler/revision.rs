@@ -0,0 +1,125 @@
+use flowfs_client::ClientError;
+
+use crate::Context;
+use crate::model::*;
+
+pub async fn list_revisions(
+    ctx: &Context,
+    cell_id: uuid::Uuid,
+    json: bool,
+) -> anyhow::Result<()> {
+    let revisions = match ctx.client.list_revisions(cell_id).await {
+        Err(ClientError::NotFound) => anyhow::bail!("cell {} not found", cell_id),
+        res => res?,
+    };
+    if json {
+        println!("{}", serde_json::to_string_pretty(&revisions)?);
+        return Ok(())
+    }
+    for revision in revisions.iter() {
+        println!("{}", revision_line(revision));
+    }
+    Ok(())
+}
+
+pub async fn diff_revisions(
+    ctx: &Context,
+    cell_id: uuid::Uuid,
+    query: DiffQuery,
+    json: bool,
+) -> anyhow::Result<()> {
+    let diff = match ctx.client.diff_revisions(cell_id, &query).await {
+        Err(ClientError::NotFound) => anyhow::bail!("cell {} or its revision not found", cell_id),
+        res => res?,
+    };
+    if json {
+        println!("{}", serde_json::to_string_pretty(&diff)?);
+        return Ok(())
+    }
+    println!("revision {} -> {}", diff.from, diff.to);
+    if let Some(text) = diff.text.as_ref() {
+        for line in text.old.lines() {
+            println!("- {}", line);
+        }
+        for line in text.new.lines() {
+            println!("+ {}", line);
+        }
+    }
+    if let Some(is_open) = diff.is_open.as_ref() {
+        println!("~ open: {} -> {}", is_open.old, is_open.new);
+    }
+    for parent_id in diff.parents_removed.iter() {
+        println!("- parent {}", parent_id);
+    }
+    for parent_id in diff.parents_added.iter() {
+        println!("+ parent {}", parent_id);
+    }
+    print_files_diff(&diff.files);
+    Ok(())
+}
+
+pub async fn diff_cells(
+    ctx: &Context,
+    cell_id: uuid::Uuid,
+    other_id: uuid::Uuid,
+    json: bool,
+) -> anyhow::Result<()> {
+    let diff = match ctx.client.diff_cells(cell_id, other_id).await {
+        Err(ClientError::NotFound) => anyhow::bail!("cell {} or {} not found", cell_id, other_id),
+        res => res?,
+    };
+    if json {
+        println!("{}", serde_json::to_string_pretty(&diff)?);
+        return Ok(())
+    }
+    println!("{} -> {}", diff.cell_id, diff.other_id);
+    print_files_diff(&diff.files);
+    Ok(())
+}
+
+pub async fn revert_cell(
+    ctx: &Context,
+    cell_id: uuid::Uuid,
+    revision: i32,
+) -> anyhow::Result<()> {
+    let reverted = match ctx.client.revert_cell(cell_id, revision, Some(&ctx.device_id)).await {
+        Err(ClientError::NotFound) => anyhow::bail!("cell {} has no revision {}", cell_id, revision),
+        res => res?,
+    };
+    println!("{}", revision_line(&reverted));
+    Ok(())
+}
+
+// One line per file or directory: + added, - removed, ~ changed, > moved
+pub fn print_files_diff(files: &FilesDiff) {
+    for fileprop in files.removed.iter() {
+        println!("- {}", fileprop.path);
+    }
+    for fileprop in files.added.iter() {
+        println!("+ {}", fileprop.path);
+    }
+    for change in files.changed.iter() {
+        println!("~ {}", change.new.path);
+    }
+    for change in files.moved.iter() {
+        println!("> {} -> {}", change.old.path, change.new.path);
+    }
+    for dir in files.dirs_removed.iter() {
+        println!("- {}/", dir);
+    }
+    for dir in files.dirs_added.iter() {
+        println!("+ {}/", dir);
+    }
+}
+
+fn revision_line(revision: &Revision) -> String {
+    let first_line = revision.text.lines().next().unwrap_or("");
+    format!(
+        "#{}  {}  {}  {} files  {}",
+        revision.revision,
+        revision.created_at.format("%Y/%m/%d %H:%M"),
+        revision.device_id.as_deref().unwrap_or("-"),
+        revision.fileprops.len(),
+        first_line,
+    )
+}
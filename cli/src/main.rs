@@ -0,0 +1,369 @@
+use std::path::PathBuf;
+
+use clap::{Parser, Subcommand};
+
+use flowfs_client::{model, FlowfsClient};
+
+mod utils;
+mod handler;
+
+#[derive(Parser, Debug)]
+#[command(name = "flowfs", version, about = "Post and fetch flowfs cells from the command line")]
+struct Cli {
+    /// Config file holding api_url, user_id and device_id
+    #[arg(long, global = true, env = "FLOWFS_CONFIG", default_value = "./flowfs.toml")]
+    config: String,
+    /// Base URL of the flowfs backend
+    #[arg(long, global = true, env = "FLOWFS_API_URL")]
+    api_url: Option<String>,
+    /// User the cells are posted as and listed for
+    #[arg(long, global = true, env = "FLOWFS_USER_ID")]
+    user_id: Option<uuid::Uuid>,
+    /// Device name recorded on posted cells
+    #[arg(long, global = true, env = "FLOWFS_DEVICE_ID")]
+    device_id: Option<String>,
+    /// Bearer token sent to the backend
+    #[arg(long, global = true, env = "FLOWFS_TOKEN", hide_env_values = true)]
+    token: Option<String>,
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Create a cell, optionally attaching the files of a local folder or archive
+    Post {
+        /// Cell text, or "-" to read it from stdin
+        text: String,
+        /// Folder whose files are uploaded and attached to the cell, or a
+        /// zip, tar or tar.gz file that the server expands into the cell
+        dir: Option<PathBuf>,
+        /// Parent cell ids
+        #[arg(long = "parent")]
+        parent_ids: Vec<uuid::Uuid>,
+        #[arg(long)]
+        open: bool,
+    },
+    /// List cells, newest first
+    Ls {
+        #[arg(long)]
+        device: Option<String>,
+        /// Only cells whose text contains this string
+        #[arg(long)]
+        text: Option<String>,
+        /// Only cells created at or after this date (YYYY-MM-DD[THH:MM:SS])
+        #[arg(long, value_parser = handler::parse_datetime)]
+        since: Option<chrono::NaiveDateTime>,
+        /// Only cells created before this date (YYYY-MM-DD[THH:MM:SS])
+        #[arg(long, value_parser = handler::parse_datetime)]
+        until: Option<chrono::NaiveDateTime>,
+        #[arg(long)]
+        limit: Option<i64>,
+        /// Print the raw JSON response
+        #[arg(long)]
+        json: bool,
+    },
+    /// Print a cell with its parent and child tree
+    Show {
+        cell_id: uuid::Uuid,
+        /// Print the raw JSON response
+        #[arg(long)]
+        json: bool,
+    },
+    /// List a cell's parents, their parents and so on, nearest first
+    Ancestors {
+        cell_id: uuid::Uuid,
+        /// Stop after this many generations
+        #[arg(long)]
+        depth: Option<i32>,
+        /// Print the raw JSON response
+        #[arg(long)]
+        json: bool,
+    },
+    /// List a cell's replies, their replies and so on, nearest first
+    Descendants {
+        cell_id: uuid::Uuid,
+        /// Stop after this many generations
+        #[arg(long)]
+        depth: Option<i32>,
+        /// Print the raw JSON response
+        #[arg(long)]
+        json: bool,
+    },
+    /// List cells without parents, newest first
+    Roots {
+        #[arg(long)]
+        limit: Option<i64>,
+        /// Print the raw JSON response
+        #[arg(long)]
+        json: bool,
+    },
+    /// List cells without replies, newest first
+    Leaves {
+        #[arg(long)]
+        limit: Option<i64>,
+        /// Print the raw JSON response
+        #[arg(long)]
+        json: bool,
+    },
+    /// Print the shortest chain of replies or parents between two cells
+    Path {
+        cell_id: uuid::Uuid,
+        other_id: uuid::Uuid,
+        /// Print the raw JSON response
+        #[arg(long)]
+        json: bool,
+    },
+    /// List the cells two cells both descend from, nearest first
+    Common {
+        cell_id: uuid::Uuid,
+        other_id: uuid::Uuid,
+        /// Print the raw JSON response
+        #[arg(long)]
+        json: bool,
+    },
+    /// List the revisions of a cell, oldest first
+    Revisions {
+        cell_id: uuid::Uuid,
+        /// Print the raw JSON response
+        #[arg(long)]
+        json: bool,
+    },
+    /// Show what changed between two revisions of a cell, or between the
+    /// files of two cells when another cell is given
+    Diff {
+        cell_id: uuid::Uuid,
+        /// Cell whose files are compared with those of the first one
+        #[arg(conflicts_with_all = ["from", "to"])]
+        other_id: Option<uuid::Uuid>,
+        /// Older revision, defaults to the one before --to
+        #[arg(long)]
+        from: Option<i32>,
+        /// Newer revision, defaults to the latest
+        #[arg(long)]
+        to: Option<i32>,
+        /// Print the raw JSON response
+        #[arg(long)]
+        json: bool,
+    },
+    /// Bring a cell back to one of its revisions, as a new revision
+    Revert {
+        cell_id: uuid::Uuid,
+        revision: i32,
+    },
+    /// Find attached files across cells, newest cell first
+    Find {
+        /// File name pattern with * and ?; matched against the path when it holds a '/'
+        glob: Option<String>,
+        /// Only files with exactly this name, ignoring case
+        #[arg(long)]
+        name: Option<String>,
+        /// Only files of this MIME type, e.g. image/png or image/*
+        #[arg(long)]
+        mime: Option<String>,
+        /// Only files with this SHA-256
+        #[arg(long)]
+        sha256: Option<String>,
+        /// Only files of cells created at or after this date (YYYY-MM-DD[THH:MM:SS])
+        #[arg(long, value_parser = handler::parse_datetime)]
+        since: Option<chrono::NaiveDateTime>,
+        /// Only files of cells created before this date (YYYY-MM-DD[THH:MM:SS])
+        #[arg(long, value_parser = handler::parse_datetime)]
+        until: Option<chrono::NaiveDateTime>,
+        #[arg(long)]
+        limit: Option<i64>,
+        /// Print the raw JSON response
+        #[arg(long)]
+        json: bool,
+    },
+    /// Download the files attached to a cell into a folder
+    Pull {
+        cell_id: uuid::Uuid,
+        dest: PathBuf,
+    },
+    /// Create a cell replying to another one
+    Reply {
+        cell_id: uuid::Uuid,
+        /// Reply text, or "-" to read it from stdin (the default)
+        #[arg(default_value = "-")]
+        text: String,
+        /// Folder whose files are uploaded and attached to the reply
+        #[arg(long)]
+        dir: Option<PathBuf>,
+        #[arg(long)]
+        open: bool,
+    },
+    /// Reply to a cell with a copy of its files, shared on the server until
+    /// they are changed
+    Fork {
+        cell_id: uuid::Uuid,
+        /// Reply text, or "-" to read it from stdin (the default)
+        #[arg(default_value = "-")]
+        text: String,
+        /// Add a local file at a path of the reply, as PATH=LOCAL_FILE
+        #[arg(long = "add", value_parser = handler::parse_put)]
+        adds: Vec<(String, PathBuf)>,
+        /// Put a local file in place of one of the parent's, as PATH=LOCAL_FILE
+        #[arg(long = "replace", value_parser = handler::parse_put)]
+        replaces: Vec<(String, PathBuf)>,
+        /// Leave out a file of the parent
+        #[arg(long = "remove")]
+        removes: Vec<String>,
+        #[arg(long)]
+        open: bool,
+    },
+    /// Save all of the user's cells and files into a tar archive
+    Export {
+        dest: PathBuf,
+    },
+    /// Recreate cells from an archive written by `flowfs export`
+    Import {
+        archive: PathBuf,
+        /// What to do with cells whose id already exists
+        #[arg(long, value_enum, default_value_t = ConflictArg::Fail)]
+        on_conflict: ConflictArg,
+    },
+    /// Browse the user's cells as files under a mount point (Linux)
+    #[cfg(all(target_os = "linux", feature = "mount"))]
+    Mount {
+        mountpoint: PathBuf,
+        /// Where downloaded file content is kept, defaults to ~/.cache/flowfs
+        #[arg(long)]
+        cache_dir: Option<PathBuf>,
+    },
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug)]
+enum ConflictArg {
+    Fail,
+    Skip,
+    Overwrite,
+}
+
+impl From<ConflictArg> for model::OnConflict {
+    fn from(arg: ConflictArg) -> Self {
+        match arg {
+            ConflictArg::Fail => model::OnConflict::Fail,
+            ConflictArg::Skip => model::OnConflict::Skip,
+            ConflictArg::Overwrite => model::OnConflict::Overwrite,
+        }
+    }
+}
+
+pub struct Context {
+    pub client:         FlowfsClient,
+    pub user_id:        uuid::Uuid,
+    pub device_id:      String,
+}
+
+#[tokio::main]
+async fn main() {
+    let cli = Cli::parse();
+    if let Err(e) = run(cli).await {
+        eprintln!("flowfs: {:#}", e);
+        std::process::exit(1);
+    }
+}
+
+async fn run(cli: Cli) -> anyhow::Result<()> {
+    let config = utils::get_config(&cli.config)?;
+    let api_url = cli.api_url
+        .or(config.api_url)
+        .unwrap_or("http://127.0.0.1:8080".to_string());
+    let mut client = FlowfsClient::new(&api_url);
+    if let Some(token) = cli.token.or(config.token) {
+        client = client.with_token(token);
+    }
+    let ctx = Context {
+        client,
+        user_id: cli.user_id.or(config.user_id).unwrap_or(uuid::Uuid::nil()),
+        device_id: cli.device_id.or(config.device_id).unwrap_or("cli".to_string()),
+    };
+
+    match cli.command {
+        Command::Post { text, dir, parent_ids, open } => {
+            let id = handler::post_cell(&ctx, &text, dir.as_deref(), parent_ids, open).await?;
+            println!("{}", id);
+        }
+        Command::Ls { device, text, since, until, limit, json } => {
+            let filter = model::CellFilter {
+                user_id: ctx.user_id,
+                device_id: device,
+                text,
+                since,
+                until,
+                limit,
+            };
+            handler::list_cells(&ctx, filter, json).await?;
+        }
+        Command::Show { cell_id, json } => {
+            handler::show_cell(&ctx, cell_id, json).await?;
+        }
+        Command::Ancestors { cell_id, depth, json } => {
+            handler::list_ancestors(&ctx, cell_id, depth, json).await?;
+        }
+        Command::Descendants { cell_id, depth, json } => {
+            handler::list_descendants(&ctx, cell_id, depth, json).await?;
+        }
+        Command::Roots { limit, json } => {
+            handler::list_ends(&ctx, false, limit, json).await?;
+        }
+        Command::Leaves { limit, json } => {
+            handler::list_ends(&ctx, true, limit, json).await?;
+        }
+        Command::Path { cell_id, other_id, json } => {
+            handler::shortest_path(&ctx, cell_id, other_id, json).await?;
+        }
+        Command::Common { cell_id, other_id, json } => {
+            handler::common_ancestors(&ctx, cell_id, other_id, json).await?;
+        }
+        Command::Revisions { cell_id, json } => {
+            handler::list_revisions(&ctx, cell_id, json).await?;
+        }
+        Command::Diff { cell_id, other_id: Some(other_id), json, .. } => {
+            handler::diff_cells(&ctx, cell_id, other_id, json).await?;
+        }
+        Command::Diff { cell_id, other_id: None, from, to, json } => {
+            handler::diff_revisions(&ctx, cell_id, model::DiffQuery { from, to }, json).await?;
+        }
+        Command::Revert { cell_id, revision } => {
+            handler::revert_cell(&ctx, cell_id, revision).await?;
+        }
+        Command::Find { glob, name, mime, sha256, since, until, limit, json } => {
+            let filter = model::FileFilter {
+                user_id: ctx.user_id,
+                name,
+                glob,
+                mime,
+                sha256,
+                since,
+                until,
+                limit,
+            };
+            handler::find_files(&ctx, filter, json).await?;
+        }
+        Command::Pull { cell_id, dest } => {
+            handler::pull_cell(&ctx, cell_id, &dest).await?;
+        }
+        Command::Reply { cell_id, text, dir, open } => {
+            let id = handler::post_cell(&ctx, &text, dir.as_deref(), vec![cell_id], open).await?;
+            println!("{}", id);
+        }
+        Command::Fork { cell_id, text, adds, replaces, removes, open } => {
+            let id = handler::fork_cell(&ctx, cell_id, &text, adds, replaces, removes, open).await?;
+            println!("{}", id);
+        }
+        Command::Export { dest } => {
+            handler::export_user(&ctx, &dest).await?;
+        }
+        Command::Import { archive, on_conflict } => {
+            handler::import_user(&ctx, &archive, on_conflict.into()).await?;
+        }
+        #[cfg(all(target_os = "linux", feature = "mount"))]
+        Command::Mount { mountpoint, cache_dir } => {
+            let cache_dir = cache_dir.unwrap_or_else(utils::default_cache_dir);
+            handler::mount(ctx, &mountpoint, cache_dir).await?;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,40 @@
+use std::fs;
+use std::io::{BufReader, Read};
+use serde::Deserialize;
+
+#[derive(Debug, Default, Deserialize)]
+pub struct Config {
+  pub api_url: Option<String>,
+  pub user_id: Option<uuid::Uuid>,
+  pub device_id: Option<String>,
+  pub token: Option<String>,
+}
+
+fn read_file(path: &str) -> Result<String, String> {
+  let mut file_content = String::new();
+  let mut fr = fs::File::open(path)
+      .map(BufReader::new)
+      .map_err(|e| e.to_string())?;
+  fr.read_to_string(&mut file_content)
+      .map_err(|e| e.to_string())?;
+  Ok(file_content)
+}
+
+// The config file is optional so that the CLI can run from CI jobs
+// with everything given by flags or FLOWFS_* environment variables.
+pub fn get_config(path: &str) -> anyhow::Result<Config> {
+  let Ok(s) = read_file(path) else {
+    return Ok(Config::default())
+  };
+  toml::from_str(&s).map_err(|e| anyhow::anyhow!("fail to parse {}: {}", path, e))
+}
+
+// $XDG_CACHE_HOME/flowfs, falling back to ~/.cache/flowfs
+#[cfg(all(target_os = "linux", feature = "mount"))]
+pub fn default_cache_dir() -> std::path::PathBuf {
+  let base = std::env::var_os("XDG_CACHE_HOME")
+    .map(std::path::PathBuf::from)
+    .or_else(|| std::env::var_os("HOME").map(|home| std::path::PathBuf::from(home).join(".cache")))
+    .unwrap_or_else(std::env::temp_dir);
+  base.join("flowfs")
+}
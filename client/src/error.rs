@@ -0,0 +1,44 @@
+use reqwest::StatusCode;
+
+#[derive(thiserror::Error, Debug)]
+pub enum ClientError {
+    /// The server could not be reached or the connection broke
+    #[error("request failed: {0}")]
+    Http(#[from] reqwest::Error),
+    #[error("not found")]
+    NotFound,
+    #[error("bad request: {0}")]
+    BadRequest(String),
+    #[error("unauthorized")]
+    Unauthorized,
+    #[error("conflict: {0}")]
+    Conflict(String),
+    #[error("server error {0}: {1}")]
+    Server(StatusCode, String),
+    #[error("i/o error: {0}")]
+    Io(#[from] std::io::Error),
+}
+
+impl ClientError {
+    pub fn from_status(status: StatusCode, body: String) -> Self {
+        match status {
+            StatusCode::NOT_FOUND => ClientError::NotFound,
+            StatusCode::BAD_REQUEST => ClientError::BadRequest(body),
+            StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => ClientError::Unauthorized,
+            StatusCode::CONFLICT => ClientError::Conflict(body),
+            _ => ClientError::Server(status, body),
+        }
+    }
+
+    // Transport failures and gateway/overload statuses are worth another try
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            ClientError::Http(e) => e.is_connect() || e.is_timeout(),
+            ClientError::Server(status, _) => matches!(
+                *status,
+                StatusCode::BAD_GATEWAY | StatusCode::SERVICE_UNAVAILABLE | StatusCode::GATEWAY_TIMEOUT
+            ),
+            _ => false,
+        }
+    }
+}
@@ -0,0 +1,18 @@
+//! SHA-256 digests in the lowercase hex form used by `FileProp::sha256`.
+
+use std::io::{self, BufReader};
+use std::path::Path;
+
+use sha2::{Digest, Sha256};
+
+pub fn sha256_bytes(bytes: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(bytes))
+}
+
+/// Size and digest of a local file
+pub fn sha256_file(path: &Path) -> io::Result<(u64, String)> {
+    let mut file = BufReader::new(std::fs::File::open(path)?);
+    let mut hasher = Sha256::new();
+    let size = io::copy(&mut file, &mut hasher)?;
+    Ok((size, format!("{:x}", hasher.finalize())))
+}
@@ -0,0 +1,455 @@
+//! Typed HTTP client for the flowfs backend API, shared by the desktop app
+//! and the `flowfs` command-line tool.
+
+use std::future::Future;
+use std::path::Path;
+use std::time::Duration;
+
+use futures_util::StreamExt;
+use log::warn;
+use reqwest::{header, Method, RequestBuilder, Response};
+use serde::de::DeserializeOwned;
+use tokio::io::AsyncWriteExt;
+use tokio_util::io::ReaderStream;
+
+pub mod error;
+pub mod hash;
+pub mod model;
+#[cfg(feature = "mock")]
+pub mod mock;
+
+pub use error::ClientError;
+pub use flowfs_common::{diff, patch, tree, validate};
+use model::*;
+
+pub type Result<T> = std::result::Result<T, ClientError>;
+
+#[derive(Clone, Debug)]
+pub struct FlowfsClient {
+    http:               reqwest::Client,
+    base_url:           String,
+    token:              Option<String>,
+    max_retries:        u32,
+    retry_delay:        Duration,
+}
+
+impl FlowfsClient {
+    pub fn new(base_url: &str) -> Self {
+        FlowfsClient {
+            http: reqwest::Client::new(),
+            base_url: base_url.trim_end_matches('/').to_string(),
+            token: None,
+            max_retries: 3,
+            retry_delay: Duration::from_millis(200),
+        }
+    }
+
+    /// Send `Authorization: Bearer <token>` with every request to the backend
+    pub fn with_token(mut self, token: impl Into<String>) -> Self {
+        self.token = Some(token.into());
+        self
+    }
+
+    /// Retry failed requests up to `max_retries` times, doubling `retry_delay` each time
+    pub fn with_retries(mut self, max_retries: u32, retry_delay: Duration) -> Self {
+        self.max_retries = max_retries;
+        self.retry_delay = retry_delay;
+        self
+    }
+
+    pub fn base_url(&self) -> &str {
+        &self.base_url
+    }
+
+    /// URL at which an uploaded file can be downloaded, as stored in `FileProp::url`
+    pub fn file_url(&self, file_id: uuid::Uuid) -> String {
+        format!("{}/files/{}", self.base_url, file_id)
+    }
+
+    /// URL of the file at `path` within a cell, served with Range support,
+    /// for [`download_url`](Self::download_url) or [`get_file`](Self::get_file)
+    pub fn cell_file_url(&self, cell_id: uuid::Uuid, path: &str) -> String {
+        let base = self.url(&format!("/cells/{}/files", cell_id));
+        let Ok(mut url) = reqwest::Url::parse(&base) else {
+            return format!("{}/{}", base, path)
+        };
+        if let Ok(mut segments) = url.path_segments_mut() {
+            segments.extend(path.split('/'));
+        }
+        url.to_string()
+    }
+
+    pub async fn list_users(&self) -> Result<Users> {
+        self.json(Method::GET, "/users", None::<&()>).await
+    }
+
+    pub async fn create_user(&self, user: &User) -> Result<IdRes> {
+        self.json(Method::POST, "/users", Some(user)).await
+    }
+
+    pub async fn show_user(&self, user_id: uuid::Uuid) -> Result<UserRes> {
+        self.json(Method::GET, &format!("/users/{}", user_id), None::<&()>).await
+    }
+
+    pub async fn delete_user(&self, user_id: uuid::Uuid) -> Result<IdRes> {
+        self.json(Method::DELETE, &format!("/users/{}", user_id), None::<&()>).await
+    }
+
+    pub async fn list_cells(&self, filter: &CellFilter) -> Result<Cells> {
+        self.json(Method::GET, "/cells", Some(filter)).await
+    }
+
+    /// Create a cell. Sending the same request again is safe: it succeeds
+    /// with the same id, while a different one for that `cell_id` gives
+    /// `ClientError::Conflict`.
+    pub async fn create_cell(&self, req: &CellReq) -> Result<IdRes> {
+        self.json(Method::POST, "/cells", Some(req)).await
+    }
+
+    /// Create many cells in one transaction. A batch that was refused still
+    /// gives its report, with `committed` false and the reason per cell.
+    pub async fn create_cells(&self, cells: Vec<CellReq>) -> Result<BatchRes> {
+        let url = self.url("/cells/batch");
+        let batch = CellBatch { cells };
+        let res = self.retry(|| async {
+            Ok(self.request(Method::POST, &url).json(&batch).send().await?)
+        }).await?;
+        let status = res.status();
+        let body = res.text().await?;
+        let is_report = status.is_success()
+            || status == reqwest::StatusCode::BAD_REQUEST
+            || status == reqwest::StatusCode::CONFLICT;
+        match serde_json::from_str(&body) {
+            Ok(report) if is_report => Ok(report),
+            _ => Err(ClientError::from_status(status, body)),
+        }
+    }
+
+    pub async fn show_cell(&self, cell_id: uuid::Uuid) -> Result<CellExtracted> {
+        self.json(Method::GET, &format!("/cells/{}", cell_id), None::<&()>).await
+    }
+
+    pub async fn delete_cell(&self, cell_id: uuid::Uuid) -> Result<IdRes> {
+        self.json(Method::DELETE, &format!("/cells/{}", cell_id), None::<&()>).await
+    }
+
+    /// Parents, their parents and so on, nearest first
+    pub async fn list_ancestors(&self, cell_id: uuid::Uuid, query: &DagQuery) -> Result<Dag> {
+        self.query(&format!("/cells/{}/ancestors", cell_id), query).await
+    }
+
+    /// Replies, their replies and so on, nearest first
+    pub async fn list_descendants(&self, cell_id: uuid::Uuid, query: &DagQuery) -> Result<Dag> {
+        self.query(&format!("/cells/{}/descendants", cell_id), query).await
+    }
+
+    /// The user's cells without parents, newest first
+    pub async fn list_roots(&self, filter: &DagFilter) -> Result<Dag> {
+        self.query("/cells/roots", filter).await
+    }
+
+    /// The user's cells without replies, newest first
+    pub async fn list_leaves(&self, filter: &DagFilter) -> Result<Dag> {
+        self.query("/cells/leaves", filter).await
+    }
+
+    /// The shortest chain of replies or parents from `cell_id` to `other_id`,
+    /// `ClientError::NotFound` if there is none
+    pub async fn shortest_path(&self, cell_id: uuid::Uuid, other_id: uuid::Uuid, query: &DagQuery) -> Result<Dag> {
+        self.query(&format!("/cells/{}/path/{}", cell_id, other_id), query).await
+    }
+
+    /// Cells both cells descend from, nearest first; a cell counts as its own ancestor
+    pub async fn common_ancestors(&self, cell_id: uuid::Uuid, other_id: uuid::Uuid, query: &DagQuery) -> Result<Dag> {
+        self.query(&format!("/cells/{}/common/{}", cell_id, other_id), query).await
+    }
+
+    /// Change a cell's text, flag, files or parents, giving the revision made.
+    /// An update changing nothing gives the latest revision.
+    pub async fn update_cell(&self, cell_id: uuid::Uuid, update: &CellUpdate) -> Result<Revision> {
+        self.json(Method::PUT, &format!("/cells/{}", cell_id), Some(update)).await
+    }
+
+    /// Every revision of a cell, oldest first
+    pub async fn list_revisions(&self, cell_id: uuid::Uuid) -> Result<Vec<Revision>> {
+        self.json(Method::GET, &format!("/cells/{}/revisions", cell_id), None::<&()>).await
+    }
+
+    pub async fn show_revision(&self, cell_id: uuid::Uuid, revision: i32) -> Result<Revision> {
+        self.json(Method::GET, &format!("/cells/{}/revisions/{}", cell_id, revision), None::<&()>).await
+    }
+
+    pub async fn diff_revisions(&self, cell_id: uuid::Uuid, query: &DiffQuery) -> Result<RevisionDiff> {
+        self.query(&format!("/cells/{}/revisions/diff", cell_id), query).await
+    }
+
+    /// Bring a cell back to how it was at `revision`, which makes a new revision
+    pub async fn revert_cell(&self, cell_id: uuid::Uuid, revision: i32, device_id: Option<&str>) -> Result<Revision> {
+        let url = self.url(&format!("/cells/{}/revisions/{}/revert", cell_id, revision));
+        let res = self.retry(|| {
+            let mut builder = self.request(Method::POST, &url);
+            if let Some(device_id) = device_id {
+                builder = builder.query(&[("device_id", device_id)]);
+            }
+            self.execute(builder)
+        }).await?;
+        Ok(res.json().await?)
+    }
+
+    /// Reply to `parent_id` with its files, changed by `fork.ops`. The
+    /// parent's files are not uploaded again: both cells refer to the same
+    /// stored content until an op adds or replaces a file.
+    pub async fn fork_cell(&self, parent_id: uuid::Uuid, fork: &CellFork) -> Result<IdRes> {
+        self.json(Method::POST, &format!("/cells/{}/fork", parent_id), Some(fork)).await
+    }
+
+    /// How the files of `other_id` differ from those of `cell_id`
+    pub async fn diff_cells(&self, cell_id: uuid::Uuid, other_id: uuid::Uuid) -> Result<CellsDiff> {
+        self.json(Method::GET, &format!("/cells/{}/diff/{}", cell_id, other_id), None::<&()>).await
+    }
+
+    /// Apply `patch` to the files of a cell, all operations or none
+    pub async fn patch_files(&self, cell_id: uuid::Uuid, patch: &FilePatch) -> Result<CellFiles> {
+        self.json(Method::PATCH, &format!("/cells/{}/files", cell_id), Some(patch)).await
+    }
+
+    /// Write a tar archive of the user's cells, edges and files to `dest`
+    pub async fn export_user(&self, user_id: uuid::Uuid, dest: &Path) -> Result<u64> {
+        self.download_url(&self.url(&format!("/users/{}/export", user_id)), dest).await
+    }
+
+    /// Recreate the cells of an archive written by `export_user` under `user_id`
+    pub async fn import_user(
+        &self,
+        user_id: uuid::Uuid,
+        archive: &Path,
+        on_conflict: OnConflict,
+    ) -> Result<ImportRes> {
+        let url = self.url(&format!("/users/{}/import", user_id));
+        let options = ImportOptions { on_conflict };
+        let res = self.retry(|| async {
+            let file = tokio::fs::File::open(archive).await?;
+            let body = reqwest::Body::wrap_stream(ReaderStream::new(file));
+            self.execute(self.request(Method::POST, &url).query(&options).body(body)).await
+        }).await?;
+        Ok(res.json().await?)
+    }
+
+    /// Upload a local file, streaming it from disk
+    pub async fn upload_file(&self, path: &Path) -> Result<FileRes> {
+        let url = self.url("/files");
+        let res = self.retry(|| async {
+            let file = tokio::fs::File::open(path).await?;
+            let body = reqwest::Body::wrap_stream(ReaderStream::new(file));
+            self.execute(self.request(Method::POST, &url).body(body)).await
+        }).await?;
+        Ok(res.json().await?)
+    }
+
+    /// Find attached files across the cells of `filter.user_id`, newest cell first
+    pub async fn search_files(&self, filter: &FileFilter) -> Result<FileMatches> {
+        let url = self.url("/files");
+        let res = self.retry(|| self.execute(self.request(Method::GET, &url).query(filter))).await?;
+        Ok(res.json().await?)
+    }
+
+    /// Look up stored content by its SHA-256, `None` if the server lacks it
+    pub async fn find_file(&self, sha256: &str) -> Result<Option<FileRes>> {
+        match self.json::<(), FileRes>(Method::GET, &format!("/files/sha256/{}", sha256), None).await {
+            Ok(res) => Ok(Some(res)),
+            Err(ClientError::NotFound) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Upload a local file unless the server already has the same content.
+    /// The returned flag tells whether the bytes were transferred.
+    pub async fn upload_file_once(&self, path: &Path) -> Result<(FileRes, bool)> {
+        let local = path.to_path_buf();
+        let (_, sha256) = tokio::task::spawn_blocking(move || hash::sha256_file(&local))
+            .await
+            .map_err(std::io::Error::other)??;
+        if let Some(res) = self.find_file(&sha256).await? {
+            return Ok((res, false))
+        }
+        Ok((self.upload_file(path).await?, true))
+    }
+
+    pub async fn upload_bytes(&self, bytes: Vec<u8>) -> Result<FileRes> {
+        let url = self.url("/files");
+        let res = self.retry(|| {
+            self.execute(self.request(Method::POST, &url).body(bytes.clone()))
+        }).await?;
+        Ok(res.json().await?)
+    }
+
+    /// Upload a zip, tar or tar.gz file and have the server store each of its
+    /// entries, returning them as a cell's files and empty directories
+    pub async fn expand_archive(&self, path: &Path) -> Result<CellFiles> {
+        let url = self.url("/files/expand");
+        let res = self.retry(|| async {
+            let file = tokio::fs::File::open(path).await?;
+            let body = reqwest::Body::wrap_stream(ReaderStream::new(file));
+            self.execute(self.request(Method::POST, &url).body(body)).await
+        }).await?;
+        Ok(res.json().await?)
+    }
+
+    /// Download an uploaded file to `dest`, returning the number of bytes written
+    pub async fn download_file(&self, file_id: uuid::Uuid, dest: &Path) -> Result<u64> {
+        self.download_url(&self.file_url(file_id), dest).await
+    }
+
+    /// Write a cell's files and empty directories to `dest` as one archive
+    pub async fn download_cell_archive(
+        &self,
+        cell_id: uuid::Uuid,
+        format: ArchiveFormat,
+        dest: &Path,
+    ) -> Result<u64> {
+        let url = self.url(&format!("/cells/{}/archive", cell_id));
+        let query = ArchiveQuery { format };
+        let res = self.retry(|| self.execute(self.request(Method::GET, &url).query(&query))).await?;
+        save_response(res, dest).await
+    }
+
+    /// Write an archive of files stored on the server, such as those of a
+    /// cell kept elsewhere, to `dest`
+    pub async fn download_archive(&self, req: &ArchiveReq, format: ArchiveFormat, dest: &Path) -> Result<u64> {
+        let url = self.url("/archive");
+        let query = ArchiveQuery { format };
+        let res = self.retry(|| {
+            self.execute(self.request(Method::POST, &url).query(&query).json(req))
+        }).await?;
+        save_response(res, dest).await
+    }
+
+    /// Download the file behind a `FileProp::url` to `dest`
+    pub async fn download_url(&self, url: &str, dest: &Path) -> Result<u64> {
+        let res = self.get_file(url, 0).await?;
+        save_response(res, dest).await
+    }
+
+    /// Start fetching the file behind a `FileProp::url`, asking for the bytes
+    /// from `offset` on. Servers without range support answer with the whole
+    /// file (200 instead of 206), so check the status before appending.
+    pub async fn get_file(&self, url: &str, offset: u64) -> Result<Response> {
+        self.retry(|| {
+            let mut builder = self.request(Method::GET, url);
+            if offset > 0 {
+                builder = builder.header(header::RANGE, format!("bytes={}-", offset));
+            }
+            self.execute(builder)
+        }).await
+    }
+
+    fn url(&self, path: &str) -> String {
+        format!("{}{}", self.base_url, path)
+    }
+
+    fn request(&self, method: Method, url: &str) -> RequestBuilder {
+        let builder = self.http.request(method, url);
+        match &self.token {
+            Some(token) if self.is_backend(url) => builder.bearer_auth(token),
+            _ => builder,
+        }
+    }
+
+    // Whether `url` is served by the backend, and may get the token. File
+    // URLs pointing at another scheme, host or port must never see it.
+    fn is_backend(&self, url: &str) -> bool {
+        match (reqwest::Url::parse(&self.base_url), reqwest::Url::parse(url)) {
+            (Ok(base), Ok(url)) => base.origin() == url.origin(),
+            _ => false,
+        }
+    }
+
+    async fn json<B, T>(&self, method: Method, path: &str, body: Option<&B>) -> Result<T>
+    where
+        B: serde::Serialize + ?Sized,
+        T: DeserializeOwned,
+    {
+        let url = self.url(path);
+        let res = self.retry(|| {
+            let mut builder = self.request(method.clone(), &url);
+            if let Some(body) = body {
+                builder = builder.json(body);
+            }
+            self.execute(builder)
+        }).await?;
+        Ok(res.json().await?)
+    }
+
+    async fn query<Q, T>(&self, path: &str, query: &Q) -> Result<T>
+    where
+        Q: serde::Serialize + ?Sized,
+        T: DeserializeOwned,
+    {
+        let url = self.url(path);
+        let res = self.retry(|| self.execute(self.request(Method::GET, &url).query(query))).await?;
+        Ok(res.json().await?)
+    }
+
+    async fn execute(&self, builder: RequestBuilder) -> Result<Response> {
+        let res = builder.send().await?;
+        let status = res.status();
+        if status.is_success() {
+            return Ok(res)
+        }
+        let body = res.text().await.unwrap_or_default();
+        Err(ClientError::from_status(status, body))
+    }
+
+    async fn retry<F, Fut>(&self, mut attempt: F) -> Result<Response>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<Response>>,
+    {
+        let mut delay = self.retry_delay;
+        let mut tries = 0;
+        loop {
+            match attempt().await {
+                Err(e) if e.is_retryable() && tries < self.max_retries => {
+                    warn!("retrying after error: {}", e);
+                    tokio::time::sleep(delay).await;
+                    delay *= 2;
+                    tries += 1;
+                }
+                res => return res,
+            }
+        }
+    }
+}
+
+// Write the body of `res` to `dest` as it arrives
+async fn save_response(res: Response, dest: &Path) -> Result<u64> {
+    let mut file = tokio::fs::File::create(dest).await?;
+    let mut written = 0;
+    let mut stream = res.bytes_stream();
+    while let Some(chunk) = stream.next().await {
+        let chunk = chunk?;
+        file.write_all(&chunk).await?;
+        written += chunk.len() as u64;
+    }
+    file.flush().await?;
+    Ok(written)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn token_stays_with_backend() {
+        let client = FlowfsClient::new("http://host:80/api/");
+        assert!(client.is_backend("http://host/api/cells"));
+        assert!(client.is_backend("http://host:80/files/1"));
+        assert!(!client.is_backend("http://host:8080/api/cells"));
+        assert!(!client.is_backend("https://host/api/cells"));
+        let client = FlowfsClient::new("https://example.com");
+        assert!(client.is_backend("https://example.com/files/1"));
+        assert!(!client.is_backend("https://example.com.evil.org/files/1"));
+        assert!(!client.is_backend("https://example.community/files/1"));
+        assert!(!client.is_backend("files/1"));
+    }
+}
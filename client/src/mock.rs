@@ -0,0 +1,1321 @@
+//! In-memory stand-in for the backend, serving the same routes on a random
+//! local port so code built on [`FlowfsClient`] can be tested without Postgres.
+
+use std::collections::HashMap;
+use std::io::{Read, Write};
+use std::sync::{Arc, Mutex};
+
+use async_zip::{Compression, ZipEntryBuilder};
+use async_zip::base::read::mem::ZipFileReader;
+use async_zip::tokio::write::ZipFileWriter;
+use axum::{
+    body::Bytes,
+    extract::{DefaultBodyLimit, Path, Query, State},
+    http::{header, StatusCode},
+    response::{IntoResponse, Json, Response},
+    routing::{get, patch, post},
+    Router,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::FlowfsClient;
+use crate::diff::diff_files;
+use crate::patch::{self as file_patch, PatchError};
+use crate::tree;
+use crate::validate::validate_path;
+use crate::model::*;
+
+#[derive(Default, Clone)]
+struct Store {
+    url:                String,
+    users:              HashMap<uuid::Uuid, User>,
+    cells:              HashMap<uuid::Uuid, (CellReq, chrono::NaiveDateTime)>,
+    family_tree:        Vec<(uuid::Uuid, uuid::Uuid)>,
+    // Only for cells changed since they were created, as in the backend
+    revisions:          HashMap<uuid::Uuid, Vec<Revision>>,
+    files:              HashMap<uuid::Uuid, Vec<u8>>,
+}
+
+type Shared = Arc<Mutex<Store>>;
+
+pub struct MockServer {
+    url:                String,
+    store:              Shared,
+    task:               tokio::task::JoinHandle<()>,
+}
+
+impl MockServer {
+    pub async fn start() -> Self {
+        let store = Shared::default();
+        let app = Router::new()
+            .route("/users", get(list_users).post(create_user))
+            .route("/users/:user_id", get(show_user).delete(delete_user))
+            .route("/users/:user_id/export", get(export_user))
+            .route("/users/:user_id/import", post(import_user))
+            .route("/cells", get(list_cells).post(create_cell))
+            .route("/cells/batch", post(create_cells))
+            .route("/cells/roots", get(list_roots))
+            .route("/cells/leaves", get(list_leaves))
+            .route("/cells/:cell_id/ancestors", get(list_ancestors))
+            .route("/cells/:cell_id/descendants", get(list_descendants))
+            .route("/cells/:cell_id/path/:other_id", get(shortest_path))
+            .route("/cells/:cell_id/common/:other_id", get(common_ancestors))
+            .route("/cells/:cell_id/diff/:other_id", get(diff_cells))
+            .route("/cells/:cell_id/fork", post(fork_cell))
+            .route("/cells/:cell_id", get(show_cell).put(update_cell).delete(delete_cell))
+            .route("/cells/:cell_id/revisions", get(list_revisions))
+            .route("/cells/:cell_id/revisions/diff", get(diff_revisions))
+            .route("/cells/:cell_id/revisions/:revision", get(show_revision))
+            .route("/cells/:cell_id/revisions/:revision/revert", post(revert_cell))
+            .route("/cells/:cell_id/files", patch(patch_files))
+            .route("/cells/:cell_id/files/*path", get(show_file))
+            .route("/cells/:cell_id/archive", get(cell_archive))
+            .route("/archive", post(make_archive))
+            .route("/files", get(search_files).post(upload_file))
+            .route("/files/expand", post(expand_archive))
+            .route("/files/:file_id", get(download_file))
+            .route("/files/sha256/:sha256", get(find_file))
+            .layer(DefaultBodyLimit::disable())
+            .with_state(store.clone());
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        store.lock().unwrap().url = url.clone();
+        let task = tokio::spawn(async move {
+            axum::serve(listener, app).await.unwrap();
+        });
+        MockServer { url, store, task }
+    }
+
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    /// A client pointed at this server, without retries
+    pub fn client(&self) -> FlowfsClient {
+        FlowfsClient::new(&self.url).with_retries(0, std::time::Duration::ZERO)
+    }
+
+    pub fn cell_count(&self) -> usize {
+        self.store.lock().unwrap().cells.len()
+    }
+
+    pub fn file_count(&self) -> usize {
+        self.store.lock().unwrap().files.len()
+    }
+}
+
+impl Drop for MockServer {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+async fn list_users(State(store): State<Shared>) -> Json<Users> {
+    let store = store.lock().unwrap();
+    let users = store.users.values()
+        .map(|u| UserRes { user_id: u.user_id, user_name: u.user_name.clone() })
+        .collect();
+    Json(Users { users })
+}
+
+async fn create_user(
+    State(store): State<Shared>,
+    Json(payload): Json<User>,
+) -> Result<Json<IdRes>, StatusCode> {
+    let mut store = store.lock().unwrap();
+    if store.users.contains_key(&payload.user_id) {
+        return Err(StatusCode::BAD_REQUEST)
+    }
+    let id = payload.user_id;
+    store.users.insert(id, payload);
+    Ok(Json(IdRes { id }))
+}
+
+async fn show_user(
+    Path(user_id): Path<uuid::Uuid>,
+    State(store): State<Shared>,
+) -> Result<Json<UserRes>, StatusCode> {
+    let store = store.lock().unwrap();
+    let user = store.users.get(&user_id).ok_or(StatusCode::NOT_FOUND)?;
+    Ok(Json(UserRes { user_id, user_name: user.user_name.clone() }))
+}
+
+async fn delete_user(
+    Path(user_id): Path<uuid::Uuid>,
+    State(store): State<Shared>,
+) -> Json<IdRes> {
+    store.lock().unwrap().users.remove(&user_id);
+    Json(IdRes { id: user_id })
+}
+
+const EXPORT_FORMAT: &str = "flowfs-export";
+const EXPORT_VERSION: u32 = 2;
+
+// The backend's export manifest, for archives either of them can import
+#[derive(Serialize, Deserialize)]
+struct Manifest {
+    format:             String,
+    version:            u32,
+    exported_at:        chrono::NaiveDateTime,
+    user:               User,
+    cells:              Vec<ExportedCell>,
+    family_tree:        Vec<Edge>,
+    // Since version 2
+    #[serde(default)]
+    revisions:          Vec<Revision>,
+    files:              Vec<uuid::Uuid>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct ExportedCell {
+    cell_id:            uuid::Uuid,
+    user_id:            uuid::Uuid,
+    device_id:          String,
+    text:               String,
+    is_open:            bool,
+    fileprops:          Vec<FileProp>,
+    #[serde(default)]
+    dirs:               Vec<String>,
+    created_at:         chrono::NaiveDateTime,
+}
+
+async fn export_user(
+    Path(user_id): Path<uuid::Uuid>,
+    State(store): State<Shared>,
+) -> Result<Response, StatusCode> {
+    let store = store.lock().unwrap();
+    let user = store.users.get(&user_id).ok_or(StatusCode::NOT_FOUND)?.clone();
+    let mut cells: Vec<ExportedCell> = store.cells.values()
+        .filter(|(cell, _)| cell.user_id == user_id)
+        .map(|(cell, created_at)| ExportedCell {
+            cell_id: cell.cell_id,
+            user_id,
+            device_id: cell.device_id.clone(),
+            text: cell.text.clone(),
+            is_open: cell.is_open,
+            fileprops: cell.fileprops.clone(),
+            dirs: cell.dirs.clone(),
+            created_at: *created_at,
+        })
+        .collect();
+    cells.sort_by_key(|cell| cell.created_at);
+    let exported = |id: &uuid::Uuid| cells.iter().any(|cell| cell.cell_id == *id);
+    let family_tree = store.family_tree.iter()
+        .filter(|(child_id, parent_id)| exported(child_id) && exported(parent_id))
+        .map(|(child_id, parent_id)| Edge { child_id: *child_id, parent_id: *parent_id })
+        .collect();
+    let revisions: Vec<Revision> = cells.iter()
+        .flat_map(|cell| store.revisions.get(&cell.cell_id).cloned().unwrap_or_default())
+        .collect();
+    let mut files: Vec<uuid::Uuid> = cells.iter()
+        .flat_map(|cell| cell.fileprops.iter())
+        .chain(revisions.iter().flat_map(|revision| revision.fileprops.iter()))
+        .filter_map(|fileprop| file_id(&fileprop.url))
+        .filter(|file_id| store.files.contains_key(file_id))
+        .collect();
+    files.sort();
+    files.dedup();
+    let manifest = Manifest {
+        format: EXPORT_FORMAT.to_string(),
+        version: EXPORT_VERSION,
+        exported_at: chrono::Utc::now().naive_utc(),
+        user,
+        cells,
+        family_tree,
+        revisions,
+        files,
+    };
+
+    let mut builder = tar::Builder::new(vec![]);
+    let manifest_json = serde_json::to_vec_pretty(&manifest).unwrap();
+    append(&mut builder, "manifest.json", &manifest_json);
+    for file_id in manifest.files.iter() {
+        append(&mut builder, &format!("files/{}", file_id), &store.files[file_id]);
+    }
+    let archive = builder.into_inner().unwrap();
+    Ok(([(header::CONTENT_TYPE, "application/x-tar")], archive).into_response())
+}
+
+// Point files at the content this server stores, imported or found here
+fn localize_urls(fileprops: &mut [FileProp], local_ids: &HashMap<uuid::Uuid, uuid::Uuid>, url: &str) {
+    for fileprop in fileprops.iter_mut() {
+        if let Some(local_id) = file_id(&fileprop.url).and_then(|id| local_ids.get(&id)) {
+            fileprop.url = format!("{}/files/{}", url, local_id);
+        }
+    }
+}
+
+fn append<W: Write>(builder: &mut tar::Builder<W>, name: &str, bytes: &[u8]) {
+    let mut header = tar::Header::new_gnu();
+    header.set_size(bytes.len() as u64);
+    header.set_mode(0o644);
+    header.set_cksum();
+    builder.append_data(&mut header, name, bytes).unwrap();
+}
+
+// Like the backend, everything is imported or nothing: the import works on
+// a copy of the store that replaces it once every cell went in
+async fn import_user(
+    Path(user_id): Path<uuid::Uuid>,
+    Query(options): Query<ImportOptions>,
+    State(store): State<Shared>,
+    body: Bytes,
+) -> Result<Json<ImportRes>, StatusCode> {
+    let mut archive = tar::Archive::new(&body[..]);
+    let mut entries = archive.entries().map_err(|_| StatusCode::BAD_REQUEST)?;
+    let mut entry = entries.next()
+        .and_then(|entry| entry.ok())
+        .filter(|entry| entry.path().is_ok_and(|p| p.to_str() == Some("manifest.json")))
+        .ok_or(StatusCode::BAD_REQUEST)?;
+    let mut manifest_json = vec![];
+    entry.read_to_end(&mut manifest_json).map_err(|_| StatusCode::BAD_REQUEST)?;
+    let manifest: Manifest = serde_json::from_slice(&manifest_json).map_err(|_| StatusCode::BAD_REQUEST)?;
+    if manifest.format != EXPORT_FORMAT || manifest.version > EXPORT_VERSION {
+        return Err(StatusCode::BAD_REQUEST)
+    }
+    let mut contents = HashMap::new();
+    for entry in entries {
+        let mut entry = entry.map_err(|_| StatusCode::BAD_REQUEST)?;
+        let name = entry.path().map_err(|_| StatusCode::BAD_REQUEST)?.to_string_lossy().to_string();
+        let Some(file_id) = name.strip_prefix("files/")
+            .and_then(|id| uuid::Uuid::parse_str(id).ok())
+            .filter(|id| manifest.files.contains(id)) else {
+            continue
+        };
+        let mut bytes = vec![];
+        entry.read_to_end(&mut bytes).map_err(|_| StatusCode::BAD_REQUEST)?;
+        contents.insert(file_id, bytes);
+    }
+
+    let mut shared = store.lock().unwrap();
+    let mut store = shared.clone();
+    let mut res = ImportRes { created: vec![], skipped: vec![], overwritten: vec![], files: 0 };
+    // Content the store already has is kept once, under its existing id
+    let mut local_ids = HashMap::new();
+    for (file_id, bytes) in contents {
+        let existing = store.files.iter().find(|(_, stored)| **stored == bytes).map(|(id, _)| *id);
+        let local_id = match existing {
+            Some(id) => id,
+            None => {
+                store.files.insert(file_id, bytes);
+                res.files += 1;
+                file_id
+            }
+        };
+        local_ids.insert(file_id, local_id);
+    }
+    let user = User { user_id, ..manifest.user };
+    store.users.entry(user_id).or_insert(user);
+    for cell in manifest.cells {
+        let cell_id = cell.cell_id;
+        match store.cells.get(&cell_id) {
+            None => res.created.push(cell_id),
+            Some((stored, _)) if stored.user_id != user_id => return Err(StatusCode::CONFLICT),
+            Some(_) => match options.on_conflict {
+                OnConflict::Fail => return Err(StatusCode::CONFLICT),
+                OnConflict::Skip => {
+                    res.skipped.push(cell_id);
+                    continue
+                }
+                // Replies to the cell stay, its own parents and history go
+                OnConflict::Overwrite => {
+                    store.family_tree.retain(|(child_id, _)| *child_id != cell_id);
+                    store.revisions.remove(&cell_id);
+                    res.overwritten.push(cell_id);
+                }
+            },
+        }
+        let (mut fileprops, dirs) = tree::normalize(&cell.fileprops, &cell.dirs)
+            .map_err(|_| StatusCode::BAD_REQUEST)?;
+        localize_urls(&mut fileprops, &local_ids, &store.url);
+        let mut revisions: Vec<Revision> = manifest.revisions.iter()
+            .filter(|revision| revision.cell_id == cell_id)
+            .cloned()
+            .collect();
+        for revision in revisions.iter_mut() {
+            (revision.fileprops, revision.dirs) = tree::normalize(&revision.fileprops, &revision.dirs)
+                .map_err(|_| StatusCode::BAD_REQUEST)?;
+            localize_urls(&mut revision.fileprops, &local_ids, &store.url);
+        }
+        if !revisions.is_empty() {
+            store.revisions.insert(cell_id, revisions);
+        }
+        let req = CellReq {
+            cell_id,
+            user_id,
+            device_id: cell.device_id,
+            text: cell.text,
+            is_open: cell.is_open,
+            fileprops,
+            dirs,
+            rootdir: None,
+            parent_ids: vec![],
+            child_ids: vec![],
+        };
+        store.cells.insert(cell_id, (req, cell.created_at));
+    }
+    for edge in manifest.family_tree {
+        let edge = (edge.child_id, edge.parent_id);
+        if !store.family_tree.contains(&edge) {
+            store.family_tree.push(edge);
+        }
+    }
+    *shared = store;
+    Ok(Json(res))
+}
+
+async fn list_cells(
+    State(store): State<Shared>,
+    Json(payload): Json<CellFilter>,
+) -> Json<Cells> {
+    let store = store.lock().unwrap();
+    let mut matched: Vec<_> = store.cells.values()
+        .filter(|(cell, created_at)| {
+            cell.user_id == payload.user_id
+                && payload.device_id.as_ref().is_none_or(|d| &cell.device_id == d)
+                && payload.text.as_ref().is_none_or(|t| cell.text.to_lowercase().contains(&t.to_lowercase()))
+                && payload.since.is_none_or(|s| *created_at >= s)
+                && payload.until.is_none_or(|u| *created_at < u)
+        })
+        .map(|(cell, created_at)| (cell.cell_id, *created_at))
+        .collect();
+    matched.sort_by_key(|(_, created_at)| std::cmp::Reverse(*created_at));
+    if let Some(limit) = payload.limit {
+        matched.truncate(limit.max(0) as usize);
+    }
+    let cells = matched.into_iter()
+        .filter_map(|(id, _)| extract(&store, id))
+        .collect();
+    Json(Cells { cells })
+}
+
+async fn create_cell(
+    State(store): State<Shared>,
+    Json(payload): Json<CellReq>,
+) -> Result<Json<IdRes>, StatusCode> {
+    let payload = cell_layout(payload)?;
+    let mut shared = store.lock().unwrap();
+    let mut store = shared.clone();
+    if insert_cell(&mut store, &payload)? {
+        insert_edges(&mut store, &payload)?;
+        if is_ancestor(&store, payload.cell_id, payload.cell_id) {
+            return Err(StatusCode::BAD_REQUEST)
+        }
+    } else if !has_edges(&store, &payload) {
+        return Err(StatusCode::CONFLICT)
+    }
+    *shared = store;
+    Ok(Json(IdRes { id: payload.cell_id }))
+}
+
+// All cells or none, each with its result, as the backend reports them
+async fn create_cells(
+    State(store): State<Shared>,
+    Json(payload): Json<CellBatch>,
+) -> (StatusCode, Json<BatchRes>) {
+    let mut results: Vec<BatchItem> = payload.cells.iter()
+        .map(|cell| BatchItem { cell_id: cell.cell_id, status: BatchStatus::Skipped, error: None })
+        .collect();
+    let mut cells = vec![];
+    for (index, cell) in payload.cells.iter().enumerate() {
+        if payload.cells[..index].iter().any(|c| c.cell_id == cell.cell_id) {
+            results[index] = batch_item(cell.cell_id, BatchStatus::Invalid, "appears more than once");
+        }
+        match cell_layout(cell.clone()) {
+            Ok(cell) => cells.push(cell),
+            Err(_) => results[index] = batch_item(cell.cell_id, BatchStatus::Invalid, "has invalid files"),
+        }
+    }
+    if results.iter().any(|r| r.status != BatchStatus::Skipped) {
+        return batch_res(false, results)
+    }
+
+    let mut shared = store.lock().unwrap();
+    let mut store = shared.clone();
+    // Cells first and their edges after, so edges may point anywhere in the batch
+    for (index, cell) in cells.iter().enumerate() {
+        results[index] = match insert_cell(&mut store, cell) {
+            Ok(true) => batch_item(cell.cell_id, BatchStatus::Created, ""),
+            Ok(false) => batch_item(cell.cell_id, BatchStatus::Existing, ""),
+            Err(code) => failed_item(cell.cell_id, code),
+        };
+    }
+    for (index, cell) in cells.iter().enumerate() {
+        let res = match results[index].status {
+            BatchStatus::Created => insert_edges(&mut store, cell),
+            BatchStatus::Existing if has_edges(&store, cell) => Ok(()),
+            BatchStatus::Existing => Err(StatusCode::CONFLICT),
+            _ => continue,
+        };
+        if let Err(code) = res {
+            results[index] = failed_item(cell.cell_id, code);
+        }
+    }
+    for (index, cell) in cells.iter().enumerate() {
+        if results[index].status == BatchStatus::Created && is_ancestor(&store, cell.cell_id, cell.cell_id) {
+            results[index] = batch_item(cell.cell_id, BatchStatus::Invalid, "is on a cycle");
+        }
+    }
+    let stored = |r: &BatchItem| matches!(r.status, BatchStatus::Created | BatchStatus::Existing);
+    if !results.iter().all(stored) {
+        for result in results.iter_mut().filter(|r| stored(r)) {
+            result.status = BatchStatus::Skipped;
+            result.error = None;
+        }
+        return batch_res(false, results)
+    }
+    *shared = store;
+    batch_res(true, results)
+}
+
+fn batch_item(cell_id: uuid::Uuid, status: BatchStatus, error: &str) -> BatchItem {
+    let error = (!error.is_empty()).then(|| format!("cell {} {}", cell_id, error));
+    BatchItem { cell_id, status, error }
+}
+
+fn failed_item(cell_id: uuid::Uuid, code: StatusCode) -> BatchItem {
+    match code {
+        StatusCode::BAD_REQUEST => batch_item(cell_id, BatchStatus::Invalid, "refers to an unknown user or cell"),
+        StatusCode::CONFLICT => batch_item(cell_id, BatchStatus::Conflict, "already exists with other content"),
+        _ => batch_item(cell_id, BatchStatus::Failed, "could not be stored"),
+    }
+}
+
+fn batch_res(committed: bool, results: Vec<BatchItem>) -> (StatusCode, Json<BatchRes>) {
+    let status = if committed {
+        StatusCode::OK
+    } else if results.iter().any(|r| r.status == BatchStatus::Failed) {
+        StatusCode::INTERNAL_SERVER_ERROR
+    } else if results.iter().any(|r| r.status == BatchStatus::Invalid) {
+        StatusCode::BAD_REQUEST
+    } else {
+        StatusCode::CONFLICT
+    };
+    (status, Json(BatchRes { committed, results }))
+}
+
+// Kept flat and normalized, as the backend stores it
+fn cell_layout(mut payload: CellReq) -> Result<CellReq, StatusCode> {
+    let layout = match payload.rootdir.take() {
+        Some(_) if !payload.fileprops.is_empty() || !payload.dirs.is_empty() => {
+            return Err(StatusCode::BAD_REQUEST)
+        }
+        Some(rootdir) => tree::to_flat(&rootdir),
+        None => tree::normalize(&payload.fileprops, &payload.dirs),
+    };
+    (payload.fileprops, payload.dirs) = layout.map_err(|_| StatusCode::BAD_REQUEST)?;
+    Ok(payload)
+}
+
+// Whether the cell was created; a repeated request leaves the stored cell
+// alone, a different one for the same id is a conflict
+fn insert_cell(store: &mut Store, payload: &CellReq) -> Result<bool, StatusCode> {
+    let Some((stored, _)) = store.cells.get(&payload.cell_id) else {
+        store.cells.insert(payload.cell_id, (payload.clone(), chrono::Utc::now().naive_utc()));
+        return Ok(true)
+    };
+    let files = |cell: &CellReq| -> Vec<(String, String)> {
+        cell.fileprops.iter().map(|f| (f.path.clone(), f.url.clone())).collect()
+    };
+    let same = stored.user_id == payload.user_id
+        && stored.device_id == payload.device_id
+        && stored.text == payload.text
+        && stored.is_open == payload.is_open
+        && files(stored) == files(payload)
+        && stored.dirs == payload.dirs;
+    if same { Ok(false) } else { Err(StatusCode::CONFLICT) }
+}
+
+fn insert_edges(store: &mut Store, payload: &CellReq) -> Result<(), StatusCode> {
+    let id = payload.cell_id;
+    let mut linked_ids = payload.parent_ids.iter().chain(payload.child_ids.iter());
+    if linked_ids.any(|l| *l == id || !store.cells.contains_key(l)) {
+        return Err(StatusCode::BAD_REQUEST)
+    }
+    for parent_id in payload.parent_ids.iter() {
+        store.family_tree.push((id, *parent_id));
+    }
+    for child_id in payload.child_ids.iter() {
+        store.family_tree.push((*child_id, id));
+    }
+    Ok(())
+}
+
+fn has_edges(store: &Store, payload: &CellReq) -> bool {
+    let id = payload.cell_id;
+    payload.parent_ids.iter().all(|p| store.family_tree.contains(&(id, *p)))
+        && payload.child_ids.iter().all(|c| store.family_tree.contains(&(*c, id)))
+}
+
+// Whether `ancestor_id` is reached from `cell_id` going up one edge or more
+fn is_ancestor(store: &Store, cell_id: uuid::Uuid, ancestor_id: uuid::Uuid) -> bool {
+    let mut seen = vec![];
+    let mut todo = vec![cell_id];
+    while let Some(id) = todo.pop() {
+        for (_, parent_id) in store.family_tree.iter().filter(|(child_id, _)| *child_id == id) {
+            if *parent_id == ancestor_id {
+                return true
+            }
+            if !seen.contains(parent_id) {
+                seen.push(*parent_id);
+                todo.push(*parent_id);
+            }
+        }
+    }
+    false
+}
+
+// A reply sharing its parent's stored files; retrying is safe as for create_cell
+async fn fork_cell(
+    Path(parent_id): Path<uuid::Uuid>,
+    State(store): State<Shared>,
+    Json(payload): Json<CellFork>,
+) -> Result<Json<IdRes>, StatusCode> {
+    let mut shared = store.lock().unwrap();
+    let mut store = shared.clone();
+    let (parent, _) = store.cells.get(&parent_id).ok_or(StatusCode::NOT_FOUND)?;
+    let (fileprops, dirs) = apply_ops(&parent.fileprops, &parent.dirs, &payload.ops)?;
+    let req = CellReq {
+        cell_id: payload.cell_id,
+        user_id: payload.user_id,
+        device_id: payload.device_id,
+        text: payload.text,
+        is_open: payload.is_open,
+        fileprops,
+        dirs,
+        rootdir: None,
+        parent_ids: vec![parent_id],
+        child_ids: vec![],
+    };
+    if insert_cell(&mut store, &req)? {
+        insert_edges(&mut store, &req)?;
+    } else if !has_edges(&store, &req) {
+        return Err(StatusCode::CONFLICT)
+    }
+    *shared = store;
+    Ok(Json(IdRes { id: req.cell_id }))
+}
+
+async fn show_cell(
+    Path(cell_id): Path<uuid::Uuid>,
+    State(store): State<Shared>,
+) -> Result<Json<CellExtracted>, StatusCode> {
+    let store = store.lock().unwrap();
+    extract(&store, cell_id).map(Json).ok_or(StatusCode::NOT_FOUND)
+}
+
+async fn delete_cell(
+    Path(cell_id): Path<uuid::Uuid>,
+    State(store): State<Shared>,
+) -> Json<IdRes> {
+    let mut store = store.lock().unwrap();
+    store.cells.remove(&cell_id);
+    store.revisions.remove(&cell_id);
+    store.family_tree.retain(|(child, parent)| *child != cell_id && *parent != cell_id);
+    Json(IdRes { id: cell_id })
+}
+
+async fn update_cell(
+    Path(cell_id): Path<uuid::Uuid>,
+    State(store): State<Shared>,
+    Json(payload): Json<CellUpdate>,
+) -> Result<Json<Revision>, StatusCode> {
+    let layout = match (&payload.rootdir, &payload.fileprops, &payload.dirs) {
+        (None, None, None) => None,
+        (Some(_), Some(_), _) | (Some(_), _, Some(_)) => return Err(StatusCode::BAD_REQUEST),
+        (Some(rootdir), _, _) => Some(tree::to_flat(rootdir)),
+        (None, fileprops, dirs) => Some(tree::normalize(
+            fileprops.as_deref().unwrap_or_default(),
+            dirs.as_deref().unwrap_or_default(),
+        )),
+    };
+    let files = layout.transpose().map_err(|_| StatusCode::BAD_REQUEST)?;
+    let mut store = store.lock().unwrap();
+    change_cell(&mut store, cell_id, payload.device_id.as_deref(), |cell| {
+        if let Some(text) = payload.text {
+            cell.text = text;
+        }
+        if let Some(is_open) = payload.is_open {
+            cell.is_open = is_open;
+        }
+        if let Some((fileprops, dirs)) = files {
+            cell.fileprops = fileprops;
+            cell.dirs = dirs;
+        }
+        if let Some(parent_ids) = payload.parent_ids {
+            cell.parent_ids = parent_ids;
+        }
+        Ok(())
+    }).map(Json)
+}
+
+async fn list_revisions(
+    Path(cell_id): Path<uuid::Uuid>,
+    State(store): State<Shared>,
+) -> Result<Json<Vec<Revision>>, StatusCode> {
+    load_revisions(&store.lock().unwrap(), cell_id).map(Json).ok_or(StatusCode::NOT_FOUND)
+}
+
+async fn show_revision(
+    Path((cell_id, revision)): Path<(uuid::Uuid, i32)>,
+    State(store): State<Shared>,
+) -> Result<Json<Revision>, StatusCode> {
+    find_revision(&store.lock().unwrap(), cell_id, revision).map(Json).ok_or(StatusCode::NOT_FOUND)
+}
+
+async fn diff_revisions(
+    Path(cell_id): Path<uuid::Uuid>,
+    Query(query): Query<DiffQuery>,
+    State(store): State<Shared>,
+) -> Result<Json<RevisionDiff>, StatusCode> {
+    let revisions = load_revisions(&store.lock().unwrap(), cell_id).ok_or(StatusCode::NOT_FOUND)?;
+    let latest = revisions.last().map_or(1, |r| r.revision);
+    let to = query.to.unwrap_or(latest);
+    let from = query.from.unwrap_or((to - 1).max(1));
+    let find = |revision: i32| revisions.iter().find(|r| r.revision == revision).ok_or(StatusCode::NOT_FOUND);
+    Ok(Json(diff_revision(find(from)?, find(to)?)))
+}
+
+#[derive(Deserialize)]
+struct RevertQuery {
+    device_id:          Option<String>,
+}
+
+async fn revert_cell(
+    Path((cell_id, revision)): Path<(uuid::Uuid, i32)>,
+    Query(query): Query<RevertQuery>,
+    State(store): State<Shared>,
+) -> Result<Json<Revision>, StatusCode> {
+    let mut store = store.lock().unwrap();
+    let target = find_revision(&store, cell_id, revision).ok_or(StatusCode::NOT_FOUND)?;
+    change_cell(&mut store, cell_id, query.device_id.as_deref(), |cell| {
+        *cell = Revision { revision: cell.revision, device_id: cell.device_id.take(), ..target };
+        Ok(())
+    }).map(Json)
+}
+
+fn diff_revision(from: &Revision, to: &Revision) -> RevisionDiff {
+    RevisionDiff {
+        cell_id: to.cell_id,
+        from: from.revision,
+        to: to.revision,
+        text: (from.text != to.text).then(|| Change { old: from.text.clone(), new: to.text.clone() }),
+        is_open: (from.is_open != to.is_open).then_some(Change { old: from.is_open, new: to.is_open }),
+        files: diff_files(&from.fileprops, &from.dirs, &to.fileprops, &to.dirs),
+        parents_added: to.parent_ids.iter().filter(|id| !from.parent_ids.contains(id)).cloned().collect(),
+        parents_removed: from.parent_ids.iter().filter(|id| !to.parent_ids.contains(id)).cloned().collect(),
+    }
+}
+
+// Apply `change` to a cell and record the result as a new revision, unless
+// nothing changed; either way the latest revision is returned. Files must
+// come normalized.
+fn change_cell(
+    store: &mut Store,
+    cell_id: uuid::Uuid,
+    device_id: Option<&str>,
+    change: impl FnOnce(&mut Revision) -> Result<(), StatusCode>,
+) -> Result<Revision, StatusCode> {
+    let current = cell_state(store, cell_id).ok_or(StatusCode::NOT_FOUND)?;
+    let mut next = current.clone();
+    change(&mut next)?;
+    next.parent_ids.sort();
+    next.parent_ids.dedup();
+    let files_changed = current.fileprops != next.fileprops || current.dirs != next.dirs;
+    let parents_changed = current.parent_ids != next.parent_ids;
+    if current.text == next.text && current.is_open == next.is_open && !files_changed && !parents_changed {
+        return load_revisions(store, cell_id).and_then(|r| r.into_iter().last()).ok_or(StatusCode::NOT_FOUND)
+    }
+    // Parents must exist and must be neither the cell nor one of its replies
+    if parents_changed {
+        let replies = reach(store, cell_id, Direction::Down, MAX_DEPTH);
+        let bad_parent = |id: &uuid::Uuid| {
+            *id == cell_id || !store.cells.contains_key(id) || replies.iter().any(|(reply_id, _)| reply_id == id)
+        };
+        if next.parent_ids.iter().any(bad_parent) {
+            return Err(StatusCode::BAD_REQUEST)
+        }
+    }
+
+    // The cell as created goes first, on its first change
+    let now = chrono::Utc::now().naive_utc();
+    store.revisions.entry(cell_id).or_insert_with(|| vec![current]);
+    let (cell, _) = store.cells.get_mut(&cell_id).ok_or(StatusCode::NOT_FOUND)?;
+    cell.text = next.text.clone();
+    cell.is_open = next.is_open;
+    cell.fileprops = next.fileprops.clone();
+    cell.dirs = next.dirs.clone();
+    store.family_tree.retain(|(child_id, _)| *child_id != cell_id);
+    store.family_tree.extend(next.parent_ids.iter().map(|parent_id| (cell_id, *parent_id)));
+    let revision = Revision {
+        revision: next.revision + 1,
+        device_id: device_id.map(|d| d.to_string()),
+        created_at: now,
+        ..next
+    };
+    store.revisions.entry(cell_id).or_default().push(revision.clone());
+    Ok(revision)
+}
+
+// Every revision of a cell, oldest first
+fn load_revisions(store: &Store, cell_id: uuid::Uuid) -> Option<Vec<Revision>> {
+    match store.revisions.get(&cell_id) {
+        Some(revisions) => Some(revisions.clone()),
+        None => Some(vec![cell_state(store, cell_id)?]),
+    }
+}
+
+fn find_revision(store: &Store, cell_id: uuid::Uuid, revision: i32) -> Option<Revision> {
+    load_revisions(store, cell_id)?.into_iter().find(|r| r.revision == revision)
+}
+
+// The cell as stored now, numbered as its latest revision. Until it has
+// any, it is revision 1 from the device that created it.
+fn cell_state(store: &Store, cell_id: uuid::Uuid) -> Option<Revision> {
+    let (cell, created_at) = store.cells.get(&cell_id)?;
+    let mut parent_ids: Vec<uuid::Uuid> = store.family_tree.iter()
+        .filter(|(child_id, _)| *child_id == cell_id)
+        .map(|(_, parent_id)| *parent_id)
+        .collect();
+    parent_ids.sort();
+    parent_ids.dedup();
+    let latest = store.revisions.get(&cell_id).and_then(|r| r.last()).map(|r| r.revision);
+    Some(Revision {
+        cell_id,
+        revision: latest.unwrap_or(1),
+        device_id: Some(cell.device_id.clone()),
+        text: cell.text.clone(),
+        is_open: cell.is_open,
+        fileprops: cell.fileprops.clone(),
+        dirs: cell.dirs.clone(),
+        parent_ids,
+        created_at: *created_at,
+    })
+}
+
+async fn list_ancestors(
+    Path(cell_id): Path<uuid::Uuid>,
+    Query(query): Query<DagQuery>,
+    State(store): State<Shared>,
+) -> Result<Json<Dag>, StatusCode> {
+    walk(&store.lock().unwrap(), cell_id, Direction::Up, &query).map(Json)
+}
+
+async fn list_descendants(
+    Path(cell_id): Path<uuid::Uuid>,
+    Query(query): Query<DagQuery>,
+    State(store): State<Shared>,
+) -> Result<Json<Dag>, StatusCode> {
+    walk(&store.lock().unwrap(), cell_id, Direction::Down, &query).map(Json)
+}
+
+async fn list_roots(
+    Query(filter): Query<DagFilter>,
+    State(store): State<Shared>,
+) -> Json<Dag> {
+    Json(list_ends(&store.lock().unwrap(), Direction::Up, &filter))
+}
+
+async fn list_leaves(
+    Query(filter): Query<DagFilter>,
+    State(store): State<Shared>,
+) -> Json<Dag> {
+    Json(list_ends(&store.lock().unwrap(), Direction::Down, &filter))
+}
+
+async fn shortest_path(
+    Path((cell_id, other_id)): Path<(uuid::Uuid, uuid::Uuid)>,
+    Query(query): Query<DagQuery>,
+    State(store): State<Shared>,
+) -> Result<Json<Dag>, StatusCode> {
+    let store = store.lock().unwrap();
+    if !store.cells.contains_key(&cell_id) || !store.cells.contains_key(&other_id) {
+        return Err(StatusCode::NOT_FOUND)
+    }
+    let limit = query.depth.unwrap_or(MAX_DEPTH).min(MAX_DEPTH);
+    let path = [Direction::Down, Direction::Up].into_iter()
+        .find_map(|direction| find_path(&store, cell_id, other_id, direction, limit))
+        .ok_or(StatusCode::NOT_FOUND)?;
+    let depths: Vec<(uuid::Uuid, i32)> = path.iter().enumerate().map(|(i, id)| (*id, i as i32)).collect();
+    let edges = edges_between(&store, &path).into_iter()
+        .filter(|e| path.windows(2).any(|pair| {
+            (e.parent_id, e.child_id) == (pair[0], pair[1]) || (e.child_id, e.parent_id) == (pair[0], pair[1])
+        }))
+        .collect();
+    Ok(Json(Dag { cells: dag_cells(&store, &depths, query.shallow), edges }))
+}
+
+async fn common_ancestors(
+    Path((cell_id, other_id)): Path<(uuid::Uuid, uuid::Uuid)>,
+    Query(query): Query<DagQuery>,
+    State(store): State<Shared>,
+) -> Result<Json<Dag>, StatusCode> {
+    let store = store.lock().unwrap();
+    if !store.cells.contains_key(&cell_id) || !store.cells.contains_key(&other_id) {
+        return Err(StatusCode::NOT_FOUND)
+    }
+    let limit = query.depth.unwrap_or(MAX_DEPTH).min(MAX_DEPTH);
+    let mine: HashMap<uuid::Uuid, i32> = reach(&store, cell_id, Direction::Up, limit).into_iter()
+        .chain([(cell_id, 0)])
+        .collect();
+    let mut common: Vec<(uuid::Uuid, i32)> = reach(&store, other_id, Direction::Up, limit).into_iter()
+        .chain([(other_id, 0)])
+        .filter_map(|(id, depth)| mine.get(&id).map(|mine| (id, depth.max(*mine))))
+        .collect();
+    common.sort_by_key(|(id, depth)| (*depth, *id));
+    let ids: Vec<uuid::Uuid> = common.iter().map(|(id, _)| *id).collect();
+    Ok(Json(Dag { cells: dag_cells(&store, &common, query.shallow), edges: edges_between(&store, &ids) }))
+}
+
+// The backend's bound on walks of the family tree
+const MAX_DEPTH: i32 = 1000;
+
+#[derive(Clone, Copy)]
+enum Direction {
+    Up,
+    Down,
+}
+
+impl Direction {
+    // The cell an edge leads to from `cell_id`, if it starts there
+    fn step(self, (child_id, parent_id): (uuid::Uuid, uuid::Uuid), cell_id: uuid::Uuid) -> Option<uuid::Uuid> {
+        match self {
+            Direction::Up => (child_id == cell_id).then_some(parent_id),
+            Direction::Down => (parent_id == cell_id).then_some(child_id),
+        }
+    }
+}
+
+fn walk(store: &Store, cell_id: uuid::Uuid, direction: Direction, query: &DagQuery) -> Result<Dag, StatusCode> {
+    if !store.cells.contains_key(&cell_id) {
+        return Err(StatusCode::NOT_FOUND)
+    }
+    let limit = query.depth.unwrap_or(MAX_DEPTH).min(MAX_DEPTH);
+    let reached = reach(store, cell_id, direction, limit);
+    let ids: Vec<uuid::Uuid> = reached.iter().map(|(id, _)| *id).chain([cell_id]).collect();
+    Ok(Dag { cells: dag_cells(store, &reached, query.shallow), edges: edges_between(store, &ids) })
+}
+
+// Every cell within `limit` edges of `cell_id`, nearest first
+fn reach(store: &Store, cell_id: uuid::Uuid, direction: Direction, limit: i32) -> Vec<(uuid::Uuid, i32)> {
+    let mut depths: HashMap<uuid::Uuid, i32> = HashMap::from([(cell_id, 0)]);
+    let mut frontier = vec![cell_id];
+    for depth in 1..=limit {
+        frontier = store.family_tree.iter()
+            .filter_map(|edge| frontier.iter().find_map(|id| direction.step(*edge, *id)))
+            .filter(|next| !depths.contains_key(next))
+            .collect();
+        frontier.sort();
+        frontier.dedup();
+        if frontier.is_empty() {
+            break
+        }
+        depths.extend(frontier.iter().map(|id| (*id, depth)));
+    }
+    depths.remove(&cell_id);
+    let mut reached: Vec<(uuid::Uuid, i32)> = depths.into_iter().collect();
+    reached.sort_by_key(|(id, depth)| (*depth, *id));
+    reached
+}
+
+// Breadth-first search from `cell_id` for `other_id`
+fn find_path(
+    store: &Store,
+    cell_id: uuid::Uuid,
+    other_id: uuid::Uuid,
+    direction: Direction,
+    limit: i32,
+) -> Option<Vec<uuid::Uuid>> {
+    let mut came_from: HashMap<uuid::Uuid, uuid::Uuid> = HashMap::from([(cell_id, cell_id)]);
+    let mut frontier = vec![cell_id];
+    let mut depth = 0;
+    while !came_from.contains_key(&other_id) && !frontier.is_empty() && depth < limit {
+        let mut next_frontier = vec![];
+        for prev in frontier {
+            let mut nexts: Vec<uuid::Uuid> = store.family_tree.iter()
+                .filter_map(|edge| direction.step(*edge, prev))
+                .collect();
+            nexts.sort();
+            for next in nexts {
+                if let std::collections::hash_map::Entry::Vacant(entry) = came_from.entry(next) {
+                    entry.insert(prev);
+                    next_frontier.push(next);
+                }
+            }
+        }
+        frontier = next_frontier;
+        depth += 1;
+    }
+    came_from.contains_key(&other_id).then_some(())?;
+    let mut path = vec![other_id];
+    while *path.last().unwrap() != cell_id {
+        path.push(came_from[path.last().unwrap()]);
+    }
+    path.reverse();
+    Some(path)
+}
+
+// The user's cells with no edge going on in `direction`, newest first
+fn list_ends(store: &Store, direction: Direction, filter: &DagFilter) -> Dag {
+    let mut ends: Vec<(uuid::Uuid, chrono::NaiveDateTime)> = store.cells.values()
+        .filter(|(cell, _)| cell.user_id == filter.user_id)
+        .filter(|(cell, _)| store.family_tree.iter().all(|edge| direction.step(*edge, cell.cell_id).is_none()))
+        .map(|(cell, created_at)| (cell.cell_id, *created_at))
+        .collect();
+    ends.sort_by_key(|(_, created_at)| std::cmp::Reverse(*created_at));
+    if let Some(limit) = filter.limit {
+        ends.truncate(limit.max(0) as usize);
+    }
+    let ends: Vec<(uuid::Uuid, i32)> = ends.into_iter().map(|(id, _)| (id, 0)).collect();
+    Dag { cells: dag_cells(store, &ends, filter.shallow), edges: vec![] }
+}
+
+fn dag_cells(store: &Store, cells: &[(uuid::Uuid, i32)], shallow: bool) -> Vec<DagCell> {
+    cells.iter()
+        .filter_map(|(id, depth)| {
+            let cell = flat_cell(store, *id)?;
+            Some(DagCell {
+                cell_id: *id,
+                text: cell.text.clone(),
+                depth: *depth,
+                cell: (!shallow).then_some(cell),
+            })
+        })
+        .collect()
+}
+
+fn edges_between(store: &Store, cell_ids: &[uuid::Uuid]) -> Vec<Edge> {
+    let mut edges: Vec<Edge> = store.family_tree.iter()
+        .filter(|(child_id, parent_id)| cell_ids.contains(child_id) && cell_ids.contains(parent_id))
+        .map(|(child_id, parent_id)| Edge { child_id: *child_id, parent_id: *parent_id })
+        .collect();
+    edges.sort_by_key(|e| (e.parent_id, e.child_id));
+    edges.dedup();
+    edges
+}
+
+async fn show_file(
+    Path((cell_id, path)): Path<(uuid::Uuid, String)>,
+    State(store): State<Shared>,
+) -> Result<Vec<u8>, StatusCode> {
+    let store = store.lock().unwrap();
+    let (cell, _) = store.cells.get(&cell_id).ok_or(StatusCode::NOT_FOUND)?;
+    let fileprop = cell.fileprops.iter().find(|f| f.path == path).ok_or(StatusCode::NOT_FOUND)?;
+    let file_id = file_id(&fileprop.url).ok_or(StatusCode::NOT_FOUND)?;
+    store.files.get(&file_id).cloned().ok_or(StatusCode::NOT_FOUND)
+}
+
+async fn diff_cells(
+    Path((cell_id, other_id)): Path<(uuid::Uuid, uuid::Uuid)>,
+    State(store): State<Shared>,
+) -> Result<Json<CellsDiff>, StatusCode> {
+    let store = store.lock().unwrap();
+    let (old, _) = store.cells.get(&cell_id).ok_or(StatusCode::NOT_FOUND)?;
+    let (new, _) = store.cells.get(&other_id).ok_or(StatusCode::NOT_FOUND)?;
+    let files = diff_files(&old.fileprops, &old.dirs, &new.fileprops, &new.dirs);
+    Ok(Json(CellsDiff { cell_id, other_id, files }))
+}
+
+async fn patch_files(
+    Path(cell_id): Path<uuid::Uuid>,
+    State(store): State<Shared>,
+    Json(payload): Json<FilePatch>,
+) -> Result<Json<CellFiles>, StatusCode> {
+    let mut store = store.lock().unwrap();
+    let revision = change_cell(&mut store, cell_id, payload.device_id.as_deref(), |cell| {
+        (cell.fileprops, cell.dirs) = apply_ops(&cell.fileprops, &cell.dirs, &payload.ops)?;
+        Ok(())
+    })?;
+    Ok(Json(CellFiles { fileprops: revision.fileprops, dirs: revision.dirs }))
+}
+
+// patch::apply with its errors as the backend's statuses
+fn apply_ops(
+    fileprops: &[FileProp],
+    dirs: &[String],
+    ops: &[FileOp],
+) -> Result<(Vec<FileProp>, Vec<String>), StatusCode> {
+    file_patch::apply(fileprops, dirs, ops).map_err(|e| match e {
+        PatchError::Path(_) => StatusCode::BAD_REQUEST,
+        PatchError::NotFound(_) => StatusCode::NOT_FOUND,
+        _ => StatusCode::CONFLICT,
+    })
+}
+
+async fn cell_archive(
+    Path(cell_id): Path<uuid::Uuid>,
+    Query(query): Query<ArchiveQuery>,
+    State(store): State<Shared>,
+) -> Result<Response, StatusCode> {
+    let (fileprops, dirs) = {
+        let store = store.lock().unwrap();
+        let (cell, _) = store.cells.get(&cell_id).ok_or(StatusCode::NOT_FOUND)?;
+        (cell.fileprops.clone(), cell.dirs.clone())
+    };
+    send_archive(&store, &fileprops, &dirs, query.format).await
+}
+
+async fn make_archive(
+    Query(query): Query<ArchiveQuery>,
+    State(store): State<Shared>,
+    Json(payload): Json<ArchiveReq>,
+) -> Result<Response, StatusCode> {
+    let layout = match &payload.rootdir {
+        Some(_) if !payload.fileprops.is_empty() || !payload.dirs.is_empty() => {
+            return Err(StatusCode::BAD_REQUEST)
+        }
+        Some(rootdir) => tree::to_flat(rootdir),
+        None => tree::normalize(&payload.fileprops, &payload.dirs),
+    };
+    let (fileprops, dirs) = layout.map_err(|_| StatusCode::BAD_REQUEST)?;
+    send_archive(&store, &fileprops, &dirs, query.format).await
+}
+
+// Built in memory and stored uncompressed in zips; files this server does
+// not have are left out, as the backend does
+async fn send_archive(
+    store: &Shared,
+    fileprops: &[FileProp],
+    dirs: &[String],
+    format: ArchiveFormat,
+) -> Result<Response, StatusCode> {
+    let files: Vec<(String, Vec<u8>)> = {
+        let store = store.lock().unwrap();
+        fileprops.iter()
+            .filter_map(|f| Some((f.path.clone(), store.files.get(&file_id(&f.url)?)?.clone())))
+            .collect()
+    };
+    let (mime, archive) = match format {
+        ArchiveFormat::Zip => {
+            let mut zip = ZipFileWriter::with_tokio(vec![]);
+            let dirs = dirs.iter().map(|dir| (format!("{}/", dir), vec![]));
+            for (path, bytes) in dirs.chain(files) {
+                let entry = ZipEntryBuilder::new(path.into(), Compression::Stored);
+                zip.write_entry_whole(entry, &bytes).await.map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+            }
+            let archive = zip.close().await.map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?.into_inner();
+            ("application/zip", archive)
+        }
+        ArchiveFormat::TarGz => {
+            let encoder = flate2::write::GzEncoder::new(vec![], flate2::Compression::fast());
+            let mut builder = tar::Builder::new(encoder);
+            for dir in dirs.iter() {
+                let mut header = tar::Header::new_gnu();
+                header.set_entry_type(tar::EntryType::Directory);
+                header.set_size(0);
+                header.set_mode(0o755);
+                header.set_cksum();
+                builder.append_data(&mut header, format!("{}/", dir), std::io::empty()).unwrap();
+            }
+            for (path, bytes) in files.iter() {
+                append(&mut builder, path, bytes);
+            }
+            let mut encoder = builder.into_inner().unwrap();
+            encoder.flush().unwrap();
+            ("application/gzip", encoder.finish().unwrap())
+        }
+    };
+    Ok(([(header::CONTENT_TYPE, mime)], archive).into_response())
+}
+
+async fn upload_file(
+    State(store): State<Shared>,
+    body: Bytes,
+) -> Json<FileRes> {
+    let id = uuid::Uuid::new_v4();
+    let res = FileRes {
+        id,
+        size: body.len() as u64,
+        sha256: crate::hash::sha256_bytes(&body),
+    };
+    store.lock().unwrap().files.insert(id, body.to_vec());
+    Json(res)
+}
+
+// Zip, tar or tar.gz, told apart by their first bytes. Unlike the backend,
+// there are no limits on size and MIME types are not guessed.
+async fn expand_archive(
+    State(store): State<Shared>,
+    body: Bytes,
+) -> Result<Json<CellFiles>, StatusCode> {
+    let mut entries: Vec<(String, Option<Vec<u8>>)> = vec![];
+    if body.starts_with(b"PK") {
+        let zip = ZipFileReader::new(body.to_vec()).await.map_err(|_| StatusCode::BAD_REQUEST)?;
+        for (index, entry) in zip.file().entries().iter().enumerate() {
+            let name = String::from_utf8_lossy(entry.filename().as_bytes()).to_string();
+            if name.ends_with('/') {
+                entries.push((name, None));
+                continue
+            }
+            if entry.unix_permissions().is_some_and(|mode| mode & 0o170000 == 0o120000) {
+                continue
+            }
+            let mut bytes = vec![];
+            let mut reader = zip.reader_with_entry(index).await.map_err(|_| StatusCode::BAD_REQUEST)?;
+            reader.read_to_end_checked(&mut bytes).await.map_err(|_| StatusCode::BAD_REQUEST)?;
+            entries.push((name, Some(bytes)));
+        }
+    } else {
+        let reader: Box<dyn Read> = match body.starts_with(&[0x1f, 0x8b]) {
+            true => Box::new(flate2::read::GzDecoder::new(&body[..])),
+            false => Box::new(&body[..]),
+        };
+        let mut archive = tar::Archive::new(reader);
+        for entry in archive.entries().map_err(|_| StatusCode::BAD_REQUEST)? {
+            let mut entry = entry.map_err(|_| StatusCode::BAD_REQUEST)?;
+            let name = String::from_utf8_lossy(&entry.path_bytes()).to_string();
+            let entry_type = entry.header().entry_type();
+            if entry_type.is_dir() {
+                entries.push((name, None));
+            } else if entry_type.is_file() {
+                let mut bytes = vec![];
+                entry.read_to_end(&mut bytes).map_err(|_| StatusCode::BAD_REQUEST)?;
+                entries.push((name, Some(bytes)));
+            }
+        }
+    }
+
+    let mut fileprops = vec![];
+    let mut dirs = vec![];
+    let mut uploads = vec![];
+    for (name, bytes) in entries {
+        let name = name.replace('\\', "/");
+        let mut path = name.trim_end_matches('/');
+        while let Some(rest) = path.strip_prefix("./") {
+            path = rest.trim_start_matches('/');
+        }
+        if path.is_empty() || path == "." {
+            continue
+        }
+        validate_path(path).map_err(|_| StatusCode::BAD_REQUEST)?;
+        let Some(bytes) = bytes else {
+            dirs.push(path.to_string());
+            continue
+        };
+        let file_id = uuid::Uuid::new_v4();
+        fileprops.push(FileProp {
+            path: path.to_string(),
+            url: format!("{}/files/{}", store.lock().unwrap().url, file_id),
+            completed: true,
+            size: Some(bytes.len() as u64),
+            sha256: Some(crate::hash::sha256_bytes(&bytes)),
+            mime: None,
+            mtime: None,
+        });
+        uploads.push((file_id, bytes));
+    }
+    // Nothing is stored unless the whole archive fits in a cell
+    let (fileprops, dirs) = tree::normalize(&fileprops, &dirs).map_err(|_| StatusCode::BAD_REQUEST)?;
+    store.lock().unwrap().files.extend(uploads);
+    Ok(Json(CellFiles { fileprops, dirs }))
+}
+
+async fn search_files(
+    Query(filter): Query<FileFilter>,
+    State(store): State<Shared>,
+) -> Json<FileMatches> {
+    let store = store.lock().unwrap();
+    let mut files: Vec<FileMatch> = store.cells.values()
+        .filter(|(cell, created_at)| {
+            cell.user_id == filter.user_id
+                && filter.since.is_none_or(|s| *created_at >= s)
+                && filter.until.is_none_or(|u| *created_at < u)
+        })
+        .flat_map(|(cell, created_at)| cell.fileprops.iter().map(|f| (cell.cell_id, *created_at, f)))
+        .filter(|(_, _, f)| {
+            let name = f.path.rsplit('/').next().unwrap_or(&f.path);
+            filter.name.as_ref().is_none_or(|n| n.to_lowercase() == name.to_lowercase())
+                && filter.glob.as_ref().is_none_or(|g| {
+                    glob_match(g, if g.contains('/') { &f.path } else { name })
+                })
+                && filter.mime.as_ref().is_none_or(|m| f.mime.as_ref().is_some_and(|mime| glob_match(m, mime)))
+                && filter.sha256.as_ref().is_none_or(|h| f.sha256.as_ref().is_some_and(|s| s.eq_ignore_ascii_case(h)))
+        })
+        .map(|(cell_id, created_at, f)| FileMatch { cell_id, created_at, fileprop: f.clone() })
+        .collect();
+    files.sort_by(|a, b| b.created_at.cmp(&a.created_at).then(a.fileprop.path.cmp(&b.fileprop.path)));
+    if let Some(limit) = filter.limit {
+        files.truncate(limit.max(0) as usize);
+    }
+    Json(FileMatches { files })
+}
+
+// `*` and `?` wildcards, ignoring case like the backend's ILIKE
+fn glob_match(glob: &str, text: &str) -> bool {
+    fn matches(glob: &[char], text: &[char]) -> bool {
+        match glob.split_first() {
+            None => text.is_empty(),
+            Some(('*', rest)) => (0..=text.len()).any(|i| matches(rest, &text[i..])),
+            Some(('?', rest)) => !text.is_empty() && matches(rest, &text[1..]),
+            Some((c, rest)) => text.first() == Some(c) && matches(rest, &text[1..]),
+        }
+    }
+    let glob: Vec<char> = glob.to_lowercase().chars().collect();
+    let text: Vec<char> = text.to_lowercase().chars().collect();
+    matches(&glob, &text)
+}
+
+async fn download_file(
+    Path(file_id): Path<uuid::Uuid>,
+    State(store): State<Shared>,
+) -> Result<Vec<u8>, StatusCode> {
+    let store = store.lock().unwrap();
+    store.files.get(&file_id).cloned().ok_or(StatusCode::NOT_FOUND)
+}
+
+async fn find_file(
+    Path(sha256): Path<String>,
+    State(store): State<Shared>,
+) -> Result<Json<FileRes>, StatusCode> {
+    let store = store.lock().unwrap();
+    store.files.iter()
+        .find(|(_, bytes)| crate::hash::sha256_bytes(bytes) == sha256)
+        .map(|(id, bytes)| Json(FileRes { id: *id, size: bytes.len() as u64, sha256: sha256.clone() }))
+        .ok_or(StatusCode::NOT_FOUND)
+}
+
+// The id of an uploaded file from its URL
+fn file_id(url: &str) -> Option<uuid::Uuid> {
+    let (_, file_id) = url.rsplit_once("/files/")?;
+    uuid::Uuid::parse_str(file_id).ok()
+}
+
+fn extract(store: &Store, cell_id: uuid::Uuid) -> Option<CellExtracted> {
+    let mut cell = extract_tree(store, cell_id, true)?;
+    cell.parents = extract_tree(store, cell_id, false)?.parents;
+    Some(cell)
+}
+
+fn extract_tree(store: &Store, cell_id: uuid::Uuid, downwards: bool) -> Option<CellExtracted> {
+    let mut cell = flat_cell(store, cell_id)?;
+    let linked: Vec<CellExtracted> = store.family_tree.iter()
+        .filter_map(|(child, parent)| match downwards {
+            true if *parent == cell_id => Some(*child),
+            false if *child == cell_id => Some(*parent),
+            _ => None,
+        })
+        .filter_map(|id| extract_tree(store, id, downwards))
+        .collect();
+    match downwards {
+        true => cell.children = linked,
+        false => cell.parents = linked,
+    }
+    Some(cell)
+}
+
+// A cell without its parents and replies
+fn flat_cell(store: &Store, cell_id: uuid::Uuid) -> Option<CellExtracted> {
+    let (cell, created_at) = store.cells.get(&cell_id)?;
+    Some(CellExtracted {
+        cell_id,
+        user_id: cell.user_id,
+        device_id: cell.device_id.clone(),
+        text: cell.text.clone(),
+        is_open: cell.is_open,
+        fileprops: cell.fileprops.clone(),
+        dirs: cell.dirs.clone(),
+        created_at: *created_at,
+        parents: vec![],
+        children: vec![],
+    })
+}
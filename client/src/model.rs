@@ -0,0 +1,333 @@
+use serde::{Deserialize, Serialize};
+
+pub use flowfs_common::model::{Change, Dir, DirFile, FileOp, FileProp, FilesDiff};
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct IdRes { pub id: uuid::Uuid }
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Cells {
+    pub cells:          Vec<CellExtracted>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct CellReq {
+    pub cell_id:        uuid::Uuid,
+    pub user_id:        uuid::Uuid,
+    pub device_id:      String,
+    pub text:           String,
+    pub is_open:        bool,
+    /// Attachments, either flat as `fileprops` plus the paths of empty
+    /// directories in `dirs`, or nested as `rootdir`; not both
+    #[serde(default)]
+    pub fileprops:      Vec<FileProp>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub dirs:           Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rootdir:        Option<Dir>,
+    pub parent_ids:     Vec<uuid::Uuid>,
+    pub child_ids:      Vec<uuid::Uuid>,
+}
+
+/// Cells to create at once; edges may refer to cells of the same batch
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct CellBatch {
+    pub cells:          Vec<CellReq>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum BatchStatus {
+    Created,
+    /// Stored by an earlier try of the same request
+    Existing,
+    Invalid,
+    Conflict,
+    Failed,
+    /// Not created because another cell of the batch failed
+    Skipped,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct BatchItem {
+    pub cell_id:        uuid::Uuid,
+    pub status:         BatchStatus,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error:          Option<String>,
+}
+
+/// Whether a batch was stored, and what became of each cell in order
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct BatchRes {
+    pub committed:      bool,
+    pub results:        Vec<BatchItem>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct CellExtracted {
+    pub cell_id:        uuid::Uuid,
+    pub user_id:        uuid::Uuid,
+    pub device_id:      String,
+    pub text:           String,
+    pub is_open:        bool,
+    pub fileprops:      Vec<FileProp>,
+    /// Paths of directories holding no file
+    #[serde(default)]
+    pub dirs:           Vec<String>,
+    pub created_at:     chrono::NaiveDateTime,
+    pub parents:        Vec<CellExtracted>,
+    pub children:       Vec<CellExtracted>,
+}
+
+/// `depth` limits a walk of the family tree to that many edges; `shallow`
+/// leaves out everything of a cell but its id and text
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct DagQuery {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub depth:          Option<i32>,
+    pub shallow:        bool,
+}
+
+/// Which of a user's root or leaf cells to list
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct DagFilter {
+    pub user_id:        uuid::Uuid,
+    pub shallow:        bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub limit:          Option<i64>,
+}
+
+/// A cell reached in the family tree, `depth` edges away from where the
+/// walk started; `cell` is left out in shallow form
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct DagCell {
+    pub cell_id:        uuid::Uuid,
+    pub text:           String,
+    pub depth:          i32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cell:           Option<CellExtracted>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct Edge {
+    pub child_id:       uuid::Uuid,
+    pub parent_id:      uuid::Uuid,
+}
+
+/// Cells of the family tree with the edges between them
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Dag {
+    pub cells:          Vec<DagCell>,
+    pub edges:          Vec<Edge>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct CellFilter {
+    pub user_id:        uuid::Uuid,
+    pub device_id:      Option<String>,
+    pub text:           Option<String>,
+    pub since:          Option<chrono::NaiveDateTime>,
+    pub until:          Option<chrono::NaiveDateTime>,
+    pub limit:          Option<i64>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct FilePatch {
+    pub ops:            Vec<FileOp>,
+    /// Recorded with the revision the patch makes
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub device_id:      Option<String>,
+}
+
+/// A cell's files and empty directories, as left by a patch
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct CellFiles {
+    pub fileprops:      Vec<FileProp>,
+    pub dirs:           Vec<String>,
+}
+
+/// A change to a cell; what is left out stays as it is. The files are
+/// replaced as a whole when `fileprops`, `dirs` or `rootdir` is given, flat
+/// or nested as in [`CellReq`].
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct CellUpdate {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub device_id:      Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub text:           Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub is_open:        Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fileprops:      Option<Vec<FileProp>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dirs:           Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rootdir:        Option<Dir>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub parent_ids:     Option<Vec<uuid::Uuid>>,
+}
+
+/// A cell as it was after one change, revision 1 being the cell as created.
+/// `device_id` is the device that made the change, when it said.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Revision {
+    pub cell_id:        uuid::Uuid,
+    pub revision:       i32,
+    pub device_id:      Option<String>,
+    pub text:           String,
+    pub is_open:        bool,
+    pub fileprops:      Vec<FileProp>,
+    pub dirs:           Vec<String>,
+    pub parent_ids:     Vec<uuid::Uuid>,
+    pub created_at:     chrono::NaiveDateTime,
+}
+
+/// Revisions to compare; `to` defaults to the latest and `from` to the one
+/// before `to`
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct DiffQuery {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub from:           Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub to:             Option<i32>,
+}
+
+/// What differs between two revisions of a cell; parts left as `None` or
+/// empty did not change
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct RevisionDiff {
+    pub cell_id:        uuid::Uuid,
+    pub from:           i32,
+    pub to:             i32,
+    pub text:           Option<Change<String>>,
+    pub is_open:        Option<Change<bool>>,
+    pub files:          FilesDiff,
+    pub parents_added:  Vec<uuid::Uuid>,
+    pub parents_removed: Vec<uuid::Uuid>,
+}
+
+/// A reply made from the files of the cell it replies to, changed by `ops`
+/// as in a [`FilePatch`]. The other fields are those of a [`CellReq`].
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct CellFork {
+    pub cell_id:        uuid::Uuid,
+    pub user_id:        uuid::Uuid,
+    pub device_id:      String,
+    pub text:           String,
+    pub is_open:        bool,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub ops:            Vec<FileOp>,
+}
+
+/// How the files of `other_id` differ from those of `cell_id`, such as what
+/// a reply changed in its parent's files
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct CellsDiff {
+    pub cell_id:        uuid::Uuid,
+    pub other_id:       uuid::Uuid,
+    pub files:          FilesDiff,
+}
+
+/// Archive type for [`FlowfsClient::download_cell_archive`] and
+/// [`FlowfsClient::download_archive`]
+///
+/// [`FlowfsClient::download_cell_archive`]: crate::FlowfsClient::download_cell_archive
+/// [`FlowfsClient::download_archive`]: crate::FlowfsClient::download_archive
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+pub enum ArchiveFormat {
+    #[default]
+    #[serde(rename = "zip")]
+    Zip,
+    #[serde(rename = "tar.gz")]
+    TarGz,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ArchiveQuery {
+    pub format:         ArchiveFormat,
+}
+
+/// Files to pack into an archive, flat or nested as in [`CellReq`]
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct ArchiveReq {
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub fileprops:      Vec<FileProp>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub dirs:           Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rootdir:        Option<Dir>,
+}
+
+/// Answer to an upload: the new file id with the size and SHA-256 the server saw
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct FileRes {
+    pub id:             uuid::Uuid,
+    pub size:           u64,
+    pub sha256:         String,
+}
+
+/// Query for files across a user's cells; every given field has to match.
+/// `glob` takes `*` and `?` and is matched against the path when it holds a
+/// slash, else against the file name. `mime` may be a glob such as `image/*`.
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct FileFilter {
+    pub user_id:        uuid::Uuid,
+    pub name:           Option<String>,
+    pub glob:           Option<String>,
+    pub mime:           Option<String>,
+    pub sha256:         Option<String>,
+    pub since:          Option<chrono::NaiveDateTime>,
+    pub until:          Option<chrono::NaiveDateTime>,
+    pub limit:          Option<i64>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct FileMatch {
+    pub cell_id:        uuid::Uuid,
+    pub created_at:     chrono::NaiveDateTime,
+    #[serde(flatten)]
+    pub fileprop:       FileProp,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct FileMatches {
+    pub files:          Vec<FileMatch>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Users { pub users: Vec<UserRes> }
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct User {
+    pub user_id:        uuid::Uuid,
+    pub user_name:      String,
+    pub passhash:       String,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct UserRes {
+    pub user_id:        uuid::Uuid,
+    pub user_name:      String,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum OnConflict {
+    #[default]
+    Fail,
+    Skip,
+    Overwrite,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ImportOptions {
+    pub on_conflict:    OnConflict,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ImportRes {
+    pub created:        Vec<uuid::Uuid>,
+    pub skipped:        Vec<uuid::Uuid>,
+    pub overwritten:    Vec<uuid::Uuid>,
+    pub files:          usize,
+}
@@ -0,0 +1,632 @@
+use std::path::PathBuf;
+
+use flowfs_client::mock::MockServer;
+use flowfs_client::model::*;
+use flowfs_client::{tree, ClientError, FlowfsClient};
+
+// A fresh directory for files a test writes, removed when dropped
+struct TempDir(PathBuf);
+
+impl TempDir {
+    fn new() -> Self {
+        let path = std::env::temp_dir().join(format!("flowfs-client-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&path).unwrap();
+        TempDir(path)
+    }
+
+    fn join(&self, name: &str) -> PathBuf {
+        self.0.join(name)
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+async fn user(client: &FlowfsClient) -> uuid::Uuid {
+    let user = User {
+        user_id: uuid::Uuid::new_v4(),
+        user_name: "tester".to_string(),
+        passhash: String::new(),
+    };
+    client.create_user(&user).await.unwrap().id
+}
+
+fn cell(user_id: uuid::Uuid, text: &str, parent_ids: &[uuid::Uuid]) -> CellReq {
+    CellReq {
+        cell_id: uuid::Uuid::new_v4(),
+        user_id,
+        device_id: "test".to_string(),
+        text: text.to_string(),
+        is_open: true,
+        fileprops: vec![],
+        dirs: vec![],
+        rootdir: None,
+        parent_ids: parent_ids.to_vec(),
+        child_ids: vec![],
+    }
+}
+
+// Uploads `content` and gives the file as attached at `path`
+async fn attach(client: &FlowfsClient, path: &str, content: &[u8]) -> FileProp {
+    let res = client.upload_bytes(content.to_vec()).await.unwrap();
+    FileProp {
+        path: path.to_string(),
+        url: client.file_url(res.id),
+        completed: true,
+        size: Some(res.size),
+        sha256: Some(res.sha256),
+        mime: None,
+        mtime: None,
+    }
+}
+
+fn paths(fileprops: &[FileProp]) -> Vec<&str> {
+    fileprops.iter().map(|f| f.path.as_str()).collect()
+}
+
+#[tokio::test]
+async fn users() {
+    let server = MockServer::start().await;
+    let client = server.client();
+    let user_id = user(&client).await;
+    assert_eq!(client.show_user(user_id).await.unwrap().user_name, "tester");
+    assert_eq!(client.list_users().await.unwrap().users.len(), 1);
+    client.delete_user(user_id).await.unwrap();
+    assert!(matches!(client.show_user(user_id).await, Err(ClientError::NotFound)));
+}
+
+#[tokio::test]
+async fn cells() {
+    let server = MockServer::start().await;
+    let client = server.client();
+    let user_id = user(&client).await;
+    let parent = cell(user_id, "parent", &[]);
+    let reply = cell(user_id, "reply", &[parent.cell_id]);
+    client.create_cell(&parent).await.unwrap();
+    client.create_cell(&reply).await.unwrap();
+
+    let shown = client.show_cell(parent.cell_id).await.unwrap();
+    assert_eq!(shown.text, "parent");
+    assert_eq!(shown.children.len(), 1);
+    assert_eq!(shown.children[0].cell_id, reply.cell_id);
+    let shown = client.show_cell(reply.cell_id).await.unwrap();
+    assert_eq!(shown.parents[0].cell_id, parent.cell_id);
+
+    let filter = CellFilter { user_id, text: Some("REPLY".to_string()), ..Default::default() };
+    let found = client.list_cells(&filter).await.unwrap().cells;
+    assert_eq!(found.len(), 1);
+    assert_eq!(found[0].cell_id, reply.cell_id);
+
+    client.delete_cell(reply.cell_id).await.unwrap();
+    assert!(matches!(client.show_cell(reply.cell_id).await, Err(ClientError::NotFound)));
+    assert!(client.show_cell(parent.cell_id).await.unwrap().children.is_empty());
+}
+
+#[tokio::test]
+async fn create_cell_again() {
+    let server = MockServer::start().await;
+    let client = server.client();
+    let user_id = user(&client).await;
+    let req = cell(user_id, "once", &[]);
+    // The same request twice stores one cell
+    assert_eq!(client.create_cell(&req).await.unwrap().id, req.cell_id);
+    assert_eq!(client.create_cell(&req).await.unwrap().id, req.cell_id);
+    assert_eq!(server.cell_count(), 1);
+
+    let other = CellReq { text: "twice".to_string(), ..req.clone() };
+    assert!(matches!(client.create_cell(&other).await, Err(ClientError::Conflict(_))));
+    let orphan = cell(user_id, "orphan", &[uuid::Uuid::new_v4()]);
+    assert!(matches!(client.create_cell(&orphan).await, Err(ClientError::BadRequest(_))));
+    // Flat and nested files at once
+    let both = CellReq {
+        dirs: vec!["out".to_string()],
+        rootdir: Some(Dir { name: "/".to_string(), dirs: vec![], fileprops: vec![] }),
+        ..cell(user_id, "both", &[])
+    };
+    assert!(matches!(client.create_cell(&both).await, Err(ClientError::BadRequest(_))));
+}
+
+#[tokio::test]
+async fn files() {
+    let server = MockServer::start().await;
+    let client = server.client();
+    let dir = TempDir::new();
+    let local = dir.join("a.txt");
+    std::fs::write(&local, b"hello").unwrap();
+
+    let (res, sent) = client.upload_file_once(&local).await.unwrap();
+    assert!(sent);
+    assert_eq!(res.size, 5);
+    let (again, sent) = client.upload_file_once(&local).await.unwrap();
+    assert!(!sent);
+    assert_eq!(again.id, res.id);
+    assert_eq!(server.file_count(), 1);
+    assert!(client.find_file(&"0".repeat(64)).await.unwrap().is_none());
+
+    let dest = dir.join("b.txt");
+    assert_eq!(client.download_file(res.id, &dest).await.unwrap(), 5);
+    assert_eq!(std::fs::read(&dest).unwrap(), b"hello");
+    let missing = client.download_file(uuid::Uuid::new_v4(), &dest).await;
+    assert!(matches!(missing, Err(ClientError::NotFound)));
+}
+
+#[tokio::test]
+async fn cell_files() {
+    let server = MockServer::start().await;
+    let client = server.client();
+    let user_id = user(&client).await;
+    let mut req = cell(user_id, "files", &[]);
+    req.fileprops = vec![
+        attach(&client, "docs/b.md", b"# b").await,
+        attach(&client, "a.txt", b"a").await,
+    ];
+    client.create_cell(&req).await.unwrap();
+    // Stored sorted, as the backend does
+    assert_eq!(paths(&client.show_cell(req.cell_id).await.unwrap().fileprops), ["a.txt", "docs/b.md"]);
+
+    let res = client.get_file(&client.cell_file_url(req.cell_id, "docs/b.md"), 0).await.unwrap();
+    assert_eq!(res.bytes().await.unwrap().as_ref(), b"# b");
+    let missing = client.get_file(&client.cell_file_url(req.cell_id, "c.txt"), 0).await;
+    assert!(matches!(missing, Err(ClientError::NotFound)));
+
+    let ops = vec![
+        FileOp::Move { from: "a.txt".to_string(), path: "docs/a.txt".to_string() },
+        FileOp::Mkdir { path: "out".to_string() },
+    ];
+    let files = client.patch_files(req.cell_id, &FilePatch { ops, device_id: None }).await.unwrap();
+    assert_eq!(paths(&files.fileprops), ["docs/a.txt", "docs/b.md"]);
+    assert_eq!(files.dirs, ["out"]);
+    let ops = vec![FileOp::Remove { path: "a.txt".to_string() }];
+    let missing = client.patch_files(req.cell_id, &FilePatch { ops, device_id: None }).await;
+    assert!(matches!(missing, Err(ClientError::NotFound)));
+    let ops = vec![FileOp::Mkdir { path: "out".to_string() }];
+    let exists = client.patch_files(req.cell_id, &FilePatch { ops, device_id: None }).await;
+    assert!(matches!(exists, Err(ClientError::Conflict(_))));
+}
+
+#[tokio::test]
+async fn search_files() {
+    let server = MockServer::start().await;
+    let client = server.client();
+    let user_id = user(&client).await;
+    let mut req = cell(user_id, "files", &[]);
+    req.fileprops = vec![
+        attach(&client, "a.txt", b"a").await,
+        attach(&client, "docs/b.md", b"b").await,
+        attach(&client, "docs/img/c.PNG", b"c").await,
+    ];
+    req.fileprops[2].mime = Some("image/png".to_string());
+    client.create_cell(&req).await.unwrap();
+
+    let search = |filter: FileFilter| {
+        let client = client.clone();
+        async move {
+            let files = client.search_files(&filter).await.unwrap().files;
+            files.into_iter().map(|f| f.fileprop.path).collect::<Vec<_>>()
+        }
+    };
+    let filter = FileFilter { user_id, ..Default::default() };
+    assert_eq!(search(filter.clone()).await, ["a.txt", "docs/b.md", "docs/img/c.PNG"]);
+    assert_eq!(search(FileFilter { glob: Some("*.png".to_string()), ..filter.clone() }).await, ["docs/img/c.PNG"]);
+    assert_eq!(search(FileFilter { glob: Some("docs/*".to_string()), ..filter.clone() }).await.len(), 2);
+    assert_eq!(search(FileFilter { name: Some("B.MD".to_string()), ..filter.clone() }).await, ["docs/b.md"]);
+    assert_eq!(search(FileFilter { mime: Some("image/*".to_string()), ..filter.clone() }).await, ["docs/img/c.PNG"]);
+    let sha256 = req.fileprops[0].sha256.clone();
+    assert_eq!(search(FileFilter { sha256, ..filter.clone() }).await, ["a.txt"]);
+    assert!(search(FileFilter { user_id: uuid::Uuid::new_v4(), ..filter }).await.is_empty());
+}
+
+#[tokio::test]
+async fn export_import() {
+    let server = MockServer::start().await;
+    let client = server.client();
+    let dir = TempDir::new();
+    let user_id = user(&client).await;
+    let mut parent = cell(user_id, "parent", &[]);
+    parent.fileprops = vec![attach(&client, "a.txt", b"a").await];
+    parent.dirs = vec!["out".to_string()];
+    let reply = cell(user_id, "reply", &[parent.cell_id]);
+    client.create_cell(&parent).await.unwrap();
+    client.create_cell(&reply).await.unwrap();
+    let archive = dir.join("export.tar");
+    assert!(client.export_user(user_id, &archive).await.unwrap() > 0);
+    assert!(matches!(client.export_user(uuid::Uuid::new_v4(), &archive).await, Err(ClientError::NotFound)));
+
+    // Into another server, under another user
+    let other = MockServer::start().await;
+    let other_client = other.client();
+    let other_id = uuid::Uuid::new_v4();
+    let res = other_client.import_user(other_id, &archive, OnConflict::Fail).await.unwrap();
+    assert_eq!(res.created.len(), 2);
+    assert_eq!(res.files, 1);
+    let imported = other_client.show_cell(parent.cell_id).await.unwrap();
+    assert_eq!(imported.user_id, other_id);
+    assert_eq!(imported.dirs, ["out"]);
+    assert_eq!(imported.children[0].cell_id, reply.cell_id);
+    assert!(imported.fileprops[0].url.starts_with(other.url()));
+    let dest = dir.join("a.txt");
+    other_client.download_url(&imported.fileprops[0].url, &dest).await.unwrap();
+    assert_eq!(std::fs::read(&dest).unwrap(), b"a");
+
+    let again = other_client.import_user(other_id, &archive, OnConflict::Fail).await;
+    assert!(matches!(again, Err(ClientError::Conflict(_))));
+    let res = other_client.import_user(other_id, &archive, OnConflict::Skip).await.unwrap();
+    assert_eq!((res.created.len(), res.skipped.len(), res.files), (0, 2, 0));
+    other_client.delete_cell(reply.cell_id).await.unwrap();
+    let res = other_client.import_user(other_id, &archive, OnConflict::Overwrite).await.unwrap();
+    assert_eq!(res.created, [reply.cell_id]);
+    assert_eq!(res.overwritten, [parent.cell_id]);
+    assert_eq!(other.cell_count(), 2);
+    assert_eq!(other_client.show_cell(reply.cell_id).await.unwrap().parents.len(), 1);
+    // Cells of another user are never replaced
+    let theirs = other_client.import_user(uuid::Uuid::new_v4(), &archive, OnConflict::Overwrite).await;
+    assert!(matches!(theirs, Err(ClientError::Conflict(_))));
+
+    std::fs::write(&archive, b"not a tar").unwrap();
+    let broken = other_client.import_user(other_id, &archive, OnConflict::Skip).await;
+    assert!(matches!(broken, Err(ClientError::BadRequest(_))));
+}
+
+// Names of the entries of a tar.gz archive with the content of its files
+fn tar_gz_entries(path: &std::path::Path) -> Vec<(String, Vec<u8>)> {
+    use std::io::Read;
+    let file = std::fs::File::open(path).unwrap();
+    let mut archive = tar::Archive::new(flate2::read::GzDecoder::new(file));
+    archive.entries().unwrap()
+        .map(|entry| {
+            let mut entry = entry.unwrap();
+            let name = entry.path().unwrap().to_string_lossy().to_string();
+            let mut bytes = vec![];
+            entry.read_to_end(&mut bytes).unwrap();
+            (name, bytes)
+        })
+        .collect()
+}
+
+async fn zip_names(path: &std::path::Path) -> Vec<String> {
+    let zip = async_zip::base::read::mem::ZipFileReader::new(std::fs::read(path).unwrap()).await.unwrap();
+    zip.file().entries().iter()
+        .map(|entry| entry.filename().as_str().unwrap().to_string())
+        .collect()
+}
+
+#[tokio::test]
+async fn archives() {
+    let server = MockServer::start().await;
+    let client = server.client();
+    let dir = TempDir::new();
+    let user_id = user(&client).await;
+    let mut req = cell(user_id, "files", &[]);
+    let mut elsewhere = attach(&client, "remote.txt", b"r").await;
+    elsewhere.url = "http://elsewhere/files/0".to_string();
+    req.fileprops = vec![
+        attach(&client, "a.txt", b"a").await,
+        attach(&client, "docs/b.md", b"# b").await,
+        elsewhere,
+    ];
+    req.dirs = vec!["out".to_string()];
+    client.create_cell(&req).await.unwrap();
+
+    let dest = dir.join("cell.tar.gz");
+    client.download_cell_archive(req.cell_id, ArchiveFormat::TarGz, &dest).await.unwrap();
+    let entries = tar_gz_entries(&dest);
+    let names: Vec<&str> = entries.iter().map(|(name, _)| name.as_str()).collect();
+    assert_eq!(names, ["out/", "a.txt", "docs/b.md"]);
+    assert_eq!(entries[2].1, b"# b");
+    let dest = dir.join("cell.zip");
+    client.download_cell_archive(req.cell_id, ArchiveFormat::Zip, &dest).await.unwrap();
+    assert_eq!(zip_names(&dest).await, ["out/", "a.txt", "docs/b.md"]);
+    let missing = client.download_cell_archive(uuid::Uuid::new_v4(), ArchiveFormat::Zip, &dest).await;
+    assert!(matches!(missing, Err(ClientError::NotFound)));
+
+    // Files of a cell the server does not hold
+    let rootdir = tree::to_tree(&req.fileprops[..1], &[]).unwrap();
+    let archive = ArchiveReq { rootdir: Some(rootdir), ..Default::default() };
+    client.download_archive(&archive, ArchiveFormat::Zip, &dest).await.unwrap();
+    assert_eq!(zip_names(&dest).await, ["a.txt"]);
+    let both = ArchiveReq { fileprops: req.fileprops.clone(), ..archive };
+    let res = client.download_archive(&both, ArchiveFormat::TarGz, &dest).await;
+    assert!(matches!(res, Err(ClientError::BadRequest(_))));
+}
+
+#[tokio::test]
+async fn expand_archive() {
+    let server = MockServer::start().await;
+    let client = server.client();
+    let dir = TempDir::new();
+    let user_id = user(&client).await;
+    let mut req = cell(user_id, "files", &[]);
+    req.fileprops = vec![
+        attach(&client, "a.txt", b"a").await,
+        attach(&client, "docs/b.md", b"# b").await,
+    ];
+    req.dirs = vec!["out".to_string()];
+    client.create_cell(&req).await.unwrap();
+
+    // What an archive of the cell expands to makes the same cell
+    for (format, name) in [(ArchiveFormat::Zip, "cell.zip"), (ArchiveFormat::TarGz, "cell.tar.gz")] {
+        let archive = dir.join(name);
+        client.download_cell_archive(req.cell_id, format, &archive).await.unwrap();
+        let files = client.expand_archive(&archive).await.unwrap();
+        assert_eq!(paths(&files.fileprops), ["a.txt", "docs/b.md"]);
+        assert_eq!(files.dirs, ["out"]);
+        assert_eq!(files.fileprops[1].sha256, req.fileprops[1].sha256);
+        let dest = dir.join("b.md");
+        client.download_url(&files.fileprops[1].url, &dest).await.unwrap();
+        assert_eq!(std::fs::read(&dest).unwrap(), b"# b");
+    }
+
+    // A plain tar whose entry leaves the cell
+    let mut header = tar::Header::new_gnu();
+    header.as_gnu_mut().unwrap().name[..6].copy_from_slice(b"../x.t");
+    header.set_size(1);
+    header.set_mode(0o644);
+    header.set_cksum();
+    let mut builder = tar::Builder::new(vec![]);
+    builder.append(&header, &b"x"[..]).unwrap();
+    let archive = dir.join("unsafe.tar");
+    std::fs::write(&archive, builder.into_inner().unwrap()).unwrap();
+    let files_before = server.file_count();
+    assert!(matches!(client.expand_archive(&archive).await, Err(ClientError::BadRequest(_))));
+    assert_eq!(server.file_count(), files_before);
+}
+
+fn statuses(res: &BatchRes) -> Vec<BatchStatus> {
+    res.results.iter().map(|r| r.status).collect()
+}
+
+#[tokio::test]
+async fn create_cells() {
+    use BatchStatus::*;
+    let server = MockServer::start().await;
+    let client = server.client();
+    let user_id = user(&client).await;
+    // Replies listed before the cells they reply to
+    let root = cell(user_id, "root", &[]);
+    let middle = cell(user_id, "middle", &[root.cell_id]);
+    let leaf = cell(user_id, "leaf", &[middle.cell_id]);
+    let batch = vec![leaf.clone(), middle.clone(), root.clone()];
+    let res = client.create_cells(batch.clone()).await.unwrap();
+    assert!(res.committed);
+    assert_eq!(statuses(&res), [Created, Created, Created]);
+    assert_eq!(client.show_cell(root.cell_id).await.unwrap().children[0].children[0].cell_id, leaf.cell_id);
+    // Sent again, the same cells are found stored
+    let res = client.create_cells(batch).await.unwrap();
+    assert!(res.committed);
+    assert_eq!(statuses(&res), [Existing, Existing, Existing]);
+    assert_eq!(server.cell_count(), 3);
+
+    // Refused batches store nothing and tell which cell was at fault
+    let a = cell(user_id, "a", &[]);
+    let b = cell(user_id, "b", &[a.cell_id]);
+    let cycle = CellReq { parent_ids: vec![b.cell_id], ..a.clone() };
+    let res = client.create_cells(vec![cycle, b.clone(), cell(user_id, "c", &[])]).await.unwrap();
+    assert!(!res.committed);
+    assert_eq!(statuses(&res), [Invalid, Invalid, Skipped]);
+    let unknown = cell(user_id, "unknown", &[uuid::Uuid::new_v4()]);
+    let res = client.create_cells(vec![a.clone(), unknown]).await.unwrap();
+    assert_eq!(statuses(&res), [Skipped, Invalid]);
+    let res = client.create_cells(vec![a.clone(), a.clone()]).await.unwrap();
+    assert_eq!(statuses(&res), [Skipped, Invalid]);
+    let changed = CellReq { text: "changed".to_string(), ..root };
+    let res = client.create_cells(vec![a, changed]).await.unwrap();
+    assert_eq!(statuses(&res), [Skipped, Conflict]);
+    assert!(res.results[1].error.is_some());
+    assert_eq!(server.cell_count(), 3);
+}
+
+fn dag_ids(dag: &Dag) -> Vec<(uuid::Uuid, i32)> {
+    dag.cells.iter().map(|c| (c.cell_id, c.depth)).collect()
+}
+
+#[tokio::test]
+async fn dag() {
+    let server = MockServer::start().await;
+    let client = server.client();
+    let user_id = user(&client).await;
+    // root <- a, b <- leaf, and a <- c
+    let root = cell(user_id, "root", &[]);
+    let a = cell(user_id, "a", &[root.cell_id]);
+    let b = cell(user_id, "b", &[root.cell_id]);
+    let leaf = cell(user_id, "leaf", &[a.cell_id, b.cell_id]);
+    let c = cell(user_id, "c", &[a.cell_id]);
+    for req in [&root, &a, &b, &leaf, &c] {
+        client.create_cell(req).await.unwrap();
+    }
+    let (root, a, b, leaf, c) = (root.cell_id, a.cell_id, b.cell_id, leaf.cell_id, c.cell_id);
+    let sorted = |mut ids: Vec<(uuid::Uuid, i32)>| {
+        ids.sort_by_key(|(id, depth)| (*depth, *id));
+        ids
+    };
+
+    let query = DagQuery::default();
+    let down = client.list_descendants(root, &query).await.unwrap();
+    assert_eq!(dag_ids(&down), sorted(vec![(a, 1), (b, 1), (leaf, 2), (c, 2)]));
+    assert_eq!(down.edges.len(), 5);
+    assert_eq!(down.cells[0].cell.as_ref().unwrap().user_id, user_id);
+    let near = client.list_descendants(root, &DagQuery { depth: Some(1), shallow: true }).await.unwrap();
+    assert_eq!(dag_ids(&near), sorted(vec![(a, 1), (b, 1)]));
+    assert!(near.cells.iter().all(|c| c.cell.is_none()));
+    let up = client.list_ancestors(leaf, &query).await.unwrap();
+    assert_eq!(dag_ids(&up), sorted(vec![(a, 1), (b, 1), (root, 2)]));
+    let missing = client.list_ancestors(uuid::Uuid::new_v4(), &query).await;
+    assert!(matches!(missing, Err(ClientError::NotFound)));
+
+    let filter = DagFilter { user_id, ..Default::default() };
+    assert_eq!(dag_ids(&client.list_roots(&filter).await.unwrap()), [(root, 0)]);
+    assert_eq!(dag_ids(&client.list_leaves(&filter).await.unwrap()), [(c, 0), (leaf, 0)]);
+    let filter = DagFilter { limit: Some(1), ..filter };
+    assert_eq!(dag_ids(&client.list_leaves(&filter).await.unwrap()), [(c, 0)]);
+
+    // Down from the first cell or else up from it, never across
+    let path = client.shortest_path(root, c, &query).await.unwrap();
+    assert_eq!(dag_ids(&path), [(root, 0), (a, 1), (c, 2)]);
+    assert_eq!(path.edges.len(), 2);
+    let path = client.shortest_path(leaf, root, &query).await.unwrap();
+    assert_eq!(path.cells.len(), 3);
+    assert!(matches!(client.shortest_path(a, b, &query).await, Err(ClientError::NotFound)));
+    let short = DagQuery { depth: Some(1), ..Default::default() };
+    assert!(matches!(client.shortest_path(root, c, &short).await, Err(ClientError::NotFound)));
+
+    let common = client.common_ancestors(leaf, c, &query).await.unwrap();
+    assert_eq!(dag_ids(&common), [(a, 1), (root, 2)]);
+    let common = client.common_ancestors(a, leaf, &query).await.unwrap();
+    assert_eq!(dag_ids(&common), [(a, 1), (root, 2)]);
+}
+
+#[tokio::test]
+async fn revisions() {
+    let server = MockServer::start().await;
+    let client = server.client();
+    let user_id = user(&client).await;
+    let parent = cell(user_id, "parent", &[]);
+    let other = cell(user_id, "other", &[]);
+    let mut req = cell(user_id, "first", &[parent.cell_id]);
+    req.fileprops = vec![attach(&client, "a.txt", b"a").await];
+    for cell in [&parent, &other, &req] {
+        client.create_cell(cell).await.unwrap();
+    }
+    let id = req.cell_id;
+    // Unchanged, a cell is its own revision 1
+    let revisions = client.list_revisions(id).await.unwrap();
+    assert_eq!(revisions.len(), 1);
+    assert_eq!(revisions[0].device_id.as_deref(), Some("test"));
+
+    let update = CellUpdate { device_id: Some("laptop".to_string()), text: Some("second".to_string()), ..Default::default() };
+    let revision = client.update_cell(id, &update).await.unwrap();
+    assert_eq!((revision.revision, revision.text.as_str()), (2, "second"));
+    assert_eq!(revision.fileprops, req.fileprops);
+    // The same update again changes nothing
+    assert_eq!(client.update_cell(id, &update).await.unwrap().revision, 2);
+    let moved = CellUpdate { parent_ids: Some(vec![other.cell_id]), ..Default::default() };
+    assert_eq!(client.update_cell(id, &moved).await.unwrap().revision, 3);
+    assert_eq!(client.show_cell(id).await.unwrap().parents[0].cell_id, other.cell_id);
+    let ops = vec![FileOp::Mkdir { path: "out".to_string() }];
+    client.patch_files(id, &FilePatch { ops, device_id: Some("phone".to_string()) }).await.unwrap();
+    let revisions = client.list_revisions(id).await.unwrap();
+    assert_eq!(revisions.iter().map(|r| r.revision).collect::<Vec<_>>(), [1, 2, 3, 4]);
+    assert_eq!(revisions[3].device_id.as_deref(), Some("phone"));
+    assert_eq!(client.show_revision(id, 1).await.unwrap().text, "first");
+    assert!(matches!(client.show_revision(id, 5).await, Err(ClientError::NotFound)));
+
+    let diff = client.diff_revisions(id, &DiffQuery::default()).await.unwrap();
+    assert_eq!((diff.from, diff.to), (3, 4));
+    assert_eq!(diff.files.dirs_added, ["out"]);
+    assert!(diff.text.is_none());
+    let diff = client.diff_revisions(id, &DiffQuery { from: Some(1), to: None }).await.unwrap();
+    assert_eq!(diff.text.unwrap().new, "second");
+    assert_eq!((diff.parents_added, diff.parents_removed), (vec![other.cell_id], vec![parent.cell_id]));
+
+    let revision = client.revert_cell(id, 1, Some("laptop")).await.unwrap();
+    assert_eq!((revision.revision, revision.text.as_str()), (5, "first"));
+    assert_eq!(revision.device_id.as_deref(), Some("laptop"));
+    let shown = client.show_cell(id).await.unwrap();
+    assert!(shown.dirs.is_empty());
+    assert_eq!(shown.parents[0].cell_id, parent.cell_id);
+
+    // Parents closing a cycle, missing or given with nested files at once
+    let cycle = CellUpdate { parent_ids: Some(vec![id]), ..Default::default() };
+    assert!(matches!(client.update_cell(parent.cell_id, &cycle).await, Err(ClientError::BadRequest(_))));
+    assert!(matches!(client.update_cell(id, &cycle).await, Err(ClientError::BadRequest(_))));
+    let missing = CellUpdate { parent_ids: Some(vec![uuid::Uuid::new_v4()]), ..Default::default() };
+    assert!(matches!(client.update_cell(id, &missing).await, Err(ClientError::BadRequest(_))));
+    let both = CellUpdate {
+        dirs: Some(vec![]),
+        rootdir: Some(Dir { name: "/".to_string(), dirs: vec![], fileprops: vec![] }),
+        ..Default::default()
+    };
+    assert!(matches!(client.update_cell(id, &both).await, Err(ClientError::BadRequest(_))));
+    let unknown = client.update_cell(uuid::Uuid::new_v4(), &update).await;
+    assert!(matches!(unknown, Err(ClientError::NotFound)));
+
+    // History goes along with an export
+    let dir = TempDir::new();
+    let archive = dir.join("export.tar");
+    client.export_user(user_id, &archive).await.unwrap();
+    let other_server = MockServer::start().await;
+    other_server.client().import_user(user_id, &archive, OnConflict::Fail).await.unwrap();
+    let imported = other_server.client().list_revisions(id).await.unwrap();
+    assert_eq!(imported.len(), 5);
+    assert!(imported[0].fileprops[0].url.starts_with(other_server.url()));
+}
+
+#[tokio::test]
+async fn diff_cells() {
+    let server = MockServer::start().await;
+    let client = server.client();
+    let user_id = user(&client).await;
+    let a = attach(&client, "a.txt", b"a").await;
+    let b = attach(&client, "docs/b.md", b"b").await;
+    let mut old = cell(user_id, "old", &[]);
+    old.fileprops = vec![a.clone(), b.clone()];
+    old.dirs = vec!["out".to_string()];
+    let mut new = cell(user_id, "new", &[old.cell_id]);
+    let moved = FileProp { path: "b.md".to_string(), ..b };
+    new.fileprops = vec![moved.clone(), attach(&client, "a.txt", b"A").await, attach(&client, "c.txt", b"c").await];
+    client.create_cell(&old).await.unwrap();
+    client.create_cell(&new).await.unwrap();
+
+    let diff = client.diff_cells(old.cell_id, new.cell_id).await.unwrap();
+    assert_eq!((diff.cell_id, diff.other_id), (old.cell_id, new.cell_id));
+    assert_eq!(paths(&diff.files.added), ["c.txt"]);
+    assert!(diff.files.removed.is_empty());
+    assert_eq!(diff.files.changed[0].old, a);
+    assert_eq!(diff.files.moved[0].new, moved);
+    assert_eq!(diff.files.dirs_removed, ["out"]);
+    // Backwards, the same changes undone
+    let diff = client.diff_cells(new.cell_id, old.cell_id).await.unwrap();
+    assert_eq!(paths(&diff.files.removed), ["c.txt"]);
+    assert_eq!(diff.files.dirs_added, ["out"]);
+    assert_eq!(client.diff_cells(old.cell_id, old.cell_id).await.unwrap().files, FilesDiff::default());
+    let missing = client.diff_cells(old.cell_id, uuid::Uuid::new_v4()).await;
+    assert!(matches!(missing, Err(ClientError::NotFound)));
+}
+
+#[tokio::test]
+async fn fork_cell() {
+    let server = MockServer::start().await;
+    let client = server.client();
+    let user_id = user(&client).await;
+    let mut parent = cell(user_id, "parent", &[]);
+    parent.fileprops = vec![attach(&client, "a.txt", b"a").await, attach(&client, "docs/b.md", b"b").await];
+    client.create_cell(&parent).await.unwrap();
+    let files_before = server.file_count();
+
+    let fork = CellFork {
+        cell_id: uuid::Uuid::new_v4(),
+        user_id,
+        device_id: "test".to_string(),
+        text: "fork".to_string(),
+        is_open: true,
+        ops: vec![
+            FileOp::Remove { path: "a.txt".to_string() },
+            FileOp::Move { from: "docs".to_string(), path: "notes".to_string() },
+        ],
+    };
+    assert_eq!(client.fork_cell(parent.cell_id, &fork).await.unwrap().id, fork.cell_id);
+    // Retried, the reply is found as it was
+    client.fork_cell(parent.cell_id, &fork).await.unwrap();
+    let reply = client.show_cell(fork.cell_id).await.unwrap();
+    assert_eq!(paths(&reply.fileprops), ["notes/b.md"]);
+    assert_eq!(reply.fileprops[0].url, parent.fileprops[1].url);
+    assert_eq!(reply.parents[0].cell_id, parent.cell_id);
+    assert_eq!(server.file_count(), files_before);
+    let diff = client.diff_cells(parent.cell_id, fork.cell_id).await.unwrap();
+    assert_eq!(diff.files.moved.len(), 1);
+    assert_eq!(paths(&diff.files.removed), ["a.txt"]);
+
+    let changed = CellFork { text: "changed".to_string(), ..fork.clone() };
+    assert!(matches!(client.fork_cell(parent.cell_id, &changed).await, Err(ClientError::Conflict(_))));
+    let bad = CellFork {
+        cell_id: uuid::Uuid::new_v4(),
+        ops: vec![FileOp::Remove { path: "x.txt".to_string() }],
+        ..fork.clone()
+    };
+    assert!(matches!(client.fork_cell(parent.cell_id, &bad).await, Err(ClientError::NotFound)));
+    let orphan = CellFork { cell_id: uuid::Uuid::new_v4(), ..fork };
+    assert!(matches!(client.fork_cell(uuid::Uuid::new_v4(), &orphan).await, Err(ClientError::NotFound)));
+    assert_eq!(server.cell_count(), 2);
+}
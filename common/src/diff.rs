@@ -0,0 +1,67 @@
+//! Differences between two sets of files in flat form: the files of two
+//! revisions of a cell or of two cells.
+
+use std::collections::{BTreeSet, HashMap};
+
+use crate::model::{Change, FileProp, FilesDiff};
+
+// Files are the same when their content is, as far as can be told: by hash
+// when both have one, by where they are stored otherwise
+fn same_content(a: &FileProp, b: &FileProp) -> bool {
+    match (&a.sha256, &b.sha256) {
+        (Some(a), Some(b)) => a == b,
+        _ => a.url == b.url,
+    }
+}
+
+fn content_key(fileprop: &FileProp) -> &str {
+    fileprop.sha256.as_deref().unwrap_or(&fileprop.url)
+}
+
+/// How `new_fileprops` and `new_dirs` differ from the old ones. A removed
+/// file whose content is found at an added path counts as moved.
+pub fn diff_files(
+    old_fileprops: &[FileProp],
+    old_dirs: &[String],
+    new_fileprops: &[FileProp],
+    new_dirs: &[String],
+) -> FilesDiff {
+    let old: HashMap<&str, &FileProp> = old_fileprops.iter().map(|f| (f.path.as_str(), f)).collect();
+    let new: HashMap<&str, &FileProp> = new_fileprops.iter().map(|f| (f.path.as_str(), f)).collect();
+    let mut diff = FilesDiff::default();
+    let mut removed: Vec<&FileProp> = vec![];
+    for fileprop in old_fileprops.iter() {
+        match new.get(fileprop.path.as_str()) {
+            Some(now) if same_content(fileprop, now) => {}
+            Some(now) => diff.changed.push(Change { old: fileprop.clone(), new: (*now).clone() }),
+            None => removed.push(fileprop),
+        }
+    }
+    // A removed file whose content turns up at a new path was moved there
+    let mut added: Vec<&FileProp> = new_fileprops.iter().filter(|f| !old.contains_key(f.path.as_str())).collect();
+    for fileprop in removed.into_iter() {
+        let key = content_key(fileprop);
+        match added.iter().position(|f| content_key(f) == key) {
+            Some(i) => {
+                let now = added.remove(i);
+                diff.moved.push(Change { old: fileprop.clone(), new: now.clone() });
+            }
+            None => diff.removed.push(fileprop.clone()),
+        }
+    }
+    diff.added = added.into_iter().cloned().collect();
+
+    let old_dirs: BTreeSet<&String> = old_dirs.iter().collect();
+    let new_dirs: BTreeSet<&String> = new_dirs.iter().collect();
+    diff.dirs_added = new_dirs.difference(&old_dirs).map(|d| d.to_string()).collect();
+    diff.dirs_removed = old_dirs.difference(&new_dirs).map(|d| d.to_string()).collect();
+    diff
+}
+
+impl FilesDiff {
+    /// Whether the two sides hold the same files and directories
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+            && self.moved.is_empty() && self.dirs_added.is_empty() && self.dirs_removed.is_empty()
+    }
+}
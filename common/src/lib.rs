@@ -0,0 +1,9 @@
+//! Types and checks on a cell's attached files shared by the flowfs backend
+//! and its client, so that both sides accept, convert and edit the same
+//! trees the same way.
+
+pub mod diff;
+pub mod model;
+pub mod patch;
+pub mod tree;
+pub mod validate;
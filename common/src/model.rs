@@ -0,0 +1,90 @@
+use serde::{Deserialize, Serialize};
+
+/// A file attached to a cell, at a '/'-separated path relative to the cell.
+/// `url` is where its content is stored.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct FileProp {
+    pub path:           String,
+    pub url:            String,
+    pub completed:      bool,
+    #[serde(default)]
+    pub size:           Option<u64>,
+    #[serde(default)]
+    pub sha256:         Option<String>,
+    #[serde(default)]
+    pub mime:           Option<String>,
+    #[serde(default)]
+    pub mtime:          Option<chrono::NaiveDateTime>,
+}
+
+/// Nested form of a cell's attachments, as the desktop app keeps them.
+/// The root's name is "/".
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Dir {
+    pub name:           String,
+    #[serde(default)]
+    pub dirs:           Vec<Dir>,
+    #[serde(default)]
+    pub fileprops:      Vec<DirFile>,
+}
+
+/// A file of a [`Dir`], named relative to it
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct DirFile {
+    pub name:           String,
+    pub url:            String,
+    pub completed:      bool,
+    #[serde(default)]
+    pub size:           Option<u64>,
+    #[serde(default)]
+    pub sha256:         Option<String>,
+    #[serde(default)]
+    pub mime:           Option<String>,
+    #[serde(default)]
+    pub mtime:          Option<chrono::NaiveDateTime>,
+}
+
+/// One edit of a cell's files, as in `PATCH /cells/:cell_id/files`, e.g.
+/// `{"op": "move", "from": "a.txt", "path": "docs/a.txt"}`. `Add` and
+/// `Replace` take the fields of a [`FileProp`], `Replace` putting other
+/// content at the path of an existing file; `Move` and `Rmdir` work on whole
+/// directories.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(tag = "op", rename_all = "lowercase")]
+pub enum FileOp {
+    Add(FileProp),
+    Replace(FileProp),
+    Remove {
+        path:           String,
+    },
+    Move {
+        from:           String,
+        path:           String,
+    },
+    Mkdir {
+        path:           String,
+    },
+    Rmdir {
+        path:           String,
+        #[serde(default)]
+        recursive:      bool,
+    },
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Change<T> {
+    pub old:            T,
+    pub new:            T,
+}
+
+/// How one set of files became another. A file is moved when its content is
+/// found at a new path only, and changed when its path holds other content.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct FilesDiff {
+    pub added:          Vec<FileProp>,
+    pub removed:        Vec<FileProp>,
+    pub changed:        Vec<Change<FileProp>>,
+    pub moved:          Vec<Change<FileProp>>,
+    pub dirs_added:     Vec<String>,
+    pub dirs_removed:   Vec<String>,
+}
@@ -0,0 +1,160 @@
+//! Edits of a cell's files given as a list of [`FileOp`]s. They are applied
+//! in order to the flat form, and the result is only kept when every one of
+//! them succeeds and the names still fit together.
+
+use std::fmt;
+
+use crate::model::{FileOp, FileProp};
+use crate::tree;
+use crate::validate::{validate_path, PathError};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum PatchError {
+    /// A path in an operation is not acceptable on its own
+    Path(PathError),
+    NotFound(String),
+    Exists(String),
+    NotEmpty(String),
+    IntoItself(String),
+    /// The names left by the operations clash with each other
+    Conflict(PathError),
+}
+
+impl fmt::Display for PatchError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PatchError::Path(e) => write!(f, "{}", e),
+            PatchError::NotFound(p) => write!(f, "'{}' does not exist", p),
+            PatchError::Exists(p) => write!(f, "'{}' already exists", p),
+            PatchError::NotEmpty(p) => write!(f, "directory '{}' is not empty", p),
+            PatchError::IntoItself(p) => write!(f, "cannot move '{}' into itself", p),
+            PatchError::Conflict(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for PatchError {}
+
+impl From<PathError> for PatchError {
+    fn from(e: PathError) -> Self {
+        PatchError::Path(e)
+    }
+}
+
+/// Apply `ops` to a copy of the files and empty directories and return the
+/// result in normalized form
+pub fn apply(
+    fileprops: &[FileProp],
+    dirs: &[String],
+    ops: &[FileOp],
+) -> Result<(Vec<FileProp>, Vec<String>), PatchError> {
+    let mut fileprops = fileprops.to_vec();
+    let mut dirs = dirs.to_vec();
+    for op in ops.iter() {
+        apply_op(&mut fileprops, &mut dirs, op)?;
+    }
+    tree::normalize(&fileprops, &dirs).map_err(PatchError::Conflict)
+}
+
+fn apply_op(fileprops: &mut Vec<FileProp>, dirs: &mut Vec<String>, op: &FileOp) -> Result<(), PatchError> {
+    match op {
+        FileOp::Add(fileprop) => {
+            validate_path(&fileprop.path)?;
+            if is_file(fileprops, &fileprop.path) || is_dir(fileprops, dirs, &fileprop.path) {
+                return Err(PatchError::Exists(fileprop.path.clone()))
+            }
+            fileprops.push(fileprop.clone());
+        }
+        FileOp::Replace(fileprop) => {
+            validate_path(&fileprop.path)?;
+            let Some(index) = fileprops.iter().position(|f| f.path == fileprop.path) else {
+                return Err(PatchError::NotFound(fileprop.path.clone()))
+            };
+            fileprops[index] = fileprop.clone();
+        }
+        FileOp::Remove { path } => {
+            validate_path(path)?;
+            let Some(index) = fileprops.iter().position(|f| &f.path == path) else {
+                return Err(PatchError::NotFound(path.clone()))
+            };
+            fileprops.remove(index);
+            keep_parent(fileprops, dirs, path);
+        }
+        FileOp::Move { from, path } => {
+            validate_path(from)?;
+            validate_path(path)?;
+            if path.starts_with(&format!("{}/", from)) {
+                return Err(PatchError::IntoItself(from.clone()))
+            }
+            if from == path {
+                return Ok(())
+            }
+            if is_file(fileprops, path) || is_dir(fileprops, dirs, path) {
+                return Err(PatchError::Exists(path.clone()))
+            }
+            if let Some(fileprop) = fileprops.iter_mut().find(|f| &f.path == from) {
+                fileprop.path = path.clone();
+            } else if is_dir(fileprops, dirs, from) {
+                let prefix = format!("{}/", from);
+                for fileprop in fileprops.iter_mut() {
+                    if let Some(rest) = fileprop.path.strip_prefix(&prefix) {
+                        fileprop.path = format!("{}/{}", path, rest);
+                    }
+                }
+                for dir in dirs.iter_mut() {
+                    if dir == from {
+                        *dir = path.clone();
+                    } else if let Some(rest) = dir.strip_prefix(&prefix) {
+                        *dir = format!("{}/{}", path, rest);
+                    }
+                }
+            } else {
+                return Err(PatchError::NotFound(from.clone()))
+            }
+            keep_parent(fileprops, dirs, from);
+        }
+        FileOp::Mkdir { path } => {
+            validate_path(path)?;
+            if is_file(fileprops, path) || is_dir(fileprops, dirs, path) {
+                return Err(PatchError::Exists(path.clone()))
+            }
+            dirs.push(path.clone());
+        }
+        FileOp::Rmdir { path, recursive } => {
+            validate_path(path)?;
+            if !is_dir(fileprops, dirs, path) {
+                return Err(PatchError::NotFound(path.clone()))
+            }
+            let prefix = format!("{}/", path);
+            let has_content = fileprops.iter().any(|f| f.path.starts_with(&prefix))
+                || dirs.iter().any(|d| d.starts_with(&prefix));
+            if has_content && !recursive {
+                return Err(PatchError::NotEmpty(path.clone()))
+            }
+            fileprops.retain(|f| !f.path.starts_with(&prefix));
+            dirs.retain(|d| d != path && !d.starts_with(&prefix));
+            keep_parent(fileprops, dirs, path);
+        }
+    }
+    Ok(())
+}
+
+fn is_file(fileprops: &[FileProp], path: &str) -> bool {
+    fileprops.iter().any(|f| f.path == path)
+}
+
+// Listed as empty or implied by something below it
+fn is_dir(fileprops: &[FileProp], dirs: &[String], path: &str) -> bool {
+    let prefix = format!("{}/", path);
+    dirs.iter().any(|d| d == path || d.starts_with(&prefix))
+        || fileprops.iter().any(|f| f.path.starts_with(&prefix))
+}
+
+// The directory that held a removed or moved entry stays, even if now empty
+fn keep_parent(fileprops: &[FileProp], dirs: &mut Vec<String>, path: &str) {
+    if let Some((parent, _)) = path.rsplit_once('/') {
+        if !is_dir(fileprops, dirs, parent) {
+            dirs.push(parent.to_string());
+        }
+    }
+}
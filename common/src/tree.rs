@@ -0,0 +1,107 @@
+//! Conversion between the two forms of a cell's attachments: flat, as file
+//! paths plus the paths of directories holding no file, and nested, as the
+//! [`Dir`] tree the desktop app uses. Both directions check names the same
+//! way and sort their output, so a conversion there and back gives the same
+//! value.
+
+use crate::model::{Dir, DirFile, FileProp};
+use crate::validate::{validate_dir, validate_layout, PathError};
+
+/// Nest `fileprops` and the empty directories `dirs` below a root named "/"
+pub fn to_tree(fileprops: &[FileProp], dirs: &[String]) -> Result<Dir, PathError> {
+    validate_layout(
+        fileprops.iter().map(|f| f.path.as_str()),
+        dirs.iter().map(|d| d.as_str()),
+    )?;
+    let mut rootdir = Dir {
+        name: "/".to_string(),
+        dirs: vec![],
+        fileprops: vec![],
+    };
+    for path in dirs.iter() {
+        dir_at(&mut rootdir, path);
+    }
+    for fileprop in fileprops.iter() {
+        let (dir, name) = match fileprop.path.rsplit_once('/') {
+            Some((parent, name)) => (dir_at(&mut rootdir, parent), name),
+            None => (&mut rootdir, fileprop.path.as_str()),
+        };
+        dir.fileprops.push(DirFile {
+            name: name.to_string(),
+            url: fileprop.url.clone(),
+            completed: fileprop.completed,
+            size: fileprop.size,
+            sha256: fileprop.sha256.clone(),
+            mime: fileprop.mime.clone(),
+            mtime: fileprop.mtime,
+        });
+    }
+    sort_dir(&mut rootdir);
+    // Paths differing only in the case of a directory name end up as two
+    // siblings, which the flat check does not see
+    validate_dir(&rootdir)?;
+    Ok(rootdir)
+}
+
+/// Files sorted by path, and only the directories that have nothing below
+/// them, since the others are implied by their content
+pub fn to_flat(rootdir: &Dir) -> Result<(Vec<FileProp>, Vec<String>), PathError> {
+    validate_dir(rootdir)?;
+    let mut fileprops = vec![];
+    let mut dirs = vec![];
+    flatten(rootdir, "", &mut fileprops, &mut dirs);
+    fileprops.sort_by(|a, b| a.path.cmp(&b.path));
+    dirs.sort();
+    Ok((fileprops, dirs))
+}
+
+/// Checked, sorted flat form, without directories implied by files
+pub fn normalize(fileprops: &[FileProp], dirs: &[String]) -> Result<(Vec<FileProp>, Vec<String>), PathError> {
+    to_flat(&to_tree(fileprops, dirs)?)
+}
+
+fn flatten(dir: &Dir, prefix: &str, fileprops: &mut Vec<FileProp>, dirs: &mut Vec<String>) {
+    for file in dir.fileprops.iter() {
+        fileprops.push(FileProp {
+            path: format!("{}{}", prefix, file.name),
+            url: file.url.clone(),
+            completed: file.completed,
+            size: file.size,
+            sha256: file.sha256.clone(),
+            mime: file.mime.clone(),
+            mtime: file.mtime,
+        });
+    }
+    for sub_dir in dir.dirs.iter() {
+        let path = format!("{}{}", prefix, sub_dir.name);
+        if sub_dir.dirs.is_empty() && sub_dir.fileprops.is_empty() {
+            dirs.push(path);
+        } else {
+            flatten(sub_dir, &format!("{}/", path), fileprops, dirs);
+        }
+    }
+}
+
+// The directory at a '/' separated path below `dir`, created if missing
+fn dir_at<'a>(dir: &'a mut Dir, path: &str) -> &'a mut Dir {
+    path.split('/').fold(dir, |dir, name| {
+        let index = match dir.dirs.iter().position(|d| d.name == name) {
+            Some(index) => index,
+            None => {
+                dir.dirs.push(Dir {
+                    name: name.to_string(),
+                    dirs: vec![],
+                    fileprops: vec![],
+                });
+                dir.dirs.len() - 1
+            }
+        };
+        &mut dir.dirs[index]
+    })
+}
+
+fn sort_dir(dir: &mut Dir) {
+    dir.fileprops.sort_by(|a, b| a.name.cmp(&b.name));
+    dir.dirs.sort_by(|a, b| a.name.cmp(&b.name));
+    dir.dirs.iter_mut().for_each(sort_dir);
+}
@@ -0,0 +1,156 @@
+//! Checks on the file and directory names of cell attachments, so that a
+//! tree coming from the server can be written below a local folder without
+//! escaping it or clobbering its own entries.
+
+use std::collections::HashSet;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use crate::model::Dir;
+
+// Names Windows refuses for files, with or without an extension
+const RESERVED_NAMES: [&str; 22] = [
+    "CON", "PRN", "AUX", "NUL",
+    "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8", "COM9",
+    "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum PathError {
+    Empty,
+    DotName(String),
+    Separator(String),
+    ControlChar(String),
+    InvalidChar(String),
+    Reserved(String),
+    TrailingDotOrSpace(String),
+    Absolute(String),
+    Duplicate(String),
+    FileDirConflict(String),
+}
+
+impl fmt::Display for PathError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PathError::Empty => write!(f, "empty file name"),
+            PathError::DotName(n) => write!(f, "'{}' is not allowed as a file name", n),
+            PathError::Separator(n) => write!(f, "file name '{}' contains a path separator", n),
+            PathError::ControlChar(n) => write!(f, "file name '{}' contains a control character", n.escape_debug()),
+            PathError::InvalidChar(n) => write!(f, "file name '{}' contains one of <>:\"|?*", n),
+            PathError::Reserved(n) => write!(f, "'{}' is a reserved file name", n),
+            PathError::TrailingDotOrSpace(n) => write!(f, "file name '{}' ends with a dot or space", n),
+            PathError::Absolute(p) => write!(f, "'{}' is not a relative path", p),
+            PathError::Duplicate(p) => write!(f, "'{}' appears more than once", p),
+            PathError::FileDirConflict(p) => write!(f, "'{}' is both a file and a directory", p),
+        }
+    }
+}
+
+impl std::error::Error for PathError {}
+
+/// Check a single file or directory name
+pub fn validate_name(name: &str) -> Result<(), PathError> {
+    if name.is_empty() {
+        return Err(PathError::Empty)
+    }
+    if name == "." || name == ".." {
+        return Err(PathError::DotName(name.to_string()))
+    }
+    if name.contains(['/', '\\']) {
+        return Err(PathError::Separator(name.to_string()))
+    }
+    if name.chars().any(|c| c.is_control()) {
+        return Err(PathError::ControlChar(name.to_string()))
+    }
+    // ':' would also let "C:" or "name:stream" through on Windows
+    if name.contains(['<', '>', ':', '"', '|', '?', '*']) {
+        return Err(PathError::InvalidChar(name.to_string()))
+    }
+    if name.ends_with(['.', ' ']) {
+        return Err(PathError::TrailingDotOrSpace(name.to_string()))
+    }
+    let stem = name.split('.').next().unwrap_or(name).trim_end();
+    if RESERVED_NAMES.iter().any(|r| r.eq_ignore_ascii_case(stem)) {
+        return Err(PathError::Reserved(name.to_string()))
+    }
+    Ok(())
+}
+
+/// Check a '/'-separated relative path such as `FileProp::path`
+pub fn validate_path(path: &str) -> Result<(), PathError> {
+    if path.starts_with('/') || path.starts_with('\\') {
+        return Err(PathError::Absolute(path.to_string()))
+    }
+    path.split('/').try_for_each(validate_name)
+}
+
+/// Check a set of file paths for unsafe names, duplicates (ignoring case,
+/// which collide on Windows and macOS) and a file shadowing a directory
+pub fn validate_paths<'a>(paths: impl IntoIterator<Item = &'a str>) -> Result<(), PathError> {
+    validate_layout(paths, std::iter::empty())
+}
+
+/// Like [`validate_paths`], with `dirs` naming directories that exist even
+/// when no file is below them
+pub fn validate_layout<'a>(
+    files: impl IntoIterator<Item = &'a str>,
+    dirs: impl IntoIterator<Item = &'a str>,
+) -> Result<(), PathError> {
+    let mut file_keys = HashSet::new();
+    let mut dir_keys = HashSet::new();
+    for path in files {
+        validate_path(path)?;
+        let key = path.to_lowercase();
+        if !file_keys.insert(key.clone()) {
+            return Err(PathError::Duplicate(path.to_string()))
+        }
+        let names: Vec<&str> = key.split('/').collect();
+        for end in 1..names.len() {
+            dir_keys.insert(names[..end].join("/"));
+        }
+    }
+    let mut listed = HashSet::new();
+    for path in dirs {
+        validate_path(path)?;
+        let key = path.to_lowercase();
+        if !listed.insert(key.clone()) {
+            return Err(PathError::Duplicate(path.to_string()))
+        }
+        let names: Vec<&str> = key.split('/').collect();
+        for end in 1..=names.len() {
+            dir_keys.insert(names[..end].join("/"));
+        }
+    }
+    if let Some(conflict) = file_keys.iter().find(|f| dir_keys.contains(*f)) {
+        return Err(PathError::FileDirConflict(conflict.clone()))
+    }
+    Ok(())
+}
+
+/// Check every name below `rootdir`, whose own name is never used. Siblings
+/// may not share a name, ignoring case, whether files or directories.
+pub fn validate_dir(rootdir: &Dir) -> Result<(), PathError> {
+    validate_children(rootdir, "")
+}
+
+fn validate_children(dir: &Dir, prefix: &str) -> Result<(), PathError> {
+    let mut names = HashSet::new();
+    let file_names = dir.fileprops.iter().map(|f| f.name.as_str());
+    let dir_names = dir.dirs.iter().map(|d| d.name.as_str());
+    for name in file_names.chain(dir_names) {
+        validate_name(name)?;
+        if !names.insert(name.to_lowercase()) {
+            return Err(PathError::Duplicate(format!("{}{}", prefix, name)))
+        }
+    }
+    for sub_dir in dir.dirs.iter() {
+        validate_children(sub_dir, &format!("{}{}/", prefix, sub_dir.name))?;
+    }
+    Ok(())
+}
+
+/// Join a validated relative path onto `base`
+pub fn safe_join(base: &Path, path: &str) -> Result<PathBuf, PathError> {
+    validate_path(path)?;
+    Ok(path.split('/').fold(base.to_path_buf(), |joined, name| joined.join(name)))
+}
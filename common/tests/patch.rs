@@ -0,0 +1,189 @@
+use flowfs_common::model::{FileOp, FileProp};
+use flowfs_common::patch::{apply, PatchError};
+use flowfs_common::validate::PathError;
+
+fn fileprop(path: &str, url: &str) -> FileProp {
+    FileProp {
+        path: path.to_string(),
+        url: url.to_string(),
+        completed: true,
+        size: None,
+        sha256: None,
+        mime: None,
+        mtime: None,
+    }
+}
+
+// a.txt, docs/b.md, docs/img/c.png and the empty directory out
+fn files() -> (Vec<FileProp>, Vec<String>) {
+    (
+        vec![
+            fileprop("a.txt", "u1"),
+            fileprop("docs/b.md", "u2"),
+            fileprop("docs/img/c.png", "u3"),
+        ],
+        vec!["out".to_string()],
+    )
+}
+
+fn paths(fileprops: &[FileProp]) -> Vec<&str> {
+    fileprops.iter().map(|f| f.path.as_str()).collect()
+}
+
+fn run(ops: Vec<FileOp>) -> Result<(Vec<FileProp>, Vec<String>), PatchError> {
+    let (fileprops, dirs) = files();
+    apply(&fileprops, &dirs, &ops)
+}
+
+fn path(path: &str) -> String {
+    path.to_string()
+}
+
+#[test]
+fn add() {
+    let (fileprops, dirs) = run(vec![FileOp::Add(fileprop("out/d.txt", "u4"))]).unwrap();
+    assert_eq!(paths(&fileprops), ["a.txt", "docs/b.md", "docs/img/c.png", "out/d.txt"]);
+    assert!(dirs.is_empty());
+
+    let err = run(vec![FileOp::Add(fileprop("a.txt", "u4"))]).unwrap_err();
+    assert_eq!(err, PatchError::Exists(path("a.txt")));
+    let err = run(vec![FileOp::Add(fileprop("docs", "u4"))]).unwrap_err();
+    assert_eq!(err, PatchError::Exists(path("docs")));
+    let err = run(vec![FileOp::Add(fileprop("../d.txt", "u4"))]).unwrap_err();
+    assert_eq!(err, PatchError::Path(PathError::DotName(path(".."))));
+    // Below a file, or differing from an existing name only in case
+    let err = run(vec![FileOp::Add(fileprop("a.txt/d.txt", "u4"))]).unwrap_err();
+    assert_eq!(err, PatchError::Conflict(PathError::FileDirConflict(path("a.txt"))));
+    let err = run(vec![FileOp::Add(fileprop("A.TXT", "u4"))]).unwrap_err();
+    assert!(matches!(err, PatchError::Conflict(PathError::Duplicate(_))));
+}
+
+#[test]
+fn replace() {
+    let (fileprops, _) = run(vec![FileOp::Replace(fileprop("docs/b.md", "u4"))]).unwrap();
+    assert_eq!(fileprops[1], fileprop("docs/b.md", "u4"));
+    assert_eq!(fileprops.len(), 3);
+
+    let err = run(vec![FileOp::Replace(fileprop("docs/x.md", "u4"))]).unwrap_err();
+    assert_eq!(err, PatchError::NotFound(path("docs/x.md")));
+    let err = run(vec![FileOp::Replace(fileprop("docs", "u4"))]).unwrap_err();
+    assert_eq!(err, PatchError::NotFound(path("docs")));
+    let err = run(vec![FileOp::Replace(fileprop("/a.txt", "u4"))]).unwrap_err();
+    assert_eq!(err, PatchError::Path(PathError::Absolute(path("/a.txt"))));
+}
+
+#[test]
+fn remove() {
+    // The emptied directory stays
+    let (fileprops, dirs) = run(vec![FileOp::Remove { path: path("docs/img/c.png") }]).unwrap();
+    assert_eq!(paths(&fileprops), ["a.txt", "docs/b.md"]);
+    assert_eq!(dirs, ["docs/img", "out"]);
+
+    let err = run(vec![FileOp::Remove { path: path("x.txt") }]).unwrap_err();
+    assert_eq!(err, PatchError::NotFound(path("x.txt")));
+    let err = run(vec![FileOp::Remove { path: path("docs") }]).unwrap_err();
+    assert_eq!(err, PatchError::NotFound(path("docs")));
+    let err = run(vec![FileOp::Remove { path: path("docs//b.md") }]).unwrap_err();
+    assert_eq!(err, PatchError::Path(PathError::Empty));
+}
+
+#[test]
+fn move_file_and_dir() {
+    let (fileprops, dirs) = run(vec![FileOp::Move { from: path("a.txt"), path: path("out/a.txt") }]).unwrap();
+    assert_eq!(paths(&fileprops), ["docs/b.md", "docs/img/c.png", "out/a.txt"]);
+    assert!(dirs.is_empty());
+
+    let (fileprops, dirs) = run(vec![FileOp::Move { from: path("docs"), path: path("out/docs") }]).unwrap();
+    assert_eq!(paths(&fileprops), ["a.txt", "out/docs/b.md", "out/docs/img/c.png"]);
+    assert!(dirs.is_empty());
+    assert_eq!(fileprops[1].url, "u2");
+
+    let (fileprops, dirs) = run(vec![FileOp::Move { from: path("out"), path: path("docs/out") }]).unwrap();
+    assert_eq!(paths(&fileprops), ["a.txt", "docs/b.md", "docs/img/c.png"]);
+    assert_eq!(dirs, ["docs/out"]);
+
+    // Onto itself, nothing changes
+    assert_eq!(run(vec![FileOp::Move { from: path("a.txt"), path: path("a.txt") }]).unwrap(), files());
+}
+
+#[test]
+fn move_failures() {
+    let err = run(vec![FileOp::Move { from: path("x.txt"), path: path("y.txt") }]).unwrap_err();
+    assert_eq!(err, PatchError::NotFound(path("x.txt")));
+    let err = run(vec![FileOp::Move { from: path("a.txt"), path: path("docs/b.md") }]).unwrap_err();
+    assert_eq!(err, PatchError::Exists(path("docs/b.md")));
+    let err = run(vec![FileOp::Move { from: path("a.txt"), path: path("out") }]).unwrap_err();
+    assert_eq!(err, PatchError::Exists(path("out")));
+    let err = run(vec![FileOp::Move { from: path("docs"), path: path("docs/img/docs") }]).unwrap_err();
+    assert_eq!(err, PatchError::IntoItself(path("docs")));
+    let err = run(vec![FileOp::Move { from: path("a.txt"), path: path("..") }]).unwrap_err();
+    assert_eq!(err, PatchError::Path(PathError::DotName(path(".."))));
+    let err = run(vec![FileOp::Move { from: path("a\\b"), path: path("b") }]).unwrap_err();
+    assert_eq!(err, PatchError::Path(PathError::Separator(path("a\\b"))));
+}
+
+#[test]
+fn mkdir() {
+    let (_, dirs) = run(vec![FileOp::Mkdir { path: path("docs/new/deep") }]).unwrap();
+    assert_eq!(dirs, ["docs/new/deep", "out"]);
+
+    let err = run(vec![FileOp::Mkdir { path: path("out") }]).unwrap_err();
+    assert_eq!(err, PatchError::Exists(path("out")));
+    let err = run(vec![FileOp::Mkdir { path: path("docs/img") }]).unwrap_err();
+    assert_eq!(err, PatchError::Exists(path("docs/img")));
+    let err = run(vec![FileOp::Mkdir { path: path("a.txt") }]).unwrap_err();
+    assert_eq!(err, PatchError::Exists(path("a.txt")));
+    let err = run(vec![FileOp::Mkdir { path: path("a.txt/sub") }]).unwrap_err();
+    assert_eq!(err, PatchError::Conflict(PathError::FileDirConflict(path("a.txt"))));
+    let err = run(vec![FileOp::Mkdir { path: path("bad\0") }]).unwrap_err();
+    assert_eq!(err, PatchError::Path(PathError::ControlChar(path("bad\0"))));
+}
+
+#[test]
+fn rmdir() {
+    let (_, dirs) = run(vec![FileOp::Rmdir { path: path("out"), recursive: false }]).unwrap();
+    assert!(dirs.is_empty());
+    let (fileprops, dirs) = run(vec![FileOp::Rmdir { path: path("docs/img"), recursive: true }]).unwrap();
+    assert_eq!(paths(&fileprops), ["a.txt", "docs/b.md"]);
+    assert_eq!(dirs, ["out"]);
+
+    let err = run(vec![FileOp::Rmdir { path: path("docs"), recursive: false }]).unwrap_err();
+    assert_eq!(err, PatchError::NotEmpty(path("docs")));
+    let err = run(vec![FileOp::Rmdir { path: path("nope"), recursive: true }]).unwrap_err();
+    assert_eq!(err, PatchError::NotFound(path("nope")));
+    let err = run(vec![FileOp::Rmdir { path: path("a.txt"), recursive: true }]).unwrap_err();
+    assert_eq!(err, PatchError::NotFound(path("a.txt")));
+    let err = run(vec![FileOp::Rmdir { path: path("/out"), recursive: false }]).unwrap_err();
+    assert_eq!(err, PatchError::Path(PathError::Absolute(path("/out"))));
+}
+
+#[test]
+fn all_or_nothing() {
+    let (fileprops, dirs) = files();
+    let ops = vec![
+        FileOp::Remove { path: path("a.txt") },
+        FileOp::Mkdir { path: path("new") },
+        FileOp::Remove { path: path("a.txt") },
+    ];
+    assert_eq!(apply(&fileprops, &dirs, &ops).unwrap_err(), PatchError::NotFound(path("a.txt")));
+    assert_eq!((fileprops, dirs), files());
+
+    // Later operations see what earlier ones did
+    let (fileprops, dirs) = run(vec![
+        FileOp::Mkdir { path: path("new") },
+        FileOp::Move { from: path("a.txt"), path: path("new/a.txt") },
+        FileOp::Rmdir { path: path("out"), recursive: false },
+    ]).unwrap();
+    assert_eq!(paths(&fileprops), ["docs/b.md", "docs/img/c.png", "new/a.txt"]);
+    assert!(dirs.is_empty());
+}
+
+#[test]
+fn op_json() {
+    let op: FileOp = serde_json::from_str(r#"{"op": "move", "from": "a.txt", "path": "docs/a.txt"}"#).unwrap();
+    assert_eq!(op, FileOp::Move { from: path("a.txt"), path: path("docs/a.txt") });
+    let op: FileOp = serde_json::from_str(r#"{"op": "rmdir", "path": "out"}"#).unwrap();
+    assert_eq!(op, FileOp::Rmdir { path: path("out"), recursive: false });
+    let op: FileOp = serde_json::from_str(r#"{"op": "add", "path": "a", "url": "u", "completed": true}"#).unwrap();
+    assert_eq!(op, FileOp::Add(fileprop("a", "u")));
+}
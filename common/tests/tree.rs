@@ -0,0 +1,130 @@
+use flowfs_common::model::{Dir, DirFile, FileProp};
+use flowfs_common::tree::{normalize, to_flat, to_tree};
+use flowfs_common::validate::PathError;
+
+fn fileprop(path: &str) -> FileProp {
+    FileProp {
+        path: path.to_string(),
+        url: format!("http://localhost/blobs/{}", path.len()),
+        completed: true,
+        size: Some(path.len() as u64),
+        sha256: None,
+        mime: None,
+        mtime: None,
+    }
+}
+
+fn dir(name: &str, dirs: Vec<Dir>, fileprops: Vec<DirFile>) -> Dir {
+    Dir {
+        name: name.to_string(),
+        dirs,
+        fileprops,
+    }
+}
+
+fn dirfile(name: &str) -> DirFile {
+    DirFile {
+        name: name.to_string(),
+        url: "http://localhost/blobs/0".to_string(),
+        completed: true,
+        size: None,
+        sha256: None,
+        mime: None,
+        mtime: None,
+    }
+}
+
+fn strings(paths: &[&str]) -> Vec<String> {
+    paths.iter().map(|p| p.to_string()).collect()
+}
+
+#[test]
+fn round_trip() {
+    let fileprops = vec![
+        fileprop("a.txt"),
+        fileprop("docs/b.md"),
+        fileprop("docs/img/c.png"),
+        fileprop("z/y/x.bin"),
+    ];
+    let dirs = strings(&["docs/empty", "out"]);
+    let rootdir = to_tree(&fileprops, &dirs).unwrap();
+    assert_eq!(rootdir.name, "/");
+    assert_eq!(rootdir.fileprops.len(), 1);
+    assert_eq!(rootdir.dirs.len(), 3);
+    let (flat_files, flat_dirs) = to_flat(&rootdir).unwrap();
+    assert_eq!(flat_files, fileprops);
+    assert_eq!(flat_dirs, dirs);
+    assert_eq!(to_tree(&flat_files, &flat_dirs).unwrap(), rootdir);
+}
+
+#[test]
+fn empty_dirs() {
+    let rootdir = to_tree(&[], &strings(&["a", "b/c/d"])).unwrap();
+    let expected = dir("/", vec![
+        dir("a", vec![], vec![]),
+        dir("b", vec![dir("c", vec![dir("d", vec![], vec![])], vec![])], vec![]),
+    ], vec![]);
+    assert_eq!(rootdir, expected);
+    assert_eq!(to_flat(&rootdir).unwrap(), (vec![], strings(&["a", "b/c/d"])));
+
+    // Directories holding a file or another directory are implied
+    let (fileprops, dirs) = normalize(
+        &[fileprop("a/f.txt")],
+        &strings(&["a", "b", "b/c"]),
+    ).unwrap();
+    assert_eq!(fileprops, vec![fileprop("a/f.txt")]);
+    assert_eq!(dirs, strings(&["b/c"]));
+
+    let (fileprops, dirs) = to_flat(&dir("/", vec![], vec![])).unwrap();
+    assert!(fileprops.is_empty());
+    assert!(dirs.is_empty());
+}
+
+#[test]
+fn duplicate_paths() {
+    let err = to_tree(&[fileprop("a.txt"), fileprop("a.txt")], &[]).unwrap_err();
+    assert_eq!(err, PathError::Duplicate("a.txt".to_string()));
+    let err = to_tree(&[fileprop("docs/A.txt"), fileprop("docs/a.txt")], &[]).unwrap_err();
+    assert_eq!(err, PathError::Duplicate("docs/a.txt".to_string()));
+    let err = to_tree(&[], &strings(&["out", "OUT"])).unwrap_err();
+    assert_eq!(err, PathError::Duplicate("OUT".to_string()));
+    let err = to_tree(&[fileprop("a"), fileprop("a/b.txt")], &[]).unwrap_err();
+    assert_eq!(err, PathError::FileDirConflict("a".to_string()));
+    let err = to_tree(&[fileprop("a")], &strings(&["a"])).unwrap_err();
+    assert_eq!(err, PathError::FileDirConflict("a".to_string()));
+    // Directory names differing in case become two siblings
+    let err = to_tree(&[fileprop("Docs/a.txt"), fileprop("docs/b.txt")], &[]).unwrap_err();
+    assert!(matches!(err, PathError::Duplicate(_)));
+
+    let rootdir = dir("/", vec![dir("x", vec![], vec![])], vec![dirfile("X")]);
+    assert_eq!(to_flat(&rootdir).unwrap_err(), PathError::Duplicate("x".to_string()));
+    let rootdir = dir("/", vec![dir("docs", vec![], vec![dirfile("a"), dirfile("a")])], vec![]);
+    assert_eq!(to_flat(&rootdir).unwrap_err(), PathError::Duplicate("docs/a".to_string()));
+}
+
+#[test]
+fn order_only_differences() {
+    let fileprops = vec![
+        fileprop("b/2.txt"),
+        fileprop("a.txt"),
+        fileprop("b/1.txt"),
+        fileprop("c/d/e.txt"),
+    ];
+    let dirs = strings(&["z", "b/y", "m"]);
+    let mut reversed_files = fileprops.clone();
+    reversed_files.reverse();
+    let mut reversed_dirs = dirs.clone();
+    reversed_dirs.reverse();
+    let rootdir = to_tree(&fileprops, &dirs).unwrap();
+    assert_eq!(to_tree(&reversed_files, &reversed_dirs).unwrap(), rootdir);
+
+    let (flat_files, flat_dirs) = normalize(&reversed_files, &reversed_dirs).unwrap();
+    let paths: Vec<&str> = flat_files.iter().map(|f| f.path.as_str()).collect();
+    assert_eq!(paths, ["a.txt", "b/1.txt", "b/2.txt", "c/d/e.txt"]);
+    assert_eq!(flat_dirs, strings(&["b/y", "m", "z"]));
+
+    // A tree listing its entries in another order flattens the same way
+    let sorted = dir("/", vec![dir("a", vec![], vec![]), dir("b", vec![], vec![dirfile("1"), dirfile("2")])], vec![]);
+    let shuffled = dir("/", vec![dir("b", vec![], vec![dirfile("2"), dirfile("1")]), dir("a", vec![], vec![])], vec![]);
+    assert_eq!(to_flat(&shuffled).unwrap(), to_flat(&sorted).unwrap());
+}
@@ -0,0 +1,91 @@
+use std::path::Path;
+
+use flowfs_common::model::{Dir, DirFile};
+use flowfs_common::validate::{safe_join, validate_dir, validate_name, validate_path, validate_paths, PathError};
+
+fn dirfile(name: &str) -> DirFile {
+    DirFile {
+        name: name.to_string(),
+        url: "http://localhost/blobs/0".to_string(),
+        completed: true,
+        size: None,
+        sha256: None,
+        mime: None,
+        mtime: None,
+    }
+}
+
+#[test]
+fn dot_names() {
+    assert_eq!(validate_path(".."), Err(PathError::DotName("..".to_string())));
+    assert_eq!(validate_path("../a.txt"), Err(PathError::DotName("..".to_string())));
+    assert_eq!(validate_path("docs/../../a.txt"), Err(PathError::DotName("..".to_string())));
+    assert_eq!(validate_path("docs/."), Err(PathError::DotName(".".to_string())));
+    assert!(validate_path(".hidden").is_ok());
+    assert!(validate_path("a..b").is_ok());
+}
+
+#[test]
+fn absolute_paths() {
+    assert_eq!(validate_path("/etc/passwd"), Err(PathError::Absolute("/etc/passwd".to_string())));
+    assert_eq!(validate_path("\\a.txt"), Err(PathError::Absolute("\\a.txt".to_string())));
+    assert_eq!(validate_path("C:/a.txt"), Err(PathError::InvalidChar("C:".to_string())));
+    assert!(safe_join(Path::new("/tmp/cell"), "/etc/passwd").is_err());
+    assert_eq!(
+        safe_join(Path::new("/tmp/cell"), "docs/a.txt").unwrap(),
+        Path::new("/tmp/cell/docs/a.txt"),
+    );
+}
+
+#[test]
+fn control_chars() {
+    assert_eq!(validate_name("a\0b"), Err(PathError::ControlChar("a\0b".to_string())));
+    assert_eq!(validate_path("docs/a\0"), Err(PathError::ControlChar("a\0".to_string())));
+    assert!(validate_name("tab\there").is_err());
+    assert!(validate_name("line\n").is_err());
+}
+
+#[test]
+fn backslashes() {
+    assert_eq!(validate_name("a\\b"), Err(PathError::Separator("a\\b".to_string())));
+    assert_eq!(validate_path("docs\\..\\a"), Err(PathError::Separator("docs\\..\\a".to_string())));
+    assert_eq!(validate_name("a/b"), Err(PathError::Separator("a/b".to_string())));
+}
+
+#[test]
+fn empty_segments() {
+    assert_eq!(validate_path(""), Err(PathError::Empty));
+    assert_eq!(validate_path("docs//a.txt"), Err(PathError::Empty));
+    assert_eq!(validate_path("docs/"), Err(PathError::Empty));
+    assert_eq!(validate_paths(["a.txt", "b/"]), Err(PathError::Empty));
+}
+
+#[test]
+fn windows_names() {
+    assert_eq!(validate_name("a?.txt"), Err(PathError::InvalidChar("a?.txt".to_string())));
+    assert_eq!(validate_name("con.txt"), Err(PathError::Reserved("con.txt".to_string())));
+    assert_eq!(validate_name("LPT1"), Err(PathError::Reserved("LPT1".to_string())));
+    assert_eq!(validate_name("a."), Err(PathError::TrailingDotOrSpace("a.".to_string())));
+    assert_eq!(validate_name("a "), Err(PathError::TrailingDotOrSpace("a ".to_string())));
+    assert!(validate_name("console.txt").is_ok());
+}
+
+#[test]
+fn nested_names() {
+    let mut rootdir = Dir {
+        name: "/".to_string(),
+        dirs: vec![Dir {
+            name: "docs".to_string(),
+            dirs: vec![],
+            fileprops: vec![dirfile("a.txt")],
+        }],
+        fileprops: vec![],
+    };
+    assert!(validate_dir(&rootdir).is_ok());
+    rootdir.dirs[0].fileprops.push(dirfile(".."));
+    assert_eq!(validate_dir(&rootdir), Err(PathError::DotName("..".to_string())));
+    rootdir.dirs[0].fileprops[1] = dirfile("x\\y");
+    assert_eq!(validate_dir(&rootdir), Err(PathError::Separator("x\\y".to_string())));
+    rootdir.dirs[0].fileprops[1] = dirfile("A.TXT");
+    assert_eq!(validate_dir(&rootdir), Err(PathError::Duplicate("docs/A.TXT".to_string())));
+}
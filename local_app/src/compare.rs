@@ -0,0 +1,83 @@
+use dioxus::prelude::*;
+use flowfs_client::model::FilesDiff;
+
+use crate::model::{CellExtracted, Dir};
+use crate::patch;
+use crate::utils;
+
+// Files are compared as the backend's GET /cells/:cell_id/diff/:other_id
+// does: by path, a file whose content shows up at another path being renamed
+pub fn diff_dirs(old: &Dir, new: &Dir) -> FilesDiff {
+    let flat = |dir: &Dir| {
+        dir.files()
+            .into_iter()
+            .map(|(path, fileprop)| patch::to_remote(path, fileprop))
+            .collect::<Vec<_>>()
+    };
+    flowfs_client::diff::diff_files(&flat(old), &old.empty_dirs(), &flat(new), &new.empty_dirs())
+}
+
+// What a reply changed in the files of each cell it replies to
+#[component]
+pub fn ParentChanges(cell: CellExtracted) -> Element {
+    rsx! {
+        for parent in cell.parents.iter() {
+            DirDiff {
+                key: "{parent.cell_id}",
+                title: parent.text.clone(),
+                old: parent.rootdir.clone(),
+                new: cell.rootdir.clone(),
+            }
+        }
+    }
+}
+
+// Added files in green, removed in red, modified in amber and renamed in
+// blue, with a count of each above
+#[component]
+pub fn DirDiff(title: String, old: Dir, new: Dir) -> Element {
+    let diff = diff_dirs(&old, &new);
+    let summary = format!(
+        "{} added, {} removed, {} modified, {} renamed",
+        diff.added.len() + diff.dirs_added.len(),
+        diff.removed.len() + diff.dirs_removed.len(),
+        diff.changed.len(),
+        diff.moved.len(),
+    );
+    let size = |size: Option<u64>| size.map(utils::format_size).unwrap_or("?".to_string());
+    rsx! {
+        div { class: "my-1 text-sm",
+            div { class: "flex flex-row text-gray-400",
+                span { class: "flex-1 truncate", "Compared with: {title}" }
+                span { class: "ml-2", "{summary}" }
+            }
+            if diff.is_empty() {
+                p { class: "text-gray-500", "Same files." }
+            }
+            div { class: "font-mono",
+                for fileprop in diff.added.iter() {
+                    p { key: "+{fileprop.path}", class: "text-green-600", "+ {fileprop.path}" }
+                }
+                for dir in diff.dirs_added.iter() {
+                    p { key: "+{dir}/", class: "text-green-600", "+ {dir}/" }
+                }
+                for fileprop in diff.removed.iter() {
+                    p { key: "-{fileprop.path}", class: "text-red-600", "- {fileprop.path}" }
+                }
+                for dir in diff.dirs_removed.iter() {
+                    p { key: "-{dir}/", class: "text-red-600", "- {dir}/" }
+                }
+                for change in diff.changed.iter() {
+                    p { key: "~{change.new.path}", class: "text-amber-600",
+                        "~ {change.new.path} ({size(change.old.size)} → {size(change.new.size)})"
+                    }
+                }
+                for change in diff.moved.iter() {
+                    p { key: ">{change.new.path}", class: "text-blue-600",
+                        "> {change.old.path} → {change.new.path}"
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,371 @@
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use dioxus::prelude::*;
+use flowfs_client::{ClientError, FlowfsClient};
+use flowfs_client::hash::sha256_file;
+use flowfs_client::model::{ArchiveFormat, ArchiveReq};
+use futures::stream::{self, StreamExt};
+use tokio::io::AsyncWriteExt;
+
+use crate::model::Dir;
+use crate::patch;
+use crate::utils;
+use crate::validate::{validate_dir, PathError};
+
+const MAX_PARALLEL: usize = 4;
+const MAX_RETRIES: u32 = 3;
+const RETRY_DELAY: Duration = Duration::from_millis(500);
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct FileTask {
+    pub path:           String,
+    pub url:            String,
+    pub dest:           PathBuf,
+    pub size:           Option<u64>,
+    pub sha256:         Option<String>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct FileProgress {
+    pub path:           String,
+    pub done:           u64,
+    pub total:          Option<u64>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Failure {
+    pub path:           String,
+    pub error:          String,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Default)]
+pub enum DownloadState {
+    #[default]
+    Running,
+    Finished,
+    Cancelled,
+}
+
+#[derive(Clone, Debug, PartialEq, Default)]
+pub struct Progress {
+    pub total_files:    usize,
+    pub done_files:     usize,
+    pub total_bytes:    u64,
+    pub done_bytes:     u64,
+    pub active:         Vec<FileProgress>,
+    pub failures:       Vec<Failure>,
+    pub state:          DownloadState,
+}
+
+#[derive(Debug)]
+pub enum DownloadError {
+    Cancelled,
+    Client(ClientError),
+    Io(std::io::Error),
+    Mismatch(String),
+}
+
+impl fmt::Display for DownloadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DownloadError::Cancelled => write!(f, "cancelled"),
+            DownloadError::Client(e) => write!(f, "{}", e),
+            DownloadError::Io(e) => write!(f, "{}", e),
+            DownloadError::Mismatch(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl From<ClientError> for DownloadError {
+    fn from(e: ClientError) -> Self {
+        DownloadError::Client(e)
+    }
+}
+
+impl From<std::io::Error> for DownloadError {
+    fn from(e: std::io::Error) -> Self {
+        DownloadError::Io(e)
+    }
+}
+
+impl Progress {
+    // A download that was refused before any file was fetched
+    pub fn refused(error: String) -> Self {
+        Progress {
+            failures: vec![Failure { path: "/".to_string(), error }],
+            state: DownloadState::Finished,
+            ..Progress::default()
+        }
+    }
+}
+
+impl DownloadError {
+    // Broken connections and corrupted content are retried, missing files
+    // and disk errors are not
+    fn is_retryable(&self) -> bool {
+        match self {
+            DownloadError::Client(ClientError::Http(_)) => true,
+            DownloadError::Client(e) => e.is_retryable(),
+            DownloadError::Mismatch(_) => true,
+            _ => false,
+        }
+    }
+}
+
+// List the files of `rootdir` with where they go below `base`.
+// The root directory itself maps onto `base`. Trees with names that could
+// land outside `base` are refused as a whole.
+pub fn plan(rootdir: &Dir, base: &Path) -> Result<Vec<FileTask>, PathError> {
+    validate_dir(rootdir)?;
+    let mut tasks = vec![];
+    plan_dir(rootdir, base, "", &mut tasks);
+    Ok(tasks)
+}
+
+// Have the server pack the files of `rootdir` into one archive at `dest`,
+// for trees too large to fetch file by file
+pub async fn download_archive(
+    client: &FlowfsClient,
+    rootdir: &Dir,
+    format: ArchiveFormat,
+    dest: &Path,
+) -> Result<u64, ClientError> {
+    let req = ArchiveReq {
+        fileprops: rootdir.files()
+            .into_iter()
+            .map(|(path, fileprop)| patch::to_remote(path, fileprop))
+            .collect(),
+        dirs: rootdir.empty_dirs(),
+        rootdir: None,
+    };
+    client.download_archive(&req, format, dest).await
+}
+
+fn plan_dir(dir: &Dir, dest: &Path, prefix: &str, tasks: &mut Vec<FileTask>) {
+    for fileprop in dir.fileprops.iter() {
+        tasks.push(FileTask {
+            path: format!("{}{}", prefix, fileprop.name),
+            url: fileprop.url.clone(),
+            dest: dest.join(&fileprop.name),
+            size: fileprop.size,
+            sha256: fileprop.sha256.clone(),
+        });
+    }
+    for sub_dir in dir.dirs.iter() {
+        let prefix = format!("{}{}/", prefix, sub_dir.name);
+        plan_dir(sub_dir, &dest.join(&sub_dir.name), &prefix, tasks);
+    }
+}
+
+// Download every task, a few at a time, reporting into `progress` until
+// done or until `cancel` is set. Failed files are listed in the final progress.
+pub async fn download_all(
+    client: FlowfsClient,
+    tasks: Vec<FileTask>,
+    mut progress: Signal<Progress>,
+    cancel: Signal<bool>,
+) {
+    progress.set(Progress {
+        total_files: tasks.len(),
+        total_bytes: tasks.iter().filter_map(|t| t.size).sum(),
+        ..Progress::default()
+    });
+
+    let client = &client;
+    let results: Vec<(String, Result<(), DownloadError>)> = stream::iter(tasks)
+        .map(|task| async move {
+            let res = download_with_retry(client, &task, progress, cancel).await;
+            (task.path, res)
+        })
+        .buffer_unordered(MAX_PARALLEL)
+        .collect()
+        .await;
+
+    let mut progress = progress.write();
+    for (path, res) in results {
+        match res {
+            Ok(()) | Err(DownloadError::Cancelled) => {}
+            Err(e) => progress.failures.push(Failure { path, error: e.to_string() }),
+        }
+    }
+    progress.active.clear();
+    progress.state = if cancel() { DownloadState::Cancelled } else { DownloadState::Finished };
+}
+
+async fn download_with_retry(
+    client: &FlowfsClient,
+    task: &FileTask,
+    mut progress: Signal<Progress>,
+    cancel: Signal<bool>,
+) -> Result<(), DownloadError> {
+    if cancel() {
+        return Err(DownloadError::Cancelled)
+    }
+    progress.write().active.push(FileProgress {
+        path: task.path.clone(),
+        done: 0,
+        total: task.size,
+    });
+    let mut delay = RETRY_DELAY;
+    let mut tries = 0;
+    let res = loop {
+        match download_file(client, task, progress, cancel).await {
+            Err(e) if e.is_retryable() && tries < MAX_RETRIES => {
+                println!("Retrying {} after error: {}", task.path, e);
+                tokio::time::sleep(delay).await;
+                delay *= 2;
+                tries += 1;
+            }
+            res => break res,
+        }
+    };
+    let mut progress = progress.write();
+    progress.active.retain(|f| f.path != task.path);
+    if res.is_ok() {
+        progress.done_files += 1;
+    }
+    res
+}
+
+// Data is written to "<name>.part" and renamed when complete, so a partial
+// file left by a failed or cancelled attempt is resumed with a range request
+async fn download_file(
+    client: &FlowfsClient,
+    task: &FileTask,
+    progress: Signal<Progress>,
+    cancel: Signal<bool>,
+) -> Result<(), DownloadError> {
+    if let Some(parent) = task.dest.parent() {
+        tokio::fs::create_dir_all(parent).await?;
+    }
+    let part_path = part_path(&task.dest);
+    let offset = match tokio::fs::metadata(&part_path).await {
+        Ok(meta) => meta.len(),
+        Err(_) => 0,
+    };
+    let res = match client.get_file(&task.url, offset).await {
+        Ok(res) => res,
+        // The part file already holds the whole content
+        Err(ClientError::Server(status, _)) if offset > 0 && status.as_u16() == 416 => {
+            verify_part(task, &part_path).await?;
+            tokio::fs::rename(&part_path, &task.dest).await?;
+            set_file_progress(progress, &task.path, offset, Some(offset));
+            return Ok(())
+        }
+        Err(e) => return Err(e.into()),
+    };
+    let resumed = res.status().as_u16() == 206;
+    let mut done = if resumed { offset } else { 0 };
+    let total = res.content_length().map(|len| len + done).or(task.size);
+    let mut file = if resumed {
+        tokio::fs::OpenOptions::new().append(true).open(&part_path).await?
+    } else {
+        tokio::fs::File::create(&part_path).await?
+    };
+    set_file_progress(progress, &task.path, done, total);
+
+    let mut stream = res.bytes_stream();
+    while let Some(chunk) = stream.next().await {
+        if cancel() {
+            file.flush().await?;
+            return Err(DownloadError::Cancelled)
+        }
+        let chunk = chunk.map_err(ClientError::from)?;
+        file.write_all(&chunk).await?;
+        done += chunk.len() as u64;
+        set_file_progress(progress, &task.path, done, total);
+    }
+    file.flush().await?;
+    drop(file);
+    verify_part(task, &part_path).await?;
+    tokio::fs::rename(&part_path, &task.dest).await?;
+    Ok(())
+}
+
+// Check a complete part file against the digest recorded at upload. A bad
+// one is removed so that the retry starts over instead of resuming it.
+async fn verify_part(task: &FileTask, part_path: &Path) -> Result<(), DownloadError> {
+    let Some(expected) = task.sha256.clone() else {
+        return Ok(())
+    };
+    let path = part_path.to_path_buf();
+    let (_, sha256) = tokio::task::spawn_blocking(move || sha256_file(&path))
+        .await
+        .map_err(std::io::Error::other)??;
+    if sha256 != expected {
+        tokio::fs::remove_file(part_path).await?;
+        return Err(DownloadError::Mismatch(format!("sha256 mismatch, expected {} got {}", expected, sha256)))
+    }
+    Ok(())
+}
+
+fn part_path(dest: &Path) -> PathBuf {
+    let mut name = dest.file_name().unwrap_or_default().to_os_string();
+    name.push(".part");
+    dest.with_file_name(name)
+}
+
+// Record how far a file got and move the overall byte count by the change,
+// which can be negative when a server without range support restarts a file
+fn set_file_progress(mut progress: Signal<Progress>, path: &str, done: u64, total: Option<u64>) {
+    let mut progress = progress.write();
+    let Some(file) = progress.active.iter_mut().find(|f| f.path == path) else {
+        return
+    };
+    let previous = file.done;
+    file.done = done;
+    let newly_known = if file.total.is_none() { total } else { None };
+    file.total = file.total.or(total);
+    progress.total_bytes += newly_known.unwrap_or(0);
+    progress.done_bytes = (progress.done_bytes + done).saturating_sub(previous);
+}
+
+#[component]
+pub fn DownloadPanel(progress: Signal<Progress>, cancel: Signal<bool>, on_close: EventHandler<MouseEvent>) -> Element {
+    let p = progress.read();
+    let percent = (p.done_bytes * 100).checked_div(p.total_bytes).unwrap_or(0);
+    rsx! {
+        div { class: "mt-2 p-2 rounded-md bg-gray-100 text-sm",
+            div { class: "flex flex-row items-center",
+                span { class: "flex-1",
+                    "{p.done_files}/{p.total_files} files, {utils::format_size(p.done_bytes)} / {utils::format_size(p.total_bytes)}"
+                }
+                if p.state == DownloadState::Running {
+                    button {
+                        class: "ml-2 px-2 rounded-md bg-gray-200 hover:bg-gray-300",
+                        disabled: cancel(),
+                        onclick: move |_| cancel.set(true),
+                        if cancel() { "Cancelling..." } else { "Cancel" }
+                    }
+                } else {
+                    button {
+                        class: "ml-2 px-2 rounded-md bg-gray-200 hover:bg-gray-300",
+                        onclick: move |evt| on_close.call(evt),
+                        "Close"
+                    }
+                }
+            }
+            progress { class: "w-full", max: "100", value: "{percent}" }
+            for file in p.active.iter() {
+                div { class: "flex flex-row text-xs text-gray-500",
+                    span { class: "flex-1 truncate", "{file.path}" }
+                    span {
+                        {utils::format_size(file.done)}
+                        if let Some(total) = file.total { " / {utils::format_size(total)}" }
+                    }
+                }
+            }
+            match p.state {
+                DownloadState::Running => rsx! {},
+                DownloadState::Cancelled => rsx! { p { "Download cancelled." } },
+                DownloadState::Finished if p.failures.is_empty() => rsx! { p { "Download complete." } },
+                DownloadState::Finished => rsx! { p { "{p.failures.len()} of {p.total_files} files failed:" } },
+            }
+            for failure in p.failures.iter() {
+                p { class: "text-xs text-red-600", "{failure.path}: {failure.error}" }
+            }
+        }
+    }
+}
@@ -0,0 +1,250 @@
+use dioxus::prelude::*;
+use dioxus::html::HasFileData;
+use sqlx::sqlite::SqlitePoolOptions;
+
+use crate::handler;
+use crate::local_dir;
+use crate::model::{self, CellEdit, CellExtracted};
+use crate::patch;
+use crate::utils;
+use crate::validate;
+use crate::tree;
+use crate::DEVICE_ID;
+
+// Edits a posted cell's text, flag, files and parents in place. Nothing is
+// stored until "Save", and added files are uploaded then.
+#[component]
+pub fn CellEditForm(cell: CellExtracted, force_reload: Signal<i32>, on_close: EventHandler<()>) -> Element {
+    let mut text = use_signal(|| cell.text.clone());
+    let mut is_open = use_signal(|| cell.is_open);
+    let mut rootdir = use_signal(|| cell.rootdir.clone());
+    // Parents as (cell_id, text), the text being empty for ones added here
+    let mut parents = use_signal(|| {
+        cell.parents.iter().map(|p| (p.cell_id.clone(), p.text.clone())).collect::<Vec<_>>()
+    });
+    let mut new_parent = use_signal::<String>(|| "".to_string());
+    let mut message = use_signal::<Option<String>>(|| None);
+    let mut is_saving = use_signal::<bool>(|| false);
+
+    let add_paths = move |paths: Vec<String>| {
+        spawn(async move {
+            match tokio::task::spawn_blocking(move || local_dir::scan_paths(&paths)).await {
+                Ok(Ok(added)) => {
+                    let mut rootdir = rootdir.write();
+                    rootdir.dirs.extend(added.dirs);
+                    rootdir.fileprops.extend(added.fileprops);
+                    message.set(None);
+                }
+                Ok(Err(e)) => message.set(Some(format!("Failed to read the files: {e}"))),
+                Err(e) => message.set(Some(format!("Failed to read the files: {e}"))),
+            }
+        });
+    };
+
+    let pick_files = move |_| {
+        spawn(async move {
+            let Some(files) = rfd::AsyncFileDialog::new().pick_files().await else {
+                return
+            };
+            add_paths(files.iter().map(|f| f.path().to_string_lossy().to_string()).collect());
+        });
+    };
+
+    let drop_files = move |event: DragEvent| {
+        if let Some(file_engine) = event.files() {
+            add_paths(file_engine.files());
+        }
+    };
+
+    let edit_files = move |op: patch::FileOp| {
+        let edited = patch::apply(&rootdir.read(), &[op]);
+        match edited {
+            Ok(dir) => {
+                rootdir.set(dir);
+                message.set(None);
+            }
+            Err(e) => message.set(Some(format!("Cannot change files: {e}"))),
+        }
+    };
+
+    let add_parent = move |_| {
+        let parent_id = new_parent.read().trim().to_string();
+        if parent_id.is_empty() || parents.read().iter().any(|(id, _)| *id == parent_id) {
+            return
+        }
+        parents.write().push((parent_id, "".to_string()));
+        new_parent.set("".to_string());
+    };
+
+    let cell_id = cell.cell_id.clone();
+    let save = move |_| {
+        let cell_id = cell_id.clone();
+        let mut edit = CellEdit {
+            device_id: DEVICE_ID.to_string(),
+            text: text.read().to_string(),
+            is_open: *is_open.read(),
+            rootdir: rootdir.read().clone(),
+            parent_ids: parents.read().iter().map(|(id, _)| id.clone()).collect(),
+        };
+        if let Err(e) = validate::validate_dir(&edit.rootdir) {
+            message.set(Some(format!("Cannot save: {e}")));
+            return
+        }
+        spawn(async move {
+            is_saving.set(true);
+            message.set(Some("Uploading files...".to_string()));
+            let client = utils::get_client();
+            if let Err(e) = local_dir::upload_dir(&client, &mut edit.rootdir).await {
+                message.set(Some(format!("Upload failed: {e}")));
+                is_saving.set(false);
+                return
+            }
+            let db_url = utils::get_db_path();
+            let pool = SqlitePoolOptions::new()
+                .max_connections(16)
+                .connect(&db_url)
+                .await
+                .unwrap();
+            match handler::update_cell(&cell_id, edit, pool).await {
+                Ok(_) => {
+                    message.set(None);
+                    force_reload += 1;
+                    on_close.call(());
+                }
+                Err(e) => message.set(Some(format!("Failed to save: {e}"))),
+            }
+            is_saving.set(false);
+        });
+    };
+
+    rsx! {
+        div { class: "flex flex-col",
+            textarea {
+                class: "textarea textarea-bordered textarea-md w-full",
+                value: "{text}",
+                oninput: move |event| text.set(event.value()),
+            }
+            label { class: "label cursor-pointer justify-start",
+                input {
+                    r#type: "checkbox",
+                    class: "checkbox checkbox-sm",
+                    checked: is_open(),
+                    onchange: move |event| is_open.set(event.checked()),
+                }
+                span { class: "label-text ml-2", "Open to others" }
+            }
+            div {
+                class: "my-2 p-2 border-2 border-dashed rounded-md text-sm text-gray-500",
+                prevent_default: "ondragover ondrop",
+                ondragover: move |_| {},
+                ondrop: drop_files,
+                if rootdir.read().dirs.is_empty() && rootdir.read().fileprops.is_empty() {
+                    "Drop files or folders here"
+                } else {
+                    tree::FileTree { rootdir: rootdir(), on_op: edit_files }
+                }
+            }
+            div { class: "text-sm",
+                "Replies to:"
+                for (parent_id, parent_text) in parents() {
+                    div { key: "{parent_id}", class: "flex flex-row items-center",
+                        span { class: "flex-1 truncate",
+                            if parent_text.is_empty() { "{parent_id}" } else { "{parent_text}" }
+                        }
+                        button {
+                            class: "ml-2 text-gray-500 hover:text-gray-700",
+                            onclick: move |_| parents.write().retain(|(id, _)| *id != parent_id),
+                            "Remove"
+                        }
+                    }
+                }
+                div { class: "flex flex-row mt-1",
+                    input {
+                        class: "input input-bordered input-sm flex-1",
+                        placeholder: "Parent cell id",
+                        value: "{new_parent}",
+                        oninput: move |event| new_parent.set(event.value()),
+                    }
+                    button {
+                        class: "ml-2 px-2 py-1 bg-gray-200 rounded-md hover:bg-gray-300",
+                        onclick: add_parent,
+                        "Add"
+                    }
+                }
+            }
+            div { class: "flex flex-row mt-2",
+                button {
+                    class: "px-4 py-2 bg-gray-200 rounded-md hover:bg-gray-300",
+                    disabled: is_saving(),
+                    onclick: pick_files,
+                    "Add files..."
+                }
+                button {
+                    class: "ml-2 px-4 py-2 bg-gray-200 rounded-md hover:bg-gray-300",
+                    disabled: is_saving(),
+                    onclick: move |_| on_close.call(()),
+                    "Cancel"
+                }
+                button {
+                    class: "ml-2 px-4 py-2 bg-blue-500 text-white rounded-md hover:bg-blue-600 focus:outline-none focus:ring-2 focus:ring-blue-500",
+                    disabled: is_saving(),
+                    onclick: save,
+                    "Save"
+                }
+            }
+            if let Some(message) = message() {
+                p { class: "text-sm text-gray-500", "{message}" }
+            }
+        }
+    }
+}
+
+// Earlier versions of a cell, newest first
+#[component]
+pub fn RevisionList(cell_id: String, force_reload: Signal<i32>) -> Element {
+    let revisions = use_resource(use_reactive!(|(cell_id, force_reload)| async move {
+        println!("Loading revisions of {} with {}", cell_id, force_reload);
+        let db_url = utils::get_db_path();
+        let pool = SqlitePoolOptions::new()
+            .max_connections(16)
+            .connect(&db_url)
+            .await?;
+        handler::list_revisions(&cell_id, pool).await
+    }));
+
+    rsx! {
+        div { class: "text-sm",
+            match &*revisions.read_unchecked() {
+                Some(Ok(revisions)) if revisions.is_empty() => rsx! {
+                    p { class: "text-gray-500", "Not edited since it was posted." }
+                },
+                Some(Ok(revisions)) => rsx! {
+                    for revision in revisions.iter() {
+                        RevisionItem { key: "{revision.revision}", revision: revision.clone() }
+                    }
+                },
+                Some(Err(e)) => rsx! {
+                    p { class: "text-gray-500", "Failed to load the history: {e}" }
+                },
+                None => rsx! {},
+            }
+        }
+    }
+}
+
+#[component]
+fn RevisionItem(revision: model::Revision) -> Element {
+    let files = revision.rootdir.files().len();
+    let parents = revision.parent_ids.len();
+    let created_at = revision.created_at.format("%Y/%m/%d %H:%M");
+    rsx! {
+        div { class: "py-1 border-b",
+            div { class: "text-gray-400",
+                "#{revision.revision} {created_at} from {revision.device_id}"
+                if !revision.is_open { " (closed)" }
+            }
+            p { class: "line-clamp-2 break-words", "{revision.text}" }
+            div { class: "text-gray-500", "{files} files, {parents} parents" }
+        }
+    }
+}
@@ -0,0 +1,271 @@
+use std::collections::HashMap;
+
+use dioxus::prelude::*;
+use sqlx::sqlite::SqlitePoolOptions;
+
+use crate::handler;
+use crate::model::{Dag, DagCell};
+use crate::utils;
+
+// Size of the drawing area and of a cell in it, in pixels at zoom 1
+const VIEW_W: f64 = 800.0;
+const VIEW_H: f64 = 600.0;
+const NODE_W: f64 = 180.0;
+const NODE_H: f64 = 48.0;
+const GAP_X: f64 = 40.0;
+const GAP_Y: f64 = 64.0;
+// Characters of a cell's text shown in its box
+const LABEL_LEN: usize = 22;
+const MIN_ZOOM: f64 = 0.2;
+const MAX_ZOOM: f64 = 4.0;
+
+// The family tree around `cell_id`, parents above and replies below. Clicking
+// a cell selects it and brings in its own parents and replies; the wheel
+// zooms and dragging pans. Cells with several parents are drawn in amber.
+#[component]
+pub fn Graph(cell_id: String, force_reload: Signal<i32>, on_open: EventHandler<String>) -> Element {
+    let mut expanded = use_signal(|| vec![cell_id.clone()]);
+    let mut selected = use_signal(|| cell_id.clone());
+    let mut zoom = use_signal(|| 1.0_f64);
+    // Offset of the view's centre from the selected cell
+    let mut pan = use_signal(|| (0.0_f64, 0.0_f64));
+    let mut dragging = use_signal::<Option<(f64, f64)>>(|| None);
+
+    let loaded = use_resource(move || async move {
+        let cell_ids = expanded();
+        println!("Loading graph of {} cells with {}", cell_ids.len(), force_reload());
+        let db_url = utils::get_db_path();
+        let pool = SqlitePoolOptions::new()
+            .max_connections(16)
+            .connect(&db_url)
+            .await?;
+        handler::load_neighbours(&cell_ids, pool).await
+    });
+
+    let binding = loaded.read_unchecked();
+    let dag = match &*binding {
+        Some(Ok(dag)) => dag,
+        Some(Err(e)) => return rsx! {
+            p { class: "w-2/3 my-2 text-sm text-gray-500", "Failed to load the graph: {e}" }
+        },
+        None => return rsx! {},
+    };
+    let positions = layout(dag);
+    let (sx, sy) = positions.get(&selected()).copied().unwrap_or_default();
+    let (w, h) = (VIEW_W / zoom(), VIEW_H / zoom());
+    let view_box = format!(
+        "{} {} {} {}",
+        sx + NODE_W / 2.0 + pan().0 - w / 2.0,
+        sy + NODE_H / 2.0 + pan().1 - h / 2.0,
+        w,
+        h,
+    );
+    let merges: Vec<&str> = dag.cells.iter()
+        .filter(|cell| cell.parent_count > 1)
+        .map(|cell| cell.cell_id.as_str())
+        .collect();
+    let edges: Vec<(String, &'static str, String)> = dag.edges.iter()
+        .filter_map(|edge| {
+            let (px, py) = positions.get(&edge.parent_id)?;
+            let (cx, cy) = positions.get(&edge.child_id)?;
+            let (x1, y1) = (px + NODE_W / 2.0, py + NODE_H);
+            let (x2, y2) = (cx + NODE_W / 2.0, *cy);
+            let mid = (y1 + y2) / 2.0;
+            let path = format!("M {x1} {y1} C {x1} {mid}, {x2} {mid}, {x2} {y2}");
+            let color = if merges.contains(&edge.child_id.as_str()) { "#f59e0b" } else { "#9ca3af" };
+            Some((format!("{}-{}", edge.parent_id, edge.child_id), color, path))
+        })
+        .collect();
+    let selected_text = dag.cells.iter()
+        .find(|cell| cell.cell_id == selected())
+        .map(|cell| cell.text.clone())
+        .unwrap_or_default();
+
+    let mut zoom_by = move |factor: f64| {
+        zoom.set((zoom() * factor).clamp(MIN_ZOOM, MAX_ZOOM));
+    };
+
+    rsx! {
+        div { class: "w-2/3 my-2 p-4 rounded-lg bg-white shadow",
+            div { class: "flex flex-row items-center mb-2",
+                p { class: "flex-1 truncate text-sm", "{selected_text}" }
+                button {
+                    class: "ml-2 px-2 py-1 bg-gray-200 rounded-md hover:bg-gray-300",
+                    onclick: move |_| zoom_by(1.25),
+                    "+"
+                }
+                button {
+                    class: "ml-2 px-2 py-1 bg-gray-200 rounded-md hover:bg-gray-300",
+                    onclick: move |_| zoom_by(0.8),
+                    "-"
+                }
+                button {
+                    class: "ml-2 px-2 py-1 bg-gray-200 rounded-md hover:bg-gray-300",
+                    onclick: move |_| {
+                        zoom.set(1.0);
+                        pan.set((0.0, 0.0));
+                    },
+                    "Reset"
+                }
+                button {
+                    class: "ml-2 px-2 py-1 bg-gray-200 rounded-md hover:bg-gray-300",
+                    onclick: move |_| on_open.call(selected()),
+                    "Open thread"
+                }
+            }
+            div { class: "overflow-hidden border rounded-md bg-base-200",
+                svg {
+                    width: "{VIEW_W}",
+                    height: "{VIEW_H}",
+                    view_box: "{view_box}",
+                    class: if dragging().is_some() { "cursor-grabbing select-none" } else { "cursor-grab select-none" },
+                    onwheel: move |evt| {
+                        zoom_by(if evt.delta().strip_units().y > 0.0 { 0.9 } else { 1.0 / 0.9 });
+                    },
+                    onmousedown: move |evt| {
+                        let point = evt.client_coordinates();
+                        dragging.set(Some((point.x, point.y)));
+                    },
+                    onmousemove: move |evt| {
+                        if let Some((x, y)) = dragging() {
+                            let point = evt.client_coordinates();
+                            let (dx, dy) = pan();
+                            pan.set((dx - (point.x - x) / zoom(), dy - (point.y - y) / zoom()));
+                            dragging.set(Some((point.x, point.y)));
+                        }
+                    },
+                    onmouseup: move |_| dragging.set(None),
+                    onmouseleave: move |_| dragging.set(None),
+                    for (key, color, path) in edges.into_iter() {
+                        path { key: "{key}", d: "{path}", fill: "none", stroke: color, stroke_width: "2" }
+                    }
+                    for cell in dag.cells.iter() {
+                        if let Some((x, y)) = positions.get(&cell.cell_id).copied() {
+                            Node {
+                                key: "{cell.cell_id}",
+                                cell: cell.clone(),
+                                x,
+                                y,
+                                is_selected: cell.cell_id == selected(),
+                                is_expanded: expanded.read().contains(&cell.cell_id),
+                                on_click: move |cell_id: String| {
+                                    if !expanded.read().contains(&cell_id) {
+                                        expanded.write().push(cell_id.clone());
+                                    }
+                                    selected.set(cell_id);
+                                    pan.set((0.0, 0.0));
+                                },
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+// One cell's box. A "+" marks cells whose parents or replies are not all
+// shown yet.
+#[component]
+fn Node(
+    cell: DagCell,
+    x: f64,
+    y: f64,
+    is_selected: bool,
+    is_expanded: bool,
+    on_click: EventHandler<String>,
+) -> Element {
+    let is_merge = cell.parent_count > 1;
+    let fill = if is_merge { "#fef3c7" } else { "#ffffff" };
+    let stroke = if is_selected { "#3b82f6" } else if is_merge { "#f59e0b" } else { "#d1d5db" };
+    let mut label: String = cell.text.lines().next().unwrap_or("").chars().take(LABEL_LEN).collect();
+    if label.chars().count() < cell.text.chars().count() {
+        label.push('…');
+    }
+    let counts = format!("↑{} ↓{}", cell.parent_count, cell.child_count);
+    let has_more = !is_expanded && cell.parent_count + cell.child_count > 0;
+    rsx! {
+        g {
+            class: "cursor-pointer",
+            onclick: move |_| on_click.call(cell.cell_id.clone()),
+            rect {
+                x: "{x}",
+                y: "{y}",
+                width: "{NODE_W}",
+                height: "{NODE_H}",
+                rx: "8",
+                fill,
+                stroke,
+                stroke_width: if is_selected { "3" } else { "1.5" },
+            }
+            text { x: "{x + 10.0}", y: "{y + 20.0}", font_size: "13", fill: "#111827", "{label}" }
+            text { x: "{x + 10.0}", y: "{y + 38.0}", font_size: "11", fill: "#6b7280",
+                if is_merge { "merge  {counts}" } else { "{counts}" }
+            }
+            if has_more {
+                text { x: "{x + NODE_W - 18.0}", y: "{y + 38.0}", font_size: "14", fill: "#3b82f6", "+" }
+            }
+        }
+    }
+}
+
+// Top-left corner of every cell in a layered drawing: each cell goes one
+// layer below its lowest parent, and cells in a layer are ordered by where
+// their parents are. Cells on a cycle are put in the top layer.
+fn layout(dag: &Dag) -> HashMap<String, (f64, f64)> {
+    let index: HashMap<&str, usize> = dag.cells.iter()
+        .enumerate()
+        .map(|(i, cell)| (cell.cell_id.as_str(), i))
+        .collect();
+    let mut parents: Vec<Vec<usize>> = vec![vec![]; dag.cells.len()];
+    let mut children: Vec<Vec<usize>> = vec![vec![]; dag.cells.len()];
+    for edge in dag.edges.iter() {
+        if let (Some(&p), Some(&c)) = (index.get(edge.parent_id.as_str()), index.get(edge.child_id.as_str())) {
+            if p != c {
+                parents[c].push(p);
+                children[p].push(c);
+            }
+        }
+    }
+
+    // Longest path from the top, in topological order
+    let mut layer = vec![0_usize; dag.cells.len()];
+    let mut waiting: Vec<usize> = parents.iter().map(|p| p.len()).collect();
+    let mut ready: Vec<usize> = (0..dag.cells.len()).filter(|&i| waiting[i] == 0).collect();
+    while let Some(i) = ready.pop() {
+        for &c in children[i].iter() {
+            layer[c] = layer[c].max(layer[i] + 1);
+            waiting[c] -= 1;
+            if waiting[c] == 0 {
+                ready.push(c);
+            }
+        }
+    }
+
+    let depth = layer.iter().max().map_or(0, |d| d + 1);
+    let mut layers: Vec<Vec<usize>> = vec![vec![]; depth];
+    for (i, &l) in layer.iter().enumerate() {
+        layers[l].push(i);
+    }
+    // Each layer follows the mean position of its cells' parents above
+    let mut column = vec![0.0_f64; dag.cells.len()];
+    for cells in layers.iter_mut() {
+        let key = |i: usize| {
+            let placed: Vec<f64> = parents[i].iter().filter(|&&p| layer[p] < layer[i]).map(|&p| column[p]).collect();
+            if placed.is_empty() { i as f64 } else { placed.iter().sum::<f64>() / placed.len() as f64 }
+        };
+        cells.sort_by(|&a, &b| key(a).total_cmp(&key(b)).then(a.cmp(&b)));
+        for (x, &i) in cells.iter().enumerate() {
+            column[i] = x as f64 - (cells.len() as f64 - 1.0) / 2.0;
+        }
+    }
+
+    dag.cells.iter()
+        .enumerate()
+        .map(|(i, cell)| {
+            let x = column[i] * (NODE_W + GAP_X);
+            let y = layer[i] as f64 * (NODE_H + GAP_Y);
+            (cell.cell_id.clone(), (x, y))
+        })
+        .collect()
+}
@@ -1,5 +1,8 @@
 use log::error;
 use crate::model::*;
+use crate::validate::validate_dir;
+use crate::handler::file::{insert_dir, load_dir};
+use crate::handler::revision::{keep_original, record_revision};
 /*
 use axum::debug_handler;
 use axum::{
@@ -9,7 +12,7 @@ use axum::{
 };
 */
 
-use sqlx::Sqlite;
+use sqlx::{Sqlite, SqliteConnection};
 use sqlx::pool::Pool;
 
 use async_recursion::async_recursion;
@@ -48,66 +51,228 @@ pub async fn create_cell(
     pool: Pool<Sqlite>,
     payload: CellReq
 ) -> Result<IdRes, sqlx::Error> {
-    let cell_id = payload.cell_id;
-    let parent_ids = payload.parent_ids;
-    let child_ids = payload.child_ids;
-    if let Err(e) = sqlx::query(
-      "INSERT INTO cells (cell_id, user_id, device_id, text, rootdir, is_open) 
-      VALUES (?, ?, ?, ?, ?, ?)"
+    if let Err(e) = validate_dir(&payload.rootdir) {
+        error!("{}", e);
+        return Err(sqlx::Error::Io(std::io::Error::new(std::io::ErrorKind::InvalidInput, e)))
+    };
+    let cell_id = payload.cell_id.clone();
+    let mut tx = pool.begin().await?;
+    let inserted = match sqlx::query(
+      "INSERT OR IGNORE INTO cells (cell_id, user_id, device_id, text, is_open) 
+      VALUES (?, ?, ?, ?, ?)"
     )
         .bind(&cell_id)
         .bind(&payload.user_id)
         .bind(&payload.device_id)
         .bind(&payload.text)
-        .bind(&sqlx::types::Json(payload.rootdir))
-        .bind(&payload.is_open)
-        .execute(&pool)
+        .bind(payload.is_open)
+        .execute(&mut *tx)
         .await {
-            error!("{}", e);
-            return Err(e)
+            Ok(res) => res.rows_affected() > 0,
+            Err(e) => {
+                error!("{}", e);
+                return Err(e)
+            }
         };
-
-    // Prepare the query
-    // let query = "INSERT INTO family_tree (child_id, parent_id) SELECT * FROM UNNEST($1::uuid[], $2::uuid[])".to_string();
-    // let child_count = child_ids.len();
-    // let all_child_ids = [vec![cell_id; parent_ids.len()], child_ids].concat();
-    // let all_parent_ids = [parent_ids, vec![cell_id; child_count]].concat();
+    // A cell that exists already is taken as an earlier try of this request
+    if !inserted {
+        if is_same_cell(&mut tx, &payload).await? {
+            return Ok(IdRes{id: cell_id})
+        }
+        error!("cell {} already exists with other content", cell_id);
+        return Err(sqlx::Error::Io(std::io::Error::new(
+            std::io::ErrorKind::AlreadyExists,
+            format!("cell {} already exists with other content", cell_id),
+        )))
+    }
+    if let Err(e) = insert_dir(&mut tx, &cell_id, &payload.rootdir).await {
+        error!("{}", e);
+        return Err(e)
+    };
 
     // Inserting family tree relationships
-    for child_id in child_ids.iter() {
+    for child_id in payload.child_ids.iter() {
         sqlx::query(
             "INSERT INTO family_tree (child_id, parent_id) VALUES (?, ?)"
         )
-        .bind(&child_id)
+        .bind(child_id)
         .bind(&cell_id)
-        .execute(&pool)
+        .execute(&mut *tx)
         .await?;
     }
 
-    for parent_id in parent_ids.iter() {
+    for parent_id in payload.parent_ids.iter() {
         sqlx::query(
             "INSERT INTO family_tree (child_id, parent_id) VALUES (?, ?)"
         )
         .bind(&cell_id)
-        .bind(&parent_id)
-        .execute(&pool)
+        .bind(parent_id)
+        .execute(&mut *tx)
         .await?;
     }
-    /*
-    if !all_child_ids.is_empty() {
-        if let Err(e) = sqlx::query(&query)
-        .bind(all_child_ids)
-        .bind(all_parent_ids)
-        .execute(&pool)
-        .await {
-            error!("{}", e);
-            return Err(e)
-        };
-    }
-    */
+    tx.commit().await?;
     Ok(IdRes{id: cell_id})
 }
 
+// Whether the stored cell is what `payload` would have created. Edges are
+// only required to be there, as later cells may have added more.
+async fn is_same_cell(conn: &mut SqliteConnection, payload: &CellReq) -> Result<bool, sqlx::Error> {
+    let (user_id, device_id, text, is_open): (String, String, String, bool) = sqlx::query_as(
+        "SELECT user_id, device_id, text, is_open FROM cells WHERE cell_id=?"
+    )
+        .bind(&payload.cell_id)
+        .fetch_one(&mut *conn)
+        .await?;
+    if user_id != payload.user_id || device_id != payload.device_id
+        || text != payload.text || is_open != payload.is_open {
+        return Ok(false)
+    }
+    let stored = load_dir(&payload.cell_id, conn).await?;
+    if !same_files(&stored, &payload.rootdir) {
+        return Ok(false)
+    }
+    let edges: Vec<(String, String)> = sqlx::query_as(
+        "SELECT child_id, parent_id FROM family_tree WHERE child_id=? OR parent_id=?"
+    )
+        .bind(&payload.cell_id)
+        .bind(&payload.cell_id)
+        .fetch_all(&mut *conn)
+        .await?;
+    let has_edge = |child_id: &String, parent_id: &String| {
+        edges.iter().any(|(c, p)| c == child_id && p == parent_id)
+    };
+    Ok(payload.parent_ids.iter().all(|p| has_edge(&payload.cell_id, p))
+        && payload.child_ids.iter().all(|c| has_edge(c, &payload.cell_id)))
+}
+
+// Replace a cell's text, flag, files and parents. The cell as it was and as
+// it becomes are both kept as revisions, so an edit loses nothing. An edit
+// changing nothing is not recorded.
+pub async fn update_cell(
+    cell_id: &str,
+    edit: CellEdit,
+    pool: Pool<Sqlite>,
+) -> Result<IdRes, sqlx::Error> {
+    if let Err(e) = validate_dir(&edit.rootdir) {
+        error!("{}", e);
+        return Err(sqlx::Error::Io(std::io::Error::new(std::io::ErrorKind::InvalidInput, e)))
+    };
+    let mut parent_ids: Vec<String> = vec![];
+    for parent_id in edit.parent_ids.iter() {
+        if !parent_ids.contains(parent_id) {
+            parent_ids.push(parent_id.clone());
+        }
+    }
+    let mut tx = pool.begin().await?;
+    let (text, is_open): (String, bool) = sqlx::query_as("SELECT text, is_open FROM cells WHERE cell_id=?")
+        .bind(cell_id)
+        .fetch_one(&mut *tx)
+        .await?;
+    if let Err(e) = check_parents(&mut tx, cell_id, &parent_ids).await? {
+        error!("{}", e);
+        return Err(sqlx::Error::Io(std::io::Error::new(std::io::ErrorKind::InvalidInput, e)))
+    }
+
+    let rootdir = load_dir(cell_id, &mut tx).await?;
+    let old_parents: Vec<(String,)> = sqlx::query_as(
+        "SELECT DISTINCT parent_id FROM family_tree WHERE child_id=? ORDER BY parent_id"
+    )
+        .bind(cell_id)
+        .fetch_all(&mut *tx)
+        .await?;
+    let old_parents: Vec<String> = old_parents.into_iter().map(|(id,)| id).collect();
+    let mut new_parents = parent_ids.clone();
+    new_parents.sort();
+    if text == edit.text && is_open == edit.is_open
+        && same_files(&rootdir, &edit.rootdir) && old_parents == new_parents {
+        return Ok(IdRes{id: cell_id.to_string()})
+    }
+
+    keep_original(&mut tx, cell_id).await?;
+    sqlx::query("UPDATE cells SET text=?, is_open=? WHERE cell_id=?")
+        .bind(&edit.text)
+        .bind(edit.is_open)
+        .bind(cell_id)
+        .execute(&mut *tx)
+        .await?;
+    for table in ["files", "dirs"] {
+        sqlx::query(&format!("DELETE FROM {} WHERE cell_id=?", table))
+            .bind(cell_id)
+            .execute(&mut *tx)
+            .await?;
+    }
+    insert_dir(&mut tx, cell_id, &edit.rootdir).await?;
+    sqlx::query("DELETE FROM family_tree WHERE child_id=?")
+        .bind(cell_id)
+        .execute(&mut *tx)
+        .await?;
+    for parent_id in parent_ids.iter() {
+        sqlx::query("INSERT INTO family_tree (child_id, parent_id) VALUES (?, ?)")
+            .bind(cell_id)
+            .bind(parent_id)
+            .execute(&mut *tx)
+            .await?;
+    }
+    record_revision(&mut tx, cell_id, &edit.device_id).await?;
+    tx.commit().await?;
+    Ok(IdRes{id: cell_id.to_string()})
+}
+
+// Parents must exist and must not be the cell or one of its replies, which
+// would make the family tree a cycle
+async fn check_parents(
+    conn: &mut SqliteConnection,
+    cell_id: &str,
+    parent_ids: &[String],
+) -> Result<Result<(), String>, sqlx::Error> {
+    let descendants: Vec<(String,)> = sqlx::query_as(
+        "WITH RECURSIVE down(cell_id) AS (
+            SELECT child_id FROM family_tree WHERE parent_id=?
+            UNION
+            SELECT f.child_id FROM family_tree f JOIN down ON f.parent_id=down.cell_id
+        )
+        SELECT cell_id FROM down"
+    )
+        .bind(cell_id)
+        .fetch_all(&mut *conn)
+        .await?;
+    for parent_id in parent_ids.iter() {
+        if parent_id == cell_id {
+            return Ok(Err("a cell cannot be its own parent".to_string()))
+        }
+        if descendants.iter().any(|(id,)| id == parent_id) {
+            return Ok(Err(format!("cell {} replies to this cell and cannot be its parent", parent_id)))
+        }
+        let found: Option<(String,)> = sqlx::query_as("SELECT cell_id FROM cells WHERE cell_id=?")
+            .bind(parent_id)
+            .fetch_optional(&mut *conn)
+            .await?;
+        if found.is_none() {
+            return Ok(Err(format!("no cell {}", parent_id)))
+        }
+    }
+    Ok(Ok(()))
+}
+
+// Whether two trees hold the same files at the same paths, and the same
+// empty directories
+fn same_files(a: &Dir, b: &Dir) -> bool {
+    let files = |dir: &Dir| {
+        let mut files: Vec<(String, String, Option<String>)> = dir.files()
+            .into_iter()
+            .map(|(path, f)| (path, f.url.clone(), f.sha256.clone()))
+            .collect();
+        files.sort();
+        files
+    };
+    let dirs = |dir: &Dir| {
+        let mut dirs = dir.empty_dirs();
+        dirs.sort();
+        dirs
+    };
+    files(a) == files(b) && dirs(a) == dirs(b)
+}
+
 pub async fn show_cell(
     cell_id: &str,
     pool: Pool<Sqlite>,
@@ -126,6 +291,14 @@ pub async fn delete_cell(
     cell_id: &str,
     pool: Pool<Sqlite>,
 ) -> Result<IdRes, sqlx::Error> {
+    sqlx::query("DELETE FROM files WHERE cell_id=?")
+        .bind(cell_id)
+        .execute(&pool)
+        .await?;
+    sqlx::query("DELETE FROM dirs WHERE cell_id=?")
+        .bind(cell_id)
+        .execute(&pool)
+        .await?;
     match sqlx::query("DELETE FROM cells WHERE cell_id=?")
     .bind(&cell_id)
     .execute(&pool)
@@ -185,7 +358,12 @@ async fn get_cell(
     )
     .bind(cell_id)
     .fetch_one(pool).await {
-        Ok(cell) => Ok(cell),
+        Ok(cell) => {
+            let mut cell: CellRow = cell;
+            let mut conn = pool.acquire().await?;
+            cell.rootdir = load_dir(cell_id, &mut conn).await?;
+            Ok(cell)
+        }
         Err(e) => {
             println!("abc error {:?}", &e);
             error!("{}", &e);
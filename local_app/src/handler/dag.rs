@@ -0,0 +1,71 @@
+use log::error;
+use crate::model::*;
+
+use sqlx::{QueryBuilder, Sqlite};
+use sqlx::pool::Pool;
+
+// The given cells with their parents and replies, and every edge between
+// any two of them. Cells come in the order given, then the others by id.
+pub async fn load_neighbours(
+    cell_ids: &[String],
+    pool: Pool<Sqlite>,
+) -> Result<Dag, sqlx::Error> {
+    if cell_ids.is_empty() {
+        return Ok(Dag::default())
+    }
+    let mut query = QueryBuilder::<Sqlite>::new(
+        "SELECT DISTINCT child_id, parent_id FROM family_tree WHERE child_id IN "
+    );
+    push_ids(&mut query, cell_ids);
+    query.push(" OR parent_id IN ");
+    push_ids(&mut query, cell_ids);
+    let touching: Vec<Edge> = query.build_query_as().fetch_all(&pool).await.map_err(log_error)?;
+
+    let mut ids: Vec<String> = cell_ids.to_vec();
+    let mut others: Vec<String> = touching.into_iter()
+        .flat_map(|e| [e.child_id, e.parent_id])
+        .filter(|id| !cell_ids.contains(id))
+        .collect();
+    others.sort();
+    others.dedup();
+    ids.extend(others);
+
+    let mut query = QueryBuilder::<Sqlite>::new(
+        "SELECT DISTINCT child_id, parent_id FROM family_tree WHERE child_id IN "
+    );
+    push_ids(&mut query, &ids);
+    query.push(" AND parent_id IN ");
+    push_ids(&mut query, &ids);
+    query.push(" ORDER BY parent_id, child_id");
+    let edges: Vec<Edge> = query.build_query_as().fetch_all(&pool).await.map_err(log_error)?;
+
+    let mut query = QueryBuilder::<Sqlite>::new(
+        "SELECT cell_id, text,
+        (SELECT COUNT(DISTINCT parent_id) FROM family_tree WHERE child_id=cells.cell_id) AS parent_count,
+        (SELECT COUNT(DISTINCT child_id) FROM family_tree WHERE parent_id=cells.cell_id) AS child_count
+        FROM cells WHERE cell_id IN "
+    );
+    push_ids(&mut query, &ids);
+    let mut rows: Vec<DagCell> = query.build_query_as().fetch_all(&pool).await.map_err(log_error)?;
+    let cells = ids.iter()
+        .filter_map(|id| {
+            let index = rows.iter().position(|row| &row.cell_id == id)?;
+            Some(rows.swap_remove(index))
+        })
+        .collect();
+    Ok(Dag { cells, edges })
+}
+
+fn push_ids(query: &mut QueryBuilder<Sqlite>, ids: &[String]) {
+    query.push("(");
+    let mut separated = query.separated(", ");
+    for id in ids.iter() {
+        separated.push_bind(id.clone());
+    }
+    query.push(")");
+}
+
+fn log_error(e: sqlx::Error) -> sqlx::Error {
+    error!("{}", e);
+    e
+}
@@ -0,0 +1,144 @@
+use log::error;
+use crate::model::*;
+use crate::patch::{self, FileOp};
+use crate::handler::revision::{keep_original, record_revision};
+
+use sqlx::{QueryBuilder, Sqlite, SqliteConnection};
+use sqlx::pool::Pool;
+
+// Attached files live in the files table, one row per path of a cell, and
+// directories without files in the dirs table. The cell's rootdir is
+// assembled from both on read.
+
+// Find files across the user's cells, newest cell first. `name` matches the
+// file name exactly, `glob` (* and ?) matches it or, when it holds a slash,
+// the whole path. `mime` may be a glob too. Matching ignores case.
+pub async fn find_files(
+    pool: Pool<Sqlite>,
+    filter: FileFilter,
+) -> Result<Vec<FileMatch>, sqlx::Error> {
+    let mut query = QueryBuilder::<Sqlite>::new(
+        "SELECT files.* FROM files JOIN cells ON cells.cell_id=files.cell_id WHERE cells.user_id="
+    );
+    query.push_bind(filter.user_id);
+    if let Some(name) = filter.name {
+        query.push(" AND lower(files.name)=lower(").push_bind(name).push(")");
+    }
+    if let Some(glob) = filter.glob {
+        let column = if glob.contains('/') { "files.path" } else { "files.name" };
+        query.push(format!(" AND lower({}) GLOB lower(", column)).push_bind(glob_pattern(&glob)).push(")");
+    }
+    if let Some(mime) = filter.mime {
+        query.push(" AND lower(files.mime) GLOB lower(").push_bind(glob_pattern(&mime)).push(")");
+    }
+    if let Some(sha256) = filter.sha256 {
+        query.push(" AND files.sha256=").push_bind(sha256.to_lowercase());
+    }
+    query.push(" ORDER BY cells.created_at DESC, files.path");
+    if let Some(limit) = filter.limit {
+        query.push(" LIMIT ").push_bind(limit);
+    }
+    let rows: Vec<FileRow> = match query.build_query_as().fetch_all(&pool).await {
+        Ok(rows) => rows,
+        Err(e) => {
+            error!("{}", e);
+            return Err(e)
+        }
+    };
+    Ok(rows.into_iter()
+        .map(|row| FileMatch {
+            cell_id: row.cell_id.clone(),
+            path: row.path.clone(),
+            fileprop: row.into(),
+        })
+        .collect())
+}
+
+// Only * and ? are wildcards, as on the server; [ is taken literally
+fn glob_pattern(glob: &str) -> String {
+    glob.replace('[', "[[]")
+}
+
+pub async fn load_dir(
+    cell_id: &str,
+    conn: &mut SqliteConnection,
+) -> Result<Dir, sqlx::Error> {
+    let rows: Vec<FileRow> = sqlx::query_as("SELECT * FROM files WHERE cell_id=? ORDER BY path")
+        .bind(cell_id)
+        .fetch_all(&mut *conn)
+        .await?;
+    let dirs: Vec<(String,)> = sqlx::query_as("SELECT path FROM dirs WHERE cell_id=? ORDER BY path")
+        .bind(cell_id)
+        .fetch_all(&mut *conn)
+        .await?;
+    Ok(Dir::from_files(
+        rows.into_iter().map(|row| (row.path.clone(), row.into())),
+        dirs.into_iter().map(|(path,)| path),
+    ))
+}
+
+// Apply the edits `ops` to the files of a cell, all of them or none, and
+// return its new rootdir. The change is kept as a revision from `device_id`.
+pub async fn patch_files(
+    cell_id: &str,
+    ops: Vec<FileOp>,
+    device_id: &str,
+    pool: Pool<Sqlite>,
+) -> Result<Dir, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    sqlx::query("SELECT cell_id FROM cells WHERE cell_id=?")
+        .bind(cell_id)
+        .fetch_one(&mut *tx)
+        .await?;
+    let rootdir = load_dir(cell_id, &mut tx).await?;
+    let rootdir = match patch::apply(&rootdir, &ops) {
+        Ok(rootdir) => rootdir,
+        Err(e) => {
+            error!("{}", e);
+            return Err(sqlx::Error::Io(std::io::Error::new(std::io::ErrorKind::InvalidInput, e)))
+        }
+    };
+    keep_original(&mut tx, cell_id).await?;
+    for table in ["files", "dirs"] {
+        sqlx::query(&format!("DELETE FROM {} WHERE cell_id=?", table))
+            .bind(cell_id)
+            .execute(&mut *tx)
+            .await?;
+    }
+    insert_dir(&mut tx, cell_id, &rootdir).await?;
+    record_revision(&mut tx, cell_id, device_id).await?;
+    tx.commit().await?;
+    Ok(rootdir)
+}
+
+pub async fn insert_dir(
+    conn: &mut SqliteConnection,
+    cell_id: &str,
+    rootdir: &Dir,
+) -> Result<(), sqlx::Error> {
+    for (path, fileprop) in rootdir.files() {
+        sqlx::query(
+            "INSERT INTO files (cell_id, path, name, url, completed, size, sha256, mime, mtime)
+            VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)"
+        )
+        .bind(cell_id)
+        .bind(&path)
+        .bind(&fileprop.name)
+        .bind(&fileprop.url)
+        .bind(fileprop.completed)
+        .bind(fileprop.size.map(|s| s as i64))
+        .bind(&fileprop.sha256)
+        .bind(&fileprop.mime)
+        .bind(fileprop.mtime)
+        .execute(&mut *conn)
+        .await?;
+    }
+    for path in rootdir.empty_dirs() {
+        sqlx::query("INSERT INTO dirs (cell_id, path) VALUES (?, ?)")
+            .bind(cell_id)
+            .bind(&path)
+            .execute(&mut *conn)
+            .await?;
+    }
+    Ok(())
+}
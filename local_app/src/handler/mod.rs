@@ -1,4 +1,10 @@
 pub mod user;
 pub mod cell;
+pub mod file;
+pub mod dag;
+pub mod revision;
 
-pub use cell::*;
\ No newline at end of file
+pub use cell::*;
+pub use file::*;
+pub use dag::*;
+pub use revision::*;
\ No newline at end of file
@@ -0,0 +1,97 @@
+use log::error;
+use crate::model::*;
+use crate::handler::file::load_dir;
+
+use sqlx::{Sqlite, SqliteConnection};
+use sqlx::pool::Pool;
+
+// Every change to a cell is kept in the revisions table as the whole cell
+// after it: text, flag, files and parents. Cells that were never changed
+// have no revisions; the first change also records the cell as created.
+
+// A cell's revisions, newest first
+pub async fn list_revisions(
+    cell_id: &str,
+    pool: Pool<Sqlite>,
+) -> Result<Vec<Revision>, sqlx::Error> {
+    let rows: Vec<RevisionRow> = match sqlx::query_as(
+        "SELECT * FROM revisions WHERE cell_id=? ORDER BY revision DESC"
+    )
+        .bind(cell_id)
+        .fetch_all(&pool)
+        .await {
+            Ok(rows) => rows,
+            Err(e) => {
+                error!("{}", e);
+                return Err(e)
+            }
+        };
+    Ok(rows.into_iter().map(Revision::from).collect())
+}
+
+// Record the cell as created, unless it has revisions already. Called
+// before a cell is first changed.
+pub async fn keep_original(
+    conn: &mut SqliteConnection,
+    cell_id: &str,
+) -> Result<(), sqlx::Error> {
+    let (count,): (i64,) = sqlx::query_as("SELECT COUNT(*) FROM revisions WHERE cell_id=?")
+        .bind(cell_id)
+        .fetch_one(&mut *conn)
+        .await?;
+    if count > 0 {
+        return Ok(())
+    }
+    let (device_id, created_at): (String, Option<chrono::NaiveDateTime>) = sqlx::query_as(
+        "SELECT device_id, created_at FROM cells WHERE cell_id=?"
+    )
+        .bind(cell_id)
+        .fetch_one(&mut *conn)
+        .await?;
+    insert_revision(conn, cell_id, &device_id, created_at).await
+}
+
+// Record the cell as it is now, changed from `device_id`
+pub async fn record_revision(
+    conn: &mut SqliteConnection,
+    cell_id: &str,
+    device_id: &str,
+) -> Result<(), sqlx::Error> {
+    insert_revision(conn, cell_id, device_id, None).await
+}
+
+async fn insert_revision(
+    conn: &mut SqliteConnection,
+    cell_id: &str,
+    device_id: &str,
+    created_at: Option<chrono::NaiveDateTime>,
+) -> Result<(), sqlx::Error> {
+    let (text, is_open): (String, bool) = sqlx::query_as("SELECT text, is_open FROM cells WHERE cell_id=?")
+        .bind(cell_id)
+        .fetch_one(&mut *conn)
+        .await?;
+    let rootdir = load_dir(cell_id, conn).await?;
+    let parent_ids: Vec<(String,)> = sqlx::query_as(
+        "SELECT DISTINCT parent_id FROM family_tree WHERE child_id=? ORDER BY parent_id"
+    )
+        .bind(cell_id)
+        .fetch_all(&mut *conn)
+        .await?;
+    let parent_ids: Vec<String> = parent_ids.into_iter().map(|(id,)| id).collect();
+    sqlx::query(
+        "INSERT INTO revisions (cell_id, revision, device_id, text, is_open, rootdir, parent_ids, created_at)
+        SELECT ?, COALESCE(MAX(revision), 0) + 1, ?, ?, ?, ?, ?, COALESCE(?, CURRENT_TIMESTAMP)
+        FROM revisions WHERE cell_id=?"
+    )
+        .bind(cell_id)
+        .bind(device_id)
+        .bind(text)
+        .bind(is_open)
+        .bind(sqlx::types::Json(rootdir))
+        .bind(sqlx::types::Json(parent_ids))
+        .bind(created_at)
+        .bind(cell_id)
+        .execute(&mut *conn)
+        .await?;
+    Ok(())
+}
@@ -0,0 +1,134 @@
+use std::fs;
+use std::io::{self, BufReader};
+use std::path::Path;
+
+use async_recursion::async_recursion;
+use flowfs_client::FlowfsClient;
+use ignore::WalkBuilder;
+use sha2::{Digest, Sha256};
+
+use crate::model::{Dir, FileProp};
+use crate::patch;
+
+// Files picked on this machine but not uploaded yet keep their location in
+// `url` as file://<absolute path> and have `completed` set to false.
+const LOCAL_SCHEME: &str = "file://";
+
+// Walk `root` into a Dir named `name`, honouring .gitignore/.ignore files.
+// Hidden files are kept but .git directories are skipped.
+pub fn scan_dir(root: &Path, name: &str) -> io::Result<Dir> {
+    let mut rootdir = Dir {
+        name: name.to_string(),
+        dirs: Vec::new(),
+        fileprops: Vec::new(),
+    };
+    let walker = WalkBuilder::new(root)
+        .hidden(false)
+        .require_git(false)
+        .sort_by_file_name(|a, b| a.cmp(b))
+        .filter_entry(|entry| entry.file_name() != ".git")
+        .build();
+    for entry in walker {
+        let entry = entry.map_err(io::Error::other)?;
+        let Ok(rel_path) = entry.path().strip_prefix(root) else {
+            continue
+        };
+        let names: Vec<String> = rel_path.iter()
+            .map(|c| c.to_string_lossy().to_string())
+            .collect();
+        let Some((last, parents)) = names.split_last() else {
+            continue
+        };
+        let Some(file_type) = entry.file_type() else {
+            continue
+        };
+        let parent = rootdir.dir_at(parents);
+        if file_type.is_dir() {
+            parent.dir_at(std::slice::from_ref(last));
+        } else if file_type.is_file() {
+            parent.fileprops.push(scan_file(entry.path(), last)?);
+        }
+    }
+    Ok(rootdir)
+}
+
+// Build the cell root from dropped paths: folders become subdirectories,
+// plain files are attached at the top level
+pub fn scan_paths(paths: &[String]) -> io::Result<Dir> {
+    let mut rootdir = Dir::new();
+    for path in paths.iter() {
+        let path = Path::new(path);
+        let name = path.file_name()
+            .map(|n| n.to_string_lossy().to_string())
+            .unwrap_or_default();
+        if path.is_dir() {
+            rootdir.dirs.push(scan_dir(path, &name)?);
+        } else if path.is_file() {
+            rootdir.fileprops.push(scan_file(path, &name)?);
+        }
+    }
+    Ok(rootdir)
+}
+
+pub fn scan_file(path: &Path, name: &str) -> io::Result<FileProp> {
+    let mut file = BufReader::new(fs::File::open(path)?);
+    let mut hasher = Sha256::new();
+    let size = io::copy(&mut file, &mut hasher)?;
+    let mtime = fs::metadata(path)?.modified().ok()
+        .map(|t| chrono::DateTime::<chrono::Utc>::from(t).naive_utc());
+    let path = fs::canonicalize(path)?;
+    Ok(FileProp {
+        name: name.to_string(),
+        url: format!("{}{}", LOCAL_SCHEME, path.display()),
+        completed: false,
+        size: Some(size),
+        sha256: Some(format!("{:x}", hasher.finalize())),
+        mime: Some(mime_guess::from_path(name).first_or_octet_stream().to_string()),
+        mtime,
+    })
+}
+
+// Upload every file still pointing at the local disk and replace its url
+// with the server one
+#[async_recursion]
+pub async fn upload_dir(client: &FlowfsClient, dir: &mut Dir) -> Result<(), flowfs_client::ClientError> {
+    for fileprop in dir.fileprops.iter_mut() {
+        upload_file(client, fileprop).await?;
+    }
+    for sub_dir in dir.dirs.iter_mut() {
+        upload_dir(client, sub_dir).await?;
+    }
+    Ok(())
+}
+
+// Upload a zip, tar or tar.gz file for the server to expand; its entries
+// come back already uploaded, as a root directory
+pub async fn expand_archive(client: &FlowfsClient, archive: &Path) -> Result<Dir, flowfs_client::ClientError> {
+    let res = client.expand_archive(archive).await?;
+    Ok(Dir::from_files(
+        res.fileprops.into_iter().map(|f| (f.path.clone(), patch::from_remote(f))),
+        res.dirs,
+    ))
+}
+
+// Upload one file if it still points at the local disk
+pub async fn upload_file(client: &FlowfsClient, fileprop: &mut FileProp) -> Result<(), flowfs_client::ClientError> {
+    let Some(local_path) = fileprop.url.strip_prefix(LOCAL_SCHEME) else {
+        return Ok(())
+    };
+    // Content the server already has is not sent again
+    let known = match &fileprop.sha256 {
+        Some(sha256) => client.find_file(sha256).await?,
+        None => None,
+    };
+    let res = match known {
+        Some(res) => res,
+        None => client.upload_file(Path::new(local_path)).await?,
+    };
+    fileprop.url = client.file_url(res.id);
+    fileprop.completed = true;
+    // What the server received is what downloads are checked against
+    fileprop.size = Some(res.size);
+    fileprop.sha256 = Some(res.sha256);
+    Ok(())
+}
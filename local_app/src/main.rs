@@ -3,7 +3,9 @@
 // use core::ffi;
 
 use dioxus::prelude::*;
-use model::{CellExtracted, CellFilter, CellReq, Cells, FileProp};
+use dioxus::html::HasFileData;
+use flowfs_client::model::ArchiveFormat;
+use model::{CellExtracted, CellFilter, CellReq, Cells, FileFilter};
 use tracing::Level;
 
 // use futures::future::join_all;
@@ -11,16 +13,25 @@ use sqlx::sqlite::SqlitePoolOptions;
 
 // pub static BASE_API_URL: &str = "127.0.0.1:8080";
 const MY_UUID: uuid::Uuid = uuid::Uuid::nil();
+const DEVICE_ID: &str = "Dev0";
 
 const L_SIDEBAR_W: u32 = 48;
 const R_SIDEBAR_W: u32 = 48;
 const NAVBAR_H: u32 = 16;
 
+mod compare;
+mod download;
+mod edit;
+mod graph;
 mod handler;
+mod local_dir;
 mod model;
+mod patch;
 mod svg_icon;
+mod thread;
 mod tree;
 mod utils;
+mod validate;
 
 #[derive(PartialEq, Clone, Props)]
 pub struct ButtonWrapProps {
@@ -81,13 +92,20 @@ fn App() -> Element {
 
 #[component]
 fn Column(cells: Cells, force_reload: Signal<i32>) -> Element {
+    // The cell whose thread is open in place of the list
+    let thread = use_signal::<Option<String>>(|| None);
+
     rsx! {
         div { class: "flex min-h-screen",
             aside { class: "sticky top-0 h-[calc(100vh-theme(spacing.0))] w-{L_SIDEBAR_W} overflow-y-auto bg-green-200",
                 DrawerLeft { force_reload }
             }
             main { class: "flex-1 mt-{NAVBAR_H} left-{L_SIDEBAR_W} right-{R_SIDEBAR_W} bg-yellow-200",
-                Cells { cells, force_reload }
+                if let Some(cell_id) = thread() {
+                    thread::Thread { cell_id, force_reload, thread }
+                } else {
+                    Cells { cells, force_reload, thread }
+                }
                 nav { class: "fixed h-{NAVBAR_H} w-full top-0 bg-blue-200",
                     "Nav"
                 }
@@ -111,6 +129,60 @@ fn DrawerLeft(force_reload: Signal<i32>) -> Element {
                 a { "Sidebar Item 2" }
             }
             CellPostForm { force_reload }
+            FileSearch {}
+        }
+    }
+}
+
+// Files of all cells whose name contains the typed text, or matches it when
+// it holds * or ?
+#[component]
+fn FileSearch() -> Element {
+    let mut pattern = use_signal::<String>(|| "".to_string());
+
+    let found = use_resource(move || async move {
+        let pattern = pattern();
+        if pattern.trim().is_empty() {
+            return Ok(vec![])
+        }
+        let glob = if pattern.contains(['*', '?']) { pattern } else { format!("*{}*", pattern) };
+        let filter = FileFilter {
+            user_id: MY_UUID.to_string(),
+            glob: Some(glob),
+            limit: Some(50),
+            ..FileFilter::default()
+        };
+        let db_url = utils::get_db_path();
+        let pool = SqlitePoolOptions::new()
+            .max_connections(16)
+            .connect(&db_url)
+            .await?;
+        handler::find_files(pool, filter).await
+    });
+
+    rsx! {
+        li { class: "mt-4",
+            input {
+                class: "input input-bordered input-sm w-full max-w-xs",
+                placeholder: "Find files",
+                value: "{pattern}",
+                oninput: move |event| pattern.set(event.value()),
+            }
+        }
+        match &*found.read_unchecked() {
+            Some(Ok(files)) => rsx! {
+                for file in files.iter() {
+                    li { key: "{file.cell_id}/{file.path}",
+                        tree::FileItem {
+                            fileprop: model::FileProp { name: file.path.clone(), ..file.fileprop.clone() }
+                        }
+                    }
+                }
+            },
+            Some(Err(e)) => rsx! {
+                li { p { class: "text-sm text-gray-500", "Search failed: {e}" } }
+            },
+            None => rsx! {},
         }
     }
 }
@@ -129,112 +201,219 @@ fn DrawerRight() -> Element {
     }
 }
 
+// Posts a new cell, or a reply to `reply_to` when given. A `fork` reply
+// starts from the files of `reply_to`, which stay where they are stored;
+// only files added or replaced here are uploaded.
 #[component]
-fn CellPostForm(force_reload: Signal<i32>) -> Element {
-    let cell_id = uuid::Uuid::new_v4().to_string();
+fn CellPostForm(
+    force_reload: Signal<i32>,
+    reply_to: Option<CellExtracted>,
+    #[props(default)]
+    fork: bool,
+    on_posted: Option<EventHandler<()>>,
+) -> Element {
     let user_id = MY_UUID.to_string();
-    let device_id = "Dev0".to_string();
+    let device_id = DEVICE_ID.to_string();
     let mut text = use_signal::<String>(|| "".to_string());
     let is_open = use_signal::<bool>(|| false);
-    let rootdir = model::Dir {
-        name: "/".to_string(),
-        dirs: vec![model::Dir {
-            name: "test".to_string(),
-            dirs: vec![model::Dir {
-                name: "test2".to_string(),
-                dirs: Vec::new(),
-                fileprops: vec![FileProp {
-                    name: "photo.png".to_string(),
-                    url: "https://example.com/phto.png".to_string(),
-                    completed: true,
-                }]
-            }],
-            fileprops: vec![FileProp {
-                name: "photo3.docx".to_string(),
-                url: "https://example.com/phto3.docx".to_string(),
-                completed: true,
-            }],
-        }],
-        fileprops: vec![FileProp {
-            name: "photo2.png".to_string(),
-            url: "https://example.com/phto2.png".to_string(),
-            completed: true,
-        },FileProp {
-            name: "marine.txt".to_string(),
-            url: "https://example.com/marine.txt".to_string(),
-            completed: true,
-        },]
+    let mut rootdir = use_signal(|| match &reply_to {
+        Some(cell) if fork => cell.rootdir.clone(),
+        _ => model::Dir::new(),
+    });
+    let mut message = use_signal::<Option<String>>(|| None);
+    let mut is_posting = use_signal::<bool>(|| false);
+
+    let pick_folder = move |_| {
+        spawn(async move {
+            let Some(folder) = rfd::AsyncFileDialog::new().pick_folder().await else {
+                return
+            };
+            let path = folder.path().to_path_buf();
+            match tokio::task::spawn_blocking(move || local_dir::scan_dir(&path, "/")).await {
+                Ok(Ok(dir)) => {
+                    rootdir.set(dir);
+                    message.set(None);
+                }
+                Ok(Err(e)) => message.set(Some(format!("Failed to read the folder: {e}"))),
+                Err(e) => message.set(Some(format!("Failed to read the folder: {e}"))),
+            }
+        });
     };
-    let parent_ids: Vec<String> = Vec::new();
-    let child_ids: Vec<String> = Vec::new();
 
-    let mut new_cell_req: Signal<Option<model::CellReq>> = use_signal(|| None);
+    // An archive is expanded by the server, so its files are uploaded already
+    let pick_archive = move |_| {
+        spawn(async move {
+            let Some(file) = rfd::AsyncFileDialog::new()
+                .add_filter("Archive", &["zip", "tar", "gz", "tgz"])
+                .pick_file()
+                .await else {
+                return
+            };
+            is_posting.set(true);
+            message.set(Some("Expanding archive...".to_string()));
+            let client = utils::get_client();
+            match local_dir::expand_archive(&client, file.path()).await {
+                Ok(dir) => {
+                    rootdir.set(dir);
+                    message.set(None);
+                }
+                Err(e) => message.set(Some(format!("Failed to expand the archive: {e}"))),
+            }
+            is_posting.set(false);
+        });
+    };
 
-    let _new_cell_future = use_resource(move || async move {
-        let db_url = utils::get_db_path();
-        let pool = SqlitePoolOptions::new()
-            .max_connections(16)
-            .connect(&db_url)
-            .await
-            .unwrap();
-        if let Some(req) = &*new_cell_req.read() {
-            println!("I will post new cell.");
-            println!("{:?}", req);
-            let res = handler::create_cell(pool, req.clone()).await;
-            match res {
+    let drop_files = move |event: DragEvent| {
+        let Some(file_engine) = event.files() else {
+            return
+        };
+        let paths = file_engine.files();
+        spawn(async move {
+            match tokio::task::spawn_blocking(move || local_dir::scan_paths(&paths)).await {
+                Ok(Ok(dropped)) => {
+                    let mut rootdir = rootdir.write();
+                    rootdir.dirs.extend(dropped.dirs);
+                    rootdir.fileprops.extend(dropped.fileprops);
+                    message.set(None);
+                }
+                Ok(Err(e)) => message.set(Some(format!("Failed to read dropped files: {e}"))),
+                Err(e) => message.set(Some(format!("Failed to read dropped files: {e}"))),
+            }
+        });
+    };
+
+    // Files not posted yet are edited in place; added ones upload on send
+    let edit_files = move |op: patch::FileOp| {
+        let edited = patch::apply(&rootdir.read(), &[op]);
+        match edited {
+            Ok(dir) => {
+                rootdir.set(dir);
+                message.set(None);
+            }
+            Err(e) => message.set(Some(format!("Cannot change files: {e}"))),
+        }
+    };
+
+    let parent_ids: Vec<String> = reply_to.iter().map(|cell| cell.cell_id.clone()).collect();
+    let post_cell = move |_| {
+        let mut cell_req = CellReq {
+            cell_id: uuid::Uuid::new_v4().to_string(),
+            user_id: user_id.clone(),
+            device_id: device_id.clone(),
+            text: text.read().to_string(),
+            is_open: *is_open.read(),
+            rootdir: rootdir.read().clone(),
+            parent_ids: parent_ids.clone(),
+            child_ids: Vec::new(),
+        };
+        if let Err(e) = validate::validate_dir(&cell_req.rootdir) {
+            message.set(Some(format!("Cannot post: {e}")));
+            return
+        }
+        spawn(async move {
+            is_posting.set(true);
+            message.set(Some("Uploading files...".to_string()));
+            let client = utils::get_client();
+            if let Err(e) = local_dir::upload_dir(&client, &mut cell_req.rootdir).await {
+                message.set(Some(format!("Upload failed: {e}")));
+                is_posting.set(false);
+                return
+            }
+            let db_url = utils::get_db_path();
+            let pool = SqlitePoolOptions::new()
+                .max_connections(16)
+                .connect(&db_url)
+                .await
+                .unwrap();
+            match handler::create_cell(pool, cell_req).await {
                 Ok(res) => {
                     println!("Successfully posted: {:?}", res);
+                    text.set("".to_string());
+                    rootdir.set(model::Dir::new());
+                    message.set(None);
                     force_reload += 1;
-                    println!("Restarted......, right?");
+                    if let Some(on_posted) = on_posted {
+                        on_posted.call(());
+                    }
                 }
                 Err(e) => {
-                    println!("Failed: {:?}", e);
+                    message.set(Some(format!("Failed to post: {e}")));
                 }
             }
-        }
-    });
+            is_posting.set(false);
+        });
+    };
+
+    let has_files = !rootdir.read().dirs.is_empty() || !rootdir.read().fileprops.is_empty();
 
     rsx! {
+        if let Some(reply_to) = &reply_to {
+            p { class: "w-full max-w-xs mb-1 text-sm text-gray-500 truncate",
+                if fork { "Forking: {reply_to.text}" } else { "Replying to: {reply_to.text}" }
+            }
+        }
         textarea {
             value: "{text}",
-            placeholder: "Bio2",
+            placeholder: if reply_to.is_some() { "Reply" } else { "Bio2" },
             oninput: move |event| {
                 text.set(event.value().clone());
             },
-            // prevent_default: "onchange",
-            onchange: move |event| {},
             class: "textarea textarea-bordered textarea-md w-full max-w-xs"
         }
-        button {
-            class: "ml-2 px-4 py-2 bg-blue-500 text-white rounded-md hover:bg-blue-600 focus:outline-none focus:ring-2 focus:ring-blue-500",
-            onclick: move |event| {
-                println!("Clicked! Event: {event:?}");
-                println!("Current text: {text}");
-                let cell_req = CellReq {
-                    cell_id: cell_id.clone(),
-                    user_id: user_id.clone(),
-                    device_id: device_id.clone(),
-                    text: text.read().to_string(),
-                    is_open: *is_open.read(),
-                    rootdir: rootdir.clone(),
-                    parent_ids: parent_ids.clone(),
-                    child_ids: child_ids.clone(),
-                };
-                new_cell_req.set(Some(cell_req));
-            },
-            "Send"
+        div {
+            class: "w-full max-w-xs my-2 p-2 border-2 border-dashed rounded-md text-sm text-gray-500",
+            prevent_default: "ondragover ondrop",
+            ondragover: move |_| {},
+            ondrop: drop_files,
+            if has_files {
+                tree::FileTree { rootdir: rootdir(), on_op: edit_files }
+            } else {
+                "Drop files or folders here"
+            }
+        }
+        div { class: "flex flex-row",
+            button {
+                class: "px-4 py-2 bg-gray-200 rounded-md hover:bg-gray-300",
+                disabled: is_posting(),
+                onclick: pick_folder,
+                "Folder..."
+            }
+            button {
+                class: "ml-2 px-4 py-2 bg-gray-200 rounded-md hover:bg-gray-300",
+                disabled: is_posting(),
+                onclick: pick_archive,
+                "Archive..."
+            }
+            if has_files {
+                button {
+                    class: "ml-2 px-4 py-2 bg-gray-200 rounded-md hover:bg-gray-300",
+                    disabled: is_posting(),
+                    onclick: move |_| rootdir.set(model::Dir::new()),
+                    "Clear"
+                }
+            }
+            button {
+                class: "ml-2 px-4 py-2 bg-blue-500 text-white rounded-md hover:bg-blue-600 focus:outline-none focus:ring-2 focus:ring-blue-500",
+                disabled: is_posting(),
+                onclick: post_cell,
+                "Send"
+            }
+        }
+        if let Some(message) = message() {
+            p { class: "text-sm text-gray-500", "{message}" }
         }
     }
 }
 
 #[component]
-fn Cells(cells: Cells, force_reload: Signal<i32>) -> Element {
+fn Cells(cells: Cells, force_reload: Signal<i32>, thread: Signal<Option<String>>) -> Element {
     rsx! {
         div { class: "flex flex-col p-6 items-center bg-base-200",
             for cell in cells.cells.iter() {
                 Cell {
                     cell: cell.clone(),
                     force_reload,
+                    thread,
                 }
             }
         }
@@ -242,8 +421,94 @@ fn Cells(cells: Cells, force_reload: Signal<i32>) -> Element {
 }
 
 #[component]
-fn Cell(cell: CellExtracted, force_reload: Signal<i32>) -> Element {
+fn Cell(cell: CellExtracted, force_reload: Signal<i32>, thread: Signal<Option<String>>) -> Element {
     let mut is_trancated: Signal<bool> = use_signal(|| true);
+    let mut is_replying = use_signal::<bool>(|| false);
+    let mut is_forking = use_signal::<bool>(|| false);
+    let mut is_editing = use_signal::<bool>(|| false);
+    let show_history = use_signal::<bool>(|| false);
+    let mut show_changes = use_signal::<bool>(|| false);
+    let mut download_progress = use_signal(download::Progress::default);
+    let mut download_cancel = use_signal::<bool>(|| false);
+    let mut is_downloading = use_signal::<bool>(|| false);
+    let rootdir = cell.rootdir.clone();
+    let mut file_message = use_signal::<Option<String>>(|| None);
+
+    let start_download = move |rootdir: model::Dir| {
+        spawn(async move {
+            let Some(folder) = rfd::AsyncFileDialog::new().pick_folder().await else {
+                return
+            };
+            download_cancel.set(false);
+            is_downloading.set(true);
+            match download::plan(&rootdir, folder.path()) {
+                Ok(tasks) => {
+                    download::download_all(utils::get_client(), tasks, download_progress, download_cancel).await;
+                }
+                Err(e) => download_progress.set(download::Progress::refused(e.to_string())),
+            }
+        });
+    };
+
+    // One archive built by the server, saved where the user picks
+    let save_archive = {
+        let rootdir = cell.rootdir.clone();
+        let cell_id = cell.cell_id.clone();
+        move |format: ArchiveFormat| {
+            let rootdir = rootdir.clone();
+            let extension = match format {
+                ArchiveFormat::Zip => "zip",
+                ArchiveFormat::TarGz => "tar.gz",
+            };
+            let file_name = format!("{}.{}", cell_id, extension);
+            spawn(async move {
+                let Some(file) = rfd::AsyncFileDialog::new().set_file_name(&file_name).save_file().await else {
+                    return
+                };
+                file_message.set(Some("Downloading archive...".to_string()));
+                let client = utils::get_client();
+                match download::download_archive(&client, &rootdir, format, file.path()).await {
+                    Ok(_) => file_message.set(Some(format!("Saved {}", file.path().display()))),
+                    Err(e) => file_message.set(Some(format!("Download failed: {e}"))),
+                }
+            });
+        }
+    };
+
+    let edit_files = {
+        let cell_id = cell.cell_id.clone();
+        move |op: patch::FileOp| {
+            let cell_id = cell_id.clone();
+            spawn(async move {
+                let mut op = op;
+                if let patch::FileOp::Add(added) | patch::FileOp::Replace(added) = &mut op {
+                    file_message.set(Some("Uploading files...".to_string()));
+                    let mut fileprop = patch::from_remote(added.clone());
+                    if let Err(e) = local_dir::upload_file(&utils::get_client(), &mut fileprop).await {
+                        file_message.set(Some(format!("Upload failed: {e}")));
+                        return
+                    }
+                    *added = patch::to_remote(added.path.clone(), &fileprop);
+                }
+                let db_url = utils::get_db_path();
+                let pool = SqlitePoolOptions::new()
+                    .max_connections(16)
+                    .connect(&db_url)
+                    .await
+                    .unwrap();
+                match handler::patch_files(&cell_id, vec![op], DEVICE_ID, pool).await {
+                    Ok(_) => {
+                        file_message.set(None);
+                        force_reload += 1;
+                    }
+                    Err(e) => file_message.set(Some(format!("Cannot change files: {e}"))),
+                }
+            });
+        }
+    };
+
+    // Only the cell itself is needed to reply to it
+    let reply_to = CellExtracted { parents: vec![], children: vec![], ..cell.clone() };
 
     // let window = web_sys::window().unwrap();
     // let document = window.document().unwrap();
@@ -267,22 +532,63 @@ fn Cell(cell: CellExtracted, force_reload: Signal<i32>) -> Element {
                         }
                     }
                     div { class: "flex flex-row mt-4",
-                        // DotsMenu {cell_id: cell.cell_id, force_reload}
+                        details { class: "dropdown ml-4",
+                            summary { class: "list-none cursor-pointer", svg_icon::download{} }
+                            ul { class: "dropdown-content menu menu-xs bg-base-100 rounded-box shadow z-10 w-40",
+                                li {
+                                    button {
+                                        disabled: is_downloading(),
+                                        onclick: move |evt| {
+                                            println!("push download: {evt:?}");
+                                            start_download(rootdir.clone());
+                                        },
+                                        "Into a folder..."
+                                    }
+                                }
+                                li {
+                                    a {
+                                        onclick: {
+                                            let save_archive = save_archive.clone();
+                                            move |_| save_archive(ArchiveFormat::Zip)
+                                        },
+                                        "As zip..."
+                                    }
+                                }
+                                li {
+                                    a { onclick: move |_| save_archive(ArchiveFormat::TarGz), "As tar.gz..." }
+                                }
+                            }
+                        }
                         button {
                             class: "ml-4",
-                            onclick: move |evt| {
-                                println!("push download: {evt:?}");
-                                // std::fs::create_dir("t").unwrap();
-                                utils::download_dir();
+                            onclick: move |_| {
+                                is_replying.toggle();
+                                is_forking.set(false);
                             },
-                            svg_icon::download{},
+                            svg_icon::reply{},
                         }
                         button {
-                            class: "ml-4",
-                            onclick: move |evt| {
-                                println!("push reply: {evt:?}");
+                            class: "ml-4 text-sm text-gray-500 hover:text-gray-700",
+                            onclick: move |_| {
+                                is_forking.toggle();
+                                is_replying.set(false);
                             },
-                            svg_icon::reply{},
+                            "Fork"
+                        }
+                        button {
+                            class: "ml-4 text-sm text-gray-500 hover:text-gray-700",
+                            onclick: {
+                                let cell_id = cell.cell_id.clone();
+                                move |_| thread.set(Some(cell_id.clone()))
+                            },
+                            "Thread"
+                        }
+                        if !cell.parents.is_empty() {
+                            button {
+                                class: "ml-4 text-sm text-gray-500 hover:text-gray-700",
+                                onclick: move |_| show_changes.toggle(),
+                                "Changes"
+                            }
                         }
                         button {
                             class: "ml-4",
@@ -319,100 +625,134 @@ fn Cell(cell: CellExtracted, force_reload: Signal<i32>) -> Element {
                             },
                             svg_icon::trash{},
                         } 
-                        button {class: "ml-4", svg_icon::dotsmenu{}} 
+                        DotsMenu { cell_id: cell.cell_id.clone(), force_reload, is_editing, show_history }
                     }
                 }
                 div { class: "min-w-60",
-                    tree::FileTree {rootdir: cell.rootdir}
+                    tree::FileTree {
+                        rootdir: cell.rootdir.clone(),
+                        on_op: edit_files,
+                        // One file alone goes into the picked folder
+                        on_download: move |fileprop: model::FileProp| {
+                            start_download(model::Dir::from_files([(fileprop.name.clone(), fileprop)], []));
+                        },
+                    }
+                    if let Some(message) = file_message() {
+                        p { class: "text-sm text-gray-500", "{message}" }
+                    }
+                }
+            }
+            if is_editing() {
+                div { class: "mt-2 pt-2 border-t",
+                    edit::CellEditForm {
+                        cell: CellExtracted { children: vec![], ..cell.clone() },
+                        force_reload,
+                        on_close: move |_| is_editing.set(false),
+                    }
+                }
+            }
+            if show_changes() {
+                div { class: "mt-2 pt-2 border-t",
+                    compare::ParentChanges { cell: CellExtracted { children: vec![], ..cell.clone() } }
+                }
+            }
+            if show_history() {
+                div { class: "mt-2 pt-2 border-t",
+                    edit::RevisionList { cell_id: cell.cell_id.clone(), force_reload }
+                }
+            }
+            if is_replying() {
+                div { class: "mt-2 pt-2 border-t",
+                    CellPostForm {
+                        force_reload,
+                        reply_to: reply_to.clone(),
+                        on_posted: move |_| is_replying.set(false),
+                    }
+                }
+            }
+            if is_forking() {
+                div { class: "mt-2 pt-2 border-t",
+                    CellPostForm {
+                        force_reload,
+                        reply_to: reply_to.clone(),
+                        fork: true,
+                        on_posted: move |_| is_forking.set(false),
+                    }
+                }
+            }
+            if is_downloading() {
+                download::DownloadPanel {
+                    progress: download_progress,
+                    cancel: download_cancel,
+                    on_close: move |_| is_downloading.set(false),
                 }
             }
         }
     }
 }
 
+// Menu of the actions changing a cell
 #[component]
-fn DotsMenu(cell_id: String, force_reload: Signal<i32>) -> Element {
-
+fn DotsMenu(
+    cell_id: String,
+    force_reload: Signal<i32>,
+    is_editing: Signal<bool>,
+    show_history: Signal<bool>,
+) -> Element {
     let mut is_menu_open: Signal<bool> = use_signal(||false);
-    // let close_menu = |evt: Event<MouseData>| {
-    //     let elem = evt.data.target();
-    //     elem.set_focus(false);
-    // };
 
-    rsx! {
-            ul { class: "menu lg:menu-horizontal rounded-box lg:mb-64",
-            li {
-                a { "Item 1" }
+    let delete_cell = move |_| {
+        is_menu_open.set(false);
+        let cell_id = cell_id.clone();
+        spawn(async move {
+            let db_url = utils::get_db_path();
+            let pool = SqlitePoolOptions::new()
+                .max_connections(16)
+                .connect(&db_url)
+                .await
+                .unwrap();
+            match handler::delete_cell(&cell_id, pool).await {
+                Ok(res) => {
+                    println!("Successfully deleted: {:?}", res);
+                    force_reload += 1;
+                }
+                Err(e) => {
+                    println!("Failed: {:?}", e);
+                }
             }
-            li {
-                a { "Item 3" }
+        });
+    };
+
+    rsx! {
+        details { class: "dropdown ml-4", open: is_menu_open(),
+            summary {
+                class: "list-none cursor-pointer",
+                prevent_default: "onclick",
+                onclick: move |_| is_menu_open.toggle(),
+                svg_icon::dotsmenu {}
             }
-            li {
-                details { open: is_menu_open, 
-                    summary { svg_icon::dotsmenu {} }
-                    ul {
-                        li {
-                            onclick: move |evt| {
-                                println!("{:?}", is_menu_open);
-                                // is_menu_open.toggle();
-                                is_menu_open.toggle();
-                                println!("{:?}", is_menu_open);
-                                // is_menu_open.set(true);            
-                            },
-                            // onfocus: move |evt| {
-                            //     println!("{:?}", is_menu_open);
-                            // },
-                            a { "Submenu 1" }
-                        }
-                        li {
-                            onclick: move |evt| {
-                                // del_cell_sig.set(false);
-                                is_menu_open.toggle();
-                                println!("push delete: {evt:?}");
-                                let cell_id = cell_id.clone();
-                                let _del_cell_future = use_resource(move ||
-                                    {
-                                        let cell_id = cell_id.clone();
-                                        async move {
-                                            let db_url = utils::get_db_path();
-                                            let pool = SqlitePoolOptions::new()
-                                                .max_connections(16)
-                                                .connect(&db_url)
-                                                .await
-                                                .unwrap();
-                                            println!("I will delete the cell.");
-                                            let res = handler::delete_cell(&cell_id, pool).await;
-                                            match res {
-                                                Ok(res) => {
-                                                    println!("Successfully posted: {:?}", res);
-                                                    force_reload += 1;
-                                                    println!("Restarted......, right?");
-                                                }
-                                                Err(e) => {
-                                                    println!("Failed: {:?}", e);
-                                                }
-                                            }
-                                        }
-                                    }
-                                );
-                            },
-                            a { svg_icon::trash {} "削除" },
-                        }
-                        li {
-                            details { open: "false",
-                                summary { "Parent" }
-                                ul {
-                                    li {
-                                        a { "item 1" }
-                                    }
-                                    li {
-                                        a { "item 2" }
-                                    }
-                                }
-                            }
-                        }
+            ul { class: "dropdown-content menu menu-xs bg-base-100 rounded-box shadow z-10 w-40",
+                li {
+                    a {
+                        onclick: move |_| {
+                            is_menu_open.set(false);
+                            is_editing.set(true);
+                        },
+                        "Edit"
+                    }
+                }
+                li {
+                    a {
+                        onclick: move |_| {
+                            is_menu_open.set(false);
+                            show_history.toggle();
+                        },
+                        if show_history() { "Hide history" } else { "History" }
                     }
                 }
+                li {
+                    a { onclick: delete_cell, svg_icon::trash {} "削除" }
+                }
             }
         }
     }
@@ -440,7 +780,6 @@ async fn init_db(db: &sqlx::pool::Pool<sqlx::Sqlite>) -> Result<(), sqlx::Error>
             user_id     TEXT NOT NULL,
             device_id   TEXT NOT NULL,
             text        TEXT NOT NULL,
-            rootdir     TEXT NOT NULL,
             is_open     INTEGER NOT NULL CHECK (is_open IN (0, 1)),
             created_at  TIMESTAMP DEFAULT CURRENT_TIMESTAMP
         )",
@@ -450,6 +789,68 @@ async fn init_db(db: &sqlx::pool::Pool<sqlx::Sqlite>) -> Result<(), sqlx::Error>
 
     println!("{:?}", _r);
 
+    let _r = sqlx::query(
+        "CREATE TABLE IF NOT EXISTS files (
+            cell_id     TEXT NOT NULL,
+            path        TEXT NOT NULL,
+            name        TEXT NOT NULL,
+            url         TEXT NOT NULL,
+            completed   INTEGER NOT NULL CHECK (completed IN (0, 1)),
+            size        INTEGER,
+            sha256      TEXT,
+            mime        TEXT,
+            mtime       TIMESTAMP,
+            PRIMARY KEY (cell_id, path),
+            FOREIGN KEY (cell_id) REFERENCES cells(cell_id) ON DELETE CASCADE
+        )",
+    )
+    .execute(db)
+    .await?;
+
+    println!("{:?}", _r);
+
+    let _r = sqlx::query(
+        "CREATE TABLE IF NOT EXISTS dirs (
+            cell_id     TEXT NOT NULL,
+            path        TEXT NOT NULL,
+            PRIMARY KEY (cell_id, path),
+            FOREIGN KEY (cell_id) REFERENCES cells(cell_id) ON DELETE CASCADE
+        )",
+    )
+    .execute(db)
+    .await?;
+
+    println!("{:?}", _r);
+
+    for index in [
+        "CREATE INDEX IF NOT EXISTS files_name_idx ON files (lower(name))",
+        "CREATE INDEX IF NOT EXISTS files_sha256_idx ON files (sha256)",
+        "CREATE INDEX IF NOT EXISTS files_mime_idx ON files (mime)",
+    ] {
+        sqlx::query(index).execute(db).await?;
+    }
+
+    // Cells created before the files table kept their rootdir as JSON text
+    let legacy: Option<(String,)> = sqlx::query_as(
+        "SELECT name FROM pragma_table_info('cells') WHERE name='rootdir'",
+    )
+    .fetch_optional(db)
+    .await?;
+    if legacy.is_some() {
+        let mut tx = db.begin().await?;
+        let cells: Vec<(String, sqlx::types::Json<model::Dir>)> =
+            sqlx::query_as("SELECT cell_id, rootdir FROM cells")
+                .fetch_all(&mut *tx)
+                .await?;
+        for (cell_id, rootdir) in cells.iter() {
+            handler::insert_dir(&mut tx, cell_id, rootdir).await?;
+        }
+        sqlx::query("ALTER TABLE cells DROP COLUMN rootdir")
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+    }
+
     let _r = sqlx::query(
         "CREATE TABLE IF NOT EXISTS family_tree (
             child_id    TEXT NOT NULL,
@@ -463,5 +864,23 @@ async fn init_db(db: &sqlx::pool::Pool<sqlx::Sqlite>) -> Result<(), sqlx::Error>
 
     println!("{:?}", _r);
 
+    // The whole cell after each change, rootdir and parent_ids as JSON
+    let _r = sqlx::query(
+        "CREATE TABLE IF NOT EXISTS revisions (
+            cell_id     TEXT NOT NULL,
+            revision    INTEGER NOT NULL,
+            device_id   TEXT NOT NULL,
+            text        TEXT NOT NULL,
+            is_open     INTEGER NOT NULL CHECK (is_open IN (0, 1)),
+            rootdir     TEXT NOT NULL,
+            parent_ids  TEXT NOT NULL,
+            created_at  TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+            PRIMARY KEY (cell_id, revision),
+            FOREIGN KEY (cell_id) REFERENCES cells(cell_id) ON DELETE CASCADE
+        )",
+    )
+    .execute(db)
+    .await?;
+
     Ok(())
 }
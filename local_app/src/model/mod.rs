@@ -77,7 +77,7 @@ impl CellExtracted {
             device_id: cell_row.device_id,
             text: cell_row.text,
             is_open: cell_row.is_open,
-            rootdir: cell_row.rootdir.0,
+            rootdir: cell_row.rootdir,
             parents: parent_cells,
             children: child_cells,
         }
@@ -118,13 +118,87 @@ pub struct CellRow {
     pub device_id:      String,
     pub text:           String,
     pub is_open:        bool,
-    pub rootdir:        sqlx::types::Json<Dir>,
+    // Assembled from the files table after the cell is read
+    #[sqlx(skip)]
+    pub rootdir:        Dir,
 }
 #[derive(FromRow, Serialize, Deserialize, Debug)]
 pub struct CellFilter {
     pub user_id:        String,
 }
 
+// What an edit of a cell sets; anything left as it was is unchanged
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct CellEdit {
+    pub device_id:      String,
+    pub text:           String,
+    pub is_open:        bool,
+    pub rootdir:        Dir,
+    pub parent_ids:     Vec<String>,
+}
+
+#[derive(FromRow, Debug)]
+pub struct RevisionRow {
+    pub cell_id:        String,
+    pub revision:       i64,
+    pub device_id:      String,
+    pub text:           String,
+    pub is_open:        bool,
+    pub rootdir:        sqlx::types::Json<Dir>,
+    pub parent_ids:     sqlx::types::Json<Vec<String>>,
+    pub created_at:     chrono::NaiveDateTime,
+}
+
+// A cell as it was after one change, revision 1 being the cell as created
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Revision {
+    pub cell_id:        String,
+    pub revision:       i64,
+    pub device_id:      String,
+    pub text:           String,
+    pub is_open:        bool,
+    pub rootdir:        Dir,
+    pub parent_ids:     Vec<String>,
+    pub created_at:     chrono::NaiveDateTime,
+}
+
+impl From<RevisionRow> for Revision {
+    fn from(row: RevisionRow) -> Self {
+        Revision {
+            cell_id: row.cell_id,
+            revision: row.revision,
+            device_id: row.device_id,
+            text: row.text,
+            is_open: row.is_open,
+            rootdir: row.rootdir.0,
+            parent_ids: row.parent_ids.0,
+            created_at: row.created_at,
+        }
+    }
+}
+
+// A cell in a graph of the family tree, with the number of its parents and
+// replies whether they are in the graph or not
+#[derive(FromRow, Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct DagCell {
+    pub cell_id:        String,
+    pub text:           String,
+    pub parent_count:   i64,
+    pub child_count:    i64,
+}
+
+#[derive(FromRow, Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Edge {
+    pub child_id:       String,
+    pub parent_id:      String,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Default)]
+pub struct Dag {
+    pub cells:          Vec<DagCell>,
+    pub edges:          Vec<Edge>,
+}
+
 /*
 #[derive(FromRow, Serialize, Deserialize, Debug)]
 pub struct FileProp {
@@ -134,6 +208,50 @@ pub struct FileProp {
 }
 */
 
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct FileFilter {
+    pub user_id:        String,
+    pub name:           Option<String>,
+    pub glob:           Option<String>,
+    pub mime:           Option<String>,
+    pub sha256:         Option<String>,
+    pub limit:          Option<i64>,
+}
+
+#[derive(FromRow, Debug)]
+pub struct FileRow {
+    pub cell_id:        String,
+    pub path:           String,
+    pub name:           String,
+    pub url:            String,
+    pub completed:      bool,
+    pub size:           Option<i64>,
+    pub sha256:         Option<String>,
+    pub mime:           Option<String>,
+    pub mtime:          Option<chrono::NaiveDateTime>,
+}
+
+impl From<FileRow> for FileProp {
+    fn from(row: FileRow) -> Self {
+        FileProp {
+            name: row.name,
+            url: row.url,
+            completed: row.completed,
+            size: row.size.map(|s| s as u64),
+            sha256: row.sha256,
+            mime: row.mime,
+            mtime: row.mtime,
+        }
+    }
+}
+
+#[derive(PartialEq, Clone, Debug)]
+pub struct FileMatch {
+    pub cell_id:        String,
+    pub path:           String,
+    pub fileprop:       FileProp,
+}
+
 #[derive(Props, PartialEq, Clone, Serialize, Deserialize, Debug)]
 pub struct Dir {
     pub name:           String,
@@ -149,6 +267,92 @@ impl Dir {
             fileprops: Vec::new(),
         }
     }
+
+    // The subdirectory at `names` below this one, created if missing
+    pub fn dir_at(&mut self, names: &[String]) -> &mut Dir {
+        let Some((first, rest)) = names.split_first() else {
+            return self
+        };
+        let index = match self.dirs.iter().position(|d| &d.name == first) {
+            Some(index) => index,
+            None => {
+                self.dirs.push(Dir {
+                    name: first.clone(),
+                    dirs: Vec::new(),
+                    fileprops: Vec::new(),
+                });
+                self.dirs.len() - 1
+            }
+        };
+        self.dirs[index].dir_at(rest)
+    }
+
+    // Every file below this directory with its '/' separated path
+    pub fn files(&self) -> Vec<(String, &FileProp)> {
+        let mut files = vec![];
+        self.collect_files("", &mut files);
+        files
+    }
+
+    fn collect_files<'a>(&'a self, prefix: &str, files: &mut Vec<(String, &'a FileProp)>) {
+        for fileprop in self.fileprops.iter() {
+            files.push((format!("{}{}", prefix, fileprop.name), fileprop));
+        }
+        for sub_dir in self.dirs.iter() {
+            sub_dir.collect_files(&format!("{}{}/", prefix, sub_dir.name), files);
+        }
+    }
+
+    // Paths of the directories below this one that hold nothing at all;
+    // the others are implied by the paths of their files
+    pub fn empty_dirs(&self) -> Vec<String> {
+        let mut dirs = vec![];
+        self.collect_empty_dirs("", &mut dirs);
+        dirs
+    }
+
+    fn collect_empty_dirs(&self, prefix: &str, dirs: &mut Vec<String>) {
+        for sub_dir in self.dirs.iter() {
+            let path = format!("{}{}", prefix, sub_dir.name);
+            if sub_dir.dirs.is_empty() && sub_dir.fileprops.is_empty() {
+                dirs.push(path);
+            } else {
+                sub_dir.collect_empty_dirs(&format!("{}/", path), dirs);
+            }
+        }
+    }
+
+    // Rebuild a root directory from files and their paths plus the empty
+    // directories, with entries sorted by name
+    pub fn from_files(
+        files: impl IntoIterator<Item = (String, FileProp)>,
+        empty_dirs: impl IntoIterator<Item = String>,
+    ) -> Self {
+        let mut rootdir = Dir::new();
+        for path in empty_dirs {
+            let names: Vec<String> = path.split('/').map(str::to_string).collect();
+            rootdir.dir_at(&names);
+        }
+        for (path, fileprop) in files {
+            let mut names: Vec<String> = path.split('/').map(str::to_string).collect();
+            names.pop();
+            rootdir.dir_at(&names).fileprops.push(fileprop);
+        }
+        rootdir.sort();
+        rootdir
+    }
+
+    pub fn sort(&mut self) {
+        self.fileprops.sort_by(|a, b| a.name.cmp(&b.name));
+        self.dirs.sort_by(|a, b| a.name.cmp(&b.name));
+        self.dirs.iter_mut().for_each(Dir::sort);
+    }
+}
+
+impl Default for Dir {
+    fn default() -> Self {
+        Dir::new()
+    }
 }
 
 #[derive(Props, PartialEq, Clone, Serialize, Deserialize, Debug)]
@@ -156,4 +360,12 @@ pub struct FileProp {
     pub name:           String,
     pub url:            String,
     pub completed:      bool,
+    #[serde(default)]
+    pub size:           Option<u64>,
+    #[serde(default)]
+    pub sha256:         Option<String>,
+    #[serde(default)]
+    pub mime:           Option<String>,
+    #[serde(default)]
+    pub mtime:          Option<chrono::NaiveDateTime>,
 }
@@ -0,0 +1,55 @@
+use flowfs_client::model as remote;
+
+pub use flowfs_client::model::FileOp;
+pub use flowfs_client::patch::PatchError;
+
+use crate::model::{Dir, FileProp};
+
+// Edits from the file tree go through the same code as the backend's
+// PATCH /cells/:cell_id/files, on the flat form of the rootdir
+
+pub fn apply(rootdir: &Dir, ops: &[FileOp]) -> Result<Dir, PatchError> {
+    let fileprops: Vec<remote::FileProp> = rootdir.files()
+        .into_iter()
+        .map(|(path, fileprop)| to_remote(path, fileprop))
+        .collect();
+    let (fileprops, dirs) = flowfs_client::patch::apply(&fileprops, &rootdir.empty_dirs(), ops)?;
+    Ok(Dir::from_files(
+        fileprops.into_iter().map(|f| (f.path.clone(), from_remote(f))),
+        dirs,
+    ))
+}
+
+// An op adding `fileprop` at `path`
+pub fn add(path: String, fileprop: &FileProp) -> FileOp {
+    FileOp::Add(to_remote(path, fileprop))
+}
+
+// An op putting `fileprop` in place of the file at `path`
+pub fn replace(path: String, fileprop: &FileProp) -> FileOp {
+    FileOp::Replace(to_remote(path, fileprop))
+}
+
+pub fn to_remote(path: String, fileprop: &FileProp) -> remote::FileProp {
+    remote::FileProp {
+        path,
+        url: fileprop.url.clone(),
+        completed: fileprop.completed,
+        size: fileprop.size,
+        sha256: fileprop.sha256.clone(),
+        mime: fileprop.mime.clone(),
+        mtime: fileprop.mtime,
+    }
+}
+
+pub fn from_remote(fileprop: remote::FileProp) -> FileProp {
+    FileProp {
+        name: fileprop.path.rsplit('/').next().unwrap_or(&fileprop.path).to_string(),
+        url: fileprop.url,
+        completed: fileprop.completed,
+        size: fileprop.size,
+        sha256: fileprop.sha256,
+        mime: fileprop.mime,
+        mtime: fileprop.mtime,
+    }
+}
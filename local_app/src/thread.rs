@@ -0,0 +1,123 @@
+use dioxus::prelude::*;
+use sqlx::sqlite::SqlitePoolOptions;
+
+use crate::graph;
+use crate::handler;
+use crate::model::CellExtracted;
+use crate::utils;
+
+// Ancestors are indented by this much per level up, replies per level down
+const INDENT_REM: usize = 2;
+
+// A cell with its ancestry above it and its replies nested below. Clicking
+// any of them makes it the focused cell; `thread` set to None goes back to
+// the list of cells. The same cells can be seen as a graph instead.
+#[component]
+pub fn Thread(cell_id: String, force_reload: Signal<i32>, thread: Signal<Option<String>>) -> Element {
+    let mut as_graph = use_signal::<bool>(|| false);
+    let graph_id = cell_id.clone();
+    let loaded = use_resource(use_reactive!(|(cell_id, force_reload)| async move {
+        println!("Loading thread of {} with {}", cell_id, force_reload);
+        let db_url = utils::get_db_path();
+        let pool = SqlitePoolOptions::new()
+            .max_connections(16)
+            .connect(&db_url)
+            .await?;
+        handler::cell::show_cell(&cell_id, pool).await
+    }));
+
+    rsx! {
+        div { class: "flex flex-col p-6 items-center bg-base-200",
+            div { class: "w-2/3",
+                button {
+                    class: "px-4 py-2 bg-gray-200 rounded-md hover:bg-gray-300",
+                    onclick: move |_| thread.set(None),
+                    "Back to all cells"
+                }
+                button {
+                    class: "ml-2 px-4 py-2 bg-gray-200 rounded-md hover:bg-gray-300",
+                    onclick: move |_| as_graph.toggle(),
+                    if as_graph() { "Thread" } else { "Graph" }
+                }
+            }
+            if as_graph() {
+                graph::Graph {
+                    key: "{graph_id}",
+                    cell_id: graph_id.clone(),
+                    force_reload,
+                    on_open: move |cell_id: String| {
+                        thread.set(Some(cell_id));
+                        as_graph.set(false);
+                    },
+                }
+            } else {
+                match &*loaded.read_unchecked() {
+                    Some(Ok(cell)) => rsx! {
+                        Ancestors { parents: cell.parents.clone(), depth: 1, thread }
+                        crate::Cell {
+                            cell: CellExtracted { children: vec![], ..cell.clone() },
+                            force_reload,
+                            thread,
+                        }
+                        Replies { children: cell.children.clone(), depth: 1, thread }
+                    },
+                    // The cell was deleted from inside the thread
+                    Some(Err(sqlx::Error::RowNotFound)) => rsx! {
+                        p { class: "w-2/3 my-2 text-sm text-gray-500", "This cell no longer exists." }
+                    },
+                    Some(Err(e)) => rsx! {
+                        p { class: "w-2/3 my-2 text-sm text-gray-500", "Failed to load the thread: {e}" }
+                    },
+                    None => rsx! {},
+                }
+            }
+        }
+    }
+}
+
+// Farthest ancestors first, so the thread reads top down. A cell reached
+// through several parents is shown under each of them.
+#[component]
+fn Ancestors(parents: Vec<CellExtracted>, depth: usize, thread: Signal<Option<String>>) -> Element {
+    rsx! {
+        for parent in parents.into_iter() {
+            Ancestors { parents: parent.parents.clone(), depth: depth + 1, thread }
+            ThreadCard { key: "{parent.cell_id}", cell: parent, depth, thread }
+        }
+    }
+}
+
+// Replies in the order they were made, each followed by its own replies
+#[component]
+fn Replies(children: Vec<CellExtracted>, depth: usize, thread: Signal<Option<String>>) -> Element {
+    rsx! {
+        for child in children.into_iter() {
+            ThreadCard { key: "{child.cell_id}", cell: child.clone(), depth, thread }
+            Replies { children: child.children, depth: depth + 1, thread }
+        }
+    }
+}
+
+// A compact cell in a thread, opening its own thread when clicked
+#[component]
+fn ThreadCard(cell: CellExtracted, depth: usize, thread: Signal<Option<String>>) -> Element {
+    let indent = depth * INDENT_REM;
+    let replies = cell.children.len();
+    let files = cell.rootdir.files().len();
+    rsx! {
+        div {
+            class: "w-2/3 my-1 px-6 py-2 rounded-lg bg-white shadow cursor-pointer hover:bg-gray-50",
+            style: "padding-left: {indent}rem",
+            onclick: move |_| thread.set(Some(cell.cell_id.clone())),
+            p { class: "line-clamp-2 break-words", "{cell.text}" }
+            div { class: "text-sm text-gray-400",
+                if replies > 0 {
+                    span { class: "mr-4", "{replies} replies" }
+                }
+                if files > 0 {
+                    span { "{files} files" }
+                }
+            }
+        }
+    }
+}
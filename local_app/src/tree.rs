@@ -1,6 +1,10 @@
 use dioxus::prelude::*;
 use crate::svg_icon;
+use crate::utils;
+use crate::local_dir;
 use crate::model::{CellExtracted, Dir, FileProp};
+use crate::patch::{self, FileOp};
+use log::error;
 
 /*
 impl CellExtracted {
@@ -19,40 +23,301 @@ struct FileTreeProps {
 */
 
 
+// The entries of `rootdir`, whose path in the cell is `path` ("" for the
+// root). With `on_op` set, a right click on an entry opens a menu whose
+// actions are passed to it as patch operations; with `on_download` set,
+// the menu of a file can also download it alone.
 #[component]
-pub fn FileTree(rootdir: Dir) -> Element {
+pub fn FileTree(
+    rootdir: Dir,
+    #[props(default)]
+    path: String,
+    on_op: Option<EventHandler<FileOp>>,
+    on_download: Option<EventHandler<FileProp>>,
+) -> Element {
+    let mut menu_open = use_signal(|| false);
+    let entries = rsx! {
+        for fileprop in rootdir.fileprops {
+            li {
+                FileEntry { path: join(&path, &fileprop.name), fileprop: fileprop.clone(), on_op, on_download }
+            }
+        }
+        for subdir in rootdir.dirs {
+            li {
+                FileTree { path: join(&path, &subdir.name), rootdir: subdir.clone(), on_op, on_download }
+            }
+        }
+    };
+    let open_menu = move |evt: MouseEvent| {
+        if on_op.is_some() {
+            evt.stop_propagation();
+            menu_open.set(true);
+        }
+    };
+    let menu = rsx! {
+        if let (true, Some(on_op)) = (menu_open(), on_op) {
+            EntryMenu { path: path.clone(), is_dir: true, on_op, on_download: None, on_close: move |_| menu_open.set(false) }
+        }
+    };
     if rootdir.name == "/" {
         rsx! {
             ul { class: "menu menu-xs bg-base-0 rounded-lg max-w-xs w-full",
-                for fileprop in rootdir.fileprops {
-                    li {
-                        a { svg_icon::general_file {} {fileprop.name} }
-                    }
-                }
-                for subdir in rootdir.dirs {
-                    li {
-                        FileTree { rootdir: subdir }
-                    }
-                }
+                prevent_default: "oncontextmenu",
+                oncontextmenu: open_menu,
+                {menu}
+                {entries}
             }
         }
     } else {
         rsx! {
             details { open: "false", 
-                summary { svg_icon::directory {} {rootdir.name} }
+                summary {
+                    prevent_default: "oncontextmenu",
+                    oncontextmenu: open_menu,
+                    svg_icon::directory {} {rootdir.name}
+                }
+                {menu}
                 ul {
-                    for fileprop in rootdir.fileprops {
-                        li {
-                            a { svg_icon::general_file {} {fileprop.name} }
+                    {entries}
+                }
+            }
+        }
+    }
+}
+
+#[component]
+fn FileEntry(
+    path: String,
+    fileprop: FileProp,
+    on_op: Option<EventHandler<FileOp>>,
+    on_download: Option<EventHandler<FileProp>>,
+) -> Element {
+    let mut menu_open = use_signal(|| false);
+    let download = on_download.map(|on_download| {
+        let fileprop = fileprop.clone();
+        EventHandler::new(move |_| on_download.call(fileprop.clone()))
+    });
+    rsx! {
+        div {
+            prevent_default: "oncontextmenu",
+            oncontextmenu: move |evt: MouseEvent| {
+                if on_op.is_some() {
+                    evt.stop_propagation();
+                    menu_open.set(true);
+                }
+            },
+            FileItem { fileprop }
+        }
+        if let (true, Some(on_op)) = (menu_open(), on_op) {
+            EntryMenu { path, is_dir: false, on_op, on_download: download, on_close: move |_| menu_open.set(false) }
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Prompt {
+    Rename,
+    NewFolder,
+}
+
+// Context menu of an entry; the root ("" path) can only get new entries
+#[component]
+fn EntryMenu(
+    path: String,
+    is_dir: bool,
+    on_op: EventHandler<FileOp>,
+    on_download: Option<EventHandler<()>>,
+    on_close: EventHandler<()>,
+) -> Element {
+    let mut prompt = use_signal::<Option<Prompt>>(|| None);
+    let mut text = use_signal(String::new);
+    let is_root = path.is_empty();
+
+    let submit = {
+        let path = path.clone();
+        move |evt: KeyboardEvent| {
+            match evt.key() {
+                Key::Enter => {}
+                Key::Escape => return on_close.call(()),
+                _ => return,
+            }
+            let name = text.read().trim().to_string();
+            if !name.is_empty() {
+                match prompt() {
+                    // The whole path is editable, so renaming can also move
+                    Some(Prompt::Rename) if name != path => {
+                        on_op.call(FileOp::Move { from: path.clone(), path: name });
+                    }
+                    Some(Prompt::NewFolder) => {
+                        on_op.call(FileOp::Mkdir { path: join(&path, &name) });
+                    }
+                    _ => {}
+                }
+            }
+            on_close.call(());
+        }
+    };
+
+    let add_file = {
+        let path = path.clone();
+        move |_| {
+            let path = path.clone();
+            on_close.call(());
+            spawn(async move {
+                let Some(file) = rfd::AsyncFileDialog::new().pick_file().await else {
+                    return
+                };
+                let name = file.file_name();
+                let local = file.path().to_path_buf();
+                let scan_name = name.clone();
+                let scanned = tokio::task::spawn_blocking(move || local_dir::scan_file(&local, &scan_name)).await;
+                match scanned {
+                    Ok(Ok(fileprop)) => on_op.call(patch::add(join(&path, &name), &fileprop)),
+                    Ok(Err(e)) => error!("{}", e),
+                    Err(e) => error!("{}", e),
+                }
+            });
+        }
+    };
+
+    // Other content under the same name
+    let replace_file = {
+        let path = path.clone();
+        move |_| {
+            let path = path.clone();
+            on_close.call(());
+            spawn(async move {
+                let Some(file) = rfd::AsyncFileDialog::new().pick_file().await else {
+                    return
+                };
+                let local = file.path().to_path_buf();
+                let name = path.rsplit('/').next().unwrap_or(&path).to_string();
+                let scanned = tokio::task::spawn_blocking(move || local_dir::scan_file(&local, &name)).await;
+                match scanned {
+                    Ok(Ok(fileprop)) => on_op.call(patch::replace(path, &fileprop)),
+                    Ok(Err(e)) => error!("{}", e),
+                    Err(e) => error!("{}", e),
+                }
+            });
+        }
+    };
+
+    let delete = {
+        let path = path.clone();
+        move |_| {
+            let path = path.clone();
+            if is_dir {
+                on_op.call(FileOp::Rmdir { path, recursive: true });
+            } else {
+                on_op.call(FileOp::Remove { path });
+            }
+            on_close.call(());
+        }
+    };
+
+    rsx! {
+        if prompt().is_some() {
+            input {
+                class: "input input-bordered input-xs w-full max-w-xs",
+                value: "{text}",
+                autofocus: true,
+                oninput: move |evt| text.set(evt.value()),
+                onkeydown: submit,
+                onfocusout: move |_| on_close.call(()),
+            }
+        } else {
+            ul { class: "menu menu-xs bg-base-100 rounded-box shadow z-10",
+                onmouseleave: move |_| on_close.call(()),
+                if !is_root {
+                    li {
+                        a {
+                            onclick: {
+                                let path = path.clone();
+                                move |_| {
+                                    text.set(path.clone());
+                                    prompt.set(Some(Prompt::Rename));
+                                }
+                            },
+                            "Rename / move..."
                         }
                     }
-                    for subdir in rootdir.dirs {
-                        li {
-                            FileTree { rootdir: subdir }
+                }
+                if is_dir {
+                    li {
+                        a {
+                            onclick: move |_| {
+                                text.set(String::new());
+                                prompt.set(Some(Prompt::NewFolder));
+                            },
+                            "New folder..."
+                        }
+                    }
+                    li {
+                        a { onclick: add_file, "Add file..." }
+                    }
+                }
+                if !is_dir {
+                    li {
+                        a { onclick: replace_file, "Replace..." }
+                    }
+                }
+                if let Some(on_download) = on_download {
+                    li {
+                        a {
+                            onclick: move |_| {
+                                on_download.call(());
+                                on_close.call(());
+                            },
+                            svg_icon::download {} "Download..."
                         }
                     }
                 }
+                if !is_root {
+                    li {
+                        a { onclick: delete, svg_icon::trash {} "Delete" }
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn join(path: &str, name: &str) -> String {
+    if path.is_empty() {
+        name.to_string()
+    } else {
+        format!("{}/{}", path, name)
+    }
+}
+
+// A file entry with its size; the MIME type, modification time and digest
+// are shown on hover
+#[component]
+pub fn FileItem(fileprop: FileProp) -> Element {
+    let mut details = vec![];
+    if let Some(mime) = &fileprop.mime {
+        details.push(mime.clone());
+    }
+    if let Some(mtime) = &fileprop.mtime {
+        details.push(format!("modified {}", mtime.format("%Y/%m/%d %H:%M")));
+    }
+    if let Some(sha256) = &fileprop.sha256 {
+        details.push(format!("sha256 {}", sha256));
+    }
+    let size = fileprop.size.map(utils::format_size).unwrap_or_default();
+    let is_image = fileprop.mime.as_deref().is_some_and(|m| m.starts_with("image/"));
+    let is_document = fileprop.mime.as_deref().is_some_and(|m| m.starts_with("text/") || m == "application/pdf");
+    rsx! {
+        a { title: details.join("\n"),
+            if is_image {
+                svg_icon::image_file {}
+            } else if is_document {
+                svg_icon::document {}
+            } else {
+                svg_icon::general_file {}
             }
+            span { class: "flex-1", {fileprop.name} }
+            span { class: "text-gray-400", "{size}" }
         }
     }
 }
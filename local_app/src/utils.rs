@@ -2,20 +2,14 @@ use std::fs;
 use std::io::{BufReader, Read};
 // use sha2::{Sha256, Digest};
 use serde::Deserialize;
-use crate::model::{Dir};
 
-use reqwest;
-use std::fs;
-use std::io::prelude::*;
-use std::path::Path;
-use std::error::Error;
-use serde::{Serialize, Deserialize};
-use serde_json;
+use flowfs_client::FlowfsClient;
 
 #[derive(Debug, Deserialize)]
 struct Config {
   web_url: Option<String>,
   db_path: Option<String>,
+  api_url: Option<String>,
 }
 
 // Ref: https://cipepser.hatenablog.com/entry/rust-toml
@@ -54,39 +48,14 @@ pub fn get_db_path() -> String {
     .unwrap_or("flowfs".to_string())
 }
 
-// Function to download a file from a URL
-async fn download_file(url: &str, output_path: &Path) -> Result<(), Box<dyn Error>> {
-  let response = reqwest::get(url).await?.bytes().await?;
-  let mut file = fs::File::create(output_path)?;
-  file.write_all(&response)?;
-  Ok(())
-}
-
-// Function to create directories recursively
-fn create_directories(path: &Path) -> std::io::Result<()> {
-  if !path.exists() {
-      fs::create_dir_all(path)?;
-  }
-  Ok(())
+pub fn get_api_url() -> String {
+  get_config()
+    .api_url
+    .unwrap_or("http://127.0.0.1:8080".to_string())
 }
 
-// Function to recursively download files and create directories
-async fn download_dir(dir: &Dir, base_path: &Path) -> Result<(), Box<dyn Error>> {
-  let current_path = base_path.join(&dir.name);
-  create_directories(&current_path)?;
-
-  // Download files in the current directory
-  for fileprop in &dir.fileprops {
-      let file_path = current_path.join(&fileprop.name);
-      download_file(&fileprop.url, &file_path).await?;
-  }
-
-  // Recurse into subdirectories
-  for sub_dir in &dir.dirs {
-      download_dir(sub_dir, &current_path).await?;
-  }
-
-  Ok(())
+pub fn get_client() -> FlowfsClient {
+  FlowfsClient::new(&get_api_url())
 }
 
 /*
@@ -131,4 +100,18 @@ pub fn download_tree(rootdir: Dir) {
     }
   }
 }
-*/
\ No newline at end of file
+*/
+pub fn format_size(bytes: u64) -> String {
+  const UNITS: [&str; 5] = ["B", "KB", "MB", "GB", "TB"];
+  let mut size = bytes as f64;
+  let mut unit = 0;
+  while size >= 1024.0 && unit < UNITS.len() - 1 {
+    size /= 1024.0;
+    unit += 1;
+  }
+  if unit == 0 {
+    format!("{} {}", bytes, UNITS[0])
+  } else {
+    format!("{:.1} {}", size, UNITS[unit])
+  }
+}
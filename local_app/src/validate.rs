@@ -0,0 +1,31 @@
+pub use flowfs_client::validate::PathError;
+
+use flowfs_client::model as remote;
+
+use crate::model::Dir;
+
+// Check every name below `rootdir` with the same code as the backend. The
+// root's own name ("/") is never written to disk and is not checked.
+// Siblings may not share a name, ignoring case, whether they are files or
+// directories.
+pub fn validate_dir(rootdir: &Dir) -> Result<(), PathError> {
+    flowfs_client::validate::validate_dir(&to_remote_dir(rootdir))
+}
+
+fn to_remote_dir(dir: &Dir) -> remote::Dir {
+    remote::Dir {
+        name: dir.name.clone(),
+        dirs: dir.dirs.iter().map(to_remote_dir).collect(),
+        fileprops: dir.fileprops.iter()
+            .map(|f| remote::DirFile {
+                name: f.name.clone(),
+                url: f.url.clone(),
+                completed: f.completed,
+                size: f.size,
+                sha256: f.sha256.clone(),
+                mime: f.mime.clone(),
+                mtime: f.mtime,
+            })
+            .collect(),
+    }
+}